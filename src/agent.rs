@@ -1,15 +1,28 @@
 use crate::bytes::SecretBytes;
-use crate::shrine::{Mode, ShrinePassword};
+use crate::shrine::{EncryptionAlgorithm, Mode, Shrine, ShrinePassword};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use uuid::Uuid;
 
+#[cfg(unix)]
+pub mod audit;
 pub mod client;
 #[cfg(unix)]
+pub mod paths;
+#[cfg(unix)]
+mod peer;
+#[cfg(unix)]
 pub mod server;
+#[cfg(unix)]
+pub mod tls;
+#[cfg(unix)]
+pub mod token;
 
 #[derive(Deserialize, Serialize, Debug)]
 #[cfg(unix)]
@@ -20,8 +33,20 @@ pub enum ErrorResponse {
     Io(String),
     Unauthorized(Uuid),
     Forbidden(Uuid),
-    KeyNotFound { file: String, key: String },
+    KeyNotFound {
+        file: String,
+        key: String,
+    },
+    NotTotp {
+        file: String,
+        key: String,
+    },
     Regex(String),
+    ExecutableNotAllowed(String),
+    InvalidToken,
+    OutOfScope(String),
+    /// The request's shrine file could not be read.
+    InvalidShrine,
 }
 
 #[cfg(unix)]
@@ -32,10 +57,15 @@ impl ErrorResponse {
             ErrorResponse::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             ErrorResponse::Forbidden(_) => StatusCode::FORBIDDEN,
             ErrorResponse::KeyNotFound { .. } => StatusCode::NOT_FOUND,
+            ErrorResponse::NotTotp { .. } => StatusCode::BAD_REQUEST,
             ErrorResponse::Read(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ErrorResponse::Write(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ErrorResponse::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ErrorResponse::Regex(_) => StatusCode::BAD_REQUEST,
+            ErrorResponse::ExecutableNotAllowed(_) => StatusCode::FORBIDDEN,
+            ErrorResponse::InvalidToken => StatusCode::UNAUTHORIZED,
+            ErrorResponse::OutOfScope(_) => StatusCode::FORBIDDEN,
+            ErrorResponse::InvalidShrine => StatusCode::BAD_REQUEST,
         }
     }
 }
@@ -65,4 +95,154 @@ pub struct SetSecretRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GetSecretsRequest {
     pub regexp: Option<String>,
+    /// Whether the records' fields and the secrets' summaries are listed too.
+    #[serde(default)]
+    pub long: bool,
+}
+
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+pub struct SetSecretsRequest {
+    pub secrets: Vec<(String, SetSecretRequest)>,
+}
+
+/// A shrine file taken from git, e.g. by the diff filter, for the agent to open with the password
+/// it caches.
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+pub struct TextconvRequest {
+    pub shrine: Vec<u8>,
+}
+
+/// The shrine files given to the merge driver by git; `base` is not set when they have no common
+/// ancestor.
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+pub struct MergeRequest {
+    pub base: Option<Vec<u8>>,
+    pub ours: Vec<u8>,
+    pub theirs: Vec<u8>,
+}
+
+/// The merged shrine file, encrypted like ours, and the conflicts.
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+pub struct MergeResponse {
+    pub shrine: Vec<u8>,
+    pub conflicts: Vec<String>,
+}
+
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+pub struct SetConfigRequest {
+    pub value: String,
+}
+
+/// How the agent re-encrypts a shrine: the new password, if it changes, and the new encryption
+/// algorithm, if it changes.
+#[cfg(unix)]
+#[derive(Serialize, Deserialize)]
+pub struct ConvertRequest {
+    pub password: Option<ShrinePassword>,
+    pub encryption_algorithm: Option<EncryptionAlgorithm>,
+}
+
+/// How the lifetime of a password cached by the agent is computed.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// The password expires when it was not used for the configured duration.
+    #[default]
+    Sliding,
+    /// The password expires the configured duration after it was provided.
+    Absolute,
+}
+
+impl FromStr for Expiry {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sliding" => Ok(Expiry::Sliding),
+            "absolute" => Ok(Expiry::Absolute),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Expiry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expiry::Sliding => write!(f, "sliding"),
+            Expiry::Absolute => write!(f, "absolute"),
+        }
+    }
+}
+
+/// A password cached by the agent.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedPassword {
+    pub uuid: Uuid,
+    pub expiry: Expiry,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The secrets to get at once: the listed keys, or the keys matching a pattern, all of them if it
+/// is not set.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum KeySelection {
+    Keys(Vec<String>),
+    Pattern(Option<String>),
+}
+
+/// A change of a secret, notified to the agent's watchers.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub key: String,
+    pub kind: ChangeKind,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// The secret was added or its value changed.
+    Set,
+    Deleted,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            ChangeKind::Set => write!(f, "set {}", self.key),
+            ChangeKind::Deleted => write!(f, "deleted {}", self.key),
+        }
+    }
+}
+
+/// The metadata of a shrine, as printed by `shrine info`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    pub version: u8,
+    pub uuid: Uuid,
+    pub serialization_format: String,
+    pub encryption_algorithm: String,
+}
+
+impl<T> From<&Shrine<T>> for Info {
+    fn from(shrine: &Shrine<T>) -> Self {
+        Self {
+            version: shrine.version(),
+            uuid: shrine.uuid(),
+            serialization_format: shrine.serialization_format().to_string(),
+            encryption_algorithm: shrine.encryption_algorithm().to_string(),
+        }
+    }
+}
+
+/// The agent's counters, since it started.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    /// Requests served from an already opened shrine.
+    pub cache_hits: u64,
+    /// Requests that had to read the file and derive its key.
+    pub cache_misses: u64,
 }
@@ -0,0 +1,301 @@
+use crate::settings::Settings;
+use crate::utils::state_dir;
+use crate::Error;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+/// An access to the agent, one JSON line in the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub timestamp: DateTime<Utc>,
+    pub operation: AuditOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<Uuid>,
+    /// The folder containing the shrine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<String>,
+    /// The address of a TCP client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// The name of the token a TCP client authenticated with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub result: AuditResult,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+    /// Secrets were listed.
+    List,
+    Read,
+    Write,
+    Delete,
+    /// A one-time code was generated.
+    Otp,
+    /// Secrets were imported.
+    Import,
+    /// The shrine's configuration was read.
+    ReadConfig,
+    WriteConfig,
+    /// A client subscribed to the changes of secrets.
+    Watch,
+    /// A shrine file was listed for git to diff it.
+    Diff,
+    /// Shrine files were merged for git.
+    Merge,
+    /// The shrine was re-encrypted.
+    Convert,
+    /// A password was cached.
+    Unlock,
+    /// A cached password was evicted.
+    Lock,
+    /// All cached passwords were evicted.
+    LockAll,
+    /// A cached password expired.
+    Expire,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Ok,
+    /// The password is not cached.
+    Unauthorized,
+    /// The cached password is invalid.
+    Forbidden,
+    /// The executable is not allowed to read secrets, or the token does not allow the request.
+    Denied,
+    NotFound,
+    Error,
+}
+
+impl Display for AuditOperation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let operation = serde_json::to_value(self).expect("serializable");
+        f.pad(operation.as_str().expect("a string"))
+    }
+}
+
+impl Display for AuditResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let result = serde_json::to_value(self).expect("serializable");
+        f.pad(result.as_str().expect("a string"))
+    }
+}
+
+impl Display for AuditEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {:13} {:12}",
+            self.timestamp
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            self.operation,
+            self.result
+        )?;
+        if let Some(uuid) = self.uuid {
+            write!(f, " {}", uuid)?;
+        }
+        if let Some(key) = &self.key {
+            write!(f, " {}", key)?;
+        }
+        if let Some(pid) = self.pid {
+            write!(f, " pid {}", pid)?;
+        }
+        if let Some(executable) = &self.executable {
+            write!(f, " ({})", executable)?;
+        }
+        if let Some(address) = &self.address {
+            write!(f, " {}", address)?;
+        }
+        if let Some(token) = &self.token {
+            write!(f, " token {}", token)?;
+        }
+        Ok(())
+    }
+}
+
+/// The audit log file, rotated to `<path>.1`, `<path>.2`, ... when it reaches `max_size`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    /// How many rotated files are kept.
+    keep: u32,
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf, max_size: u64, keep: u32) -> Self {
+        Self {
+            path,
+            max_size,
+            keep,
+        }
+    }
+
+    /// The audit log configured by `agent.audit.path`, `agent.audit.max-size` and
+    /// `agent.audit.keep`; `None` if `agent.audit.path` is empty.
+    pub fn from_settings(settings: &Settings) -> Result<Option<Self>, Error> {
+        let path = match settings.get("agent.audit.path") {
+            Some("") => return Ok(None),
+            Some(path) => PathBuf::from(path),
+            None => match state_dir() {
+                None => return Ok(None),
+                Some(dir) => dir.join("audit.log"),
+            },
+        };
+
+        Ok(Some(Self::new(
+            path,
+            settings
+                .get_size("agent.audit.max-size")?
+                .unwrap_or(10 << 20),
+            settings.get_parsed("agent.audit.keep")?.unwrap_or(5),
+        )))
+    }
+
+    fn rotated(&self, index: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    pub fn append(&self, event: &AuditEvent) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(event).map_err(Error::JsonWrite)?;
+        line.push(b'\n');
+
+        let write_error = |e| Error::WriteFile(self.path.clone(), e);
+
+        let size = match self.path.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(write_error(e)),
+        };
+        if size > 0 && size + line.len() as u64 > self.max_size {
+            self.rotate().map_err(write_error)?;
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(write_error)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(write_error)
+    }
+
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for index in (1..self.keep).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(from, self.rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    /// The logged events, oldest first; unreadable lines are skipped.
+    pub fn read(&self) -> Result<Vec<AuditEvent>, Error> {
+        let mut events = Vec::new();
+        let files = (1..=self.keep)
+            .rev()
+            .map(|i| self.rotated(i))
+            .chain([self.path.clone()]);
+        for path in files {
+            let file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(Error::ReadFile(path, e)),
+            };
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|e| Error::ReadFile(path.clone(), e))?;
+                if let Ok(event) = serde_json::from_str(&line) {
+                    events.push(event);
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn event(key: &str) -> AuditEvent {
+        AuditEvent {
+            timestamp: Utc::now(),
+            operation: AuditOperation::Read,
+            uuid: Some(Uuid::nil()),
+            path: Some("/tmp".to_string()),
+            key: Some(key.to_string()),
+            pid: Some(1234),
+            executable: Some("/usr/bin/shrine".to_string()),
+            address: None,
+            token: None,
+            result: AuditResult::Ok,
+        }
+    }
+
+    #[test]
+    fn append_and_rotate() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("audit").join("audit.log");
+        let size = serde_json::to_vec(&event("key-0")).unwrap().len() as u64 + 1;
+        let log = AuditLog::new(path.clone(), 2 * size, 2);
+
+        for i in 0..7 {
+            log.append(&event(&format!("key-{}", i))).unwrap();
+        }
+
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(log.rotated(2).exists());
+        assert!(!log.rotated(3).exists());
+        let keys = log
+            .read()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.key.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["key-2", "key-3", "key-4", "key-5", "key-6"]);
+    }
+
+    #[test]
+    fn json_line() {
+        let mut event = event("key");
+        event.timestamp = DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        event.path = None;
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"timestamp":"2026-10-18T12:00:00Z","operation":"read","uuid":"00000000-0000-0000-0000-000000000000","key":"key","pid":1234,"executable":"/usr/bin/shrine","result":"ok"}"#
+        );
+        assert_eq!(
+            event.to_string(),
+            "2026-10-18T12:00:00Z read          ok           \
+            00000000-0000-0000-0000-000000000000 key pid 1234 (/usr/bin/shrine)"
+        );
+    }
+}
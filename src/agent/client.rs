@@ -1,18 +1,35 @@
-use crate::agent::{ErrorResponse, GetSecretsRequest, SetPasswordRequest, SetSecretRequest};
+use crate::agent::paths::AgentPaths;
+use crate::agent::tls::client_config;
+use crate::agent::{
+    CachedPassword, Change, ConvertRequest, ErrorResponse, GetSecretsRequest, Info, KeySelection,
+    MergeRequest, MergeResponse, Metrics, SetConfigRequest, SetPasswordRequest, SetSecretRequest,
+    SetSecretsRequest, TextconvRequest,
+};
 use crate::bytes::SecretBytes;
-use crate::shrine::{Key, Mode, Secret};
+use crate::settings::Settings;
+use crate::shrine::{EncryptionAlgorithm, Key, Mode, Secret, ShrinePassword};
+use crate::totp::Code;
 use crate::utils::read_password;
 use crate::Error;
 use async_recursion::async_recursion;
 use hyper::body::HttpBody;
 use hyper::client::connect::Connect;
 use hyper::client::HttpConnector;
+use hyper::header::AUTHORIZATION;
 use hyper::{http, Body, Method, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyperlocal::{UnixClientExt, UnixConnector, Uri};
+use nix::fcntl::{flock, FlockArg};
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::fs::OpenOptions;
+use std::os::unix::io::AsRawFd;
 use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
 use tokio::runtime::Runtime;
+use uuid::Uuid;
 
 pub trait Client {
     fn is_running(&self) -> bool;
@@ -27,9 +44,69 @@ pub trait Client {
 
     fn delete_key(&self, path: &str, key: &str) -> Result<Vec<Secret>, Error>;
 
-    fn ls(&self, path: &str, regexp: Option<&str>) -> Result<Vec<Key>, Error>;
+    /// Sets all the `secrets` at once, e.g. when importing a file.
+    fn set_keys(&self, path: &str, secrets: Vec<(String, Vec<u8>, Mode)>) -> Result<(), Error>;
+
+    /// Gets the selected secrets in a single request, sorted by key, each one with its own error.
+    #[allow(clippy::type_complexity)]
+    fn get_keys(
+        &self,
+        path: &str,
+        keys: &KeySelection,
+    ) -> Result<Vec<(String, Result<Secret, Error>)>, Error>;
+
+    /// The shrine's configuration, sorted by key.
+    fn config(&self, path: &str) -> Result<Vec<(String, String)>, Error>;
+
+    fn set_config(&self, path: &str, key: &str, value: &str) -> Result<(), Error>;
+
+    fn info(&self, path: &str) -> Result<Info, Error>;
+
+    /// Re-encrypts the shrine in the agent, with its cached password unless `password` is set.
+    fn convert(
+        &self,
+        path: &str,
+        password: Option<ShrinePassword>,
+        encryption_algorithm: Option<EncryptionAlgorithm>,
+    ) -> Result<(), Error>;
+
+    /// Subscribes to the changes of the secrets whose key matches `regexp`, and calls `on_change`
+    /// with each batch of changes until it returns `false`.
+    fn watch(
+        &self,
+        path: &str,
+        regexp: Option<&str>,
+        on_change: &mut dyn FnMut(Vec<Change>) -> bool,
+    ) -> Result<(), Error>;
+
+    fn ls(&self, path: &str, regexp: Option<&str>, long: bool) -> Result<Vec<Key>, Error>;
+
+    fn get_otp(&self, path: &str, key: &str) -> Result<Code, Error>;
+
+    /// The listing printed by `textconv` for the shrine file `shrine`, made by the agent.
+    fn textconv(&self, shrine: &[u8]) -> Result<String, Error>;
+
+    /// Merges the shrine files `ours` and `theirs` with the agent, `base` being their common
+    /// ancestor. Returns the merged file and the conflicts.
+    fn merge(
+        &self,
+        base: Option<&[u8]>,
+        ours: &[u8],
+        theirs: &[u8],
+    ) -> Result<(Vec<u8>, Vec<String>), Error>;
 
     fn clear_passwords(&self) -> Result<(), Error>;
+
+    /// Caches the password of the shrine `uuid`.
+    fn set_password(&self, uuid: Uuid, password: ShrinePassword) -> Result<(), Error>;
+
+    /// Forgets the cached password of the shrine `uuid`.
+    fn delete_password(&self, uuid: Uuid) -> Result<(), Error>;
+
+    /// The cached passwords and when they expire.
+    fn passwords(&self) -> Result<Vec<CachedPassword>, Error>;
+
+    fn metrics(&self) -> Result<Metrics, Error>;
 }
 
 #[cfg(unix)]
@@ -45,12 +122,20 @@ pub trait ClientConnector {
     type H: Connect + Clone + Send + Sync + 'static;
     fn uri(&self, uri: &str) -> http::Uri;
     fn client(&self) -> &hyper::Client<Self::H>;
+
+    /// The value of the requests' `Authorization` header, if any.
+    fn authorization(&self) -> Option<String> {
+        None
+    }
 }
 
 #[cfg(unix)]
 pub struct SocketClient {
-    socket: String,
+    paths: AgentPaths,
+    token: Option<String>,
     client: hyper::Client<UnixConnector>,
+    /// Whether to start the agent when it is not running, see [`HttpClient::autostart`].
+    autostart: bool,
 }
 
 #[cfg(unix)]
@@ -58,39 +143,101 @@ impl ClientConnector for SocketClient {
     type H = UnixConnector;
 
     fn uri(&self, uri: &str) -> http::Uri {
-        Uri::new(&self.socket, uri).into()
+        Uri::new(&self.paths.socket, uri).into()
     }
 
     fn client(&self) -> &hyper::Client<Self::H> {
         &self.client
     }
+
+    fn authorization(&self) -> Option<String> {
+        self.token.as_ref().map(|t| format!("Bearer {}", t))
+    }
 }
 
 #[cfg(unix)]
 impl HttpClient<SocketClient> {
+    /// The client of the agent's socket, see [`AgentPaths`], authenticated with
+    /// `$SHRINE_AGENT_TOKEN` if it is set.
     pub fn new() -> Result<Self, Error> {
+        let settings = Settings::load()?;
         Ok(Self {
             rt: tokio::runtime::Builder::new_current_thread()
                 .enable_all()
                 .build()
                 .unwrap(),
             client: SocketClient {
-                socket: env::var("XDG_RUNTIME_DIR")
-                    .map(|s| format!("{}/shrine.socket", s))
-                    .map_err(|_| Error::Agent("XDG_RUNTIME_DIR not set".to_string()))?,
+                paths: AgentPaths::from_settings(&settings),
+                token: env::var("SHRINE_AGENT_TOKEN").ok(),
                 client: hyper::Client::unix(),
+                autostart: settings.get_parsed("agent.autostart")?.unwrap_or(false),
             },
         })
     }
+
+    /// Starts the agent in the background if the `agent.autostart` setting is enabled and it is
+    /// not running, then waits until it answers. Concurrent calls start a single agent, by holding
+    /// a lock next to the socket.
+    pub fn autostart(&self) -> Result<(), Error> {
+        if !self.client.autostart || self.is_running() {
+            return Ok(());
+        }
+
+        self.client.paths.create_dirs()?;
+        let lock = self.client.paths.socket.with_extension("lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock)
+            .map_err(|e| Error::WriteFile(lock.clone(), e))?;
+        flock(file.as_raw_fd(), FlockArg::LockExclusive)
+            .map_err(|e| Error::WriteFile(lock.clone(), e.into()))?;
+
+        // another process may have started it while this one waited for the lock
+        if self.is_running() {
+            return Ok(());
+        }
+
+        let executable = env::current_exe().map_err(|e| Error::Command("shrine".to_string(), e))?;
+        let status = Command::new(&executable)
+            .args(["agent", "start"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()
+            .map_err(|e| Error::Command(executable.display().to_string(), e))?;
+        if !status.success() {
+            return Err(Error::Agent(format!("`agent start` failed ({})", status)));
+        }
+
+        for _ in 0..100 {
+            if self.is_running() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        Err(Error::Agent("the agent did not start".to_string()))
+    }
 }
 
 pub struct TcpClient {
     host: String,
-    client: hyper::Client<HttpConnector>,
+    token: Option<String>,
+    client: hyper::Client<HttpsConnector<HttpConnector>>,
+}
+
+impl TcpClient {
+    fn connector(ca: Option<&std::path::Path>) -> Result<HttpsConnector<HttpConnector>, Error> {
+        Ok(HttpsConnectorBuilder::new()
+            .with_tls_config(client_config(ca)?)
+            .https_only()
+            .enable_http1()
+            .build())
+    }
 }
 
 impl ClientConnector for TcpClient {
-    type H = HttpConnector;
+    type H = HttpsConnector<HttpConnector>;
 
     fn uri(&self, uri: &str) -> http::Uri {
         http::Uri::try_from(format!("{}{}", &self.host, uri)).unwrap()
@@ -99,6 +246,10 @@ impl ClientConnector for TcpClient {
     fn client(&self) -> &hyper::Client<Self::H> {
         &self.client
     }
+
+    fn authorization(&self) -> Option<String> {
+        self.token.as_ref().map(|t| format!("Bearer {}", t))
+    }
 }
 
 impl HttpClient<TcpClient> {
@@ -110,10 +261,56 @@ impl HttpClient<TcpClient> {
                 .unwrap(),
             client: TcpClient {
                 host,
-                client: hyper::Client::new(),
+                token: None,
+                client: hyper::Client::builder()
+                    .build(TcpClient::connector(None).expect("system certificates")),
             },
         }
     }
+
+    /// Authenticates the requests with `token`.
+    pub fn with_token(mut self, token: String) -> Self {
+        self.client.token = Some(token);
+        self
+    }
+
+    /// Only trusts the certificates in the PEM file `ca`.
+    pub fn with_ca(mut self, ca: &std::path::Path) -> Result<Self, Error> {
+        self.client.client = hyper::Client::builder().build(TcpClient::connector(Some(ca))?);
+        Ok(self)
+    }
+
+    /// The client of the agent at `$SHRINE_AGENT_URL`, authenticated with `$SHRINE_AGENT_TOKEN`
+    /// and trusting `$SHRINE_AGENT_CA`, if `$SHRINE_AGENT_URL` is set.
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let Ok(url) = env::var("SHRINE_AGENT_URL") else {
+            return Ok(None);
+        };
+        let mut client = Self::new(parse_url(&url)?);
+        if let Ok(token) = env::var("SHRINE_AGENT_TOKEN") {
+            client = client.with_token(token);
+        }
+        if let Some(ca) = env::var_os("SHRINE_AGENT_CA") {
+            client = client.with_ca(&PathBuf::from(ca))?;
+        }
+        Ok(Some(client))
+    }
+}
+
+/// The agent's base URL, without its trailing `/`; only `https://` URLs are accepted, so that the
+/// token and passwords are never sent in cleartext.
+fn parse_url(url: &str) -> Result<String, Error> {
+    let base = url.trim_end_matches('/');
+    match http::Uri::try_from(base) {
+        Ok(uri)
+            if uri.scheme() == Some(&http::uri::Scheme::HTTPS)
+                && uri.authority().is_some()
+                && uri.query().is_none() =>
+        {
+            Ok(base.to_string())
+        }
+        _ => Err(Error::InvalidAgentUrl(url.to_string())),
+    }
 }
 
 #[cfg(unix)]
@@ -122,6 +319,13 @@ where
     C: ClientConnector,
     C::H: Connect + Clone + Send + Sync + 'static,
 {
+    fn request(&self) -> http::request::Builder {
+        match self.client.authorization() {
+            None => Request::builder(),
+            Some(authorization) => Request::builder().header(AUTHORIZATION, authorization),
+        }
+    }
+
     async fn get<T>(&self, uri: &str) -> Result<T, Error>
     where
         T: DoDeserialize,
@@ -141,7 +345,8 @@ where
         T: DoDeserialize,
     {
         loop {
-            let request = Request::builder()
+            let request = self
+                .request()
                 .method(method.clone())
                 .uri(self.client.uri(uri))
                 .body(Body::empty())
@@ -154,13 +359,30 @@ where
     }
 
     async fn put<P, T>(&self, uri: &str, payload: &P) -> Result<T, Error>
+    where
+        P: Serialize,
+        T: DoDeserialize,
+    {
+        self.with_body(uri, Method::PUT, payload).await
+    }
+
+    async fn post<P, T>(&self, uri: &str, payload: &P) -> Result<T, Error>
+    where
+        P: Serialize,
+        T: DoDeserialize,
+    {
+        self.with_body(uri, Method::POST, payload).await
+    }
+
+    async fn with_body<P, T>(&self, uri: &str, method: Method, payload: &P) -> Result<T, Error>
     where
         P: Serialize,
         T: DoDeserialize,
     {
         loop {
-            let request = Request::builder()
-                .method(Method::PUT)
+            let request = self
+                .request()
+                .method(method.clone())
                 .header("content-type", "application/json")
                 .uri(self.client.uri(uri))
                 .body(Body::from(
@@ -197,6 +419,12 @@ where
                 .map(|s| Some(s));
         }
 
+        self.handle_error(payload).await.map(|_| None)
+    }
+
+    /// Converts an error returned by the agent; returns `Ok` when the request may be sent again,
+    /// after the password was provided.
+    async fn handle_error(&self, payload: Vec<u8>) -> Result<(), Error> {
         match serde_json::from_slice::<ErrorResponse>(&payload).map_err(|_| {
             Error::Agent(format!(
                 "invalid error data: {:?}",
@@ -213,13 +441,93 @@ where
                     },
                 )
                 .await?;
-                Ok(None)
+                Ok(())
             }
             ErrorResponse::KeyNotFound { key, .. } => Err(Error::KeyNotFound(key)),
+            ErrorResponse::NotTotp { key, .. } => Err(Error::NotTotp(key)),
             ErrorResponse::Regex(e) => Err(Error::InvalidPattern(regex::Error::Syntax(e))),
+            ErrorResponse::ExecutableNotAllowed(executable) => {
+                Err(Error::ExecutableNotAllowed(executable))
+            }
+            ErrorResponse::InvalidToken => Err(Error::InvalidToken),
+            ErrorResponse::OutOfScope(reason) => Err(Error::OutOfScope(reason)),
+            ErrorResponse::InvalidShrine => Err(Error::Read()),
             _ => Err(Error::Agent("unknown error".to_string())),
         }
     }
+
+    /// Reads the server-sent events of `uri`, passing the changes to `on_change` until it returns
+    /// `false`. Connects again once an `error` event is resolved, e.g. by unlocking the shrine.
+    async fn stream_changes(
+        &self,
+        uri: &str,
+        on_change: &mut dyn FnMut(Vec<Change>) -> bool,
+    ) -> Result<(), Error> {
+        'connect: loop {
+            let request = self
+                .request()
+                .method(Method::GET)
+                .uri(self.client.uri(uri))
+                .body(Body::empty())
+                .unwrap();
+            let mut response = self
+                .client
+                .client()
+                .request(request)
+                .await
+                .map_err(|_| Error::Agent("communication problem".to_string()))?;
+
+            let success = response.status().is_success();
+            let mut payload = Vec::<u8>::new();
+            while let Some(data) = response.data().await {
+                let data =
+                    data.map_err(|_| Error::Agent("could not get response data".to_string()))?;
+                payload.extend(data);
+                if !success {
+                    continue;
+                }
+
+                while let Some(end) = payload.windows(2).position(|w| w == b"\n\n") {
+                    let message = payload.drain(..end + 2).collect::<Vec<u8>>();
+                    let (event, data) = parse_event(&String::from_utf8_lossy(&message));
+                    match event.as_deref() {
+                        Some("change") => {
+                            let changes = serde_json::from_str::<Vec<Change>>(&data)
+                                .map_err(|_| Error::Agent("invalid response data".to_string()))?;
+                            if !on_change(changes) {
+                                return Ok(());
+                            }
+                        }
+                        Some("error") => {
+                            self.handle_error(data.into_bytes()).await?;
+                            continue 'connect;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if success {
+                return Err(Error::Agent("the agent closed the connection".to_string()));
+            }
+            self.handle_error(payload).await?;
+        }
+    }
+}
+
+/// The name and data of a server-sent event.
+#[cfg(unix)]
+fn parse_event(message: &str) -> (Option<String>, String) {
+    let mut event = None;
+    let mut data = Vec::new();
+    for line in message.lines() {
+        if let Some(name) = line.strip_prefix("event:") {
+            event = Some(name.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+    (event, data.join("\n"))
 }
 
 #[cfg(unix)]
@@ -271,59 +579,308 @@ where
         )))
     }
 
-    fn ls(&self, path: &str, regexp: Option<&str>) -> Result<Vec<Key>, Error> {
+    fn set_keys(&self, path: &str, secrets: Vec<(String, Vec<u8>, Mode)>) -> Result<(), Error> {
+        self.rt
+            .block_on(
+                self.put::<_, Empty>(
+                    &format!("/keys/{}", urlencoding::encode(path)),
+                    &SetSecretsRequest {
+                        secrets: secrets
+                            .into_iter()
+                            .map(|(key, value, mode)| {
+                                (
+                                    key,
+                                    SetSecretRequest {
+                                        secret: SecretBytes::from(value.as_slice()),
+                                        mode,
+                                    },
+                                )
+                            })
+                            .collect(),
+                    },
+                ),
+            )
+            .map(|_| ())
+    }
+
+    fn get_keys(
+        &self,
+        path: &str,
+        keys: &KeySelection,
+    ) -> Result<Vec<(String, Result<Secret, Error>)>, Error> {
+        self.rt
+            .block_on(
+                self.post::<_, Vec<(String, Result<Secret, ErrorResponse>)>>(
+                    &format!("/batch/{}", urlencoding::encode(path)),
+                    keys,
+                ),
+            )
+            .map(|secrets| {
+                secrets
+                    .into_iter()
+                    .map(|(key, secret)| (key, secret.map_err(key_error)))
+                    .collect()
+            })
+    }
+
+    fn config(&self, path: &str) -> Result<Vec<(String, String)>, Error> {
+        self.rt.block_on(
+            self.get::<Vec<(String, String)>>(&format!("/config/{}", urlencoding::encode(path))),
+        )
+    }
+
+    fn set_config(&self, path: &str, key: &str, value: &str) -> Result<(), Error> {
+        self.rt
+            .block_on(self.put::<_, Empty>(
+                &format!(
+                    "/config/{}/{}",
+                    urlencoding::encode(path),
+                    urlencoding::encode(key)
+                ),
+                &SetConfigRequest {
+                    value: value.to_string(),
+                },
+            ))
+            .map(|_| ())
+    }
+
+    fn info(&self, path: &str) -> Result<Info, Error> {
+        self.rt
+            .block_on(self.get::<Info>(&format!("/info/{}", urlencoding::encode(path))))
+    }
+
+    fn convert(
+        &self,
+        path: &str,
+        password: Option<ShrinePassword>,
+        encryption_algorithm: Option<EncryptionAlgorithm>,
+    ) -> Result<(), Error> {
+        self.rt
+            .block_on(self.post::<_, Empty>(
+                &format!("/convert/{}", urlencoding::encode(path)),
+                &ConvertRequest {
+                    password,
+                    encryption_algorithm,
+                },
+            ))
+            .map(|_| ())
+    }
+
+    fn watch(
+        &self,
+        path: &str,
+        regexp: Option<&str>,
+        on_change: &mut dyn FnMut(Vec<Change>) -> bool,
+    ) -> Result<(), Error> {
+        self.rt.block_on(self.stream_changes(
+            &format!(
+                "/watch/{}?{}",
+                urlencoding::encode(path),
+                serde_qs::to_string(&GetSecretsRequest {
+                    regexp: regexp.map(|s| s.to_string()),
+                    long: false,
+                })
+                .unwrap()
+            ),
+            on_change,
+        ))
+    }
+
+    fn ls(&self, path: &str, regexp: Option<&str>, long: bool) -> Result<Vec<Key>, Error> {
         self.rt.block_on(self.get::<Vec<Key>>(&format!(
             "/keys/{}?{}",
             urlencoding::encode(path),
             serde_qs::to_string(&GetSecretsRequest {
-                regexp: regexp.map(|s| s.to_string())
+                regexp: regexp.map(|s| s.to_string()),
+                long,
             })
             .unwrap()
         )))
     }
 
+    fn get_otp(&self, path: &str, key: &str) -> Result<Code, Error> {
+        self.rt.block_on(self.get::<Code>(&format!(
+            "/otp/{}/{}",
+            urlencoding::encode(path),
+            urlencoding::encode(key)
+        )))
+    }
+
+    fn textconv(&self, shrine: &[u8]) -> Result<String, Error> {
+        self.rt.block_on(self.post::<_, String>(
+            "/textconv",
+            &TextconvRequest {
+                shrine: shrine.to_vec(),
+            },
+        ))
+    }
+
+    fn merge(
+        &self,
+        base: Option<&[u8]>,
+        ours: &[u8],
+        theirs: &[u8],
+    ) -> Result<(Vec<u8>, Vec<String>), Error> {
+        self.rt
+            .block_on(self.post::<_, MergeResponse>(
+                "/merge",
+                &MergeRequest {
+                    base: base.map(|b| b.to_vec()),
+                    ours: ours.to_vec(),
+                    theirs: theirs.to_vec(),
+                },
+            ))
+            .map(|response| (response.shrine, response.conflicts))
+    }
+
     fn clear_passwords(&self) -> Result<(), Error> {
         self.rt
             .block_on(self.delete::<Empty>("/passwords"))
             .map(|_| ())
     }
+
+    fn set_password(&self, uuid: Uuid, password: ShrinePassword) -> Result<(), Error> {
+        self.rt
+            .block_on(self.put::<_, Empty>("/passwords", &SetPasswordRequest { uuid, password }))
+            .map(|_| ())
+    }
+
+    fn delete_password(&self, uuid: Uuid) -> Result<(), Error> {
+        self.rt
+            .block_on(self.delete::<Empty>(&format!("/passwords/{}", uuid)))
+            .map(|_| ())
+    }
+
+    fn passwords(&self) -> Result<Vec<CachedPassword>, Error> {
+        self.rt
+            .block_on(self.get::<Vec<CachedPassword>>("/passwords"))
+    }
+
+    fn metrics(&self) -> Result<Metrics, Error> {
+        self.rt.block_on(self.get::<Metrics>("/metrics"))
+    }
 }
 
-#[cfg(not(unix))]
+/// A client for when the agent is not used: it is never running, so the shrine files are accessed
+/// directly.
 pub struct NoClient {}
 
-#[cfg(not(unix))]
 impl Client for NoClient {
     fn is_running(&self) -> bool {
         false
     }
 
     fn pid(&self) -> Option<u32> {
-        unimplemented!()
+        None
     }
 
     fn stop(&self) -> Result<(), Error> {
-        unimplemented!()
+        Ok(())
     }
 
     fn get_key(&self, _path: &str, _key: &str) -> Result<Secret, Error> {
-        unimplemented!()
+        Err(Error::AgentNotRunning)
     }
 
-    fn set_key(&self, _path: &str, _key: &str, _value: Vec<u8>, _mode: Mode) -> Result<(), Error> {
-        unimplemented!()
+    fn set_key(&self, _path: &str, _key: &str, _value: &[u8], _mode: Mode) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
     }
 
     fn delete_key(&self, _path: &str, _key: &str) -> Result<Vec<Secret>, Error> {
-        unimplemented!()
+        Err(Error::AgentNotRunning)
+    }
+
+    fn set_keys(&self, _path: &str, _secrets: Vec<(String, Vec<u8>, Mode)>) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn get_keys(
+        &self,
+        _path: &str,
+        _keys: &KeySelection,
+    ) -> Result<Vec<(String, Result<Secret, Error>)>, Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn config(&self, _path: &str) -> Result<Vec<(String, String)>, Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn set_config(&self, _path: &str, _key: &str, _value: &str) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn info(&self, _path: &str) -> Result<Info, Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn convert(
+        &self,
+        _path: &str,
+        _password: Option<ShrinePassword>,
+        _encryption_algorithm: Option<EncryptionAlgorithm>,
+    ) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn watch(
+        &self,
+        _path: &str,
+        _regexp: Option<&str>,
+        _on_change: &mut dyn FnMut(Vec<Change>) -> bool,
+    ) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn ls(&self, _path: &str, _regexp: Option<&str>, _long: bool) -> Result<Vec<Key>, Error> {
+        Err(Error::AgentNotRunning)
     }
 
-    fn ls(&self, _path: &str, _regexp: Option<&str>) -> Result<Vec<Key>, Error> {
-        unimplemented!()
+    fn get_otp(&self, _path: &str, _key: &str) -> Result<Code, Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn textconv(&self, _shrine: &[u8]) -> Result<String, Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn merge(
+        &self,
+        _base: Option<&[u8]>,
+        _ours: &[u8],
+        _theirs: &[u8],
+    ) -> Result<(Vec<u8>, Vec<String>), Error> {
+        Err(Error::AgentNotRunning)
     }
 
     fn clear_passwords(&self) -> Result<(), Error> {
-        unimplemented!()
+        Err(Error::AgentNotRunning)
+    }
+
+    fn set_password(&self, _uuid: Uuid, _password: ShrinePassword) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn delete_password(&self, _uuid: Uuid) -> Result<(), Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn passwords(&self) -> Result<Vec<CachedPassword>, Error> {
+        Err(Error::AgentNotRunning)
+    }
+
+    fn metrics(&self) -> Result<Metrics, Error> {
+        Err(Error::AgentNotRunning)
+    }
+}
+
+/// The error of a single secret of a batch.
+#[cfg(unix)]
+fn key_error(error: ErrorResponse) -> Error {
+    match error {
+        ErrorResponse::KeyNotFound { key, .. } => Error::KeyNotFound(key),
+        ErrorResponse::OutOfScope(reason) => Error::OutOfScope(reason),
+        _ => Error::Agent("unknown error".to_string()),
     }
 }
 
@@ -355,6 +912,7 @@ where
 }
 
 #[cfg(test)]
+#[allow(clippy::type_complexity, clippy::expect_fun_call)]
 pub mod mock {
     use super::*;
     use std::cell::RefCell;
@@ -366,7 +924,25 @@ pub mod mock {
         get_keys: RefCell<HashMap<(String, String), Result<Secret, Error>>>,
         set_keys: RefCell<HashMap<(String, String, Vec<u8>, Mode), Result<(), Error>>>,
         delete_key: RefCell<HashMap<(String, String), Result<Vec<Secret>, Error>>>,
-        ls: RefCell<HashMap<(String, Option<String>), Result<Vec<Key>, Error>>>,
+        set_keys_batch: RefCell<HashMap<(String, Vec<(String, Vec<u8>, Mode)>), Result<(), Error>>>,
+        get_keys_batch: RefCell<
+            HashMap<(String, KeySelection), Result<Vec<(String, Result<Secret, Error>)>, Error>>,
+        >,
+        config: RefCell<HashMap<String, Result<Vec<(String, String)>, Error>>>,
+        set_config: RefCell<HashMap<(String, String, String), Result<(), Error>>>,
+        info: RefCell<HashMap<String, Result<Info, Error>>>,
+        convert: RefCell<
+            HashMap<(String, Option<String>, Option<EncryptionAlgorithm>), Result<(), Error>>,
+        >,
+        watch: RefCell<HashMap<(String, Option<String>), Vec<Vec<Change>>>>,
+        ls: RefCell<HashMap<(String, Option<String>, bool), Result<Vec<Key>, Error>>>,
+        get_otp: RefCell<HashMap<(String, String), Result<Code, Error>>>,
+        textconv: RefCell<HashMap<Vec<u8>, Result<String, Error>>>,
+        merge: RefCell<
+            HashMap<(Option<Vec<u8>>, Vec<u8>, Vec<u8>), Result<(Vec<u8>, Vec<String>), Error>>,
+        >,
+        set_password: RefCell<HashMap<(Uuid, String), Result<(), Error>>>,
+        delete_password: RefCell<HashMap<Uuid, Result<(), Error>>>,
     }
 
     impl MockClient {
@@ -389,26 +965,135 @@ pub mod mock {
             result: Result<(), Error>,
         ) {
             self.set_keys.borrow_mut().insert(
+                (path.to_string(), key.to_string(), value.to_vec(), *mode),
+                result,
+            );
+        }
+
+        pub fn with_delete_key(&self, path: &str, key: &str, result: Result<Vec<Secret>, Error>) {
+            self.delete_key
+                .borrow_mut()
+                .insert((path.to_string(), key.to_string()), result);
+        }
+
+        pub fn with_set_keys(
+            &self,
+            path: &str,
+            secrets: Vec<(&str, &[u8], Mode)>,
+            result: Result<(), Error>,
+        ) {
+            self.set_keys_batch.borrow_mut().insert(
                 (
                     path.to_string(),
-                    key.to_string(),
-                    value.to_vec(),
-                    mode.clone(),
+                    secrets
+                        .into_iter()
+                        .map(|(k, v, m)| (k.to_string(), v.to_vec(), m))
+                        .collect(),
                 ),
                 result,
             );
         }
 
-        pub fn with_delete_key(&self, path: &str, key: &str, result: Result<Vec<Secret>, Error>) {
-            self.delete_key
+        pub fn with_get_keys(
+            &self,
+            path: &str,
+            keys: KeySelection,
+            result: Result<Vec<(String, Result<Secret, Error>)>, Error>,
+        ) {
+            self.get_keys_batch
+                .borrow_mut()
+                .insert((path.to_string(), keys), result);
+        }
+
+        pub fn with_config(&self, path: &str, result: Result<Vec<(String, String)>, Error>) {
+            self.config.borrow_mut().insert(path.to_string(), result);
+        }
+
+        pub fn with_set_config(
+            &self,
+            path: &str,
+            key: &str,
+            value: &str,
+            result: Result<(), Error>,
+        ) {
+            self.set_config.borrow_mut().insert(
+                (path.to_string(), key.to_string(), value.to_string()),
+                result,
+            );
+        }
+
+        pub fn with_info(&self, path: &str, result: Result<Info, Error>) {
+            self.info.borrow_mut().insert(path.to_string(), result);
+        }
+
+        pub fn with_convert(
+            &self,
+            path: &str,
+            password: Option<&str>,
+            encryption_algorithm: Option<EncryptionAlgorithm>,
+            result: Result<(), Error>,
+        ) {
+            self.convert.borrow_mut().insert(
+                (
+                    path.to_string(),
+                    password.map(|p| p.to_string()),
+                    encryption_algorithm,
+                ),
+                result,
+            );
+        }
+
+        /// Expects `watch`, which notifies each batch of `changes` then returns.
+        pub fn with_watch(&self, path: &str, regexp: Option<&str>, changes: Vec<Vec<Change>>) {
+            self.watch
+                .borrow_mut()
+                .insert((path.to_string(), regexp.map(|r| r.to_string())), changes);
+        }
+
+        pub fn with_ls(
+            &self,
+            path: &str,
+            regexp: Option<&str>,
+            long: bool,
+            result: Result<Vec<Key>, Error>,
+        ) {
+            self.ls.borrow_mut().insert(
+                (path.to_string(), regexp.map(|r| r.to_string()), long),
+                result,
+            );
+        }
+
+        pub fn with_get_otp(&self, path: &str, key: &str, result: Result<Code, Error>) {
+            self.get_otp
                 .borrow_mut()
                 .insert((path.to_string(), key.to_string()), result);
         }
 
-        pub fn with_ls(&self, path: &str, regexp: Option<&str>, result: Result<Vec<Key>, Error>) {
-            self.ls
+        pub fn with_textconv(&self, shrine: &[u8], result: Result<String, Error>) {
+            self.textconv.borrow_mut().insert(shrine.to_vec(), result);
+        }
+
+        pub fn with_merge(
+            &self,
+            base: Option<&[u8]>,
+            ours: &[u8],
+            theirs: &[u8],
+            result: Result<(Vec<u8>, Vec<String>), Error>,
+        ) {
+            self.merge.borrow_mut().insert(
+                (base.map(|b| b.to_vec()), ours.to_vec(), theirs.to_vec()),
+                result,
+            );
+        }
+
+        pub fn with_set_password(&self, uuid: Uuid, password: &str, result: Result<(), Error>) {
+            self.set_password
                 .borrow_mut()
-                .insert((path.to_string(), regexp.map(|r| r.to_string())), result);
+                .insert((uuid, password.to_string()), result);
+        }
+
+        pub fn with_delete_password(&self, uuid: Uuid, result: Result<(), Error>) {
+            self.delete_password.borrow_mut().insert(uuid, result);
         }
     }
 
@@ -449,16 +1134,147 @@ pub mod mock {
                 .expect(&format!("unexpected delete_key(\"{}\", \"{}\")", path, key))
         }
 
-        fn ls(&self, path: &str, regexp: Option<&str>) -> Result<Vec<Key>, Error> {
-            self.ls
+        fn set_keys(&self, path: &str, secrets: Vec<(String, Vec<u8>, Mode)>) -> Result<(), Error> {
+            self.set_keys_batch
+                .borrow_mut()
+                .remove(&(path.to_string(), secrets.clone()))
+                .expect(&format!("unexpected set_keys(\"{}\", {:?})", path, secrets))
+        }
+
+        fn get_keys(
+            &self,
+            path: &str,
+            keys: &KeySelection,
+        ) -> Result<Vec<(String, Result<Secret, Error>)>, Error> {
+            self.get_keys_batch
+                .borrow_mut()
+                .remove(&(path.to_string(), keys.clone()))
+                .expect(&format!("unexpected get_keys(\"{}\", {:?})", path, keys))
+        }
+
+        fn config(&self, path: &str) -> Result<Vec<(String, String)>, Error> {
+            self.config
+                .borrow_mut()
+                .remove(path)
+                .expect(&format!("unexpected config(\"{}\")", path))
+        }
+
+        fn set_config(&self, path: &str, key: &str, value: &str) -> Result<(), Error> {
+            self.set_config
+                .borrow_mut()
+                .remove(&(path.to_string(), key.to_string(), value.to_string()))
+                .expect(&format!(
+                    "unexpected set_config(\"{}\", \"{}\", \"{}\")",
+                    path, key, value
+                ))
+        }
+
+        fn info(&self, path: &str) -> Result<Info, Error> {
+            self.info
+                .borrow_mut()
+                .remove(path)
+                .expect(&format!("unexpected info(\"{}\")", path))
+        }
+
+        fn convert(
+            &self,
+            path: &str,
+            password: Option<ShrinePassword>,
+            encryption_algorithm: Option<EncryptionAlgorithm>,
+        ) -> Result<(), Error> {
+            self.convert
+                .borrow_mut()
+                .remove(&(
+                    path.to_string(),
+                    password.map(|p| p.expose_secret().to_string()),
+                    encryption_algorithm,
+                ))
+                .expect(&format!(
+                    "unexpected convert(\"{}\", .., {:?})",
+                    path, encryption_algorithm
+                ))
+        }
+
+        fn watch(
+            &self,
+            path: &str,
+            regexp: Option<&str>,
+            on_change: &mut dyn FnMut(Vec<Change>) -> bool,
+        ) -> Result<(), Error> {
+            let changes = self
+                .watch
                 .borrow_mut()
                 .remove(&(path.to_string(), regexp.map(|r| r.to_string())))
-                .expect(&format!("unexpected ls(\"{}\", \"{:?}\")", path, regexp))
+                .expect(&format!("unexpected watch(\"{}\", {:?})", path, regexp));
+            for changes in changes {
+                if !on_change(changes) {
+                    break;
+                }
+            }
+            Ok(())
+        }
+
+        fn ls(&self, path: &str, regexp: Option<&str>, long: bool) -> Result<Vec<Key>, Error> {
+            self.ls
+                .borrow_mut()
+                .remove(&(path.to_string(), regexp.map(|r| r.to_string()), long))
+                .expect(&format!(
+                    "unexpected ls(\"{}\", \"{:?}\", {})",
+                    path, regexp, long
+                ))
+        }
+
+        fn get_otp(&self, path: &str, key: &str) -> Result<Code, Error> {
+            self.get_otp
+                .borrow_mut()
+                .remove(&(path.to_string(), key.to_string()))
+                .expect(&format!("unexpected get_otp(\"{}\", \"{}\")", path, key))
+        }
+
+        fn textconv(&self, shrine: &[u8]) -> Result<String, Error> {
+            self.textconv
+                .borrow_mut()
+                .remove(shrine)
+                .expect("unexpected textconv(..)")
+        }
+
+        fn merge(
+            &self,
+            base: Option<&[u8]>,
+            ours: &[u8],
+            theirs: &[u8],
+        ) -> Result<(Vec<u8>, Vec<String>), Error> {
+            self.merge
+                .borrow_mut()
+                .remove(&(base.map(|b| b.to_vec()), ours.to_vec(), theirs.to_vec()))
+                .expect("unexpected merge(..)")
         }
 
         fn clear_passwords(&self) -> Result<(), Error> {
             todo!()
         }
+
+        fn set_password(&self, uuid: Uuid, password: ShrinePassword) -> Result<(), Error> {
+            self.set_password
+                .borrow_mut()
+                .remove(&(uuid, password.expose_secret().to_string()))
+                .expect(&format!("unexpected set_password({}, ..)", uuid))
+        }
+
+        fn delete_password(&self, uuid: Uuid) -> Result<(), Error> {
+            self.delete_password
+                .borrow_mut()
+                .remove(&uuid)
+                .expect(&format!("unexpected delete_password({})", uuid))
+        }
+
+        fn passwords(&self) -> Result<Vec<CachedPassword>, Error> {
+            todo!()
+        }
+
+        fn metrics(&self) -> Result<Metrics, Error> {
+            todo!()
+        }
     }
 }
 
@@ -467,6 +1283,53 @@ mod tests {
     use super::*;
     use httpmock::prelude::*;
 
+    /// A client of `httpmock`'s server, which only speaks plain HTTP.
+    fn http_client(url: String) -> HttpClient<TcpClient> {
+        let mut client = HttpClient::<TcpClient>::new(url);
+        client.client.client = hyper::Client::builder().build(
+            HttpsConnectorBuilder::new()
+                .with_tls_config(client_config(None).unwrap())
+                .https_or_http()
+                .enable_http1()
+                .build(),
+        );
+        client
+    }
+
+    #[test]
+    fn parse_url() {
+        assert_eq!(
+            super::parse_url("https://127.0.0.1:7878/").unwrap(),
+            "https://127.0.0.1:7878"
+        );
+        for url in [
+            "http://127.0.0.1:7878",
+            "127.0.0.1:7878",
+            "https://",
+            "not a url",
+        ] {
+            assert_eq!(
+                super::parse_url(url).unwrap_err().to_string(),
+                format!("Invalid agent URL `{}`: an `https://` URL is expected", url)
+            );
+        }
+    }
+
+    #[test]
+    fn https_only() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/pid");
+            then.status(200)
+                .body(serde_json::to_string(&1234u32).unwrap());
+        });
+
+        let client = HttpClient::<TcpClient>::new(server.base_url()).with_token("token".into());
+
+        assert_eq!(client.pid(), None);
+        mock.assert_hits(0);
+    }
+
     #[test]
     fn pid() {
         let server = MockServer::start();
@@ -476,7 +1339,7 @@ mod tests {
                 .body(serde_json::to_string(&1234u32).unwrap());
         });
 
-        let client = HttpClient::<TcpClient>::new(server.base_url());
+        let client = http_client(server.base_url());
 
         let pid = client.pid().expect("PID expected");
 
@@ -484,6 +1347,30 @@ mod tests {
         assert_eq!(pid, 1234u32);
     }
 
+    #[test]
+    fn get_key_out_of_scope() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/keys/path/key")
+                .header("authorization", "Bearer token");
+            then.status(403).body(
+                serde_json::to_string(&ErrorResponse::OutOfScope("access to `key`".to_string()))
+                    .unwrap(),
+            );
+        });
+
+        let client = http_client(server.base_url()).with_token("token".to_string());
+
+        let error = client.get_key("path", "key").unwrap_err();
+
+        mock.assert();
+        assert_eq!(
+            error.to_string(),
+            "The agent token does not allow access to `key`"
+        );
+    }
+
     #[test]
     fn get_key() {
         let server = MockServer::start();
@@ -501,7 +1388,7 @@ mod tests {
             );
         });
 
-        let client = HttpClient::<TcpClient>::new(server.base_url());
+        let client = http_client(server.base_url());
 
         let secret = client.get_key("path", "key").expect("Secret expected");
 
@@ -512,6 +1399,119 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_keys() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(POST)
+                .path("/batch/path")
+                .json_body(serde_json::json!({"Keys": ["key", "missing"]}));
+            then.status(200).body(
+                r#"
+                [
+                    ["key", {"Ok": {
+                        "value": [115,101,99,114,101,116],
+                        "mode": "Text",
+                        "created_by": "cpollet@localhost",
+                        "created_at": "2023-06-20T17:51:11.786655084Z"
+                    }}],
+                    ["missing", {"Err": {"KeyNotFound": {"file": "path", "key": "missing"}}}]
+                ]
+            "#,
+            );
+        });
+
+        let client = http_client(server.base_url());
+
+        let secrets = client
+            .get_keys(
+                "path",
+                &KeySelection::Keys(vec!["key".to_string(), "missing".to_string()]),
+            )
+            .expect("Secrets expected");
+
+        mock.assert();
+        assert_eq!(secrets.len(), 2);
+        assert_eq!(
+            secrets[0]
+                .1
+                .as_ref()
+                .unwrap()
+                .value()
+                .expose_secret_as_bytes(),
+            "secret".as_bytes()
+        );
+        assert_eq!(
+            secrets[1].1.as_ref().unwrap_err().to_string(),
+            "Key `missing` does not exist"
+        );
+    }
+
+    #[test]
+    fn watch() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET)
+                .path("/watch/path")
+                .query_param("regexp", "^env/");
+            then.status(200)
+                .header("content-type", "text/event-stream")
+                .body(concat!(
+                    ":\n\n",
+                    "event:change\ndata:[{\"key\":\"env/a\",\"kind\":\"set\"}]\n\n",
+                    "event: change\n",
+                    "data: [{\"key\":\"env/b\",\"kind\":\"deleted\"}]\n\n",
+                ));
+        });
+
+        let client = http_client(server.base_url());
+
+        let mut changes = Vec::new();
+        let result = client.watch("path", Some("^env/"), &mut |c| {
+            changes.extend(c);
+            true
+        });
+
+        mock.assert();
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            Error::Agent("the agent closed the connection".to_string()).to_string()
+        );
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>(),
+            vec!["set env/a", "deleted env/b"]
+        );
+
+        let mut changes = Vec::new();
+        client
+            .watch("path", Some("^env/"), &mut |c| {
+                changes.extend(c);
+                false
+            })
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+    }
+
+    #[test]
+    fn get_otp() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/otp/path/key");
+            then.status(200).body(r#"{"code":"123456","remaining":12}"#);
+        });
+
+        let client = http_client(server.base_url());
+
+        let code = client.get_otp("path", "key").expect("Code expected");
+
+        mock.assert();
+        assert_eq!(code.code, "123456");
+        assert_eq!(code.remaining, 12);
+    }
+
     #[test]
     fn set_key() {
         let server = MockServer::start();
@@ -526,7 +1526,7 @@ mod tests {
             then.status(204);
         });
 
-        let client = HttpClient::<TcpClient>::new(server.base_url());
+        let client = http_client(server.base_url());
 
         client
             .set_key("path", "key", "value".as_bytes(), Mode::Binary)
@@ -552,7 +1552,7 @@ mod tests {
             );
         });
 
-        let client = HttpClient::<TcpClient>::new(server.base_url());
+        let client = http_client(server.base_url());
 
         let secret = client.delete_key("path", "key").expect("Secret expected");
 
@@ -564,6 +1564,44 @@ mod tests {
         );
     }
 
+    #[test]
+    fn passwords() {
+        let server = MockServer::start();
+        let uuid = Uuid::new_v4();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/passwords");
+            then.status(200).body(format!(
+                r#"[{{"uuid":"{}","expiry":"Absolute","expires_at":"2026-10-18T12:00:00Z"}}]"#,
+                uuid
+            ));
+        });
+
+        let client = http_client(server.base_url());
+
+        let passwords = client.passwords().expect("Passwords expected");
+
+        mock.assert();
+        assert_eq!(passwords.len(), 1);
+        assert_eq!(passwords[0].uuid, uuid);
+        assert_eq!(passwords[0].expiry, crate::agent::Expiry::Absolute);
+    }
+
+    #[test]
+    fn delete_password() {
+        let server = MockServer::start();
+        let uuid = Uuid::new_v4();
+        let mock = server.mock(|when, then| {
+            when.method(DELETE).path(format!("/passwords/{}", uuid));
+            then.status(200);
+        });
+
+        let client = http_client(server.base_url());
+
+        client.delete_password(uuid).expect("Ok(()) expected");
+
+        mock.assert();
+    }
+
     #[test]
     fn ls() {
         let server = MockServer::start();
@@ -577,14 +1615,16 @@ mod tests {
                     created_at: Default::default(),
                     updated_by: None,
                     updated_at: None,
+                    fields: vec![],
+                    info: None,
                 }])
                 .unwrap(),
             );
         });
 
-        let client = HttpClient::<TcpClient>::new(server.base_url());
+        let client = http_client(server.base_url());
 
-        let keys = client.ls("path", None).expect("Secret expected");
+        let keys = client.ls("path", None, false).expect("Secret expected");
 
         mock.assert();
         assert_eq!(keys.len(), 1);
@@ -0,0 +1,127 @@
+use crate::settings::Settings;
+use crate::Error;
+use nix::unistd::Uid;
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::PathBuf;
+
+/// Where the agent keeps its socket, PID file and logs. Each one can be set in the settings, and
+/// defaults to the runtime folder, see [`runtime_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentPaths {
+    pub socket: PathBuf,
+    pub pid_file: PathBuf,
+    /// The agent's standard output, when it runs in the background.
+    pub log_file: PathBuf,
+    /// The agent's standard error, when it runs in the background.
+    pub error_file: PathBuf,
+}
+
+impl AgentPaths {
+    pub fn from_settings(settings: &Settings) -> Self {
+        let runtime_dir = runtime_dir();
+        let path = |key: &str, filename: &str| {
+            settings
+                .get(key)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .unwrap_or_else(|| runtime_dir.join(filename))
+        };
+
+        Self {
+            socket: path("agent.socket", "shrine.socket"),
+            pid_file: path("agent.pid-file", "shrine.pid"),
+            log_file: path("agent.log-file", "shrine.out"),
+            error_file: path("agent.error-file", "shrine.err"),
+        }
+    }
+
+    /// Creates the missing folders, accessible to the current user only. When `$XDG_RUNTIME_DIR`
+    /// is not set, the fallback runtime folder must not be accessible to other users either.
+    pub fn create_dirs(&self) -> Result<(), Error> {
+        for path in [
+            &self.socket,
+            &self.pid_file,
+            &self.log_file,
+            &self.error_file,
+        ] {
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                DirBuilder::new()
+                    .recursive(true)
+                    .mode(0o700)
+                    .create(dir)
+                    .map_err(|e| Error::WriteFile(dir.to_path_buf(), e))?;
+            }
+        }
+
+        let runtime_dir = runtime_dir();
+        if xdg_runtime_dir().is_none()
+            && !fs::metadata(&runtime_dir)
+                .map(|m| private(&m))
+                .unwrap_or(true)
+        {
+            return Err(Error::InsecureAgentDir(runtime_dir));
+        }
+        Ok(())
+    }
+}
+
+/// The folder of the agent's files: `$XDG_RUNTIME_DIR`, or `shrine-$UID` in the temporary folder
+/// when it is not set.
+pub fn runtime_dir() -> PathBuf {
+    xdg_runtime_dir().unwrap_or_else(|| env::temp_dir().join(format!("shrine-{}", Uid::current())))
+}
+
+fn xdg_runtime_dir() -> Option<PathBuf> {
+    // https://specifications.freedesktop.org/basedir-spec/latest/ar01s03.html
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Whether the file is owned by the current user and inaccessible to the others.
+fn private(metadata: &fs::Metadata) -> bool {
+    metadata.uid() == Uid::current().as_raw() && metadata.mode() & 0o077 == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_settings() {
+        let paths = AgentPaths::from_settings(&Settings::parse(
+            "agent.socket=/run/shrine/agent.sock\n\
+            agent.pid-file=\n\
+            agent.log-file=/var/log/shrine/agent.log\n",
+        ));
+
+        assert_eq!(
+            paths,
+            AgentPaths {
+                socket: PathBuf::from("/run/shrine/agent.sock"),
+                pid_file: runtime_dir().join("shrine.pid"),
+                log_file: PathBuf::from("/var/log/shrine/agent.log"),
+                error_file: runtime_dir().join("shrine.err"),
+            }
+        );
+    }
+
+    #[test]
+    fn create_dirs() {
+        let folder = tempfile::tempdir().unwrap();
+        let paths = AgentPaths {
+            socket: folder.path().join("run/shrine.socket"),
+            pid_file: folder.path().join("run/shrine.pid"),
+            log_file: folder.path().join("log/shrine.out"),
+            error_file: folder.path().join("log/shrine.err"),
+        };
+
+        paths.create_dirs().unwrap();
+
+        for dir in ["run", "log"] {
+            assert!(private(&fs::metadata(folder.path().join(dir)).unwrap()));
+        }
+    }
+}
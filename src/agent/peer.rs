@@ -0,0 +1,199 @@
+use axum::extract::connect_info::Connected;
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrStream;
+use hyper_rustls::acceptor::TlsStream;
+use nix::sys::stat::{umask, Mode};
+use nix::unistd::Uid;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{env, io, process};
+use tokio::net::{UnixListener, UnixStream};
+use tracing::log::warn;
+
+/// The process at the other end of a connection to the agent's socket, or the address of a TCP
+/// client.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerInfo {
+    pub pid: Option<i32>,
+    /// The peer's executable, when it can be resolved.
+    pub executable: Option<PathBuf>,
+    pub address: Option<SocketAddr>,
+}
+
+impl PeerInfo {
+    fn of(stream: &UnixStream) -> Self {
+        let pid = stream.peer_cred().ok().and_then(|c| c.pid());
+        Self {
+            pid,
+            executable: pid.and_then(|pid| std::fs::read_link(format!("/proc/{}/exe", pid)).ok()),
+            address: None,
+        }
+    }
+}
+
+impl Connected<&UnixStream> for PeerInfo {
+    fn connect_info(target: &UnixStream) -> Self {
+        Self::of(target)
+    }
+}
+
+impl Connected<&TlsStream<AddrStream>> for PeerInfo {
+    fn connect_info(target: &TlsStream<AddrStream>) -> Self {
+        Self {
+            address: target.io().map(AddrStream::remote_addr),
+            ..Self::default()
+        }
+    }
+}
+
+impl Display for PeerInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(address) = self.address {
+            return write!(f, "{}", address);
+        }
+        match self.pid {
+            None => write!(f, "unknown process")?,
+            Some(pid) => write!(f, "pid {}", pid)?,
+        }
+        match &self.executable {
+            None => Ok(()),
+            Some(executable) => write!(f, " ({})", executable.display()),
+        }
+    }
+}
+
+/// The agent's listening socket, either created by the agent or passed by the service manager.
+pub struct AgentSocket {
+    listener: net::UnixListener,
+    /// The socket file, when it was created by the agent.
+    path: Option<PathBuf>,
+}
+
+impl AgentSocket {
+    /// Creates the socket at `path`, readable and writable by its owner only.
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let previous = umask(Mode::from_bits_truncate(0o177));
+        let listener = net::UnixListener::bind(path.as_ref());
+        umask(previous);
+
+        Ok(Self {
+            listener: listener?,
+            path: Some(path.as_ref().to_path_buf()),
+        })
+    }
+
+    /// The first socket passed by the service manager, if it was passed to the current process,
+    /// see `sd_listen_fds(3)`.
+    pub fn from_env() -> Option<Self> {
+        let pid = env::var("LISTEN_PID").ok();
+        let fds = env::var("LISTEN_FDS").ok();
+        for variable in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(variable);
+        }
+
+        if pid?.parse::<u32>().ok()? != process::id() || fds?.parse::<i32>().ok()? < 1 {
+            return None;
+        }
+
+        Some(Self {
+            // the passed sockets start at file descriptor 3
+            listener: unsafe { net::UnixListener::from_raw_fd(3) },
+            path: None,
+        })
+    }
+
+    /// The socket file to remove when the agent stops.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+/// Accepts the connections to the agent's socket made by the user running the agent, and drops
+/// the other ones.
+pub struct OwnerIncoming {
+    listener: UnixListener,
+    uid: u32,
+}
+
+impl OwnerIncoming {
+    /// Accepts the connections of `socket`; must be called from a tokio runtime.
+    pub fn new(socket: AgentSocket) -> io::Result<Self> {
+        socket.listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener: UnixListener::from_std(socket.listener)?,
+            uid: Uid::current().as_raw(),
+        })
+    }
+}
+
+impl Accept for OwnerIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        loop {
+            match self.listener.poll_accept(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Ok((stream, _))) => match stream.peer_cred() {
+                    Ok(cred) if cred.uid() == self.uid => return Poll::Ready(Some(Ok(stream))),
+                    Ok(cred) => warn!(
+                        "Rejected connection from uid {}: {}",
+                        cred.uid(),
+                        PeerInfo::of(&stream)
+                    ),
+                    Err(e) => warn!("Rejected connection: {}", e),
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[tokio::test]
+    async fn bind_and_identify_peer() {
+        let folder = tempfile::tempdir().unwrap();
+        let socket = folder.path().join("shrine.socket");
+
+        let incoming = OwnerIncoming::new(AgentSocket::bind(&socket).unwrap()).unwrap();
+        assert_eq!(
+            socket.metadata().unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        let _client = UnixStream::connect(&socket).await.unwrap();
+        let (stream, _) = incoming.listener.accept().await.unwrap();
+
+        let peer = PeerInfo::of(&stream);
+        assert_eq!(peer.pid, Some(std::process::id() as i32));
+        if cfg!(target_os = "linux") {
+            assert_eq!(peer.executable, Some(std::env::current_exe().unwrap()));
+        }
+    }
+
+    #[test]
+    fn socket_from_env() {
+        env::set_var("LISTEN_PID", (process::id() + 1).to_string());
+        env::set_var("LISTEN_FDS", "1");
+
+        // passed to another process
+        assert!(AgentSocket::from_env().is_none());
+        assert!(env::var("LISTEN_PID").is_err());
+        assert!(env::var("LISTEN_FDS").is_err());
+
+        // not passed
+        assert!(AgentSocket::from_env().is_none());
+    }
+}
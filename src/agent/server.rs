@@ -1,36 +1,60 @@
-use crate::agent::{ErrorResponse, GetSecretsRequest, SetPasswordRequest, SetSecretRequest};
-
-use crate::git::Repository;
-use crate::shrine::{Closed, Key, Secret, Shrine, ShrinePassword};
+use crate::agent::audit::{AuditEvent, AuditLog, AuditOperation, AuditResult};
+pub use crate::agent::peer::AgentSocket;
+use crate::agent::peer::{OwnerIncoming, PeerInfo};
+use crate::agent::tls::{Listen, TlsListener};
+use crate::agent::token::{Permission, Token, Tokens};
+use crate::agent::{
+    CachedPassword, Change, ChangeKind, ConvertRequest, ErrorResponse, Expiry, GetSecretsRequest,
+    Info, KeySelection, MergeRequest, MergeResponse, Metrics, SetConfigRequest, SetPasswordRequest,
+    SetSecretRequest, SetSecretsRequest, TextconvRequest,
+};
+use crate::controller::textconv::write_listing;
+use crate::encrypt::aes::DerivedKey;
+
+use crate::git::{Operation, Repository};
+use crate::settings::Settings;
+use crate::shrine::{Closed, Key, Secret, Shrine, ShrineBuilder, ShrinePassword};
+use crate::totp::Totp;
 use crate::{Error, SHRINE_FILENAME};
-use axum::extract::{Path, Query, State};
-use axum::http::StatusCode;
+use axum::extract::{ConnectInfo, MatchedPath, Path, Query, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{Method, Request, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
-use axum::routing::{delete, get, put};
-use axum::{Json, Router};
+use axum::routing::{delete, get, post, put};
+use axum::{Extension, Json, Router};
 use chrono::{DateTime, Utc};
-use hyper::Server;
-use hyperlocal::UnixServerExt;
+use hyper::{Body, Server};
 use regex::Regex;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fs::remove_file;
+use std::os::unix::fs::MetadataExt;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use std::{mem, process};
 use tokio::signal::ctrl_c;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::oneshot::{channel, Receiver, Sender};
+use tokio::sync::{broadcast, watch};
 use tokio_cron_scheduler::{Job, JobScheduler};
-use tracing::log::{error, info};
+use tracing::log::{error, info, warn};
 use tracing::Level;
 use tracing_subscriber::filter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use uuid::Uuid;
 
-pub async fn serve(pidfile: String, socketfile: String) {
+pub async fn serve(
+    pidfile: PathBuf,
+    socket: AgentSocket,
+    config: AgentConfig,
+    listener: Option<TlsListener>,
+) {
     let filter = filter::Targets::new()
         .with_target("tower_http::trace::on_response", Level::DEBUG)
         .with_target("tower_http::trace::on_request", Level::INFO)
@@ -42,7 +66,7 @@ pub async fn serve(pidfile: String, socketfile: String) {
         .init();
 
     let (tx, rx) = channel::<()>();
-    let state = AgentState::new(DefaultShrineProvider::default(), tx);
+    let state = AgentState::new(DefaultShrineProvider::default(), tx).with_config(config);
 
     let mut scheduler = JobScheduler::new().await.unwrap();
 
@@ -52,6 +76,10 @@ pub async fn serve(pidfile: String, socketfile: String) {
             .add(
                 Job::new_repeated(Duration::from_secs(1), move |_uuid, _l| {
                     state.clean_expired_passwords();
+                    if state.is_idle() {
+                        info!("Idle for too long");
+                        state.shutdown();
+                    }
                 })
                 .unwrap(),
             )
@@ -67,15 +95,53 @@ pub async fn serve(pidfile: String, socketfile: String) {
 
     scheduler.start().await.unwrap();
 
-    if let Ok(builder) = Server::bind_unix(&socketfile) {
-        builder
-            .serve(router().with_state(state).into_make_service())
-            .with_graceful_shutdown(shutdown(rx))
-            .await
-            .unwrap();
+    let (stop_tx, stop_rx) = watch::channel(());
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            shutdown(rx).await;
+            state.stop_watchers();
+            let _ = stop_tx.send(());
+        });
+    }
+    let stopped = |mut stop_rx: watch::Receiver<()>| async move {
+        let _ = stop_rx.changed().await;
+    };
+
+    let socketfile = socket.path().map(|p| p.to_path_buf());
+    if let Ok(incoming) = OwnerIncoming::new(socket) {
+        let socket = Server::builder(incoming)
+            .serve(router(state.clone()).into_make_service_with_connect_info::<PeerInfo>())
+            .with_graceful_shutdown(stopped(stop_rx.clone()));
+
+        let tcp = async {
+            let Some(listener) = listener else {
+                return;
+            };
+            let address = listener.local_addr();
+            match listener.into_acceptor() {
+                Ok(acceptor) => {
+                    info!(
+                        "Listen on {}",
+                        address.map(|a| a.to_string()).unwrap_or_default()
+                    );
+                    Server::builder(acceptor)
+                        .serve(tcp_router(state).into_make_service_with_connect_info::<PeerInfo>())
+                        .with_graceful_shutdown(stopped(stop_rx))
+                        .await
+                        .unwrap();
+                }
+                Err(e) => error!("Could not listen for TCP connections: {}", e),
+            }
+        };
 
-        remove_file(pidfile).unwrap();
-        remove_file(socketfile).unwrap();
+        let (socket, _) = tokio::join!(socket, tcp);
+        socket.unwrap();
+
+        let _ = remove_file(pidfile);
+        if let Some(socketfile) = socketfile {
+            let _ = remove_file(socketfile);
+        }
     } else {
         error!("Could not open socket.")
     }
@@ -83,19 +149,383 @@ pub async fn serve(pidfile: String, socketfile: String) {
     scheduler.shutdown().await.unwrap();
 }
 
-fn router<P>() -> Router<AgentState<P>>
+fn router<P>(state: AgentState<P>) -> Router
 where
     P: ShrineProvider,
 {
+    let allowed = middleware::from_fn_with_state(state.clone(), allowed_executable::<P>);
+    let unrestricted = middleware::from_fn(unrestricted_token);
+
     Router::new()
-        .route("/", delete(delete_agent))
+        .route("/", delete(delete_agent).route_layer(unrestricted.clone()))
         .route("/pid", get(get_pid))
-        .route("/passwords", put(put_password))
-        .route("/passwords", delete(delete_passwords))
-        .route("/keys/:file", get(get_keys))
-        .route("/keys/:file/:key", get(get_key))
+        .route(
+            "/metrics",
+            get(get_metrics).route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/passwords",
+            get(get_passwords).route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/passwords",
+            put(put_password).route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/passwords",
+            delete(delete_passwords).route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/passwords/:uuid",
+            delete(delete_password).route_layer(unrestricted.clone()),
+        )
+        .route("/keys/:file", get(get_keys).route_layer(allowed.clone()))
+        .route("/keys/:file", put(put_keys))
+        .route(
+            "/keys/:file/:key",
+            get(get_key).route_layer(allowed.clone()),
+        )
         .route("/keys/:file/:key", put(put_key))
         .route("/keys/:file/:key", delete(delete_key))
+        .route("/otp/:file/:key", get(get_otp).route_layer(allowed.clone()))
+        .route(
+            "/batch/:file",
+            post(post_keys_batch).route_layer(allowed.clone()),
+        )
+        .route(
+            "/textconv",
+            post(post_textconv)
+                .route_layer(allowed.clone())
+                .route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/merge",
+            post(post_merge)
+                .route_layer(allowed.clone())
+                .route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/config/:file",
+            get(get_config)
+                .route_layer(allowed.clone())
+                .route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/config/:file/:key",
+            put(put_config).route_layer(unrestricted.clone()),
+        )
+        .route(
+            "/convert/:file",
+            post(post_convert).route_layer(unrestricted),
+        )
+        .route("/info/:file", get(get_info))
+        .route("/watch/:file", get(get_watch).route_layer(allowed))
+        .layer(middleware::from_fn_with_state(state.clone(), audit::<P>))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            optional_token::<P>,
+        ))
+        .layer(middleware::from_fn(log_peer))
+        .with_state(state)
+}
+
+/// The UUID of the shrine a request accessed, for the audit log.
+#[derive(Clone, Copy)]
+struct AuditUuid(Uuid);
+
+/// The keys a batch request accessed, each one logged as a separate event.
+#[derive(Clone)]
+struct AuditKeys(Vec<(String, AuditResult)>);
+
+/// Records the accesses to secrets and passwords in the audit log.
+async fn audit<P>(
+    State(state): State<AgentState<P>>,
+    matched_path: Option<MatchedPath>,
+    Path(params): Path<HashMap<String, String>>,
+    peer: Option<ConnectInfo<PeerInfo>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    let operation = match (request.method(), matched_path.as_ref().map(|p| p.as_str())) {
+        (&Method::GET, Some("/keys/:file")) => AuditOperation::List,
+        (&Method::GET, Some("/keys/:file/:key")) => AuditOperation::Read,
+        (&Method::PUT, Some("/keys/:file/:key")) => AuditOperation::Write,
+        (&Method::DELETE, Some("/keys/:file/:key")) => AuditOperation::Delete,
+        (&Method::GET, Some("/otp/:file/:key")) => AuditOperation::Otp,
+        (&Method::PUT, Some("/keys/:file")) => AuditOperation::Import,
+        (&Method::POST, Some("/batch/:file")) => AuditOperation::Read,
+        (&Method::GET, Some("/config/:file")) => AuditOperation::ReadConfig,
+        (&Method::PUT, Some("/config/:file/:key")) => AuditOperation::WriteConfig,
+        (&Method::GET, Some("/watch/:file")) => AuditOperation::Watch,
+        (&Method::POST, Some("/textconv")) => AuditOperation::Diff,
+        (&Method::POST, Some("/merge")) => AuditOperation::Merge,
+        (&Method::POST, Some("/convert/:file")) => AuditOperation::Convert,
+        (&Method::PUT, Some("/passwords")) => AuditOperation::Unlock,
+        (&Method::DELETE, Some("/passwords/:uuid")) => AuditOperation::Lock,
+        (&Method::DELETE, Some("/passwords")) => AuditOperation::LockAll,
+        _ => return next.run(request).await,
+    };
+
+    let token = request
+        .extensions()
+        .get::<AuthenticatedToken>()
+        .map(|t| t.0.name.clone());
+    let response = next.run(request).await;
+
+    let path = params.get("file").cloned();
+    let uuid = response
+        .extensions()
+        .get::<AuditUuid>()
+        .map(|u| u.0)
+        .or_else(|| params.get("uuid").and_then(|u| Uuid::from_str(u).ok()))
+        .or_else(|| path.as_deref().and_then(|p| state.cached_uuid(p)));
+    let result = match response.extensions().get::<AuditResult>() {
+        Some(result) => *result,
+        None => match response.status() {
+            s if s.is_success() => AuditResult::Ok,
+            StatusCode::UNAUTHORIZED => AuditResult::Unauthorized,
+            StatusCode::FORBIDDEN => AuditResult::Forbidden,
+            StatusCode::NOT_FOUND => AuditResult::NotFound,
+            _ => AuditResult::Error,
+        },
+    };
+    let peer = peer.map(|ConnectInfo(p)| p).unwrap_or_default();
+
+    let event = AuditEvent {
+        timestamp: Utc::now(),
+        operation,
+        uuid,
+        path,
+        key: params.get("key").cloned(),
+        pid: peer.pid,
+        executable: peer.executable.map(|e| e.display().to_string()),
+        address: peer.address.map(|a| a.to_string()),
+        token,
+        result,
+    };
+    match response.extensions().get::<AuditKeys>() {
+        None => state.audit(event),
+        Some(AuditKeys(keys)) => {
+            for (key, result) in keys {
+                state.audit(AuditEvent {
+                    key: Some(key.clone()),
+                    result: *result,
+                    ..event.clone()
+                });
+            }
+        }
+    }
+
+    response
+}
+
+/// The router of the TCP listener, which requires a token.
+fn tcp_router<P>(state: AgentState<P>) -> Router
+where
+    P: ShrineProvider,
+{
+    router(state.clone()).layer(middleware::from_fn_with_state(state, required_token::<P>))
+}
+
+/// The token a client authenticated with.
+#[derive(Clone)]
+struct AuthenticatedToken(Token);
+
+async fn required_token<P>(
+    State(state): State<AgentState<P>>,
+    peer: Option<ConnectInfo<PeerInfo>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    authenticate(state, peer, request, next, true).await
+}
+
+/// Authenticates the requests to the socket that bear a token, so that its scope applies.
+async fn optional_token<P>(
+    State(state): State<AgentState<P>>,
+    peer: Option<ConnectInfo<PeerInfo>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    authenticate(state, peer, request, next, false).await
+}
+
+/// Lets only the requests bearing one of the issued tokens through, or also the ones bearing no
+/// token if it is not `required`. `GET /pid` is always let through so that clients with an
+/// invalid token find the agent running and report the rejected token.
+async fn authenticate<P>(
+    state: AgentState<P>,
+    peer: Option<ConnectInfo<PeerInfo>>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+    required: bool,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    if request.extensions().get::<AuthenticatedToken>().is_some()
+        || (request.method() == Method::GET && request.uri().path() == "/pid")
+    {
+        return next.run(request).await;
+    }
+
+    let value = match request.headers().get(AUTHORIZATION) {
+        None if !required => return next.run(request).await,
+        None => None,
+        Some(value) => value.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")),
+    };
+
+    let tokens = match &state.config.tokens {
+        None => Tokens::default(),
+        Some(path) => match Tokens::load(path) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                error!("Could not load tokens: {}", e);
+                return ErrorResponse::Io(e.to_string()).into();
+            }
+        },
+    };
+
+    match value.and_then(|v| tokens.verify(v)) {
+        None => {
+            warn!(
+                "Rejected `{}` from {}: invalid token",
+                request.uri().path(),
+                peer.map(|ConnectInfo(p)| p).unwrap_or_default()
+            );
+            ErrorResponse::InvalidToken.into()
+        }
+        Some(token) => {
+            request
+                .extensions_mut()
+                .insert(AuthenticatedToken(token.clone()));
+            next.run(request).await
+        }
+    }
+}
+
+/// Denies the requests bearing a token restricted to a scope, which only gives access to secrets.
+async fn unrestricted_token(
+    token: Option<Extension<AuthenticatedToken>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    match token {
+        Some(Extension(AuthenticatedToken(token))) if !token.scope.is_unrestricted() => {
+            warn!("Token `{}` denied `{}`", token.name, request.uri().path());
+            out_of_scope("this request".to_string(), None)
+        }
+        _ => next.run(request).await,
+    }
+}
+
+/// What a request does with a shrine, checked against the scope of the token it bears.
+struct Access<'a> {
+    token: Option<&'a Token>,
+    permission: Permission,
+    key: Option<&'a str>,
+}
+
+impl<'a> Access<'a> {
+    fn new(
+        token: &'a Option<Extension<AuthenticatedToken>>,
+        permission: Permission,
+        key: Option<&'a str>,
+    ) -> Self {
+        Self {
+            token: token.as_ref().map(|Extension(t)| &t.0),
+            permission,
+            key,
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check(&self, path: &str, uuid: Uuid) -> Result<(), Response> {
+        match self.denial(path, uuid) {
+            None => Ok(()),
+            Some(reason) => Err(out_of_scope(reason, Some(uuid))),
+        }
+    }
+
+    /// Why the token does not allow the access, if it does not.
+    fn denial(&self, path: &str, uuid: Uuid) -> Option<String> {
+        let token = self.token?;
+        let scope = &token.scope;
+
+        let reason = if !scope.allows_shrine(path, uuid) {
+            format!("access to shrine {}", uuid)
+        } else if !scope.allows(self.permission) {
+            format!("`{}` on `{}`", self.permission, self.key.unwrap_or(path))
+        } else {
+            format!("access to `{}`", self.key.filter(|k| !scope.allows_key(k))?)
+        };
+
+        warn!("Token `{}` denied {}", token.name, reason);
+        Some(reason)
+    }
+
+    /// Whether the token lets `key` be listed.
+    fn lists(&self, key: &str) -> bool {
+        self.token.map(|t| t.scope.allows_key(key)).unwrap_or(true)
+    }
+}
+
+fn out_of_scope(reason: String, uuid: Option<Uuid>) -> Response {
+    let response: Response = ErrorResponse::OutOfScope(reason).into();
+    match uuid {
+        None => (Extension(AuditResult::Denied), response).into_response(),
+        Some(uuid) => (
+            Extension(AuditResult::Denied),
+            Extension(AuditUuid(uuid)),
+            response,
+        )
+            .into_response(),
+    }
+}
+
+async fn log_peer(
+    peer: Option<ConnectInfo<PeerInfo>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    info!(
+        "{} {} from {}",
+        request.method(),
+        request.uri().path(),
+        peer.map(|ConnectInfo(p)| p).unwrap_or_default()
+    );
+    next.run(request).await
+}
+
+/// Lets only the allowed executables, if any are configured, read secrets.
+async fn allowed_executable<P>(
+    State(state): State<AgentState<P>>,
+    peer: Option<ConnectInfo<PeerInfo>>,
+    request: Request<Body>,
+    next: Next<Body>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    let executable = peer.and_then(|ConnectInfo(p)| p.executable);
+    if !state.config.is_allowed(executable.as_deref()) {
+        let executable = executable
+            .map(|e| e.display().to_string())
+            .unwrap_or_else(|| "unknown executable".to_string());
+        warn!("Denied `{}` to {}", request.uri().path(), executable);
+        let response: Response = ErrorResponse::ExecutableNotAllowed(executable).into();
+        return (Extension(AuditResult::Denied), response).into_response();
+    }
+    next.run(request).await
 }
 
 async fn shutdown(shutdown_http_signal_rx: Receiver<()>) {
@@ -128,11 +558,7 @@ where
     P: ShrineProvider,
 {
     info!("delete_agent");
-    let channel = channel::<()>();
-
-    let mut sig = state.http_shutdown_tx.lock().unwrap();
-
-    let _ = mem::replace(&mut *sig, channel.0).send(());
+    state.shutdown();
 }
 
 async fn get_pid() -> String {
@@ -140,14 +566,33 @@ async fn get_pid() -> String {
     serde_json::to_string(&process::id()).unwrap()
 }
 
+async fn get_metrics<P>(State(state): State<AgentState<P>>) -> Json<Metrics>
+where
+    P: ShrineProvider,
+{
+    info!("get_metrics");
+    Json(state.metrics.lock().unwrap().clone())
+}
+
 async fn put_password<P>(
     State(state): State<AgentState<P>>,
     Json(set_password_request): Json<SetPasswordRequest>,
-) where
+) -> Response
+where
     P: ShrineProvider,
 {
     info!("set_password");
-    state.set_password(set_password_request.uuid, set_password_request.password);
+    let uuid = set_password_request.uuid;
+    state.set_password(uuid, set_password_request.password);
+    (Extension(AuditUuid(uuid)), StatusCode::OK).into_response()
+}
+
+async fn get_passwords<P>(State(state): State<AgentState<P>>) -> Json<Vec<CachedPassword>>
+where
+    P: ShrineProvider,
+{
+    info!("get_passwords");
+    Json(state.cached_passwords())
 }
 
 async fn delete_passwords<P>(State(state): State<AgentState<P>>)
@@ -158,10 +603,19 @@ where
     state.delete_passwords();
 }
 
+async fn delete_password<P>(State(state): State<AgentState<P>>, Path(uuid): Path<Uuid>)
+where
+    P: ShrineProvider,
+{
+    info!("delete_password for `{}`", uuid);
+    state.delete_password(uuid);
+}
+
 async fn get_keys<P>(
     State(state): State<AgentState<P>>,
     Path(path): Path<String>,
     Query(params): Query<GetSecretsRequest>,
+    token: Option<Extension<AuthenticatedToken>>,
 ) -> Response
 where
     P: ShrineProvider,
@@ -182,7 +636,8 @@ where
         Ok(regex) => regex,
     };
 
-    let shrine = match open_shrine::<P>(&state, &path) {
+    let access = Access::new(&token, Permission::List, None);
+    let shrine = match open_shrine::<P>(&state, &path, &access) {
         Ok((shrine, _)) => shrine,
         Err(response) => return response,
     };
@@ -190,6 +645,7 @@ where
     let mut keys = shrine
         .keys()
         .into_iter()
+        .filter(|k| access.lists(k))
         .filter(|k| regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
         .collect::<Vec<String>>();
     keys.sort_unstable();
@@ -201,7 +657,7 @@ where
 
     let secrets = secrets
         .into_iter()
-        .map(|(s, k)| (Key::from((k, s))))
+        .map(|(s, k)| Key::new(k, s, params.long))
         .collect::<Vec<Key>>();
 
     Json(secrets).into_response()
@@ -210,13 +666,15 @@ where
 async fn get_key<P>(
     State(state): State<AgentState<P>>,
     Path((path, key)): Path<(String, String)>,
+    token: Option<Extension<AuthenticatedToken>>,
 ) -> Response
 where
     P: ShrineProvider,
 {
     info!("get_key `{}` from file `{}/{}`", key, path, SHRINE_FILENAME);
 
-    let shrine = match open_shrine::<P>(&state, &path) {
+    let access = Access::new(&token, Permission::Read, Some(&key));
+    let shrine = match open_shrine::<P>(&state, &path, &access) {
         Ok((shrine, _)) => shrine,
         Err(response) => return response,
     };
@@ -227,44 +685,258 @@ where
     }
 }
 
-fn open_shrine<P>(state: &AgentState<P>, path: &str) -> Result<(Shrine, ShrinePassword), Response>
+async fn get_otp<P>(
+    State(state): State<AgentState<P>>,
+    Path((path, key)): Path<(String, String)>,
+    token: Option<Extension<AuthenticatedToken>>,
+) -> Response
 where
     P: ShrineProvider,
 {
-    let shrine = match state
-        .shrine_provider
-        .load_from_path(PathBuf::from_str(path).unwrap())
-    {
-        Err(Error::FileNotFound(_)) => {
-            return Err(ErrorResponse::FileNotFound(path.to_string()).into())
-        }
-        Err(Error::IoRead(_)) => return Err(ErrorResponse::Read(path.to_string()).into()),
-        Err(_) => return Err(ErrorResponse::Io(path.to_string()).into()),
+    info!("get_otp `{}` from file `{}/{}`", key, path, SHRINE_FILENAME);
+
+    let access = Access::new(&token, Permission::Read, Some(&key));
+    let shrine = match open_shrine::<P>(&state, &path, &access) {
+        Ok((shrine, _)) => shrine,
+        Err(response) => return response,
+    };
+
+    let secret = match shrine.get(&key) {
+        Err(_) => return ErrorResponse::KeyNotFound { file: path, key }.into(),
+        Ok(secret) => secret,
+    };
+
+    match Totp::from_secret(&key, secret) {
+        Err(_) => ErrorResponse::NotTotp { file: path, key }.into(),
+        Ok(totp) => Json(totp.generate()).into_response(),
+    }
+}
+
+async fn post_textconv<P>(
+    State(state): State<AgentState<P>>,
+    Json(request): Json<TextconvRequest>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!("textconv");
+
+    let (shrine, password) = match open_bytes(&state, &request.shrine) {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+
+    let mut listing = Vec::<u8>::new();
+    write_listing(&shrine, &password, &mut listing);
+    (
+        Extension(AuditUuid(shrine.uuid())),
+        Json(String::from_utf8(listing).unwrap()),
+    )
+        .into_response()
+}
+
+async fn post_merge<P>(
+    State(state): State<AgentState<P>>,
+    Json(request): Json<MergeRequest>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!("merge");
+
+    let base = match request.base {
+        None => None,
+        Some(base) => match open_bytes(&state, &base) {
+            Ok((shrine, _)) => Some(shrine),
+            Err(response) => return response,
+        },
+    };
+    let (mut merged, password) = match open_bytes(&state, &request.ours) {
+        Ok(opened) => opened,
+        Err(response) => return response,
+    };
+    let theirs = match open_bytes(&state, &request.theirs) {
+        Ok((shrine, _)) => shrine,
+        Err(response) => return response,
+    };
+
+    let uuid = merged.uuid();
+    let conflicts = match merged.merge(base.as_ref(), &theirs) {
+        Ok(conflicts) => conflicts,
+        Err(_) => return ErrorResponse::InvalidShrine.into(),
+    };
+    let shrine = match merged.close(&password).and_then(|shrine| shrine.as_bytes()) {
+        Ok(bytes) => bytes,
+        Err(_) => return ErrorResponse::InvalidShrine.into(),
+    };
+
+    (
+        Extension(AuditUuid(uuid)),
+        Json(MergeResponse {
+            shrine,
+            conflicts: conflicts.iter().map(|c| c.to_string()).collect(),
+        }),
+    )
+        .into_response()
+}
+
+/// Opens a shrine file sent by the client, e.g. taken from git, with its cached password.
+#[allow(clippy::result_large_err)]
+fn open_bytes<P>(state: &AgentState<P>, bytes: &[u8]) -> Result<(Shrine, ShrinePassword), Response>
+where
+    P: ShrineProvider,
+{
+    state.touch();
+
+    let shrine = match Shrine::from_bytes(bytes) {
+        Err(_) => return Err(ErrorResponse::InvalidShrine.into()),
         Ok(shrine) => shrine,
     };
 
     let uuid = shrine.uuid();
+    let password = if shrine.requires_password() {
+        match state.get_password(uuid) {
+            None => return Err(with_uuid(ErrorResponse::Unauthorized(uuid), uuid)),
+            Some(p) => p,
+        }
+    } else {
+        ShrinePassword::default()
+    };
+
+    match shrine.open(&password) {
+        Err(_) => Err(with_uuid(ErrorResponse::Forbidden(uuid), uuid)),
+        Ok(shrine) => Ok((shrine, password)),
+    }
+}
+
+/// Opens the shrine at `path`, from the cache if the file did not change since it was opened and
+/// its password is still cached. Returns the key it was decrypted with, to close it again. The
+/// `access` is checked before the password is needed.
+#[allow(clippy::result_large_err)]
+fn open_shrine<P>(
+    state: &AgentState<P>,
+    path: &str,
+    access: &Access,
+) -> Result<(Shrine, Option<DerivedKey>), Response>
+where
+    P: ShrineProvider,
+{
+    state.touch();
+
+    let stamp = state.shrine_provider.stamp(path);
+    if let Some(cached) = stamp.and_then(|stamp| state.cached_shrine(path, stamp)) {
+        access.check(path, cached.0.uuid())?;
+        return Ok(cached);
+    }
+
+    let shrine = load_shrine(state, path)?;
+
+    let uuid = shrine.uuid();
+    access.check(path, uuid)?;
 
     let shrine_password = if shrine.requires_password() {
         match state.get_password(uuid) {
-            None => return Err(ErrorResponse::Unauthorized(uuid).into()),
+            None => return Err(with_uuid(ErrorResponse::Unauthorized(uuid), uuid)),
             Some(p) => p,
         }
     } else {
         ShrinePassword::default()
     };
 
-    let shrine = match shrine.open(&shrine_password) {
-        Err(_) => return Err(ErrorResponse::Forbidden(uuid).into()),
+    let key = match shrine.derive_key(&shrine_password) {
+        Err(_) => return Err(with_uuid(ErrorResponse::Forbidden(uuid), uuid)),
+        Ok(key) => key,
+    };
+    let shrine = match shrine.open_with_key(key.as_ref()) {
+        Err(_) => return Err(with_uuid(ErrorResponse::Forbidden(uuid), uuid)),
+        Ok(shrine) => shrine,
+    };
+
+    if let Some(stamp) = stamp {
+        state.cache_shrine(path, stamp, key.clone(), shrine.clone());
+    }
+
+    Ok((shrine, key))
+}
+
+#[allow(clippy::result_large_err)]
+fn load_shrine<P>(state: &AgentState<P>, path: &str) -> Result<Shrine<Closed>, Response>
+where
+    P: ShrineProvider,
+{
+    match state
+        .shrine_provider
+        .load_from_path(PathBuf::from_str(path).unwrap())
+    {
+        Err(Error::FileNotFound(_)) => Err(ErrorResponse::FileNotFound(path.to_string()).into()),
+        Err(Error::IoRead(_)) => Err(ErrorResponse::Read(path.to_string()).into()),
+        Err(_) => Err(ErrorResponse::Io(path.to_string()).into()),
+        Ok(shrine) => Ok(shrine),
+    }
+}
+
+fn with_uuid(error: ErrorResponse, uuid: Uuid) -> Response {
+    let response: Response = error.into();
+    (Extension(AuditUuid(uuid)), response).into_response()
+}
+
+/// Commits the change if `git.commit.auto` is set. The commit is pushed in the background if
+/// `git.push.auto` is set, so that the response does not wait for the remote; a failed push is
+/// only logged, and the commit is pushed along with the next one.
+fn commit(repository: Option<Repository<PathBuf>>, operation: Operation) -> Result<(), Error> {
+    let repository = match repository {
+        Some(repository) if repository.commit_auto() => repository.open()?,
+        _ => return Ok(()),
+    };
+
+    repository.commit(&repository.message(&operation))?;
+    if repository.push_auto() {
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = repository.push() {
+                warn!("Could not push the commit: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Encrypts and writes the shrine at `path`, keeping the opened shrine in the cache, and wakes up
+/// its watchers.
+#[allow(clippy::result_large_err)]
+fn save_shrine<P>(
+    state: &AgentState<P>,
+    path: &str,
+    shrine: Shrine,
+    key: Option<DerivedKey>,
+) -> Result<(), Response>
+where
+    P: ShrineProvider,
+{
+    let closed = match shrine.clone().close_with_key(key.as_ref()) {
         Ok(shrine) => shrine,
+        Err(_) => return Err(ErrorResponse::Write(path.to_string()).into()),
     };
 
-    Ok((shrine, shrine_password))
+    if state.shrine_provider.save_to_path(path, closed).is_err() {
+        state.shrines.lock().unwrap().remove(path);
+        return Err(ErrorResponse::Write(path.to_string()).into());
+    }
+
+    match state.shrine_provider.stamp(path) {
+        Some(stamp) => state.cache_shrine(path, stamp, key, shrine),
+        None => {
+            state.shrines.lock().unwrap().remove(path);
+        }
+    }
+    state.notify(path);
+
+    Ok(())
 }
 
 async fn put_key<P>(
     State(state): State<AgentState<P>>,
     Path((path, key)): Path<(String, String)>,
+    token: Option<Extension<AuthenticatedToken>>,
     Json(request): Json<SetSecretRequest>,
 ) -> Response
 where
@@ -272,7 +944,8 @@ where
 {
     info!("set_key `{}` on file `{}/{}`", key, path, SHRINE_FILENAME);
 
-    let (mut shrine, shrine_password) = match open_shrine::<P>(&state, &path) {
+    let access = Access::new(&token, Permission::Write, Some(&key));
+    let (mut shrine, derived_key) = match open_shrine::<P>(&state, &path, &access) {
         Ok(v) => v,
         Err(response) => return response,
     };
@@ -287,24 +960,12 @@ where
         Err(_) => return ErrorResponse::Write(path).into(),
     }
 
-    let shrine = match shrine.close(&shrine_password) {
-        Ok(shrine) => shrine,
-        Err(_) => return ErrorResponse::Write(path).into(),
-    };
-
-    if state.shrine_provider.save_to_path(&path, shrine).is_err() {
-        return ErrorResponse::Write(path).into();
+    if let Err(response) = save_shrine(&state, &path, shrine, derived_key) {
+        return response;
     }
 
-    if let Some(repository) = repository {
-        if repository.commit_auto()
-            && repository
-                .open()
-                .and_then(|r| r.create_commit("Update shrine"))
-                .is_err()
-        {
-            return ErrorResponse::Write(path).into();
-        }
+    if commit(repository, Operation::Set(key.clone())).is_err() {
+        return ErrorResponse::Write(path).into();
     }
 
     Response::builder()
@@ -316,6 +977,7 @@ where
 async fn delete_key<P>(
     State(state): State<AgentState<P>>,
     Path((path, key)): Path<(String, String)>,
+    token: Option<Extension<AuthenticatedToken>>,
 ) -> Response
 where
     P: ShrineProvider,
@@ -325,7 +987,8 @@ where
         key, path, SHRINE_FILENAME
     );
 
-    let (mut shrine, shrine_password) = match open_shrine::<P>(&state, &path) {
+    let access = Access::new(&token, Permission::Delete, Some(&key));
+    let (mut shrine, derived_key) = match open_shrine::<P>(&state, &path, &access) {
         Ok(v) => v,
         Err(response) => return response,
     };
@@ -336,23 +999,12 @@ where
         return ErrorResponse::KeyNotFound { file: path, key }.into();
     }
 
-    let shrine = match shrine.close(&shrine_password) {
-        Ok(shrine) => shrine,
-        Err(_) => return ErrorResponse::Write(path).into(),
-    };
-    if state.shrine_provider.save_to_path(&path, shrine).is_err() {
-        return ErrorResponse::Write(path).into();
+    if let Err(response) = save_shrine(&state, &path, shrine, derived_key) {
+        return response;
     }
 
-    if let Some(repository) = repository {
-        if repository.commit_auto()
-            && repository
-                .open()
-                .and_then(|r| r.create_commit("Update shrine"))
-                .is_err()
-        {
-            return ErrorResponse::Write(path).into();
-        }
+    if commit(repository, Operation::Remove(vec![key.clone()])).is_err() {
+        return ErrorResponse::Write(path).into();
     }
 
     Response::builder()
@@ -361,16 +1013,621 @@ where
         .unwrap()
 }
 
-#[derive(Clone)]
-struct AgentState<P>
-where
-    P: ShrineProvider,
+async fn put_keys<P>(
+    State(state): State<AgentState<P>>,
+    Path(path): Path<String>,
+    token: Option<Extension<AuthenticatedToken>>,
+    Json(request): Json<SetSecretsRequest>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!(
+        "set_keys ({} keys) on file `{}/{}`",
+        request.secrets.len(),
+        path,
+        SHRINE_FILENAME
+    );
+
+    let access = Access::new(&token, Permission::Write, None);
+    let (mut shrine, derived_key) = match open_shrine::<P>(&state, &path, &access) {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+
+    for (key, _) in request.secrets.iter() {
+        let access = Access::new(&token, Permission::Write, Some(key));
+        if let Err(response) = access.check(&path, shrine.uuid()) {
+            return response;
+        }
+    }
+
+    for (key, request) in request.secrets {
+        match shrine.set(&key, request.secret, request.mode) {
+            Ok(_) => {}
+            Err(Error::KeyNotFound(key)) => {
+                return ErrorResponse::KeyNotFound { file: path, key }.into()
+            }
+            Err(_) => return ErrorResponse::Write(path).into(),
+        }
+    }
+
+    if let Err(response) = save_shrine(&state, &path, shrine, derived_key) {
+        return response;
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Default::default())
+        .unwrap()
+}
+
+/// Gets several secrets at once; a missing or denied key does not fail the others.
+async fn post_keys_batch<P>(
+    State(state): State<AgentState<P>>,
+    Path(path): Path<String>,
+    token: Option<Extension<AuthenticatedToken>>,
+    Json(request): Json<KeySelection>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!(
+        "get_keys from file `{}/{}` ({:?})",
+        path, SHRINE_FILENAME, request
+    );
+
+    let access = Access::new(&token, Permission::Read, None);
+    let shrine = match open_shrine::<P>(&state, &path, &access) {
+        Ok((shrine, _)) => shrine,
+        Err(response) => return response,
+    };
+
+    let mut keys = match request {
+        KeySelection::Keys(keys) => keys,
+        KeySelection::Pattern(pattern) => {
+            let regex = match pattern
+                .as_ref()
+                .map(|p| Regex::new(p.as_ref()))
+                .transpose()
+                .map_err(Error::InvalidPattern)
+            {
+                Err(e) => return ErrorResponse::Regex(e.to_string()).into(),
+                Ok(regex) => regex,
+            };
+            shrine
+                .keys()
+                .into_iter()
+                .filter(|k| access.lists(k))
+                .filter(|k| regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
+                .collect()
+        }
+    };
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut audit = Vec::with_capacity(keys.len());
+    let secrets = keys
+        .into_iter()
+        .map(|key| {
+            let access = Access::new(&token, Permission::Read, Some(&key));
+            let secret = match access.denial(&path, shrine.uuid()) {
+                Some(reason) => Err(ErrorResponse::OutOfScope(reason)),
+                None => shrine.get(&key).map_err(|_| ErrorResponse::KeyNotFound {
+                    file: path.clone(),
+                    key: key.clone(),
+                }),
+            };
+            let result = match &secret {
+                Ok(_) => AuditResult::Ok,
+                Err(ErrorResponse::OutOfScope(_)) => AuditResult::Denied,
+                Err(_) => AuditResult::NotFound,
+            };
+            audit.push((key.clone(), result));
+            (key, secret)
+        })
+        .collect::<Vec<(String, Result<&Secret, ErrorResponse>)>>();
+
+    (
+        Extension(AuditUuid(shrine.uuid())),
+        Extension(AuditKeys(audit)),
+        Json(secrets),
+    )
+        .into_response()
+}
+
+async fn get_config<P>(
+    State(state): State<AgentState<P>>,
+    Path(path): Path<String>,
+    token: Option<Extension<AuthenticatedToken>>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!("get_config from file `{}/{}`", path, SHRINE_FILENAME);
+
+    let access = Access::new(&token, Permission::Read, None);
+    let shrine = match open_shrine::<P>(&state, &path, &access) {
+        Ok((shrine, _)) => shrine,
+        Err(response) => return response,
+    };
+
+    let mut keys = shrine.keys_private();
+    keys.sort_unstable();
+
+    let config = keys
+        .into_iter()
+        .map(|k| {
+            let value = shrine.get_private(k).expect("must be there");
+            (k.to_string(), value.to_string())
+        })
+        .collect::<Vec<(String, String)>>();
+
+    Json(config).into_response()
+}
+
+async fn put_config<P>(
+    State(state): State<AgentState<P>>,
+    Path((path, key)): Path<(String, String)>,
+    token: Option<Extension<AuthenticatedToken>>,
+    Json(request): Json<SetConfigRequest>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!(
+        "set_config `{}` on file `{}/{}`",
+        key, path, SHRINE_FILENAME
+    );
+
+    let access = Access::new(&token, Permission::Write, None);
+    let (mut shrine, derived_key) = match open_shrine::<P>(&state, &path, &access) {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+
+    let repository = Repository::new(PathBuf::from_str(&path).unwrap(), &shrine);
+
+    shrine.set_private(key.clone(), request.value);
+
+    if let Err(response) = save_shrine(&state, &path, shrine, derived_key) {
+        return response;
+    }
+
+    if commit(repository, Operation::Config(key.clone())).is_err() {
+        return ErrorResponse::Write(path).into();
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Default::default())
+        .unwrap()
+}
+
+/// Re-encrypts the shrine with a new UUID, and caches its password in place of the old one. The
+/// password is kept unless the request has a new one.
+async fn post_convert<P>(
+    State(state): State<AgentState<P>>,
+    Path(path): Path<String>,
+    token: Option<Extension<AuthenticatedToken>>,
+    Json(request): Json<ConvertRequest>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!("convert file `{}/{}`", path, SHRINE_FILENAME);
+
+    let access = Access::new(&token, Permission::Write, None);
+    let (shrine, _) = match open_shrine::<P>(&state, &path, &access) {
+        Ok(v) => v,
+        Err(response) => return response,
+    };
+    let uuid = shrine.uuid();
+
+    let mut new_shrine = ShrineBuilder::new()
+        .with_encryption_algorithm(
+            request
+                .encryption_algorithm
+                .unwrap_or(shrine.encryption_algorithm()),
+        )
+        .build();
+    let requires_password = new_shrine.requires_password();
+    let password = match request.password {
+        Some(password) => password,
+        None if !requires_password => ShrinePassword::default(),
+        None => match state.get_password(uuid) {
+            Some(password) => password,
+            None => return with_uuid(ErrorResponse::Unauthorized(uuid), uuid),
+        },
+    };
+
+    shrine.move_to(&mut new_shrine);
+    let new_uuid = new_shrine.uuid();
+    let repository = Repository::new(PathBuf::from_str(&path).unwrap(), &new_shrine);
+
+    let closed = match new_shrine.close(&password) {
+        Ok(shrine) => shrine,
+        Err(_) => return with_uuid(ErrorResponse::Write(path), uuid),
+    };
+    if state.shrine_provider.save_to_path(&path, closed).is_err() {
+        state.shrines.lock().unwrap().remove(&path);
+        return with_uuid(ErrorResponse::Write(path), uuid);
+    }
+
+    state.delete_password(uuid);
+    if requires_password {
+        state.set_password(new_uuid, password);
+    }
+    state.notify(&path);
+
+    if commit(repository, Operation::Convert).is_err() {
+        return with_uuid(ErrorResponse::Write(path), uuid);
+    }
+
+    (Extension(AuditUuid(uuid)), StatusCode::NO_CONTENT).into_response()
+}
+
+/// The shrine's metadata, which is readable without its password.
+async fn get_info<P>(
+    State(state): State<AgentState<P>>,
+    Path(path): Path<String>,
+    token: Option<Extension<AuthenticatedToken>>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!("get_info from file `{}/{}`", path, SHRINE_FILENAME);
+
+    state.touch();
+
+    let shrine = match load_shrine(&state, &path) {
+        Ok(shrine) => shrine,
+        Err(response) => return response,
+    };
+
+    let access = Access::new(&token, Permission::List, None);
+    if let Err(response) = access.check(&path, shrine.uuid()) {
+        return response;
+    }
+
+    Json(Info::from(&shrine)).into_response()
+}
+
+/// Streams the changes of the secrets matching the pattern as server-sent `change` events, each one
+/// carrying the changes found at once. The file is checked every second for changes made by other
+/// processes, and right away after the agent wrote it. The stream ends with an `error` event if
+/// the shrine cannot be read anymore, e.g. when its password expired.
+async fn get_watch<P>(
+    State(state): State<AgentState<P>>,
+    Path(path): Path<String>,
+    Query(params): Query<GetSecretsRequest>,
+    token: Option<Extension<AuthenticatedToken>>,
+) -> Response
+where
+    P: ShrineProvider,
+{
+    info!("watch file `{}/{}` ({:?})", path, SHRINE_FILENAME, params);
+
+    let regex = match params
+        .regexp
+        .as_ref()
+        .map(|p| Regex::new(p.as_ref()))
+        .transpose()
+        .map_err(Error::InvalidPattern)
+    {
+        Err(e) => return ErrorResponse::Regex(e.to_string()).into(),
+        Ok(regex) => regex,
+    };
+
+    let notifications = state.changes.subscribe();
+    let stamp = state.shrine_provider.stamp(&path);
+    let access = Access::new(&token, Permission::List, None);
+    let shrine = match open_shrine::<P>(&state, &path, &access) {
+        Ok((shrine, _)) => shrine,
+        Err(response) => return response,
+    };
+
+    let mut watcher = Watcher {
+        state,
+        path,
+        token,
+        regex,
+        stamp,
+        digests: HashMap::new(),
+        notifications,
+        interval: tokio::time::interval(Duration::from_secs(1)),
+        done: false,
+    };
+    watcher.diff(&shrine);
+
+    let events = futures::stream::unfold(watcher, |mut watcher| async move {
+        watcher
+            .next()
+            .await
+            .map(|event| (Ok::<_, Infallible>(event), watcher))
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// The state of a `watch` request.
+struct Watcher<P>
+where
+    P: ShrineProvider,
+{
+    state: AgentState<P>,
+    path: String,
+    token: Option<Extension<AuthenticatedToken>>,
+    regex: Option<Regex>,
+    stamp: Option<FileStamp>,
+    /// The digests of the watched secrets' values, to tell which ones changed.
+    digests: HashMap<String, Vec<u8>>,
+    notifications: broadcast::Receiver<Notification>,
+    interval: tokio::time::Interval,
+    done: bool,
+}
+
+impl<P> Watcher<P>
+where
+    P: ShrineProvider,
+{
+    async fn next(&mut self) -> Option<Event> {
+        while !self.done {
+            tokio::select! {
+                notification = self.notifications.recv() => match notification {
+                    Ok(Notification::Changed(path)) if path == self.path => {}
+                    Ok(Notification::Changed(_)) => continue,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Ok(Notification::Stopping) | Err(broadcast::error::RecvError::Closed) => {
+                        return None
+                    }
+                },
+                _ = self.interval.tick() => {
+                    self.state.touch();
+                    if self.state.shrine_provider.stamp(&self.path) == self.stamp {
+                        continue;
+                    }
+                }
+            }
+
+            self.stamp = self.state.shrine_provider.stamp(&self.path);
+            let access = Access::new(&self.token, Permission::List, None);
+            match open_shrine::<P>(&self.state, &self.path, &access) {
+                Ok((shrine, _)) => {
+                    let changes = self.diff(&shrine);
+                    if !changes.is_empty() {
+                        return Some(
+                            Event::default()
+                                .event("change")
+                                .json_data(changes)
+                                .expect("changes are serializable"),
+                        );
+                    }
+                }
+                Err(response) => {
+                    self.done = true;
+                    let error = hyper::body::to_bytes(response.into_body())
+                        .await
+                        .unwrap_or_default();
+                    return Some(
+                        Event::default()
+                            .event("error")
+                            .data(String::from_utf8_lossy(&error)),
+                    );
+                }
+            }
+        }
+        None
+    }
+
+    /// The changes of the watched secrets since the previous call.
+    fn diff(&mut self, shrine: &Shrine) -> Vec<Change> {
+        let access = Access::new(&self.token, Permission::List, None);
+        let mut digests = shrine
+            .keys()
+            .into_iter()
+            .filter(|k| access.lists(k))
+            .filter(|k| self.regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
+            .map(|k| {
+                let secret = shrine.get(&k).expect("must be there");
+                let digest = Sha256::new()
+                    .chain_update(secret.mode().to_string())
+                    .chain_update(secret.value().expose_secret_as_bytes())
+                    .finalize()
+                    .to_vec();
+                (k, digest)
+            })
+            .collect::<HashMap<String, Vec<u8>>>();
+
+        let mut changes = digests
+            .iter()
+            .filter(|(k, digest)| self.digests.get(*k) != Some(digest))
+            .map(|(k, _)| Change {
+                key: k.clone(),
+                kind: ChangeKind::Set,
+            })
+            .chain(
+                self.digests
+                    .keys()
+                    .filter(|k| !digests.contains_key(*k))
+                    .map(|k| Change {
+                        key: k.clone(),
+                        kind: ChangeKind::Deleted,
+                    }),
+            )
+            .collect::<Vec<Change>>();
+        changes.sort_unstable_by(|a, b| a.key.cmp(&b.key));
+
+        mem::swap(&mut self.digests, &mut digests);
+        changes
+    }
+}
+
+/// The agent's settings, read from the global [`Settings`] when it starts.
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    /// How long passwords are cached, and how the duration is computed.
+    ttl: (chrono::Duration, Expiry),
+    /// Per shrine overrides of `ttl`.
+    shrines: HashMap<Uuid, (Option<chrono::Duration>, Option<Expiry>)>,
+    /// How long the agent may go unused before it stops.
+    idle_timeout: Option<chrono::Duration>,
+    /// The executables allowed to read secrets; all of them if not set.
+    allowed_executables: Option<Vec<PathBuf>>,
+    audit_log: Option<AuditLog>,
+    /// Where the agent listens for TCP connections, in addition to its socket.
+    listen: Option<Listen>,
+    /// The file containing the tokens accepted from TCP clients.
+    tokens: Option<PathBuf>,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            ttl: (chrono::Duration::minutes(15), Expiry::Sliding),
+            shrines: Default::default(),
+            idle_timeout: None,
+            allowed_executables: None,
+            audit_log: None,
+            listen: None,
+            tokens: Tokens::path(),
+        }
+    }
+}
+
+impl AgentConfig {
+    pub fn from_settings(settings: &Settings) -> Result<Self, Error> {
+        let default = Self::default();
+
+        let mut shrines = HashMap::<Uuid, (Option<chrono::Duration>, Option<Expiry>)>::new();
+        for (prefix, is_ttl) in [
+            ("agent.password.ttl.", true),
+            ("agent.password.expiry.", false),
+        ] {
+            for (uuid, value) in settings.with_prefix(prefix) {
+                let key = format!("{}{}", prefix, uuid);
+                let uuid = Uuid::from_str(uuid)
+                    .map_err(|_| Error::InvalidSetting(key.clone(), value.to_string()))?;
+                let entry = shrines.entry(uuid).or_default();
+                if is_ttl {
+                    entry.0 = settings.get_duration(&key)?;
+                } else {
+                    entry.1 = settings.get_parsed(&key)?;
+                }
+            }
+        }
+
+        Ok(Self {
+            ttl: (
+                settings
+                    .get_duration("agent.password.ttl")?
+                    .unwrap_or(default.ttl.0),
+                settings
+                    .get_parsed("agent.password.expiry")?
+                    .unwrap_or(default.ttl.1),
+            ),
+            shrines,
+            idle_timeout: settings.get_duration("agent.idle-timeout")?,
+            allowed_executables: settings.get("agent.allowed-executables").map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|e| !e.is_empty())
+                    .map(PathBuf::from)
+                    .collect()
+            }),
+            audit_log: AuditLog::from_settings(settings)?,
+            listen: match settings.get("agent.listen") {
+                None => None,
+                Some(address) => Some(Listen {
+                    address: settings.get_parsed("agent.listen")?.ok_or_else(|| {
+                        Error::InvalidSetting("agent.listen".to_string(), address.to_string())
+                    })?,
+                    tls_cert: settings
+                        .get("agent.tls-cert")
+                        .map(PathBuf::from)
+                        .ok_or_else(|| {
+                            Error::Tls("`agent.listen` requires `agent.tls-cert`".to_string())
+                        })?,
+                    tls_key: settings
+                        .get("agent.tls-key")
+                        .map(PathBuf::from)
+                        .ok_or_else(|| {
+                            Error::Tls("`agent.listen` requires `agent.tls-key`".to_string())
+                        })?,
+                }),
+            },
+            tokens: default.tokens,
+        })
+    }
+
+    /// Listens for TCP connections as set by `agent start --listen`, instead of `agent.listen`.
+    pub fn with_listen(mut self, listen: Listen) -> Self {
+        self.listen = Some(listen);
+        self
+    }
+
+    pub fn listen(&self) -> Option<&Listen> {
+        self.listen.as_ref()
+    }
+
+    fn is_allowed(&self, executable: Option<&std::path::Path>) -> bool {
+        match (&self.allowed_executables, executable) {
+            (None, _) => true,
+            (Some(allowed), Some(executable)) => allowed.iter().any(|a| a == executable),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// How long the password of shrine `uuid` is cached, and how the duration is computed.
+    fn ttl(&self, uuid: Uuid) -> (chrono::Duration, Expiry) {
+        match self.shrines.get(&uuid) {
+            None => self.ttl,
+            Some((ttl, expiry)) => (ttl.unwrap_or(self.ttl.0), expiry.unwrap_or(self.ttl.1)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AgentState<P>
+where
+    P: ShrineProvider,
 {
     shrine_provider: P,
+    config: Arc<AgentConfig>,
     http_shutdown_tx: Arc<Mutex<Sender<()>>>,
-    passwords: Arc<Mutex<HashMap<Uuid, ATimePassword>>>,
+    /// Locked before `shrines` when both are needed, never after.
+    passwords: Arc<Mutex<HashMap<Uuid, CacheEntry>>>,
+    /// The opened shrines, by path.
+    shrines: Arc<Mutex<HashMap<String, CachedShrine>>>,
+    metrics: Arc<Mutex<Metrics>>,
+    last_activity: Arc<Mutex<DateTime<Utc>>>,
+    changes: broadcast::Sender<Notification>,
+    /// Serializes the writes to the audit log, which checks its size and rotates it before each
+    /// append.
+    audit_lock: Arc<Mutex<()>>,
+}
+
+/// What the agent tells its watchers.
+#[derive(Clone, Debug)]
+enum Notification {
+    /// The agent wrote the shrine at the path.
+    Changed(String),
+    Stopping,
+}
+
+struct CachedShrine {
+    /// The state of the file when the shrine was read.
+    stamp: FileStamp,
+    key: Option<DerivedKey>,
+    shrine: Shrine,
+}
+
+struct CacheEntry {
+    password: ShrinePassword,
+    unlocked_at: DateTime<Utc>,
+    used_at: DateTime<Utc>,
 }
-type ATimePassword = (DateTime<Utc>, ShrinePassword);
 
 impl<P> AgentState<P>
 where
@@ -379,39 +1636,194 @@ where
     fn new(shrine_provider: P, http_shutdown_tx: Sender<()>) -> Self {
         Self {
             shrine_provider,
+            config: Default::default(),
             http_shutdown_tx: Arc::new(Mutex::new(http_shutdown_tx)),
             passwords: Arc::new(Mutex::new(Default::default())),
+            shrines: Arc::new(Mutex::new(Default::default())),
+            metrics: Arc::new(Mutex::new(Default::default())),
+            last_activity: Arc::new(Mutex::new(Utc::now())),
+            changes: broadcast::channel(64).0,
+            audit_lock: Default::default(),
+        }
+    }
+
+    fn with_config(mut self, config: AgentConfig) -> Self {
+        self.config = Arc::new(config);
+        self
+    }
+
+    fn shutdown(&self) {
+        let channel = channel::<()>();
+        let mut sig = self.http_shutdown_tx.lock().unwrap();
+        let _ = mem::replace(&mut *sig, channel.0).send(());
+    }
+
+    /// Wakes the watchers of the shrine at `path` up after the agent wrote it.
+    fn notify(&self, path: &str) {
+        let _ = self.changes.send(Notification::Changed(path.to_string()));
+    }
+
+    /// Ends the watchers' streams, which would otherwise keep the server from shutting down.
+    fn stop_watchers(&self) {
+        let _ = self.changes.send(Notification::Stopping);
+    }
+
+    /// Records that a client used the agent, postponing the idle shutdown.
+    fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Utc::now();
+    }
+
+    fn is_idle(&self) -> bool {
+        match self.config.idle_timeout {
+            None => false,
+            Some(timeout) => *self.last_activity.lock().unwrap() + timeout < Utc::now(),
         }
     }
 
     fn set_password(&self, uuid: Uuid, password: ShrinePassword) {
-        self.passwords
-            .lock()
-            .unwrap()
-            .insert(uuid, (Utc::now(), password));
+        self.touch();
+        let now = Utc::now();
+        self.passwords.lock().unwrap().insert(
+            uuid,
+            CacheEntry {
+                password,
+                unlocked_at: now,
+                used_at: now,
+            },
+        );
     }
 
     fn delete_passwords(&self) {
         self.passwords.lock().unwrap().clear();
+        self.shrines.lock().unwrap().clear();
+    }
+
+    fn delete_password(&self, uuid: Uuid) {
+        self.passwords.lock().unwrap().remove(&uuid);
+        self.forget_shrines(uuid);
     }
 
     fn get_password(&self, uuid: Uuid) -> Option<ShrinePassword> {
+        self.touch();
+        let now = Utc::now();
         let mut passwords = self.passwords.lock().unwrap();
-        match passwords.remove(&uuid) {
-            None => None,
-            Some((_, password)) => {
-                passwords.insert(uuid, (Utc::now(), password.clone()));
-                Some(password)
+        match passwords.get_mut(&uuid) {
+            Some(entry) if self.expires_at(uuid, entry) > now => {
+                entry.used_at = now;
+                Some(entry.password.clone())
             }
+            Some(_) => {
+                passwords.remove(&uuid);
+                self.forget_shrines(uuid);
+                None
+            }
+            None => None,
         }
     }
 
+    fn expires_at(&self, uuid: Uuid, entry: &CacheEntry) -> DateTime<Utc> {
+        match self.config.ttl(uuid) {
+            (ttl, Expiry::Sliding) => entry.used_at + ttl,
+            (ttl, Expiry::Absolute) => entry.unlocked_at + ttl,
+        }
+    }
+
+    /// The cached passwords, the first to expire first.
+    fn cached_passwords(&self) -> Vec<CachedPassword> {
+        let mut passwords = self
+            .passwords
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(uuid, entry)| CachedPassword {
+                uuid: *uuid,
+                expiry: self.config.ttl(*uuid).1,
+                expires_at: self.expires_at(*uuid, entry),
+            })
+            .collect::<Vec<_>>();
+        passwords.sort_by_key(|p| p.expires_at);
+        passwords
+    }
+
     fn clean_expired_passwords(&self) {
-        let lowest_barrier = Utc::now() - chrono::Duration::minutes(15);
-        self.passwords
+        let now = Utc::now();
+        let mut passwords = self.passwords.lock().unwrap();
+        let expired = passwords
+            .iter()
+            .filter(|(uuid, entry)| self.expires_at(**uuid, entry) <= now)
+            .map(|(uuid, _)| *uuid)
+            .collect::<Vec<_>>();
+        for uuid in expired {
+            info!("Password of `{}` expired", uuid);
+            passwords.remove(&uuid);
+            self.forget_shrines(uuid);
+            self.audit(AuditEvent {
+                timestamp: now,
+                operation: AuditOperation::Expire,
+                uuid: Some(uuid),
+                path: None,
+                key: None,
+                pid: None,
+                executable: None,
+                address: None,
+                token: None,
+                result: AuditResult::Ok,
+            });
+        }
+    }
+
+    fn audit(&self, event: AuditEvent) {
+        if let Some(audit_log) = &self.config.audit_log {
+            let _lock = self.audit_lock.lock().unwrap();
+            if let Err(e) = audit_log.append(&event) {
+                error!("Could not write the audit log: {}", e);
+            }
+        }
+    }
+
+    /// The UUID of the shrine at `path`, if it is opened.
+    fn cached_uuid(&self, path: &str) -> Option<Uuid> {
+        self.shrines
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|c| c.shrine.uuid())
+    }
+
+    /// The opened shrine at `path`, if the file has not changed since and, when it is encrypted,
+    /// its password is still cached.
+    fn cached_shrine(&self, path: &str, stamp: FileStamp) -> Option<(Shrine, Option<DerivedKey>)> {
+        let cached = self
+            .shrines
+            .lock()
+            .unwrap()
+            .get(path)
+            .filter(|c| c.stamp == stamp)
+            .map(|c| (c.shrine.clone(), c.key.clone()));
+        // the `shrines` lock is released: checking the password takes the `passwords` one
+        let cached = cached
+            .filter(|(shrine, key)| key.is_none() || self.get_password(shrine.uuid()).is_some());
+
+        let mut metrics = self.metrics.lock().unwrap();
+        match cached {
+            Some(_) => metrics.cache_hits += 1,
+            None => metrics.cache_misses += 1,
+        }
+        cached
+    }
+
+    fn cache_shrine(&self, path: &str, stamp: FileStamp, key: Option<DerivedKey>, shrine: Shrine) {
+        self.shrines
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), CachedShrine { stamp, key, shrine });
+    }
+
+    fn forget_shrines(&self, uuid: Uuid) {
+        self.shrines
             .lock()
             .unwrap()
-            .retain(|_, (atime, _)| (*atime).gt(&lowest_barrier));
+            .retain(|_, c| c.shrine.uuid() != uuid);
     }
 }
 
@@ -423,6 +1835,19 @@ trait ShrineProvider: Clone + Send + Sync + 'static {
     fn save_to_path<P>(&self, path: P, shrine: Shrine<Closed>) -> Result<(), Error>
     where
         P: AsRef<std::path::Path>;
+
+    /// The state of the shrine file in the folder `path`, `None` if it cannot be read.
+    fn stamp<P>(&self, path: P) -> Option<FileStamp>
+    where
+        P: AsRef<std::path::Path>;
+}
+
+/// Identifies a version of a file: it changes when the file is written or replaced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: SystemTime,
+    inode: u64,
+    len: u64,
 }
 
 #[derive(Clone, Default)]
@@ -442,6 +1867,18 @@ impl ShrineProvider for DefaultShrineProvider {
     {
         shrine.to_path(path)
     }
+
+    fn stamp<P>(&self, path: P) -> Option<FileStamp>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let metadata = path.as_ref().join(SHRINE_FILENAME).metadata().ok()?;
+        Some(FileStamp {
+            modified: metadata.modified().ok()?,
+            inode: metadata.ino(),
+            len: metadata.len(),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -449,23 +1886,32 @@ mod tests {
     use super::*;
     use crate::bytes::SecretBytes;
     use crate::shrine::{Closed, EncryptionAlgorithm, Mode, ShrineBuilder};
+    use crate::totp::Code;
     use axum::body::HttpBody;
-    use axum::http::Request;
-    use hyper::Body;
+    use axum::extract::connect_info::MockConnectInfo;
     use std::cell::RefCell;
     use tower::ServiceExt;
 
     #[derive(Clone)]
     struct MockShrineProvider {
         shrine: Arc<Mutex<RefCell<Shrine<Closed>>>>,
+        /// Incremented each time the shrine is saved.
+        version: Arc<Mutex<u64>>,
     }
 
     impl MockShrineProvider {
         fn new(shrine: Shrine<Closed>) -> Self {
             Self {
                 shrine: Arc::new(Mutex::new(RefCell::new(shrine))),
+                version: Default::default(),
             }
         }
+
+        /// Replaces the shrine, as if the file was written by another process.
+        fn replace(&self, shrine: Shrine<Closed>) {
+            self.shrine.lock().unwrap().replace(shrine);
+            *self.version.lock().unwrap() += 1;
+        }
     }
 
     impl ShrineProvider for MockShrineProvider {
@@ -484,19 +1930,346 @@ mod tests {
         where
             P: AsRef<std::path::Path>,
         {
-            self.shrine.lock().unwrap().replace(shrine);
+            self.replace(shrine);
             Ok(())
         }
+
+        fn stamp<P>(&self, _path: P) -> Option<FileStamp>
+        where
+            P: AsRef<std::path::Path>,
+        {
+            Some(FileStamp {
+                modified: SystemTime::UNIX_EPOCH,
+                inode: 0,
+                len: *self.version.lock().unwrap(),
+            })
+        }
     }
 
     #[tokio::test]
     async fn get_pid() {
         let pid = super::get_pid().await;
-        assert!(pid != "");
+        assert!(!pid.is_empty());
+    }
+
+    #[tokio::test]
+    async fn get_key() {
+        let (tx, _) = channel::<()>();
+
+        let shrine = {
+            let mut shrine = ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+                .build();
+            shrine.set("key", "value", Mode::Text).unwrap();
+            shrine.close(&ShrinePassword::default()).unwrap()
+        };
+
+        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+
+        let response = super::get_key(
+            state,
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let secret: Secret =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+
+        assert_eq!(secret.value().expose_secret_as_bytes(), "value".as_bytes())
+    }
+
+    #[tokio::test]
+    async fn post_textconv() {
+        let (tx, _) = channel::<()>();
+        let password = ShrinePassword::from("password");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        shrine.set("key", "secret", Mode::Text).unwrap();
+        let uuid = shrine.uuid();
+        let bytes = shrine.close(&password).unwrap().as_bytes().unwrap();
+
+        let state = AgentState::new(
+            MockShrineProvider::new(ShrineBuilder::new().build().close(&password).unwrap()),
+            tx,
+        );
+        let request = || {
+            super::post_textconv(
+                State(state.clone()),
+                Json(TextconvRequest {
+                    shrine: bytes.clone(),
+                }),
+            )
+        };
+
+        let response = request().await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        state.set_password(uuid, password);
+        let response = request().await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let listing: String =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+        assert!(listing.starts_with("key txt "));
+        assert!(!listing.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn post_merge() {
+        let (tx, _) = channel::<()>();
+        let password = ShrinePassword::from("password");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        let uuid = shrine.uuid();
+        shrine.set("key", "base", Mode::Text).unwrap();
+        let base = shrine.clone().close(&password).unwrap().as_bytes().unwrap();
+        shrine.set("key", "ours", Mode::Text).unwrap();
+        let ours = shrine.clone().close(&password).unwrap().as_bytes().unwrap();
+        shrine.set("key", "theirs", Mode::Text).unwrap();
+        shrine.set("other", "theirs", Mode::Text).unwrap();
+        let theirs = shrine.close(&password).unwrap().as_bytes().unwrap();
+
+        let state = AgentState::new(
+            MockShrineProvider::new(ShrineBuilder::new().build().close(&password).unwrap()),
+            tx,
+        );
+        state.set_password(uuid, password.clone());
+
+        let response = super::post_merge(
+            State(state),
+            Json(MergeRequest {
+                base: Some(base),
+                ours,
+                theirs,
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let merged: MergeResponse =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap())
+                .unwrap();
+        assert_eq!(
+            merged.conflicts,
+            vec!["`key` was modified on both sides".to_string()]
+        );
+        let shrine = Shrine::from_bytes(&merged.shrine)
+            .unwrap()
+            .open(&password)
+            .unwrap();
+        assert_eq!(shrine.keys(), vec!["key", "other"]);
+        assert_eq!(
+            shrine.get("key").unwrap().value().expose_secret_as_bytes(),
+            b"ours"
+        );
+    }
+
+    #[tokio::test]
+    async fn post_convert() {
+        let (tx, _) = channel::<()>();
+        let password = ShrinePassword::from("password");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        let uuid = shrine.uuid();
+        shrine.set("key", "value", Mode::Text).unwrap();
+
+        let state = AgentState::new(
+            MockShrineProvider::new(shrine.close(&password).unwrap()),
+            tx,
+        );
+        state.set_password(uuid, password);
+
+        let response = super::post_convert(
+            State(state.clone()),
+            Path("fake_path".to_string()),
+            None,
+            Json(ConvertRequest {
+                password: Some(ShrinePassword::from("new")),
+                encryption_algorithm: None,
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let shrine = state.shrine_provider.load_from_path("fake_path").unwrap();
+        let new_uuid = shrine.uuid();
+        assert_ne!(new_uuid, uuid);
+        let shrine = shrine.open(&ShrinePassword::from("new")).unwrap();
+        assert_eq!(
+            shrine.get("key").unwrap().value().expose_secret_as_bytes(),
+            b"value"
+        );
+
+        assert!(state.get_password(uuid).is_none());
+        assert_eq!(state.get_password(new_uuid).unwrap().expose_secret(), "new");
+    }
+
+    #[tokio::test]
+    async fn get_not_found() {
+        let (tx, _) = channel::<()>();
+
+        let shrine = {
+            let mut shrine = ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+                .build();
+            shrine.set("key", "value", Mode::Text).unwrap();
+            shrine.close(&ShrinePassword::default()).unwrap()
+        };
+
+        let response = super::get_key(
+            State(AgentState::new(MockShrineProvider::new(shrine), tx)),
+            Path(("fake_path".to_string(), "unknown key".to_string())),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn get_password_required() {
+        let (tx, _) = channel::<()>();
+
+        let shrine = {
+            let mut shrine = ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+                .build();
+            shrine.set("key", "value", Mode::Text).unwrap();
+            shrine.close(&ShrinePassword::default()).unwrap()
+        };
+
+        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+
+        let response = super::get_key(
+            state,
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn get_password_required_and_set() {
+        let (tx, _) = channel::<()>();
+
+        let shrine_password = ShrinePassword::from("password");
+        let shrine = {
+            let mut shrine = ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+                .build();
+            shrine.set("key", "value", Mode::Text).unwrap();
+            shrine.close(&shrine_password).unwrap()
+        };
+
+        let uuid = shrine.uuid();
+
+        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+
+        state.set_password(uuid, shrine_password);
+
+        let response = super::get_key(
+            state,
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let secret: Secret =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+
+        assert_eq!(secret.value().expose_secret_as_bytes(), "value".as_bytes())
+    }
+
+    #[tokio::test]
+    async fn get_secrets() {
+        let (tx, _) = channel::<()>();
+
+        let shrine = {
+            let mut shrine = ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+                .build();
+            shrine.set("key", "text", Mode::Text).unwrap();
+            shrine.set("binkey", "bin", Mode::Binary).unwrap();
+            shrine.close(&ShrinePassword::default()).unwrap()
+        };
+
+        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+
+        let response = super::get_keys(
+            state,
+            Path("fake_path".to_string()),
+            Query(GetSecretsRequest {
+                regexp: Some("bin.*".to_string()),
+                long: false,
+            }),
+            None,
+        )
+        .await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let secrets: Vec<Key> =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+
+        assert_eq!(secrets.len(), 1)
+    }
+
+    #[tokio::test]
+    async fn get_secrets_long() {
+        for long in [false, true] {
+            let (tx, _) = channel::<()>();
+
+            let shrine = {
+                let mut shrine = ShrineBuilder::new()
+                    .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+                    .build();
+                shrine
+                    .set("record", r#"{"user":"alice"}"#, Mode::Record)
+                    .unwrap();
+                shrine.close(&ShrinePassword::default()).unwrap()
+            };
+
+            let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+
+            let response = super::get_keys(
+                state,
+                Path("fake_path".to_string()),
+                Query(GetSecretsRequest { regexp: None, long }),
+                None,
+            )
+            .await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let secrets: Vec<Key> = serde_json::from_slice(
+                response.into_body().data().await.unwrap().unwrap().as_ref(),
+            )
+            .unwrap();
+
+            // the fields are only listed in the long listing
+            assert_eq!(secrets[0].fields.is_empty(), !long);
+        }
     }
 
     #[tokio::test]
-    async fn get_key() {
+    async fn put_key() {
         let (tx, _) = channel::<()>();
 
         let shrine = {
@@ -509,20 +2282,28 @@ mod tests {
 
         let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
 
-        let response =
-            super::get_key(state, Path(("fake_path".to_string(), "key".to_string()))).await;
+        super::put_key(
+            state.clone(),
+            Path((String::default(), "key".to_string())),
+            None,
+            Json(SetSecretRequest {
+                secret: SecretBytes::from("secret"),
+                mode: Mode::Text,
+            }),
+        )
+        .await;
 
-        assert_eq!(response.status(), StatusCode::OK);
+        let value = super::get_key(state, Path((String::default(), "key".to_string())), None).await;
 
         let secret: Secret =
-            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+            serde_json::from_slice(value.into_body().data().await.unwrap().unwrap().as_ref())
                 .unwrap();
 
-        assert_eq!(secret.value().expose_secret_as_bytes(), "value".as_bytes())
+        assert_eq!(secret.value().expose_secret_as_bytes(), "secret".as_bytes())
     }
 
     #[tokio::test]
-    async fn get_not_found() {
+    async fn delete_key() {
         let (tx, _) = channel::<()>();
 
         let shrine = {
@@ -533,209 +2314,715 @@ mod tests {
             shrine.close(&ShrinePassword::default()).unwrap()
         };
 
-        let response = super::get_key(
-            State(AgentState::new(MockShrineProvider::new(shrine), tx)),
-            Path(("fake_path".to_string(), "unknown key".to_string())),
+        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+        super::delete_key(
+            state.clone(),
+            Path((String::default(), "key".to_string())),
+            None,
         )
         .await;
 
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let value = super::get_key(state, Path((String::default(), "key".to_string())), None).await;
+
+        assert_eq!(value.status(), StatusCode::NOT_FOUND);
     }
 
     #[tokio::test]
-    async fn get_password_required() {
+    async fn get_otp() {
         let (tx, _) = channel::<()>();
 
         let shrine = {
             let mut shrine = ShrineBuilder::new()
-                .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
                 .build();
-            shrine.set("key", "value", Mode::Text).unwrap();
+            shrine
+                .set(
+                    "key",
+                    "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ",
+                    Mode::Totp,
+                )
+                .unwrap();
             shrine.close(&ShrinePassword::default()).unwrap()
         };
 
         let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
 
-        let response =
-            super::get_key(state, Path(("fake_path".to_string(), "key".to_string()))).await;
+        let response = super::get_otp(
+            state,
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
+        )
+        .await;
 
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let code: Code =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+
+        assert_eq!(code.code.len(), 6);
     }
 
     #[tokio::test]
-    async fn get_password_required_and_set() {
+    async fn get_otp_not_totp() {
         let (tx, _) = channel::<()>();
 
-        let shrine_password = ShrinePassword::from("password");
         let shrine = {
             let mut shrine = ShrineBuilder::new()
-                .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
                 .build();
             shrine.set("key", "value", Mode::Text).unwrap();
-            shrine.close(&shrine_password).unwrap()
+            shrine.close(&ShrinePassword::default()).unwrap()
         };
 
-        let uuid = shrine.uuid();
+        let response = super::get_otp(
+            State(AgentState::new(MockShrineProvider::new(shrine), tx)),
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
+        )
+        .await;
 
-        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 
-        state.set_password(uuid, shrine_password);
+    #[tokio::test]
+    async fn route_get_pid() {
+        let (tx, _) = channel::<()>();
+        let state = AgentState::new(
+            MockShrineProvider::new(Shrine::default().close(&ShrinePassword::default()).unwrap()),
+            tx,
+        );
 
-        let response =
-            super::get_key(state, Path(("fake_path".to_string(), "key".to_string()))).await;
+        let response = router(state)
+            .oneshot(Request::get("/pid").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
+    }
 
-        let secret: Secret =
-            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
-                .unwrap();
+    #[tokio::test]
+    async fn route_get_key_requires_password() {
+        let (tx, _) = channel::<()>();
+        let shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build()
+            .close(&ShrinePassword::default())
+            .unwrap();
+        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        assert_eq!(secret.value().expose_secret_as_bytes(), "value".as_bytes())
+        let response = router(state)
+            .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
     #[tokio::test]
-    async fn get_secrets() {
+    async fn route_put_password_then_get_key_then_delete_password() {
         let (tx, _) = channel::<()>();
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        shrine.set("key", "value", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::from("password")).unwrap();
 
-        let shrine = {
-            let mut shrine = ShrineBuilder::new()
-                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
-                .build();
-            shrine.set("key", "text", Mode::Text).unwrap();
-            shrine.set("binkey", "bin", Mode::Binary).unwrap();
-            shrine.close(&ShrinePassword::default()).unwrap()
-        };
+        let uuid = shrine.uuid();
+        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+        let response = router(state.clone())
+            .oneshot(
+                Request::put("/passwords")
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&SetPasswordRequest {
+                            uuid,
+                            password: ShrinePassword::from("password"),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let response = super::get_keys(
-            state,
-            Path("fake_path".to_string()),
-            Query(GetSecretsRequest {
-                regexp: Some("bin.*".to_string()),
-            }),
+        let response = router(state.clone())
+            .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router(state.clone())
+            .oneshot(Request::delete("/passwords").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router(state)
+            .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    use crate::agent::token::Scope;
+
+    fn agent_config(settings: &str) -> AgentConfig {
+        AgentConfig::from_settings(&Settings::parse(settings)).unwrap()
+    }
+
+    #[test]
+    fn config_from_settings() {
+        let uuid = Uuid::new_v4();
+        let config = agent_config(&format!(
+            "agent.password.ttl=1h\n\
+            agent.password.ttl.{uuid}=30s\n\
+            agent.password.expiry.{uuid}=absolute\n\
+            agent.idle-timeout=2h\n"
+        ));
+
+        assert_eq!(
+            config.ttl(Uuid::new_v4()),
+            (chrono::Duration::hours(1), Expiry::Sliding)
+        );
+        assert_eq!(
+            config.ttl(uuid),
+            (chrono::Duration::seconds(30), Expiry::Absolute)
+        );
+        assert_eq!(config.idle_timeout, Some(chrono::Duration::hours(2)));
+
+        let err = AgentConfig::from_settings(&Settings::parse("agent.password.expiry=never"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value `never` for setting `agent.password.expiry`"
+        );
+    }
+
+    #[tokio::test]
+    async fn password_expiry() {
+        let (tx, _) = channel::<()>();
+        let sliding = Uuid::new_v4();
+        let absolute = Uuid::new_v4();
+        let state = AgentState::new(
+            MockShrineProvider::new(Shrine::default().close(&ShrinePassword::default()).unwrap()),
+            tx,
         )
-        .await;
+        .with_config(agent_config(&format!(
+            "agent.password.ttl=10m\nagent.password.expiry.{absolute}=absolute"
+        )));
+
+        state.set_password(sliding, ShrinePassword::from("sliding"));
+        state.set_password(absolute, ShrinePassword::from("absolute"));
+
+        // both were unlocked 8 minutes ago and just used
+        for entry in state.passwords.lock().unwrap().values_mut() {
+            entry.unlocked_at -= chrono::Duration::minutes(8);
+        }
+        state.clean_expired_passwords();
+        assert!(state.get_password(sliding).is_some());
+        assert!(state.get_password(absolute).is_some());
+
+        // 3 more minutes later: the sliding one was used recently enough
+        for entry in state.passwords.lock().unwrap().values_mut() {
+            entry.unlocked_at -= chrono::Duration::minutes(3);
+            entry.used_at -= chrono::Duration::minutes(3);
+        }
+        let passwords = state.cached_passwords();
+        assert_eq!(passwords[0].uuid, absolute);
+        assert_eq!(passwords[0].expiry, Expiry::Absolute);
+        assert!(passwords[0].expires_at < Utc::now());
+
+        state.clean_expired_passwords();
+        assert!(state.get_password(sliding).is_some());
+        assert!(state.get_password(absolute).is_none());
+    }
+
+    #[tokio::test]
+    async fn idle() {
+        let (tx, _) = channel::<()>();
+        let state = AgentState::new(
+            MockShrineProvider::new(Shrine::default().close(&ShrinePassword::default()).unwrap()),
+            tx,
+        );
+        *state.last_activity.lock().unwrap() = Utc::now() - chrono::Duration::days(1);
+        assert!(!state.is_idle());
+
+        let state = state.with_config(agent_config("agent.idle-timeout=1h"));
+        assert!(state.is_idle());
+
+        state.set_password(Uuid::new_v4(), ShrinePassword::from("password"));
+        assert!(!state.is_idle());
+    }
+
+    #[tokio::test]
+    async fn route_get_then_delete_password() {
+        let (tx, _) = channel::<()>();
+        let uuid = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let state = AgentState::new(
+            MockShrineProvider::new(Shrine::default().close(&ShrinePassword::default()).unwrap()),
+            tx,
+        );
+        state.set_password(uuid, ShrinePassword::from("password"));
+        state.set_password(other, ShrinePassword::from("password"));
+
+        let response = router(state.clone())
+            .oneshot(
+                Request::delete(format!("/passwords/{}", uuid))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
+        let response = router(state)
+            .oneshot(Request::get("/passwords").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
-        let secrets: Vec<Key> =
+        let passwords: Vec<CachedPassword> =
             serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
                 .unwrap();
+        assert_eq!(passwords.len(), 1);
+        assert_eq!(passwords[0].uuid, other);
+        assert_eq!(passwords[0].expiry, Expiry::Sliding);
+    }
 
-        assert_eq!(secrets.len(), 1)
+    fn encrypted_shrine(value: &str) -> Shrine<Closed> {
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        shrine.set("key", value, Mode::Text).unwrap();
+        shrine.close(&ShrinePassword::from("password")).unwrap()
+    }
+
+    async fn get_value(state: &AgentState<MockShrineProvider>) -> Result<String, StatusCode> {
+        let response = super::get_key(
+            State(state.clone()),
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
+        )
+        .await;
+        if response.status() != StatusCode::OK {
+            return Err(response.status());
+        }
+        let secret: Secret =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+        Ok(String::from_utf8(secret.value().expose_secret_as_bytes().to_vec()).unwrap())
     }
 
     #[tokio::test]
-    async fn put_key() {
+    async fn shrine_cache() {
         let (tx, _) = channel::<()>();
+        let shrine = encrypted_shrine("value");
+        let uuid = shrine.uuid();
+        let provider = MockShrineProvider::new(shrine);
+        let state = AgentState::new(provider.clone(), tx);
+        state.set_password(uuid, ShrinePassword::from("password"));
+
+        // the provider hands the shrine out once: later reads must come from the cache
+        assert_eq!(get_value(&state).await, Ok("value".to_string()));
+        assert_eq!(get_value(&state).await, Ok("value".to_string()));
+        assert_eq!(
+            *state.metrics.lock().unwrap(),
+            Metrics {
+                cache_hits: 1,
+                cache_misses: 1,
+            }
+        );
 
-        let shrine = {
-            let mut shrine = ShrineBuilder::new()
-                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
-                .build();
-            shrine.set("key", "value", Mode::Text).unwrap();
-            shrine.close(&ShrinePassword::default()).unwrap()
-        };
+        // the file changed
+        let changed = encrypted_shrine("changed");
+        let uuid = changed.uuid();
+        state.set_password(uuid, ShrinePassword::from("password"));
+        provider.replace(changed);
+        assert_eq!(get_value(&state).await, Ok("changed".to_string()));
+        assert_eq!(state.metrics.lock().unwrap().cache_misses, 2);
+
+        // locking the shrine evicts it
+        state.delete_password(uuid);
+        assert!(state.shrines.lock().unwrap().is_empty());
+        assert_eq!(get_value(&state).await, Err(StatusCode::UNAUTHORIZED));
+    }
 
-        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
+    #[tokio::test]
+    async fn shrine_cache_expired_password() {
+        let (tx, _) = channel::<()>();
+        let shrine = encrypted_shrine("value");
+        let uuid = shrine.uuid();
+        let provider = MockShrineProvider::new(shrine);
+        let state = AgentState::new(provider.clone(), tx);
+        state.set_password(uuid, ShrinePassword::from("password"));
+        assert_eq!(get_value(&state).await, Ok("value".to_string()));
 
-        super::put_key(
-            state.clone(),
-            Path((String::default(), "key".to_string())),
+        // the password expired before the cleanup job ran
+        {
+            let mut passwords = state.passwords.lock().unwrap();
+            let entry = passwords.get_mut(&uuid).unwrap();
+            entry.unlocked_at -= chrono::Duration::hours(1);
+            entry.used_at -= chrono::Duration::hours(1);
+        }
+
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        {
+            let state = state.clone();
+            let stamp = provider.stamp("fake_path").unwrap();
+            std::thread::spawn(move || {
+                done_tx
+                    .send(state.cached_shrine("fake_path", stamp).is_none())
+                    .unwrap()
+            });
+        }
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+        assert!(state.shrines.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shrine_cache_after_write() {
+        let (tx, _) = channel::<()>();
+        let shrine = encrypted_shrine("value");
+        let uuid = shrine.uuid();
+        let provider = MockShrineProvider::new(shrine);
+        let state = AgentState::new(provider.clone(), tx);
+        state.set_password(uuid, ShrinePassword::from("password"));
+
+        let response = super::put_key(
+            State(state.clone()),
+            Path(("fake_path".to_string(), "key".to_string())),
+            None,
             Json(SetSecretRequest {
-                secret: SecretBytes::from("secret"),
+                secret: SecretBytes::from("new"),
                 mode: Mode::Text,
             }),
         )
         .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
 
-        let value = super::get_key(state, Path((String::default(), "key".to_string()))).await;
-
-        let secret: Secret =
-            serde_json::from_slice(value.into_body().data().await.unwrap().unwrap().as_ref())
-                .unwrap();
-
-        assert_eq!(secret.value().expose_secret_as_bytes(), "secret".as_bytes())
+        // the written shrine is cached and the file can be opened with the password
+        assert_eq!(get_value(&state).await, Ok("new".to_string()));
+        assert_eq!(state.metrics.lock().unwrap().cache_hits, 1);
+        let shrine = provider
+            .load_from_path("fake_path")
+            .unwrap()
+            .open(&ShrinePassword::from("password"))
+            .unwrap();
+        assert_eq!(
+            shrine.get("key").unwrap().value().expose_secret_as_bytes(),
+            b"new"
+        );
     }
 
     #[tokio::test]
-    async fn delete_key() {
-        let (tx, _) = channel::<()>();
-
-        let shrine = {
+    async fn route_allowed_executables() {
+        let uuid = Uuid::new_v4();
+        let request = |executable: &str| {
+            let (tx, _) = channel::<()>();
             let mut shrine = ShrineBuilder::new()
                 .with_encryption_algorithm(EncryptionAlgorithm::Plain)
                 .build();
             shrine.set("key", "value", Mode::Text).unwrap();
-            shrine.close(&ShrinePassword::default()).unwrap()
+            let state = AgentState::new(
+                MockShrineProvider::new(shrine.close(&ShrinePassword::default()).unwrap()),
+                tx,
+            )
+            .with_config(agent_config(
+                "agent.allowed-executables=/usr/bin/shrine, /opt/deploy",
+            ));
+            state.set_password(uuid, ShrinePassword::from("password"));
+
+            router(state).layer(MockConnectInfo(PeerInfo {
+                pid: Some(1234),
+                executable: Some(PathBuf::from(executable)),
+                address: None,
+            }))
         };
 
-        let state = State(AgentState::new(MockShrineProvider::new(shrine), tx));
-        super::delete_key(state.clone(), Path((String::default(), "key".to_string()))).await;
+        for executable in ["/usr/bin/shrine", "/opt/deploy"] {
+            let response = request(executable)
+                .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", executable);
+        }
+
+        for uri in [
+            "/keys/file/key".to_string(),
+            "/keys/file".to_string(),
+            "/otp/file/key".to_string(),
+        ] {
+            let response = request("/usr/bin/cat")
+                .oneshot(Request::get(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", uri);
+            let error: ErrorResponse = serde_json::from_slice(
+                response.into_body().data().await.unwrap().unwrap().as_ref(),
+            )
+            .unwrap();
+            assert!(matches!(
+                error,
+                ErrorResponse::ExecutableNotAllowed(e) if e == "/usr/bin/cat"
+            ));
+        }
+
+        let response = request("/usr/bin/cat")
+            .oneshot(Request::get("/pid").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn config_listen() {
+        let config = agent_config(
+            "agent.listen=127.0.0.1:7878\n\
+            agent.tls-cert=/etc/shrine/cert.pem\n\
+            agent.tls-key=/etc/shrine/key.pem",
+        );
+        assert_eq!(
+            config.listen(),
+            Some(&Listen {
+                address: "127.0.0.1:7878".parse().unwrap(),
+                tls_cert: PathBuf::from("/etc/shrine/cert.pem"),
+                tls_key: PathBuf::from("/etc/shrine/key.pem"),
+            })
+        );
 
-        let value = super::get_key(state, Path((String::default(), "key".to_string()))).await;
+        let err =
+            AgentConfig::from_settings(&Settings::parse("agent.listen=localhost")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid value `localhost` for setting `agent.listen`"
+        );
 
-        assert_eq!(value.status(), StatusCode::NOT_FOUND);
+        let err = AgentConfig::from_settings(&Settings::parse("agent.listen=127.0.0.1:7878"))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid TLS configuration: `agent.listen` requires `agent.tls-cert`"
+        );
     }
 
     #[tokio::test]
-    async fn route_get_pid() {
+    async fn route_bearer_token() {
+        let folder = tempfile::tempdir().unwrap();
+        let audit_log = folder.path().join("audit.log");
+        let tokens_path = folder.path().join("agent-tokens.json");
+        let mut tokens = Tokens::load(&tokens_path).unwrap();
+        let token = tokens.create("ci", Scope::default(), None).unwrap();
+        tokens.save().unwrap();
+
         let (tx, _) = channel::<()>();
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("key", "value", Mode::Text).unwrap();
+        let mut config = agent_config(&format!("agent.audit.path={}", audit_log.display()));
+        config.tokens = Some(tokens_path);
         let state = AgentState::new(
-            MockShrineProvider::new(Shrine::default().close(&ShrinePassword::default()).unwrap()),
+            MockShrineProvider::new(shrine.close(&ShrinePassword::default()).unwrap()),
             tx,
-        );
+        )
+        .with_config(config);
+        let request = |uri: &str, authorization: Option<&str>| {
+            let mut request = Request::get(uri);
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            tcp_router(state.clone())
+                .layer(MockConnectInfo(PeerInfo {
+                    address: Some("127.0.0.1:40000".parse().unwrap()),
+                    ..PeerInfo::default()
+                }))
+                .oneshot(request.body(Body::empty()).unwrap())
+        };
 
-        let response = router()
-            .with_state(state)
-            .oneshot(Request::get("/pid").body(Body::empty()).unwrap())
-            .await
+        for authorization in [None, Some("Bearer invalid"), Some(token.as_str())] {
+            let response = request("/keys/file/key", authorization).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{:?}",
+                authorization
+            );
+            let error: ErrorResponse = serde_json::from_slice(
+                response.into_body().data().await.unwrap().unwrap().as_ref(),
+            )
             .unwrap();
+            assert!(matches!(error, ErrorResponse::InvalidToken));
+        }
 
+        let response = request("/pid", None).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-    }
-
-    #[tokio::test]
-    async fn route_get_key_requires_password() {
-        let (tx, _) = channel::<()>();
-        let shrine = ShrineBuilder::new()
-            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
-            .build()
-            .close(&ShrinePassword::default())
-            .unwrap();
-        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let response = router()
-            .with_state(state)
-            .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
+        let response = request("/keys/file/key", Some(&format!("Bearer {}", token)))
             .await
             .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let events = AuditLog::new(audit_log, 1 << 20, 0).read().unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].address.as_deref(), Some("127.0.0.1:40000"));
+        assert_eq!(events[0].token.as_deref(), Some("ci"));
     }
 
     #[tokio::test]
-    async fn route_put_password_then_get_key_then_delete_password() {
+    async fn route_token_scope() {
+        let folder = tempfile::tempdir().unwrap();
+        let tokens_path = folder.path().join("agent-tokens.json");
+        let mut tokens = Tokens::load(&tokens_path).unwrap();
+        let reader = tokens
+            .create(
+                "reader",
+                Scope {
+                    prefixes: vec!["prod/".to_string()],
+                    permissions: vec![Permission::Read, Permission::List],
+                    ..Scope::default()
+                },
+                None,
+            )
+            .unwrap();
+        let elsewhere = tokens
+            .create(
+                "elsewhere",
+                Scope {
+                    shrines: vec!["/elsewhere".to_string()],
+                    ..Scope::default()
+                },
+                None,
+            )
+            .unwrap();
+        tokens.save().unwrap();
+
         let (tx, _) = channel::<()>();
         let mut shrine = ShrineBuilder::new()
             .with_encryption_algorithm(EncryptionAlgorithm::Aes)
             .build();
-        shrine.set("key", "value", Mode::Text).unwrap();
+        shrine.set("prod/db", "prod", Mode::Text).unwrap();
+        shrine.set("dev/db", "dev", Mode::Text).unwrap();
+        let uuid = shrine.uuid();
         let shrine = shrine.close(&ShrinePassword::from("password")).unwrap();
+        let config = AgentConfig {
+            tokens: Some(tokens_path),
+            ..AgentConfig::default()
+        };
+        let state =
+            AgentState::new(MockShrineProvider::new(shrine.clone()), tx).with_config(config);
+        let request = |token: Option<&str>, request: axum::http::request::Builder| {
+            let request = match token {
+                None => request,
+                Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            };
+            router(state.clone())
+                .layer(MockConnectInfo(PeerInfo::default()))
+                .oneshot(request.body(Body::empty()).unwrap())
+        };
+        let out_of_scope = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            match serde_json::from_slice::<ErrorResponse>(
+                response.into_body().data().await.unwrap().unwrap().as_ref(),
+            )
+            .unwrap()
+            {
+                ErrorResponse::OutOfScope(reason) => reason,
+                e => panic!("unexpected {:?}", e),
+            }
+        };
 
-        let uuid = shrine.uuid();
-        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
+        // the shrine is checked before its password is required
+        let response = request(Some(&elsewhere), Request::get("/keys/file/prod%2Fdb"))
+            .await
+            .unwrap();
+        assert_eq!(
+            out_of_scope(response).await,
+            format!("access to shrine {}", uuid)
+        );
+
+        state.shrine_provider.replace(shrine);
+        state.set_password(uuid, ShrinePassword::from("password"));
+
+        let response = request(Some(&reader), Request::get("/keys/file/prod%2Fdb"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = request(Some(&reader), Request::get("/keys/file/dev%2Fdb"))
+            .await
+            .unwrap();
+        assert_eq!(out_of_scope(response).await, "access to `dev/db`");
+
+        let response = request(Some(&reader), Request::delete("/keys/file/prod%2Fdb"))
+            .await
+            .unwrap();
+        assert_eq!(out_of_scope(response).await, "`delete` on `prod/db`");
+
+        let response = request(Some(&reader), Request::get("/keys/file"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let keys: Vec<Key> =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+        assert_eq!(
+            keys.into_iter().map(|k| k.key).collect::<Vec<_>>(),
+            vec!["prod/db".to_string()]
+        );
+
+        let response = router(state.clone())
+            .layer(MockConnectInfo(PeerInfo::default()))
+            .oneshot(
+                Request::post("/batch/file")
+                    .header(AUTHORIZATION, format!("Bearer {}", reader))
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        serde_json::to_string(&KeySelection::Keys(vec![
+                            "dev/db".to_string(),
+                            "prod/db".to_string(),
+                        ]))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let secrets: Vec<(String, Result<Secret, ErrorResponse>)> =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+        assert!(matches!(
+            &secrets[0],
+            (key, Err(ErrorResponse::OutOfScope(reason)))
+                if key == "dev/db" && reason == "access to `dev/db`"
+        ));
+        assert!(matches!(&secrets[1], (key, Ok(_)) if key == "prod/db"));
+
+        let response = request(Some(&reader), Request::get("/config/file"))
+            .await
+            .unwrap();
+        assert_eq!(out_of_scope(response).await, "this request");
+
+        for uri in ["/passwords", "/metrics"] {
+            let response = request(Some(&reader), Request::get(uri)).await.unwrap();
+            assert_eq!(out_of_scope(response).await, "this request");
+        }
 
-        let response = router()
-            .with_state(state.clone())
+        // a password cannot be replaced, whichever shrine it belongs to
+        let response = router(state.clone())
+            .layer(MockConnectInfo(PeerInfo::default()))
             .oneshot(
                 Request::put("/passwords")
+                    .header(AUTHORIZATION, format!("Bearer {}", reader))
                     .header("content-type", "application/json")
                     .body(Body::from(
                         serde_json::to_string(&SetPasswordRequest {
                             uuid,
-                            password: ShrinePassword::from("password"),
+                            password: ShrinePassword::from("poisoned"),
                         })
                         .unwrap(),
                     ))
@@ -743,28 +3030,213 @@ mod tests {
             )
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(out_of_scope(response).await, "this request");
 
-        let response = router()
-            .with_state(state.clone())
-            .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
+        let response = request(Some("invalid"), Request::get("/keys/file/prod%2Fdb"))
             .await
             .unwrap();
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        let response = router()
-            .with_state(state.clone())
-            .oneshot(Request::delete("/passwords").body(Body::empty()).unwrap())
+        // the socket does not require a token
+        let response = request(None, Request::get("/keys/file/dev%2Fdb"))
             .await
             .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+    }
 
-        let response = router()
-            .with_state(state)
-            .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
-            .await
-            .unwrap();
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    #[test]
+    fn audit_concurrent_appends() {
+        let folder = tempfile::tempdir().unwrap();
+        let audit_log = folder.path().join("audit.log");
+
+        let (tx, _) = channel::<()>();
+        let state = AgentState::new(MockShrineProvider::new(encrypted_shrine("value")), tx)
+            .with_config(agent_config(&format!(
+                "agent.audit.path={}\nagent.audit.max-size=1024\nagent.audit.keep=1000",
+                audit_log.display()
+            )));
+
+        let threads = (0..8)
+            .map(|thread| {
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    for event in 0..50 {
+                        state.audit(AuditEvent {
+                            timestamp: Utc::now(),
+                            operation: AuditOperation::Read,
+                            uuid: None,
+                            path: None,
+                            key: Some(format!("{}/{}", thread, event)),
+                            pid: None,
+                            executable: None,
+                            address: None,
+                            token: None,
+                            result: AuditResult::Ok,
+                        });
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let events = AuditLog::new(audit_log, 1024, 1000).read().unwrap();
+        assert_eq!(events.len(), 8 * 50);
+        for thread in 0..8 {
+            let keys = events
+                .iter()
+                .filter_map(|e| e.key.as_deref())
+                .filter(|k| k.starts_with(&format!("{}/", thread)))
+                .collect::<Vec<_>>();
+            let expected = (0..50)
+                .map(|event| format!("{}/{}", thread, event))
+                .collect::<Vec<_>>();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn route_audit() {
+        let folder = tempfile::tempdir().unwrap();
+        let audit_log = folder.path().join("audit.log");
+
+        let (tx, _) = channel::<()>();
+        let shrine = encrypted_shrine("value");
+        let uuid = shrine.uuid();
+        let state = AgentState::new(MockShrineProvider::new(shrine), tx).with_config(agent_config(
+            &format!(
+                "agent.audit.path={}\nagent.allowed-executables=/usr/bin/shrine",
+                audit_log.display()
+            ),
+        ));
+        let request = |executable: &str, request: Request<Body>| {
+            router(state.clone())
+                .layer(MockConnectInfo(PeerInfo {
+                    pid: Some(1234),
+                    executable: Some(PathBuf::from(executable)),
+                    address: None,
+                }))
+                .oneshot(request)
+        };
+
+        request(
+            "/usr/bin/shrine",
+            Request::get("/pid").body(Body::empty()).unwrap(),
+        )
+        .await
+        .unwrap();
+        request(
+            "/usr/bin/shrine",
+            Request::put("/passwords")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&SetPasswordRequest {
+                        uuid,
+                        password: ShrinePassword::from("password"),
+                    })
+                    .unwrap(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        request(
+            "/usr/bin/shrine",
+            Request::get("/keys/file/key").body(Body::empty()).unwrap(),
+        )
+        .await
+        .unwrap();
+        request(
+            "/usr/bin/cat",
+            Request::get("/keys/file/key").body(Body::empty()).unwrap(),
+        )
+        .await
+        .unwrap();
+        request(
+            "/usr/bin/shrine",
+            Request::post("/batch/file")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&KeySelection::Keys(vec![
+                        "key".to_string(),
+                        "missing".to_string(),
+                    ]))
+                    .unwrap(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        request(
+            "/usr/bin/shrine",
+            Request::delete(format!("/passwords/{}", uuid))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let events = AuditLog::new(audit_log, u64::MAX, 0).read().unwrap();
+        let events = events
+            .iter()
+            .map(|e| {
+                (
+                    e.operation,
+                    e.result,
+                    e.uuid,
+                    e.key.as_deref(),
+                    e.executable.as_deref(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    AuditOperation::Unlock,
+                    AuditResult::Ok,
+                    Some(uuid),
+                    None,
+                    Some("/usr/bin/shrine")
+                ),
+                (
+                    AuditOperation::Read,
+                    AuditResult::Ok,
+                    Some(uuid),
+                    Some("key"),
+                    Some("/usr/bin/shrine")
+                ),
+                (
+                    AuditOperation::Read,
+                    AuditResult::Denied,
+                    Some(uuid),
+                    Some("key"),
+                    Some("/usr/bin/cat")
+                ),
+                (
+                    AuditOperation::Read,
+                    AuditResult::Ok,
+                    Some(uuid),
+                    Some("key"),
+                    Some("/usr/bin/shrine")
+                ),
+                (
+                    AuditOperation::Read,
+                    AuditResult::NotFound,
+                    Some(uuid),
+                    Some("missing"),
+                    Some("/usr/bin/shrine")
+                ),
+                (
+                    AuditOperation::Lock,
+                    AuditResult::Ok,
+                    Some(uuid),
+                    None,
+                    Some("/usr/bin/shrine")
+                ),
+            ]
+        );
     }
 
     #[tokio::test]
@@ -777,8 +3249,7 @@ mod tests {
             .unwrap();
         let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let response = router()
-            .with_state(state)
+        let response = router(state)
             .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
             .await
             .unwrap();
@@ -796,18 +3267,16 @@ mod tests {
         let shrine = shrine.close(&ShrinePassword::default()).unwrap();
         let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let response = router()
-            .with_state(state)
+        let response = router(state)
             .oneshot(Request::get("/keys/file/key").body(Body::empty()).unwrap())
             .await
             .unwrap();
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        let secret = serde_json::from_slice::<Secret>(
-            &response.into_body().data().await.unwrap().unwrap().to_vec(),
-        )
-        .unwrap();
+        let secret =
+            serde_json::from_slice::<Secret>(&response.into_body().data().await.unwrap().unwrap())
+                .unwrap();
 
         assert_eq!(secret.value().expose_secret_as_bytes(), "value".as_bytes());
     }
@@ -822,8 +3291,7 @@ mod tests {
         let shrine = shrine.close(&ShrinePassword::default()).unwrap();
         let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let response = router()
-            .with_state(state)
+        let response = router(state)
             .oneshot(Request::get("/keys/file").body(Body::empty()).unwrap())
             .await
             .unwrap();
@@ -831,13 +3299,13 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
 
         let keys = serde_json::from_slice::<Vec<Key>>(
-            &response.into_body().data().await.unwrap().unwrap().to_vec(),
+            &response.into_body().data().await.unwrap().unwrap(),
         )
         .unwrap();
 
         assert_eq!(keys.len(), 1);
-        assert_eq!(keys.get(0).unwrap().key, "key");
-        assert_eq!(keys.get(0).unwrap().mode, Mode::Text);
+        assert_eq!(keys.first().unwrap().key, "key");
+        assert_eq!(keys.first().unwrap().mode, Mode::Text);
     }
 
     #[tokio::test]
@@ -850,8 +3318,7 @@ mod tests {
         let shrine = shrine.close(&ShrinePassword::default()).unwrap();
         let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let response = router()
-            .with_state(state.clone())
+        let response = router(state.clone())
             .oneshot(
                 Request::delete("/keys/file/key")
                     .body(Body::empty())
@@ -883,8 +3350,7 @@ mod tests {
             .unwrap();
         let state = AgentState::new(MockShrineProvider::new(shrine), tx);
 
-        let response = router()
-            .with_state(state.clone())
+        let response = router(state.clone())
             .oneshot(
                 Request::put("/keys/file/key")
                     .header("content-type", "application/json")
@@ -912,4 +3378,282 @@ mod tests {
 
         assert_eq!(secret.value().expose_secret_as_bytes(), "value".as_bytes());
     }
+
+    #[tokio::test]
+    async fn route_import_config_info() {
+        let (tx, _) = channel::<()>();
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("key", "value", Mode::Text).unwrap();
+        let uuid = shrine.uuid();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
+        let request = |request: Request<Body>| router(state.clone()).oneshot(request);
+        fn json<T: serde::Serialize>(body: &T) -> Body {
+            Body::from(serde_json::to_string(body).unwrap())
+        }
+
+        let response = request(
+            Request::put("/keys/file")
+                .header("content-type", "application/json")
+                .body(json(&SetSecretsRequest {
+                    secrets: vec![
+                        (
+                            "env/a".to_string(),
+                            SetSecretRequest {
+                                secret: SecretBytes::from("1"),
+                                mode: Mode::Text,
+                            },
+                        ),
+                        (
+                            "env/b".to_string(),
+                            SetSecretRequest {
+                                secret: SecretBytes::from("2"),
+                                mode: Mode::Text,
+                            },
+                        ),
+                    ],
+                }))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = request(
+            Request::put("/config/file/custom")
+                .header("content-type", "application/json")
+                .body(json(&SetConfigRequest {
+                    value: "value".to_string(),
+                }))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = request(Request::get("/config/file").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let config: Vec<(String, String)> =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+        assert!(config.contains(&("custom".to_string(), "value".to_string())));
+
+        let response = request(Request::get("/info/file").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let info: Info =
+            serde_json::from_slice(response.into_body().data().await.unwrap().unwrap().as_ref())
+                .unwrap();
+        assert_eq!(info.uuid, uuid);
+        assert_eq!(info.encryption_algorithm, "Not encrypted");
+    }
+
+    #[tokio::test]
+    async fn route_key_named_batch() {
+        let (tx, _) = channel::<()>();
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("batch", "1", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
+        let request = |request: Request<Body>| {
+            router(state.clone())
+                .layer(MockConnectInfo(PeerInfo::default()))
+                .oneshot(request)
+        };
+
+        let response = request(
+            Request::get("/keys/file/batch")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = request(
+            Request::put("/keys/file/batch")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::to_string(&SetSecretRequest {
+                        secret: SecretBytes::from("2"),
+                        mode: Mode::Text,
+                    })
+                    .unwrap(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = request(
+            Request::delete("/keys/file/batch")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn route_get_keys_batch() {
+        let (tx, _) = channel::<()>();
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("env/b", "2", Mode::Text).unwrap();
+        shrine.set("env/a", "1", Mode::Text).unwrap();
+        shrine.set("other", "3", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+        let state = AgentState::new(MockShrineProvider::new(shrine), tx);
+        let request = |keys: KeySelection| {
+            router(state.clone()).oneshot(
+                Request::post("/batch/file")
+                    .header("content-type", "application/json")
+                    .body(Body::from(serde_json::to_string(&keys).unwrap()))
+                    .unwrap(),
+            )
+        };
+        let secrets = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice::<Vec<(String, Result<Secret, ErrorResponse>)>>(
+                response.into_body().data().await.unwrap().unwrap().as_ref(),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|(k, s)| {
+                let value = match s {
+                    Ok(s) => {
+                        String::from_utf8_lossy(s.value().expose_secret_as_bytes()).to_string()
+                    }
+                    Err(e) => format!("{:?}", e),
+                };
+                (k, value)
+            })
+            .collect::<Vec<(String, String)>>()
+        };
+
+        let response = request(KeySelection::Pattern(Some("^env/".to_string())))
+            .await
+            .unwrap();
+        assert_eq!(
+            secrets(response).await,
+            vec![
+                ("env/a".to_string(), "1".to_string()),
+                ("env/b".to_string(), "2".to_string())
+            ]
+        );
+
+        let response = request(KeySelection::Keys(vec![
+            "other".to_string(),
+            "missing".to_string(),
+        ]))
+        .await
+        .unwrap();
+        assert_eq!(
+            secrets(response).await,
+            vec![
+                (
+                    "missing".to_string(),
+                    r#"KeyNotFound { file: "file", key: "missing" }"#.to_string()
+                ),
+                ("other".to_string(), "3".to_string())
+            ]
+        );
+
+        let response = request(KeySelection::Pattern(Some("(".to_string())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn route_watch() {
+        let (tx, _) = channel::<()>();
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("env/a", "1", Mode::Text).unwrap();
+        shrine.set("other", "2", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+        let provider = MockShrineProvider::new(shrine);
+        let state = AgentState::new(provider.clone(), tx);
+
+        let mut response = router(state.clone())
+            .oneshot(
+                Request::get("/watch/file?regexp=%5Eenv%2F")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        async fn next_event(response: &mut Response) -> Option<String> {
+            let mut event = String::new();
+            while !event.ends_with("\n\n") || event.starts_with(':') {
+                if event.starts_with(':') {
+                    event.clear();
+                }
+                let data = tokio::time::timeout(Duration::from_secs(5), response.data())
+                    .await
+                    .expect("an event was expected");
+                match data {
+                    Some(data) => event.push_str(&String::from_utf8_lossy(&data.unwrap())),
+                    None => return None,
+                }
+            }
+            Some(event)
+        }
+
+        // changed through the agent
+        for (key, value) in [("other", "3"), ("env/b", "4")] {
+            let response = router(state.clone())
+                .oneshot(
+                    Request::put(format!("/keys/file/{}", urlencoding::encode(key)))
+                        .header("content-type", "application/json")
+                        .body(Body::from(
+                            serde_json::to_string(&SetSecretRequest {
+                                secret: SecretBytes::from(value),
+                                mode: Mode::Text,
+                            })
+                            .unwrap(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        }
+        assert_eq!(
+            next_event(&mut response).await.unwrap(),
+            "event:change\ndata:[{\"key\":\"env/b\",\"kind\":\"set\"}]\n\n"
+        );
+
+        // changed on disk
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("env/b", "5", Mode::Text).unwrap();
+        provider.replace(shrine.close(&ShrinePassword::default()).unwrap());
+        assert_eq!(
+            next_event(&mut response).await.unwrap(),
+            concat!(
+                "event:change\ndata:[",
+                "{\"key\":\"env/a\",\"kind\":\"deleted\"},",
+                "{\"key\":\"env/b\",\"kind\":\"set\"}",
+                "]\n\n"
+            )
+        );
+
+        state.stop_watchers();
+        assert_eq!(next_event(&mut response).await, None);
+    }
 }
@@ -0,0 +1,180 @@
+use crate::Error;
+use hyper::server::conn::AddrIncoming;
+use hyper_rustls::TlsAcceptor;
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Where the agent listens for TCP connections, and its TLS certificate and private key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listen {
+    pub address: SocketAddr,
+    pub tls_cert: PathBuf,
+    pub tls_key: PathBuf,
+}
+
+/// The agent's TCP listener, bound before the agent daemonizes so that errors are reported to the
+/// user.
+pub struct TlsListener {
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+}
+
+impl TlsListener {
+    pub fn bind(listen: &Listen) -> Result<Self, Error> {
+        let config = server_config(&listen.tls_cert, &listen.tls_key)?;
+        let listener = TcpListener::bind(listen.address)
+            .and_then(|l| l.set_nonblocking(true).map(|_| l))
+            .map_err(|e| Error::Agent(format!("could not listen on {}: {}", listen.address, e)))?;
+        Ok(Self {
+            listener,
+            config: Arc::new(config),
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Accepts TLS connections; must be called within a tokio runtime.
+    pub fn into_acceptor(self) -> std::io::Result<TlsAcceptor> {
+        let listener = tokio::net::TcpListener::from_std(self.listener)?;
+        let incoming = AddrIncoming::from_listener(listener).map_err(std::io::Error::other)?;
+        Ok(TlsAcceptor::new(self.config, incoming))
+    }
+}
+
+fn server_config(cert: &Path, key: &Path) -> Result<ServerConfig, Error> {
+    let certificates = certificates(cert)?;
+    let key = read_pem(key, rustls_pemfile::read_all)?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| Error::Tls(format!("no private key in `{}`", key.display())))?;
+
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|e| Error::Tls(e.to_string()))
+}
+
+/// The TLS configuration of the agent's clients, trusting the certificates of `ca` if set, and
+/// the system's ones otherwise.
+pub fn client_config(ca: Option<&Path>) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore::empty();
+    match ca {
+        Some(ca) => {
+            for certificate in certificates(ca)? {
+                roots
+                    .add(&certificate)
+                    .map_err(|e| Error::Tls(e.to_string()))?;
+            }
+        }
+        None => {
+            for certificate in rustls_native_certs::load_native_certs().unwrap_or_default() {
+                let _ = roots.add(&Certificate(certificate.0));
+            }
+        }
+    }
+
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let certificates = read_pem(path, rustls_pemfile::certs)?;
+    if certificates.is_empty() {
+        return Err(Error::Tls(format!(
+            "no certificate in `{}`",
+            path.display()
+        )));
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+fn read_pem<T>(
+    path: &Path,
+    read: fn(&mut dyn std::io::BufRead) -> std::io::Result<Vec<T>>,
+) -> Result<Vec<T>, Error> {
+    let file = File::open(path).map_err(|e| Error::ReadFile(path.to_path_buf(), e))?;
+    read(&mut BufReader::new(file)).map_err(|e| Error::ReadFile(path.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::{Client, HttpClient, TcpClient};
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::{Json, Router};
+    use hyper::Server;
+    use std::fs;
+
+    #[test]
+    fn serve_over_tls() {
+        let folder = tempfile::tempdir().unwrap();
+        let certificate =
+            rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let listen = Listen {
+            address: "127.0.0.1:0".parse().unwrap(),
+            tls_cert: folder.path().join("cert.pem"),
+            tls_key: folder.path().join("key.pem"),
+        };
+
+        assert!(matches!(
+            TlsListener::bind(&listen),
+            Err(Error::ReadFile(path, _)) if path == listen.tls_cert
+        ));
+
+        fs::write(&listen.tls_cert, certificate.serialize_pem().unwrap()).unwrap();
+        fs::write(&listen.tls_key, certificate.serialize_private_key_pem()).unwrap();
+
+        let listener = TlsListener::bind(&listen).unwrap();
+        let url = format!("https://{}", listener.local_addr().unwrap());
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let server = std::thread::spawn(move || {
+            tokio::runtime::Runtime::new().unwrap().block_on(async {
+                let router = Router::new().route(
+                    "/pid",
+                    get(|headers: HeaderMap| async move {
+                        match headers.get(AUTHORIZATION) {
+                            Some(value) if value == "Bearer token" => Json(42).into_response(),
+                            _ => StatusCode::UNAUTHORIZED.into_response(),
+                        }
+                    }),
+                );
+                Server::builder(listener.into_acceptor().unwrap())
+                    .serve(router.into_make_service())
+                    .with_graceful_shutdown(async {
+                        rx.await.ok();
+                    })
+                    .await
+                    .unwrap();
+            })
+        });
+
+        let client = HttpClient::<TcpClient>::new(url.clone())
+            .with_token("token".to_string())
+            .with_ca(&listen.tls_cert)
+            .unwrap();
+        assert_eq!(client.pid(), Some(42));
+
+        let untrusted = HttpClient::<TcpClient>::new(url).with_token("token".to_string());
+        assert_eq!(untrusted.pid(), None);
+
+        tx.send(()).unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+use crate::utils::config_dir;
+use crate::Error;
+use aes_gcm_siv::aead::rand_core::RngCore;
+use aes_gcm_siv::aead::OsRng;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// A bearer token accepted by the agent. Only the token's hash is stored.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub name: String,
+    hash: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Scope::is_unrestricted")]
+    pub scope: Scope,
+}
+
+impl Token {
+    fn is_expired(&self) -> bool {
+        self.expires_at.map(|e| e <= Utc::now()).unwrap_or(false)
+    }
+}
+
+/// What a token gives access to; each empty field does not restrict it.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scope {
+    /// The shrines, by folder path or UUID.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shrines: Vec<String>,
+    /// The prefixes of the keys.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub prefixes: Vec<String>,
+    /// A pattern the keys match, as an alternative to `prefixes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<KeyPattern>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub permissions: Vec<Permission>,
+}
+
+impl Scope {
+    pub fn is_unrestricted(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn allows_shrine(&self, path: &str, uuid: Uuid) -> bool {
+        self.shrines.is_empty()
+            || self
+                .shrines
+                .iter()
+                .any(|s| Path::new(s) == Path::new(path) || Uuid::from_str(s).ok() == Some(uuid))
+    }
+
+    pub fn allows_key(&self, key: &str) -> bool {
+        if self.prefixes.is_empty() && self.regex.is_none() {
+            return true;
+        }
+        self.prefixes.iter().any(|p| key.starts_with(p))
+            || self
+                .regex
+                .as_ref()
+                .map(|r| r.0.is_match(key))
+                .unwrap_or(false)
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.permissions.is_empty() || self.permissions.contains(&permission)
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_unrestricted() {
+            return write!(f, "unrestricted");
+        }
+        let mut parts = Vec::new();
+        if !self.shrines.is_empty() {
+            parts.push(format!("shrines {}", self.shrines.join(", ")));
+        }
+        if !self.prefixes.is_empty() {
+            parts.push(format!("prefixes {}", self.prefixes.join(", ")));
+        }
+        if let Some(regex) = &self.regex {
+            parts.push(format!("regex {}", regex));
+        }
+        if !self.permissions.is_empty() {
+            parts.push(format!(
+                "allows {}",
+                self.permissions
+                    .iter()
+                    .map(Permission::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+/// The pattern of a [`Scope`], compiled when the token is created or loaded; a tokens file with an
+/// invalid pattern is rejected.
+#[derive(Debug, Clone)]
+pub struct KeyPattern(Regex);
+
+impl FromStr for KeyPattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s).map(Self)
+    }
+}
+
+impl Display for KeyPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}
+
+impl PartialEq for KeyPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for KeyPattern {}
+
+impl Serialize for KeyPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyPattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// An operation on secrets a token may be limited to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,
+    Write,
+    Delete,
+    List,
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Permission::Read),
+            "write" => Ok(Permission::Write),
+            "delete" => Ok(Permission::Delete),
+            "list" => Ok(Permission::List),
+            _ => Err(format!(
+                "invalid permission `{}`: expected read, write, delete or list",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::Read => write!(f, "read"),
+            Permission::Write => write!(f, "write"),
+            Permission::Delete => write!(f, "delete"),
+            Permission::List => write!(f, "list"),
+        }
+    }
+}
+
+/// The tokens issued by `shrine agent token create`, stored in `agent-tokens.json` next to the
+/// settings.
+#[derive(Debug, Default)]
+pub struct Tokens {
+    path: PathBuf,
+    tokens: Vec<Token>,
+}
+
+impl Tokens {
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("agent-tokens.json"))
+    }
+
+    /// Loads the tokens from `path`; a missing file contains no tokens.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let tokens = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(Error::JsonRead)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(Error::ReadFile(path, e)),
+        };
+        Ok(Self { path, tokens })
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let write_error = |e| Error::WriteFile(self.path.clone(), e);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).map_err(write_error)?;
+        }
+        let json = serde_json::to_vec_pretty(&self.tokens).map_err(Error::JsonWrite)?;
+        OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(&json))
+            .map_err(write_error)
+    }
+
+    pub fn list(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Issues a new token named `name` and returns its value, which is not stored.
+    pub fn create(
+        &mut self,
+        name: &str,
+        scope: Scope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<String, Error> {
+        if self.tokens.iter().any(|t| t.name == name) {
+            return Err(Error::TokenAlreadyExists(name.to_string()));
+        }
+
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let value = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+        self.tokens.push(Token {
+            name: name.to_string(),
+            hash: hash(&value),
+            created_at: Utc::now(),
+            expires_at,
+            scope,
+        });
+        Ok(value)
+    }
+
+    pub fn revoke(&mut self, name: &str) -> Result<(), Error> {
+        let count = self.tokens.len();
+        self.tokens.retain(|t| t.name != name);
+        if self.tokens.len() == count {
+            return Err(Error::TokenNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// The token whose value is `value`, if any and it has not expired.
+    pub fn verify(&self, value: &str) -> Option<&Token> {
+        let hash = hash(value);
+        self.tokens
+            .iter()
+            .find(|t| t.hash == hash)
+            .filter(|t| !t.is_expired())
+    }
+}
+
+fn hash(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn create_verify_revoke() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("shrine").join("agent-tokens.json");
+
+        let mut tokens = Tokens::load(&path).unwrap();
+        assert!(tokens.list().is_empty());
+
+        let ci = tokens.create("ci", Scope::default(), None).unwrap();
+        let vm = tokens.create("vm", Scope::default(), None).unwrap();
+        assert_ne!(ci, vm);
+        assert!(matches!(
+            tokens.create("ci", Scope::default(), None),
+            Err(Error::TokenAlreadyExists(name)) if name == "ci"
+        ));
+        tokens.save().unwrap();
+
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!fs::read_to_string(&path).unwrap().contains(&ci));
+
+        let mut tokens = Tokens::load(&path).unwrap();
+        assert_eq!(tokens.verify(&ci).unwrap().name, "ci");
+        assert_eq!(tokens.verify(&vm).unwrap().name, "vm");
+        assert!(tokens.verify("invalid").is_none());
+
+        tokens.revoke("ci").unwrap();
+        assert!(matches!(
+            tokens.revoke("ci"),
+            Err(Error::TokenNotFound(name)) if name == "ci"
+        ));
+        assert!(tokens.verify(&ci).is_none());
+        assert_eq!(tokens.list().len(), 1);
+
+        let expired = tokens
+            .create(
+                "expired",
+                Scope::default(),
+                Some(Utc::now() - chrono::Duration::seconds(1)),
+            )
+            .unwrap();
+        assert!(tokens.verify(&expired).is_none());
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!("(".parse::<KeyPattern>().is_err());
+
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("agent-tokens.json");
+        fs::write(
+            &path,
+            r#"[{"name": "t", "hash": "", "created_at": "2023-06-20T17:51:11Z", "scope": {"regex": "("}}]"#,
+        )
+        .unwrap();
+        assert!(matches!(Tokens::load(&path), Err(Error::JsonRead(_))));
+    }
+
+    #[test]
+    fn scope() {
+        let uuid = Uuid::new_v4();
+        let scope = Scope {
+            shrines: vec!["/srv/app".to_string(), uuid.to_string()],
+            prefixes: vec!["prod/".to_string()],
+            regex: Some("^shared/[a-z]+$".parse().unwrap()),
+            permissions: vec![Permission::Read, Permission::List],
+        };
+
+        assert!(scope.allows_shrine("/srv/app", Uuid::new_v4()));
+        assert!(scope.allows_shrine("/home/user", uuid));
+        assert!(!scope.allows_shrine("/home/user", Uuid::new_v4()));
+
+        assert!(scope.allows_key("prod/db"));
+        assert!(scope.allows_key("shared/db"));
+        assert!(!scope.allows_key("shared/db/password"));
+        assert!(!scope.allows_key("dev/db"));
+
+        assert!(scope.allows(Permission::Read));
+        assert!(!scope.allows(Permission::Write));
+
+        assert_eq!(
+            scope.to_string(),
+            format!(
+                "shrines /srv/app, {}; prefixes prod/; regex ^shared/[a-z]+$; allows read, list",
+                uuid
+            )
+        );
+
+        let scope = Scope::default();
+        assert!(scope.is_unrestricted());
+        assert!(scope.allows_shrine("/srv/app", uuid));
+        assert!(scope.allows_key("dev/db"));
+        assert!(scope.allows(Permission::Delete));
+    }
+}
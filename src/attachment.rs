@@ -0,0 +1,265 @@
+use crate::bytes::SecretBytes;
+use crate::Error;
+use bytes::{BufMut, BytesMut};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::Path;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The metadata of a file stored in a secret.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Attachment {
+    pub filename: String,
+    pub size: u64,
+    pub mime: String,
+    /// The hex encoded SHA-256 digest of the content.
+    pub sha256: String,
+}
+
+impl Attachment {
+    /// Reads a file, computing its metadata while reading. The content is read directly into a
+    /// buffer of the file's size so that no other copy of it is left in memory.
+    pub fn read(path: &Path) -> Result<(SecretBytes, Self), Error> {
+        let error = |e| Error::ReadFile(path.to_path_buf(), e);
+
+        let mut file = File::open(path).map_err(error)?;
+        let size = file.metadata().map_err(error)?.len();
+
+        let mut content = BytesMut::with_capacity(size as usize);
+        let mut hasher = Sha256::new();
+        let mut chunk = [0u8; CHUNK_SIZE];
+        loop {
+            let read = file.read(&mut chunk).map_err(error)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&chunk[..read]);
+            content.put_slice(&chunk[..read]);
+        }
+        chunk.fill(0);
+
+        let attachment = Self {
+            filename: path
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            size: content.len() as u64,
+            mime: mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+            sha256: hex(&hasher.finalize()),
+        };
+
+        Ok((SecretBytes::from(content), attachment))
+    }
+
+    /// The recorded filename without its folders, `None` if it does not name a file, e.g. `..`.
+    pub fn file_name(&self) -> Option<&Path> {
+        Path::new(&self.filename).file_name().map(Path::new)
+    }
+
+    /// Checks `content` against the recorded digest, then writes it to `path`, readable and
+    /// writable by the owner only. An existing file is only replaced if `overwrite` is set.
+    pub fn write(&self, content: &[u8], path: &Path, overwrite: bool) -> Result<(), Error> {
+        let error = |e| Error::WriteFile(path.to_path_buf(), e);
+
+        self.verify(content)?;
+
+        let mut options = OpenOptions::new();
+        match overwrite {
+            true => options.write(true).create(true).truncate(true),
+            false => options.write(true).create_new(true),
+        };
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path).map_err(|e| match e.kind() {
+            ErrorKind::AlreadyExists => Error::OutputExists(path.to_path_buf()),
+            _ => error(e),
+        })?;
+        #[cfg(unix)]
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))
+            .map_err(error)?;
+
+        write_chunks(content, &mut file).map_err(error)
+    }
+
+    /// Checks `content` against the recorded digest, then writes it to `out`, chunk by chunk.
+    pub fn write_to<W>(&self, content: &[u8], out: &mut W) -> Result<(), Error>
+    where
+        W: Write,
+    {
+        self.verify(content)?;
+        write_chunks(content, out).map_err(Error::IoWrite)
+    }
+
+    fn verify(&self, content: &[u8]) -> Result<(), Error> {
+        let mut hasher = Sha256::new();
+        for chunk in content.chunks(CHUNK_SIZE) {
+            hasher.update(chunk);
+        }
+
+        if hex(&hasher.finalize()) != self.sha256 {
+            return Err(Error::ChecksumMismatch(self.filename.clone()));
+        }
+        Ok(())
+    }
+
+    /// A one-line description, e.g. `config.yaml (1.2 KiB, application/yaml)`.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} ({}, {})",
+            self.filename,
+            human_size(self.size),
+            self.mime
+        )
+    }
+}
+
+fn write_chunks<W>(content: &[u8], out: &mut W) -> io::Result<()>
+where
+    W: Write,
+{
+    for chunk in content.chunks(CHUNK_SIZE) {
+        out.write_all(chunk)?;
+    }
+    out.flush()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn human_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut size = size as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn read_write() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("config.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let (content, attachment) = Attachment::read(&path).unwrap();
+
+        assert_eq!(content.expose_secret_as_bytes(), b"{}");
+        assert_eq!(
+            attachment,
+            Attachment {
+                filename: "config.json".to_string(),
+                size: 2,
+                mime: "application/json".to_string(),
+                sha256: "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+                    .to_string(),
+            }
+        );
+
+        let target = folder.path().join("extracted");
+        attachment
+            .write(content.expose_secret_as_bytes(), &target, false)
+            .unwrap();
+
+        assert_eq!(std::fs::read(&target).unwrap(), b"{}");
+        assert_eq!(
+            target.metadata().unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn write_existing_file() {
+        let folder = tempfile::tempdir().unwrap();
+        let path = folder.path().join("config.json");
+        std::fs::write(&path, "{}").unwrap();
+        let (content, attachment) = Attachment::read(&path).unwrap();
+
+        let target = folder.path().join("existing");
+        std::fs::write(&target, "previous").unwrap();
+
+        match attachment.write(content.expose_secret_as_bytes(), &target, false) {
+            Err(Error::OutputExists(path)) => assert_eq!(path, target),
+            _ => panic!("expected Err(Error::OutputExists(_))"),
+        }
+        assert_eq!(std::fs::read(&target).unwrap(), b"previous");
+
+        attachment
+            .write(content.expose_secret_as_bytes(), &target, true)
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn file_name() {
+        let attachment = |filename: &str| Attachment {
+            filename: filename.to_string(),
+            size: 0,
+            mime: "application/octet-stream".to_string(),
+            sha256: String::new(),
+        };
+
+        assert_eq!(
+            attachment("../../.bashrc").file_name(),
+            Some(Path::new(".bashrc"))
+        );
+        assert_eq!(
+            attachment("/etc/passwd").file_name(),
+            Some(Path::new("passwd"))
+        );
+        assert_eq!(attachment("..").file_name(), None);
+        assert_eq!(attachment("").file_name(), None);
+    }
+
+    #[test]
+    fn write_checksum_mismatch() {
+        let attachment = Attachment {
+            filename: "file".to_string(),
+            size: 2,
+            mime: "application/octet-stream".to_string(),
+            sha256: "00".to_string(),
+        };
+
+        let mut out = Vec::new();
+        assert_eq!(
+            attachment
+                .write_to(b"{}", &mut out)
+                .unwrap_err()
+                .to_string(),
+            "The content of `file` does not match its SHA-256 checksum"
+        );
+        assert!(out.is_empty());
+
+        let folder = tempfile::tempdir().unwrap();
+        let target = folder.path().join("existing");
+        std::fs::write(&target, "previous").unwrap();
+        assert!(matches!(
+            attachment.write(b"{}", &target, true),
+            Err(Error::ChecksumMismatch(_))
+        ));
+        assert_eq!(std::fs::read(&target).unwrap(), b"previous");
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(12), "12 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
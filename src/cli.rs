@@ -1,23 +1,43 @@
-use clap::{command, Parser, Subcommand, ValueEnum};
-use shrine::agent::client::{HttpClient, SocketClient};
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use shrine::agent::client::{Client, HttpClient, NoClient, SocketClient, TcpClient};
+#[cfg(unix)]
+use shrine::agent::tls::Listen;
+#[cfg(unix)]
+use shrine::agent::token::{KeyPattern, Permission, Scope};
+use shrine::controller::attach::{attach, extract};
+use shrine::controller::certs::certs;
+use shrine::controller::commit::commit;
 use shrine::controller::convert::convert;
 use shrine::controller::dump::dump;
 use shrine::controller::get::get;
 use shrine::controller::import::import;
 use shrine::controller::info::{info, Fields};
 use shrine::controller::init::init;
+use shrine::controller::inspect::inspect;
+use shrine::controller::log::{blame, log};
 use shrine::controller::ls::ls;
+use shrine::controller::merge::{install_merge_driver, merge_driver};
+use shrine::controller::otp::otp;
+use shrine::controller::rewrite::rewrite_history;
 use shrine::controller::rm::rm;
 use shrine::controller::set;
 use shrine::controller::set::set;
+use shrine::controller::sync::sync;
+use shrine::controller::textconv::{install_textconv, textconv};
+use shrine::controller::watch::watch;
+use shrine::controller::Format;
 #[cfg(unix)]
 use shrine::controller::{agent, config, get};
 use shrine::shrine::{EncryptionAlgorithm, FilesystemShrineProvider, Mode, ShrinePassword};
+use shrine::utils::{parse_date, parse_duration};
 use shrine::Error;
 use std::io::stdout;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::ExitCode;
 use std::{env, fs};
+use uuid::Uuid;
 
 #[derive(Clone, Parser)]
 #[command(author, version, about, long_about = None, arg_required_else_help = true)]
@@ -28,6 +48,9 @@ struct Args {
     /// The folder containing the shrine file; default is `SHRINE_PATH` env variable or `.` if not set
     #[arg(short, long)]
     path: Option<PathBuf>,
+    /// Access the shrine file directly, even if the agent is running
+    #[arg(long, global = true)]
+    no_agent: bool,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -40,6 +63,15 @@ enum Commands {
         #[command(subcommand)]
         command: Option<AgentCommands>,
     },
+    /// Caches the shrine's password in the agent
+    #[cfg(unix)]
+    Unlock,
+    /// Evicts a shrine's password from the agent
+    #[cfg(unix)]
+    Lock {
+        /// The UUID of the shrine; default is the current shrine
+        uuid: Option<Uuid>,
+    },
     /// Initializes a shrine in the current folder
     Init {
         /// Override any existing shrine
@@ -81,6 +113,15 @@ enum Commands {
         /// The secret's mode
         #[arg(long, short, default_value = "auto")]
         mode: Modes,
+        /// Set a record's field, as `name=value`; if no value is provided, it will be prompted.
+        /// Can be repeated
+        #[arg(
+            long = "field",
+            short = 'f',
+            value_name = "NAME[=VALUE]",
+            conflicts_with_all = ["stdin", "mode", "value"]
+        )]
+        fields: Vec<String>,
         /// The secret's value; if not set and not read from stdin, will be prompted
         value: Option<String>,
     },
@@ -88,15 +129,74 @@ enum Commands {
     Get {
         /// The secret's key
         key: String,
+        /// Only get the value of this record's field
+        #[arg(long, short)]
+        field: Option<String>,
         /// The output encoding (base64 by defaults for binary secrets)
         #[arg(long, short, default_value = "auto")]
         encoding: Encoding,
+        /// Get the value as committed at this git revision (e.g. `HEAD~2`, a commit hash) or date
+        /// (e.g. `2026-10-01 12:00`)
+        #[arg(long, value_name = "REVISION|DATE")]
+        at: Option<String>,
+    },
+    /// Stores a file along with its filename, size, MIME type and SHA-256
+    Attach {
+        /// The secret's key
+        key: String,
+        /// The file to store
+        file: PathBuf,
+    },
+    /// Restores a file stored with `attach`, readable and writable by the owner only
+    Extract {
+        /// The secret's key
+        key: String,
+        /// Where to write the file, `-` for stdout; default is the original filename in the
+        /// current folder
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Overwrite the output file if it exists
+        #[arg(long, short)]
+        force: bool,
+    },
+    /// Prints the current one-time code of a TOTP secret and the seconds it remains valid
+    Otp {
+        /// The secret's key
+        key: String,
+    },
+    /// Prints a secret's metadata, e.g. a certificate's subject and expiry or a key's fingerprint
+    Inspect {
+        /// The secret's key
+        key: String,
     },
     /// Lists all secrets keys
     Ls {
         /// Only lists the key matching the provided pattern
         #[arg(value_name = "REGEX")]
         pattern: Option<String>,
+        /// Also list the records' fields and the certificates' and keys' metadata
+        #[arg(long, short)]
+        long: bool,
+    },
+    /// Prints the changes of the secrets through the agent, or runs a command again each time
+    /// they change
+    Watch {
+        /// Only watch the keys matching the provided pattern
+        #[arg(value_name = "REGEX")]
+        pattern: Option<String>,
+        /// The command to run, after `--`
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    /// Reports the certificates stored in the shrine and checks their expiry and private keys;
+    /// exits with a non-zero status if any check fails
+    Certs {
+        /// Only check the keys matching the provided pattern
+        #[arg(value_name = "REGEX")]
+        pattern: Option<String>,
+        /// Fail if a certificate expires within this duration (e.g. `30d`, `2w`, `12h`)
+        #[arg(long, short, default_value = "30d", value_parser = |s: &str| parse_duration(s).map_err(|e| e.to_string()))]
+        expiring_within: Duration,
     },
     /// Removes secrets stored in keys matching the provided pattern
     Rm {
@@ -104,22 +204,69 @@ enum Commands {
         #[arg(value_name = "REGEX")]
         key: String,
     },
-    /// Imports secret and their values from environment file
+    /// Imports secret and their values from environment file; `otpauth://` URIs are imported as
+    /// TOTP secrets
     Import {
         /// The file to import
         file: PathBuf,
         /// Prefix keys with value
         #[arg(long, short)]
         prefix: Option<String>,
+        /// The file's format
+        #[arg(long, short, default_value = "env")]
+        format: Formats,
     },
     /// Dumps the secrets in a `key=value` format
     Dump {
         /// Only dump the key matching the provided pattern
         #[arg(value_name = "REGEX")]
         pattern: Option<String>,
-        /// Include configuration keys
+        /// Include configuration keys (env format only)
         #[arg(long, short, default_value = "false")]
         config: bool,
+        /// The output format
+        #[arg(long, short, default_value = "env")]
+        format: Formats,
+        /// Dump the secrets as committed at this git revision (e.g. `HEAD~2`, a commit hash) or
+        /// date (e.g. `2026-10-01 12:00`)
+        #[arg(long, value_name = "REVISION|DATE")]
+        at: Option<String>,
+    },
+    /// Lists the commits that changed the shrine and the keys they added, changed or removed
+    Log {
+        /// Only list the commits that changed this key
+        key: Option<String>,
+    },
+    /// Shows, for each key, the commit that last changed it and its author
+    Blame,
+    /// Commits pending changes, e.g. when `git.commit.auto` is disabled
+    Commit {
+        /// The commit message; by default, the changed keys are listed
+        #[arg(long, short)]
+        message: Option<String>,
+    },
+    /// Commits pending changes, fetches and integrates the remote changes, then pushes
+    Sync,
+    /// Manages the git repository containing the shrine
+    Git {
+        #[command(subcommand)]
+        command: Option<GitCommands>,
+    },
+    /// Prints a listing of a shrine file's keys with a hash of their values, without the values;
+    /// used by git to diff shrines, see `git install-textconv`
+    Textconv {
+        /// The shrine file
+        file: PathBuf,
+    },
+    /// Merges two versions of a shrine file key by key and writes the result to OURS; used by git
+    /// as a merge driver, see `git install-merge-driver`
+    MergeDriver {
+        /// The common ancestor's version (git's `%O`)
+        base: PathBuf,
+        /// The current version, where the result is written (git's `%A`)
+        ours: PathBuf,
+        /// The other branch's version (git's `%B`)
+        theirs: PathBuf,
     },
     /// Configures the shrine
     Config {
@@ -133,13 +280,99 @@ enum Commands {
 #[cfg(unix)]
 enum AgentCommands {
     /// Starts shrine agent
-    Start,
+    Start {
+        /// Also listen for TCP connections on this address, such as `127.0.0.1:7878`
+        #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+        listen: Option<SocketAddr>,
+        /// The PEM file containing the TLS certificate served on the TCP address
+        #[arg(long, requires = "listen")]
+        tls_cert: Option<PathBuf>,
+        /// The PEM file containing the TLS private key
+        #[arg(long, requires = "listen")]
+        tls_key: Option<PathBuf>,
+        /// Stay attached to the terminal and log to the standard output, e.g. to run under a
+        /// service manager
+        #[arg(long)]
+        foreground: bool,
+    },
     /// Stops shrine agent
     Stop,
     /// Clear cached passwords
     ClearPasswords,
-    /// Returns the status of teh shrine agent
+    /// Returns the status of the shrine agent and the remaining lifetime of cached passwords
     Status,
+    /// Prints the agent's accesses to secrets and passwords
+    Audit {
+        /// Only print the events since a date, or a duration ago such as `1h`
+        #[arg(long, short, value_parser = parse_since)]
+        since: Option<DateTime<Utc>>,
+        /// Only print the events about a key
+        #[arg(long, short)]
+        key: Option<String>,
+    },
+    /// Manage the tokens of the agent's TCP clients
+    Token {
+        #[command(subcommand)]
+        command: Option<TokenCommands>,
+    },
+}
+
+#[derive(Clone, Subcommand)]
+#[command(arg_required_else_help = true)]
+#[cfg(unix)]
+enum TokenCommands {
+    /// Issues a token and prints it; it cannot be printed again
+    Create {
+        /// The name of the token
+        name: String,
+        /// Only give access to this shrine, by folder or UUID; may be repeated
+        #[arg(long = "shrine")]
+        shrines: Vec<String>,
+        /// Only give access to the keys starting with this prefix; may be repeated
+        #[arg(long = "prefix")]
+        prefixes: Vec<String>,
+        /// Only give access to the keys matching this pattern, or one of the prefixes
+        #[arg(long)]
+        regex: Option<KeyPattern>,
+        /// Only allow these operations, among read, write, delete and list
+        #[arg(long = "allow", value_delimiter = ',')]
+        permissions: Vec<Permission>,
+        /// Expire the token after this duration, such as `30d`
+        #[arg(long, value_parser = |s: &str| parse_duration(s).map_err(|e| e.to_string()))]
+        expires_in: Option<Duration>,
+    },
+    /// Lists the issued tokens
+    List,
+    /// Revokes a token
+    Revoke {
+        /// The name of the token
+        name: String,
+    },
+}
+
+#[cfg(unix)]
+fn parse_since(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_duration(value)
+        .map(|d| Utc::now() - d)
+        .or_else(|e| parse_date(value).ok_or(e.to_string()))
+}
+
+#[derive(Clone, Subcommand)]
+#[command(arg_required_else_help = true)]
+enum GitCommands {
+    /// Configures git to merge concurrent changes to the shrine key by key, using
+    /// `.gitattributes` and the repository's configuration
+    InstallMergeDriver,
+    /// Re-encrypts all the versions of the shrine in the history with the current password,
+    /// e.g. after changing it; the previous history is kept under `refs/shrine-backup/`
+    RewriteHistory {
+        /// Also replace the current branch's history with a single commit
+        #[arg(long)]
+        squash: bool,
+    },
+    /// Configures git to diff the shrine as the listing printed by `textconv`, using
+    /// `.gitattributes` and the repository's configuration
+    InstallTextconv,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -186,6 +419,14 @@ enum Modes {
     Binary,
     /// The secret is text data
     Text,
+    /// The secret is an `otpauth://totp/` URI used to generate one-time codes
+    Totp,
+    /// The secret is a JSON value; TTY output is pretty-printed
+    Json,
+    /// The secret contains PEM encoded X.509 certificates and/or private keys
+    Pem,
+    /// The secret is an OpenSSH private key
+    Ssh,
 }
 
 impl Modes {
@@ -200,6 +441,10 @@ impl Modes {
             }
             Modes::Binary => Mode::Binary,
             Modes::Text => Mode::Text,
+            Modes::Totp => Mode::Totp,
+            Modes::Json => Mode::Json,
+            Modes::Pem => Mode::Pem,
+            Modes::Ssh => Mode::Ssh,
         }
     }
 }
@@ -223,6 +468,23 @@ impl From<Encoding> for get::Encoding {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Formats {
+    /// `key=value` lines
+    Env,
+    /// A JSON object; records are nested objects and binary secrets are base64 encoded
+    Json,
+}
+
+impl From<Formats> for Format {
+    fn from(value: Formats) -> Self {
+        match value {
+            Formats::Env => Format::Env,
+            Formats::Json => Format::Json,
+        }
+    }
+}
+
 #[derive(Clone, Subcommand)]
 #[command(arg_required_else_help = true)]
 enum ConfigCommands {
@@ -253,28 +515,88 @@ fn main() -> ExitCode {
 }
 
 fn exec(cli: Args) -> Result<(), Error> {
+    if cli.no_agent {
+        return run(cli, NoClient {});
+    }
+    #[cfg(unix)]
+    match HttpClient::<TcpClient>::from_env()? {
+        Some(client) => run(cli, client),
+        None => {
+            let client = HttpClient::<SocketClient>::new()?;
+            if !matches!(cli.command, Some(Commands::Agent { .. })) {
+                if let Err(e) = client.autostart() {
+                    eprintln!("WARNING: {}; accessing the shrine directly", e);
+                    return run(cli, NoClient {});
+                }
+            }
+            run(cli, client)
+        }
+    }
+    #[cfg(not(unix))]
+    run(cli, NoClient {})
+}
+
+fn run<C: Client>(cli: Args, client: C) -> Result<(), Error> {
     let password = cli.password.map(ShrinePassword::from);
     let path = cli
         .path
         .unwrap_or_else(|| PathBuf::from(env::var("SHRINE_PATH").unwrap_or(".".to_string())));
     let path = fs::canonicalize(path).unwrap();
 
-    #[cfg(unix)]
-    let client = HttpClient::<SocketClient>::new().unwrap();
-    #[cfg(not(unix))]
-    let client = NoClient::new();
-
     let shrine_provider = FilesystemShrineProvider::new(path, password.clone());
 
     match cli.command {
         #[cfg(unix)]
         Some(Commands::Agent { command }) => match command {
-            Some(AgentCommands::Start) => agent::start(client),
+            Some(AgentCommands::Start {
+                listen,
+                tls_cert,
+                tls_key,
+                foreground,
+            }) => agent::start(
+                client,
+                listen.map(|address| Listen {
+                    address,
+                    tls_cert: tls_cert.expect("required by --listen"),
+                    tls_key: tls_key.expect("required by --listen"),
+                }),
+                foreground,
+            ),
             Some(AgentCommands::Stop) => agent::stop(client),
             Some(AgentCommands::ClearPasswords) => agent::clear_passwords(client),
             Some(AgentCommands::Status) => agent::status(client),
+            Some(AgentCommands::Audit { since, key }) => {
+                agent::audit(since, key.as_deref(), &mut stdout())
+            }
+            Some(AgentCommands::Token { command }) => match command {
+                Some(TokenCommands::Create {
+                    name,
+                    shrines,
+                    prefixes,
+                    regex,
+                    permissions,
+                    expires_in,
+                }) => agent::create_token(
+                    &name,
+                    Scope {
+                        shrines,
+                        prefixes,
+                        regex,
+                        permissions,
+                    },
+                    expires_in,
+                    &mut stdout(),
+                ),
+                Some(TokenCommands::List) => agent::list_tokens(&mut stdout()),
+                Some(TokenCommands::Revoke { name }) => agent::revoke_token(&name),
+                _ => panic!(),
+            },
             _ => panic!(),
         },
+        #[cfg(unix)]
+        Some(Commands::Unlock) => agent::unlock(client, shrine_provider, password),
+        #[cfg(unix)]
+        Some(Commands::Lock { uuid }) => agent::lock(client, shrine_provider, uuid),
         Some(Commands::Init {
             force,
             encryption,
@@ -291,16 +613,19 @@ fn exec(cli: Args) -> Result<(), Error> {
             new_password,
             encryption,
         }) => convert(
+            client,
             shrine_provider,
+            password,
             change_password,
             new_password.as_ref().map(ShrinePassword::from),
             encryption.map(|algo| algo.into()),
         ),
-        Some(Commands::Info { field }) => info(shrine_provider, field.map(Fields::from)),
+        Some(Commands::Info { field }) => info(client, shrine_provider, field.map(Fields::from)),
         Some(Commands::Set {
             key,
             stdin,
             mode,
+            fields,
             value,
         }) => set(
             client,
@@ -310,29 +635,117 @@ fn exec(cli: Args) -> Result<(), Error> {
                 read_from_stdin: stdin,
                 mode: mode.to_mode(stdin),
                 value: value.as_deref(),
+                fields: fields
+                    .iter()
+                    .map(|f| match f.split_once('=') {
+                        Some((name, value)) => (name, Some(value)),
+                        None => (f.as_str(), None),
+                    })
+                    .collect(),
             },
         ),
-        Some(Commands::Get { key, encoding }) => get(
+        Some(Commands::Get {
+            key,
+            field,
+            encoding,
+            at,
+        }) => get(
             client,
             shrine_provider,
             &key,
+            field.as_deref(),
             encoding.into(),
+            at.as_deref(),
             &mut stdout(),
         ),
-        Some(Commands::Ls { pattern }) => ls(
+        Some(Commands::Attach { key, file }) => attach(shrine_provider, &key, &file),
+        Some(Commands::Extract { key, output, force }) => extract(
             client,
             shrine_provider,
-            pattern.as_ref().map(|p| p.as_str()),
+            &key,
+            output.as_deref(),
+            force,
+            &mut stdout(),
+        ),
+        Some(Commands::Otp { key }) => otp(client, shrine_provider, &key, &mut stdout()),
+        Some(Commands::Inspect { key }) => inspect(client, shrine_provider, &key, &mut stdout()),
+        Some(Commands::Ls { pattern, long }) => ls(
+            client,
+            shrine_provider,
+            pattern.as_deref(),
+            long,
+            &mut stdout(),
+        ),
+        Some(Commands::Certs {
+            pattern,
+            expiring_within,
+        }) => certs(
+            client,
+            shrine_provider,
+            pattern.as_deref(),
+            expiring_within,
+            &mut stdout(),
+        ),
+        Some(Commands::Watch { pattern, command }) => watch(
+            client,
+            shrine_provider,
+            pattern.as_deref(),
+            &command,
             &mut stdout(),
         ),
         Some(Commands::Rm { key }) => rm(client, shrine_provider, &key),
-        Some(Commands::Import { file, prefix }) => {
-            import(shrine_provider, &file, prefix.as_deref())
+        Some(Commands::Import {
+            file,
+            prefix,
+            format,
+        }) => import(
+            client,
+            shrine_provider,
+            &file,
+            prefix.as_deref(),
+            format.into(),
+        ),
+        Some(Commands::Dump {
+            pattern,
+            config,
+            format,
+            at,
+        }) => dump(
+            client,
+            shrine_provider,
+            pattern.as_ref(),
+            config,
+            format.into(),
+            at.as_deref(),
+            &mut stdout(),
+        ),
+        Some(Commands::Log { key }) => log(shrine_provider, key.as_deref(), &mut stdout()),
+        Some(Commands::Blame) => blame(shrine_provider, &mut stdout()),
+        Some(Commands::Commit { message }) => {
+            commit(shrine_provider, message.as_deref(), &mut stdout())
+        }
+        Some(Commands::Sync) => sync(client, password, shrine_provider, &mut stdout()),
+        Some(Commands::Git { command }) => match command {
+            Some(GitCommands::InstallMergeDriver) => {
+                install_merge_driver(shrine_provider, &mut stdout())
+            }
+            Some(GitCommands::RewriteHistory { squash }) => {
+                rewrite_history(shrine_provider, squash, &mut stdout())
+            }
+            Some(GitCommands::InstallTextconv) => install_textconv(shrine_provider, &mut stdout()),
+            _ => panic!(),
+        },
+        Some(Commands::Textconv { file }) => textconv(client, password, &file, &mut stdout()),
+        Some(Commands::MergeDriver { base, ours, theirs }) => {
+            merge_driver(client, password, &base, &ours, &theirs, &mut stdout())
         }
-        Some(Commands::Dump { pattern, config }) => dump(shrine_provider, pattern.as_ref(), config),
         Some(Commands::Config { command }) => match command {
-            Some(ConfigCommands::Set { key, value }) => config::set(shrine_provider, key, value),
-            Some(ConfigCommands::Get { key }) => config::get(shrine_provider, &key),
+            Some(ConfigCommands::Set { key, value }) => {
+                config::set(client, shrine_provider, key, value)
+            }
+            Some(ConfigCommands::Get { key }) => {
+                config::get(client, shrine_provider, &key, &mut stdout())
+            }
             _ => panic!(),
         },
         _ => panic!(),
@@ -1,5 +1,8 @@
 #[cfg(unix)]
 pub mod agent;
+pub mod attach;
+pub mod certs;
+pub mod commit;
 pub mod config;
 pub mod convert;
 pub mod dump;
@@ -7,6 +10,50 @@ pub mod get;
 pub mod import;
 pub mod info;
 pub mod init;
+pub mod inspect;
+pub mod log;
 pub mod ls;
+pub mod merge;
+pub mod otp;
+pub mod rewrite;
 pub mod rm;
 pub mod set;
+pub mod sync;
+pub mod textconv;
+pub mod watch;
+
+use crate::git::Repository;
+use crate::shrine::{Shrine, ShrineProvider};
+use crate::Error;
+use std::env;
+
+/// Loads the shrine as committed at `revision`, see [`Repository::shrine_at`]. Its password is
+/// asked for if it differs from the current shrine's one.
+fn load_at<P>(shrine_provider: &mut P, revision: &str) -> Result<Shrine, Error>
+where
+    P: ShrineProvider,
+{
+    let shrine = shrine_provider.load_open()?;
+    let shrine = Repository::new(shrine_provider.path(), &shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?
+        .shrine_at(revision)?;
+    shrine_provider.open(shrine)
+}
+
+/// The git configuration value running the current executable with `args`.
+fn git_command(args: &str) -> String {
+    let executable = env::current_exe()
+        .ok()
+        .and_then(|e| e.to_str().map(|e| e.to_string()))
+        .unwrap_or_else(|| "shrine".to_string());
+    format!("'{}' {}", executable.replace('\'', r"'\''"), args)
+}
+
+/// The format of exported and imported secrets.
+pub enum Format {
+    /// `key=value` lines.
+    Env,
+    /// A JSON object mapping keys to values; records are mapped onto nested objects.
+    Json,
+}
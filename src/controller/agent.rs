@@ -1,38 +1,77 @@
+use crate::agent::audit::{AuditEvent, AuditLog};
 use crate::agent::client::Client;
+use crate::agent::paths::AgentPaths;
+use crate::agent::server::{AgentConfig, AgentSocket};
+use crate::agent::tls::{Listen, TlsListener};
+use crate::agent::token::{Scope, Tokens};
+use crate::settings::Settings;
+use crate::shrine::{ShrinePassword, ShrineProvider};
+use crate::utils::read_password;
 use crate::Error;
+use chrono::{DateTime, Duration, Utc};
 use daemonize::Daemonize;
-use std::env;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::Write;
+use std::process;
+use std::str::FromStr;
+use uuid::Uuid;
 
-pub fn start<C: Client>(client: C) -> Result<(), Error> {
-    if client.is_running() {
-        return Ok(());
-    }
+/// Starts the agent, also listening for TCP connections on `listen` if set. The agent runs in the
+/// background unless `foreground` is set, and serves the socket passed by the service manager if
+/// any.
+pub fn start<C: Client>(client: C, listen: Option<Listen>, foreground: bool) -> Result<(), Error> {
+    let settings = Settings::load()?;
+    let paths = AgentPaths::from_settings(&settings);
 
-    // https://specifications.freedesktop.org/basedir-spec/latest/ar01s03.html
-    let runtime_dir = env::var("XDG_RUNTIME_DIR").expect("$XDG_RUNTIME_DIR is not set or is invalid; read https://specifications.freedesktop.org/basedir-spec/latest/ar01s03.html");
+    // the service manager only passes the socket when nothing serves it: asking whether the agent
+    // runs would wait forever
+    let socket = match AgentSocket::from_env() {
+        Some(socket) => {
+            paths.create_dirs()?;
+            socket
+        }
+        None => {
+            if client.is_running() {
+                return Ok(());
+            }
+            paths.create_dirs()?;
+            AgentSocket::bind(&paths.socket).map_err(|e| Error::Socket(paths.socket.clone(), e))?
+        }
+    };
 
-    let stdout = File::create(format!("{}/shrine.out", runtime_dir)).unwrap();
-    let stderr = File::create(format!("{}/shrine.err", runtime_dir)).unwrap();
+    let mut config = AgentConfig::from_settings(&settings)?;
+    if let Some(listen) = listen {
+        config = config.with_listen(listen);
+    }
+    let listener = config.listen().map(TlsListener::bind).transpose()?;
 
-    let pidfile = format!("{}/shrine.pid", runtime_dir);
-    let socketfile = format!("{}/shrine.socket", runtime_dir);
+    if foreground {
+        fs::write(&paths.pid_file, process::id().to_string())
+            .map_err(|e| Error::WriteFile(paths.pid_file.clone(), e))?;
+    } else {
+        let stdout = File::create(&paths.log_file)
+            .map_err(|e| Error::WriteFile(paths.log_file.clone(), e))?;
+        let stderr = File::create(&paths.error_file)
+            .map_err(|e| Error::WriteFile(paths.error_file.clone(), e))?;
 
-    let daemonize = Daemonize::new()
-        .pid_file(&pidfile)
-        .stdout(stdout)
-        .stderr(stderr);
+        let daemonize = Daemonize::new()
+            .pid_file(&paths.pid_file)
+            .stdout(stdout)
+            .stderr(stderr);
 
-    match daemonize.start() {
-        Ok(_) => {
-            tokio::runtime::Builder::new_multi_thread()
-                .enable_all()
-                .build()
-                .unwrap()
-                .block_on(async { crate::agent::server::serve(pidfile, socketfile).await });
+        if let Err(e) = daemonize.start() {
+            eprintln!("Error, {}", e);
+            return Ok(());
         }
-        Err(e) => eprintln!("Error, {}", e),
-    };
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            crate::agent::server::serve(paths.pid_file, socket, config, listener).await
+        });
 
     Ok(())
 }
@@ -49,16 +88,322 @@ pub fn clear_passwords<C: Client>(client: C) -> Result<(), Error> {
     client.clear_passwords()
 }
 
+/// Checks the password of the current shrine and caches it in the agent.
+pub fn unlock<C, P>(
+    client: C,
+    shrine_provider: P,
+    password: Option<ShrinePassword>,
+) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+{
+    if !client.is_running() {
+        return Err(Error::AgentNotRunning);
+    }
+
+    let shrine = shrine_provider.load_closed()?;
+    let uuid = shrine.uuid();
+    if !shrine.requires_password() {
+        println!("Shrine {} does not require a password", uuid);
+        return Ok(());
+    }
+
+    let password = password.unwrap_or_else(|| read_password(uuid));
+    match shrine.open(&password) {
+        Err(Error::CryptoRead) => return Err(Error::InvalidPassword),
+        Err(e) => return Err(e),
+        Ok(_) => {}
+    }
+
+    client.set_password(uuid, password)?;
+    println!("Shrine {} unlocked", uuid);
+    Ok(())
+}
+
+/// Evicts the password of the shrine `uuid`, or of the current shrine, from the agent.
+pub fn lock<C, P>(client: C, shrine_provider: P, uuid: Option<Uuid>) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+{
+    let uuid = match uuid {
+        Some(uuid) => uuid,
+        None => shrine_provider.load_closed()?.uuid(),
+    };
+
+    if client.is_running() {
+        client.delete_password(uuid)?;
+    }
+    println!("Shrine {} locked", uuid);
+    Ok(())
+}
+
 pub fn status<C: Client>(client: C) -> Result<(), Error> {
     match client.pid() {
         None => {
             println!("Is running: false");
         }
         Some(pid) => {
-            println!("Is running: false");
+            println!("Is running: true");
             println!("PID:        {}", pid);
+
+            let passwords = client.passwords()?;
+            if passwords.is_empty() {
+                println!("Unlocked:   none");
+            } else {
+                println!("Unlocked:");
+            }
+            for password in passwords {
+                println!(
+                    "  {}  {} left ({})",
+                    password.uuid,
+                    remaining(password.expires_at - Utc::now()),
+                    password.expiry
+                );
+            }
+
+            let metrics = client.metrics()?;
+            let rate = (metrics.cache_hits * 100)
+                .checked_div(metrics.cache_hits + metrics.cache_misses)
+                .map(|r| format!(", {}% hit rate", r))
+                .unwrap_or_default();
+            println!(
+                "Cache:      {} hit(s), {} miss(es){}",
+                metrics.cache_hits, metrics.cache_misses, rate
+            );
         }
     }
 
     Ok(())
 }
+
+/// Issues a token for the agent's clients and prints it; it cannot be retrieved later. The shrines
+/// of the `scope` are given by UUID or by folder.
+pub fn create_token<O>(
+    name: &str,
+    mut scope: Scope,
+    expires_in: Option<Duration>,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    O: Write,
+{
+    for shrine in scope.shrines.iter_mut() {
+        if Uuid::from_str(shrine).is_err() {
+            if let Ok(path) = fs::canonicalize(&shrine) {
+                *shrine = path.display().to_string();
+            }
+        }
+    }
+
+    let mut tokens = load_tokens()?;
+    let value = tokens.create(name, scope, expires_in.map(|d| Utc::now() + d))?;
+    tokens.save()?;
+    writeln!(out, "{}", value).unwrap();
+    Ok(())
+}
+
+pub fn list_tokens<O>(out: &mut O) -> Result<(), Error>
+where
+    O: Write,
+{
+    for token in load_tokens()?.list() {
+        write!(
+            out,
+            "{}  created {}",
+            token.name,
+            token
+                .created_at
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        )
+        .unwrap();
+        if let Some(expires_at) = token.expires_at {
+            let verb = if expires_at > Utc::now() {
+                "expires"
+            } else {
+                "expired"
+            };
+            write!(
+                out,
+                "  {} {}",
+                verb,
+                expires_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+            )
+            .unwrap();
+        }
+        writeln!(out, "  {}", token.scope).unwrap();
+    }
+    Ok(())
+}
+
+pub fn revoke_token(name: &str) -> Result<(), Error> {
+    let mut tokens = load_tokens()?;
+    tokens.revoke(name)?;
+    tokens.save()
+}
+
+fn load_tokens() -> Result<Tokens, Error> {
+    Tokens::path()
+        .map(Tokens::load)
+        .unwrap_or_else(|| Ok(Tokens::default()))
+}
+
+/// Prints the events of the audit log, optionally only those since `since` or about `key`.
+pub fn audit<O>(since: Option<DateTime<Utc>>, key: Option<&str>, out: &mut O) -> Result<(), Error>
+where
+    O: Write,
+{
+    match AuditLog::from_settings(&Settings::load()?)? {
+        None => {
+            writeln!(out, "The audit log is disabled").unwrap();
+            Ok(())
+        }
+        Some(audit_log) => {
+            write_audit(&audit_log.read()?, since, key, out);
+            Ok(())
+        }
+    }
+}
+
+fn write_audit<O>(
+    events: &[AuditEvent],
+    since: Option<DateTime<Utc>>,
+    key: Option<&str>,
+    out: &mut O,
+) where
+    O: Write,
+{
+    for event in events
+        .iter()
+        .filter(|e| since.map(|s| e.timestamp >= s).unwrap_or(true))
+        .filter(|e| key.is_none() || e.key.as_deref() == key)
+    {
+        writeln!(out, "{}", event).unwrap();
+    }
+}
+
+/// Formats a remaining duration such as `1h 05m`, `14m 32s` or `8s`.
+fn remaining(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {:02}s", m, s),
+        (h, m, _) => format!("{}h {:02}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, ShrineBuilder};
+
+    fn shrine_provider(password: &str) -> (MockShrineProvider, Uuid) {
+        let shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build()
+            .close(&ShrinePassword::from(password))
+            .unwrap();
+        let uuid = shrine.uuid();
+        (MockShrineProvider::new(shrine), uuid)
+    }
+
+    #[test]
+    fn unlock() {
+        let (shrine_provider, uuid) = shrine_provider("password");
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_set_password(uuid, "password", Ok(()));
+
+        super::unlock(
+            client,
+            shrine_provider,
+            Some(ShrinePassword::from("password")),
+        )
+        .expect("expected Ok(())");
+    }
+
+    #[test]
+    fn unlock_invalid_password() {
+        let (shrine_provider, _) = shrine_provider("password");
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+
+        let err = super::unlock(client, shrine_provider, Some(ShrinePassword::from("wrong")))
+            .expect_err("expected Err(..)");
+
+        assert!(matches!(err, Error::InvalidPassword));
+    }
+
+    #[test]
+    fn unlock_agent_not_running() {
+        let (shrine_provider, _) = shrine_provider("password");
+
+        let err = super::unlock(MockClient::default(), shrine_provider, None)
+            .expect_err("expected Err(..)");
+
+        assert!(matches!(err, Error::AgentNotRunning));
+    }
+
+    #[test]
+    fn lock_current_shrine() {
+        let (shrine_provider, uuid) = shrine_provider("password");
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_delete_password(uuid, Ok(()));
+
+        super::lock(client, shrine_provider, None).expect("expected Ok(())");
+    }
+
+    #[test]
+    fn audit() {
+        use crate::agent::audit::{AuditOperation, AuditResult};
+
+        let event = |minutes: i64, operation: AuditOperation, key: Option<&str>| AuditEvent {
+            timestamp: DateTime::parse_from_rfc3339("2026-10-18T12:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc)
+                + Duration::minutes(minutes),
+            operation,
+            uuid: None,
+            path: None,
+            key: key.map(str::to_string),
+            pid: Some(42),
+            executable: None,
+            address: None,
+            token: None,
+            result: AuditResult::Ok,
+        };
+        let events = vec![
+            event(0, AuditOperation::Read, Some("a")),
+            event(1, AuditOperation::Read, Some("b")),
+            event(2, AuditOperation::Write, Some("a")),
+            event(3, AuditOperation::LockAll, None),
+        ];
+
+        let mut out = Vec::<u8>::new();
+        write_audit(&events, Some(events[1].timestamp), Some("a"), &mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2026-10-18T12:02:00Z write         ok           a pid 42\n"
+        );
+
+        let mut out = Vec::<u8>::new();
+        write_audit(&events, Some(events[3].timestamp), None, &mut out);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "2026-10-18T12:03:00Z lock_all      ok           pid 42\n"
+        );
+    }
+
+    #[test]
+    fn remaining() {
+        assert_eq!(super::remaining(Duration::seconds(8)), "8s");
+        assert_eq!(super::remaining(Duration::seconds(872)), "14m 32s");
+        assert_eq!(super::remaining(Duration::minutes(65)), "1h 05m");
+        assert_eq!(super::remaining(Duration::seconds(-3)), "0s");
+    }
+}
@@ -0,0 +1,242 @@
+use crate::agent::client::Client;
+use crate::attachment::Attachment;
+use crate::git::{Operation, Repository};
+use crate::shrine::{Secret, ShrineProvider};
+use crate::Error;
+use std::io::Write;
+use std::path::Path;
+
+/// Stores `file`'s content in `key`, along with its filename, size, MIME type and SHA-256.
+pub fn attach<P>(mut shrine_provider: P, key: &str, file: &Path) -> Result<(), Error>
+where
+    P: ShrineProvider,
+{
+    if !file.is_file() {
+        return Err(Error::FileNotFound(file.to_path_buf()));
+    }
+
+    let mut shrine = shrine_provider.load_open()?;
+    let (content, attachment) = Attachment::read(file)?;
+
+    let repository = Repository::new(shrine_provider.path(), &shrine);
+    shrine.attach(key, content, attachment)?;
+    shrine_provider.save_open(shrine)?;
+
+    if let Some(repository) = repository {
+        if repository.commit_auto() {
+            repository
+                .open()
+                .and_then(|r| r.create_commit(&Operation::Attach(key.to_string())))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Restores the file stored in `key` to `output`, `-` meaning stdout. If no output is provided,
+/// the file is written in the current folder under its original name, without its folders, and
+/// an existing file is only replaced if `force` is set.
+pub fn extract<C, P, O>(
+    client: C,
+    mut shrine_provider: P,
+    key: &str,
+    output: Option<&Path>,
+    force: bool,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+    O: Write,
+{
+    let mut write = |secret: &Secret| {
+        let attachment = secret
+            .attachment()
+            .ok_or_else(|| Error::NotAnAttachment(key.to_string()))?;
+        let content = secret.value().expose_secret_as_bytes();
+
+        match output {
+            Some(path) if path == Path::new("-") => attachment.write_to(content, out),
+            Some(path) => attachment.write(content, path, force),
+            None => attachment.write(
+                content,
+                attachment
+                    .file_name()
+                    .ok_or_else(|| Error::InvalidAttachmentName(attachment.filename.clone()))?,
+                force,
+            ),
+        }
+    };
+
+    if client.is_running() {
+        write(&client.get_key(shrine_provider.path().to_str().unwrap(), key)?)
+    } else {
+        let shrine = shrine_provider.load_open()?;
+        write(shrine.get(key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, Mode, ShrineBuilder, ShrinePassword};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn attach_then_extract() {
+        let folder = tempfile::tempdir().unwrap();
+        let file = folder.path().join("kubeconfig.yaml");
+        std::fs::write(&file, "apiVersion: v1\n").unwrap();
+
+        let shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+        let shrine_provider = MockShrineProvider::new(shrine);
+
+        attach(shrine_provider.clone(), "kube", &file).expect("expected Ok(())");
+
+        let shrine = shrine_provider
+            .load_closed()
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap();
+        let secret = shrine.get("kube").unwrap();
+        assert_eq!(secret.mode(), Mode::File);
+        assert_eq!(
+            secret.attachment().unwrap().filename,
+            "kubeconfig.yaml".to_string()
+        );
+        let shrine_provider =
+            MockShrineProvider::new(shrine.close(&ShrinePassword::default()).unwrap());
+
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+        let output = folder.path().join("restored");
+
+        extract(
+            client,
+            shrine_provider,
+            "kube",
+            Some(&output),
+            false,
+            &mut Vec::new(),
+        )
+        .expect("expected Ok(())");
+
+        assert_eq!(std::fs::read(&output).unwrap(), b"apiVersion: v1\n");
+        assert_eq!(
+            output.metadata().unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn extract_not_an_attachment() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("key", "secret", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let err = extract(
+            client,
+            MockShrineProvider::new(shrine),
+            "key",
+            Some(Path::new("-")),
+            false,
+            &mut Vec::new(),
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(err.to_string(), "Key `key` is not a file attachment");
+    }
+
+    #[test]
+    fn extract_through_agent() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_get_key(
+            "/path/to/shrine",
+            "key",
+            Ok(serde_json::from_str::<Secret>(
+                r#"
+                {
+                    "value": [123,125],
+                    "mode": "File",
+                    "created_by": "cpollet@localhost",
+                    "created_at": "2023-06-20T17:51:11.786655084Z",
+                    "attachment": {
+                        "filename": "config.json",
+                        "size": 2,
+                        "mime": "application/json",
+                        "sha256": "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+                    }
+                }
+            "#,
+            )
+            .unwrap()),
+        );
+
+        let mut out = Vec::<u8>::new();
+
+        extract(
+            client,
+            MockShrineProvider::default(),
+            "key",
+            Some(Path::new("-")),
+            false,
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        assert_eq!(out.as_slice(), b"{}");
+    }
+
+    #[test]
+    fn extract_invalid_filename() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_get_key(
+            "/path/to/shrine",
+            "key",
+            Ok(serde_json::from_str::<Secret>(
+                r#"
+                {
+                    "value": [123,125],
+                    "mode": "File",
+                    "created_by": "cpollet@localhost",
+                    "created_at": "2023-06-20T17:51:11.786655084Z",
+                    "attachment": {
+                        "filename": "..",
+                        "size": 2,
+                        "mime": "application/json",
+                        "sha256": "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+                    }
+                }
+            "#,
+            )
+            .unwrap()),
+        );
+
+        let err = extract(
+            client,
+            MockShrineProvider::default(),
+            "key",
+            None,
+            false,
+            &mut Vec::new(),
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(
+            err.to_string(),
+            "The attachment's filename `..` is not a file name; use `--output`"
+        );
+    }
+}
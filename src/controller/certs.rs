@@ -0,0 +1,406 @@
+use crate::agent::client::Client;
+use crate::agent::KeySelection;
+use crate::pem::Pem;
+use crate::shrine::{Mode, Secret, ShrineProvider};
+use crate::Error;
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Reports the certificates stored in secrets matching `pattern`, and fails if any of them
+/// expires within `within` or does not match the private key stored next to it.
+///
+/// Secrets are considered if their mode is [`Mode::Pem`] or if they start with a certificate
+/// (e.g. when set with `--stdin`). A certificate's private key is looked up in the same secret,
+/// then in the secrets sharing the same parent (e.g. `web/tls/key` for `web/tls/cert`).
+pub fn certs<C, P, O>(
+    client: C,
+    mut shrine_provider: P,
+    pattern: Option<&str>,
+    within: Duration,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+    O: Write,
+{
+    let regex = pattern
+        .map(Regex::new)
+        .transpose()
+        .map_err(Error::InvalidPattern)?;
+
+    let secrets = if client.is_running() {
+        let path = shrine_provider.path().to_str().unwrap();
+        let keys = client
+            .ls(path, None, false)?
+            .into_iter()
+            .filter(|k| matches!(k.mode, Mode::Pem | Mode::Binary | Mode::Text))
+            .map(|k| k.key)
+            .collect::<Vec<String>>();
+        // only the matching secrets and their siblings, which may hold their private key
+        let parents = keys
+            .iter()
+            .filter(|k| regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
+            .map(|k| parent(k))
+            .collect::<HashSet<_>>();
+        let keys = keys
+            .iter()
+            .filter(|k| parents.contains(parent(k)))
+            .cloned()
+            .collect::<Vec<String>>();
+        client
+            .get_keys(path, &KeySelection::Keys(keys))?
+            .into_iter()
+            .map(|(k, s)| s.map(|s| (k, parse(&s))))
+            .collect::<Result<Vec<_>, Error>>()?
+    } else {
+        let shrine = shrine_provider.load_open()?;
+        shrine
+            .keys()
+            .into_iter()
+            .map(|k| {
+                let pem = parse(shrine.get(&k).expect("must be there"));
+                (k, pem)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let secrets = secrets
+        .into_iter()
+        .filter_map(|(k, pem)| pem.map(|pem| (k, pem)))
+        .collect::<Vec<_>>();
+
+    let failures = check(&secrets, regex.as_ref(), Utc::now(), within, out);
+    if failures > 0 {
+        return Err(Error::CertificatesCheckFailed(failures));
+    }
+    Ok(())
+}
+
+/// Returns `None` if the secret is neither PEM nor a certificate. Other secrets starting with a
+/// private key are only kept if they parse, to be matched with the certificates next to them.
+fn parse(secret: &Secret) -> Option<Result<Pem, Error>> {
+    let value = secret.value().expose_secret_as_bytes();
+    let text = String::from_utf8_lossy(value);
+    let label = text
+        .trim_start()
+        .strip_prefix("-----BEGIN ")
+        .and_then(|t| t.split_once("-----"))
+        .map(|(label, _)| label);
+
+    match (secret.mode(), label) {
+        (Mode::Pem, _) => Some(Pem::parse(value)),
+        (Mode::Binary | Mode::Text, Some("CERTIFICATE")) => Some(Pem::parse(value)),
+        (Mode::Binary | Mode::Text, Some(label)) if label.ends_with("PRIVATE KEY") => {
+            Pem::parse(value).ok().map(Ok)
+        }
+        _ => None,
+    }
+}
+
+/// Prints the report and returns the count of certificates that failed the check.
+fn check<O>(
+    secrets: &[(String, Result<Pem, Error>)],
+    regex: Option<&Regex>,
+    now: DateTime<Utc>,
+    within: Duration,
+    out: &mut O,
+) -> usize
+where
+    O: Write,
+{
+    let mut secrets = secrets.iter().collect::<Vec<_>>();
+    secrets.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut failures = 0;
+    for (key, pem) in secrets.iter() {
+        if !regex.map(|r| r.is_match(key)).unwrap_or(true) {
+            continue;
+        }
+
+        let pem = match pem {
+            Ok(pem) => pem,
+            Err(e) => {
+                failures += 1;
+                writeln!(out, "{}\n  Status:  {}\n", key, e).unwrap();
+                continue;
+            }
+        };
+
+        // the first certificate is the leaf, the others are the chain
+        let certificate = match pem.certificates().next() {
+            None => continue,
+            Some(certificate) => certificate,
+        };
+
+        let mut problems = Vec::new();
+
+        let remaining = certificate.not_after - now;
+        if remaining < Duration::zero() {
+            problems.push("expired".to_string());
+        } else if remaining < within {
+            problems.push(format!("expires in {} days", remaining.num_days()));
+        }
+
+        let matches = |pem: &Pem| {
+            pem.private_keys()
+                .any(|k| k.public_key.as_ref() == Some(&certificate.public_key))
+        };
+        let private_key = if pem.private_keys().next().is_some() {
+            Some((key.as_str(), matches(pem)))
+        } else {
+            let siblings = secrets
+                .iter()
+                .filter(|(k, _)| k != key && parent(k) == parent(key))
+                .filter_map(|(k, p)| p.as_ref().ok().map(|p| (k.as_str(), p)))
+                .filter(|(_, p)| {
+                    p.certificates().next().is_none() && p.private_keys().next().is_some()
+                })
+                .collect::<Vec<_>>();
+            siblings
+                .iter()
+                .find(|(_, p)| matches(p))
+                .or_else(|| siblings.first())
+                .map(|(k, p)| (*k, matches(p)))
+        };
+        if let Some((_, false)) = private_key {
+            problems.push("private key does not match".to_string());
+        }
+
+        writeln!(out, "{}", key).unwrap();
+        writeln!(out, "  Subject: {}", certificate.subject).unwrap();
+        writeln!(out, "  Issuer:  {}", certificate.issuer).unwrap();
+        writeln!(
+            out,
+            "  Expires: {} ({} days)",
+            certificate.not_after,
+            remaining.num_days()
+        )
+        .unwrap();
+        if let Some((private_key, _)) = private_key {
+            writeln!(out, "  Key:     {}", private_key).unwrap();
+        }
+        if problems.is_empty() {
+            writeln!(out, "  Status:  ok\n").unwrap();
+        } else {
+            failures += 1;
+            writeln!(out, "  Status:  {}\n", problems.join(", ")).unwrap();
+        }
+    }
+
+    failures
+}
+
+fn parent(key: &str) -> &str {
+    key.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, Key, ShrineBuilder, ShrinePassword};
+    use chrono::TimeZone;
+
+    const EC_CRT: &[u8] = include_bytes!("../../tests/fixtures/ec.crt");
+    const EC_SHORT_CRT: &[u8] = include_bytes!("../../tests/fixtures/ec-short.crt");
+    const EC_KEY: &[u8] = include_bytes!("../../tests/fixtures/ec.key");
+    const RSA_KEY: &[u8] = include_bytes!("../../tests/fixtures/rsa.key");
+
+    fn run(secrets: &[(&str, &[u8])], within: Duration) -> (usize, String) {
+        let secrets = secrets
+            .iter()
+            .map(|(k, v)| (k.to_string(), Pem::parse(v)))
+            .collect::<Vec<_>>();
+        let mut out = Vec::<u8>::new();
+
+        let failures = check(
+            &secrets,
+            None,
+            Utc.with_ymd_and_hms(2026, 10, 18, 0, 0, 0).unwrap(),
+            within,
+            &mut out,
+        );
+
+        (failures, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn check_ok() {
+        let (failures, out) = run(
+            &[("web/cert", EC_CRT), ("web/key", EC_KEY)],
+            Duration::days(30),
+        );
+
+        assert_eq!(failures, 0);
+        assert_eq!(
+            out,
+            "web/cert\n  \
+                Subject: CN=example.com, O=Shrine\n  \
+                Issuer:  CN=example.com, O=Shrine\n  \
+                Expires: 2126-09-24 12:36:35 UTC (36500 days)\n  \
+                Key:     web/key\n  \
+                Status:  ok\n\n"
+        );
+    }
+
+    #[test]
+    fn check_expiring() {
+        let (failures, out) = run(&[("short", EC_SHORT_CRT)], Duration::days(30));
+
+        assert_eq!(failures, 1);
+        assert!(out.contains("  Status:  expires in 10 days\n"));
+
+        let (failures, _) = run(&[("short", EC_SHORT_CRT)], Duration::days(5));
+        assert_eq!(failures, 0);
+    }
+
+    #[test]
+    fn check_key_mismatch() {
+        let (failures, out) = run(
+            &[
+                ("web/cert", EC_CRT),
+                ("web/key", RSA_KEY),
+                ("other/key", EC_KEY),
+            ],
+            Duration::days(30),
+        );
+
+        assert_eq!(failures, 1);
+        assert!(out.contains("  Key:     web/key\n  Status:  private key does not match\n"));
+    }
+
+    #[test]
+    fn check_bundle() {
+        let (failures, out) = run(
+            &[("bundle", &[EC_CRT, EC_KEY].concat()), ("key", RSA_KEY)],
+            Duration::days(30),
+        );
+
+        assert_eq!(failures, 0);
+        assert!(out.contains("  Key:     bundle\n"));
+    }
+
+    #[test]
+    fn certs_direct() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("cert", EC_CRT, Mode::Binary).unwrap();
+        shrine.set("text", "secret", Mode::Text).unwrap();
+        shrine
+            .set(
+                "pgp",
+                "-----BEGIN PGP MESSAGE-----\nAAAA\n-----END PGP MESSAGE-----\n",
+                Mode::Text,
+            )
+            .unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        let err = certs(
+            client,
+            MockShrineProvider::new(shrine),
+            None,
+            Duration::days(365 * 200),
+            &mut out,
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(err.to_string(), "1 certificate(s) failed the check");
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("cert\n  Subject: CN=example.com, O=Shrine\n"));
+        assert!(!out.contains("text"));
+        assert!(!out.contains("pgp"));
+    }
+
+    #[test]
+    fn certs_through_agent() {
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("cert", EC_CRT, Mode::Pem).unwrap();
+        shrine.set("record", "{}", Mode::Json).unwrap();
+        let key = |key: &str| Key::new(key.to_string(), shrine.get(key).unwrap(), false);
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_ls(
+            "/path/to/shrine",
+            None,
+            false,
+            Ok(vec![key("cert"), key("record")]),
+        );
+        client.with_get_keys(
+            "/path/to/shrine",
+            KeySelection::Keys(vec!["cert".to_string()]),
+            Ok(vec![(
+                "cert".to_string(),
+                Ok(shrine.get("cert").unwrap().clone()),
+            )]),
+        );
+
+        let mut out = Vec::<u8>::new();
+
+        let err = certs(
+            client,
+            MockShrineProvider::default(),
+            None,
+            Duration::days(365 * 200),
+            &mut out,
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(err.to_string(), "1 certificate(s) failed the check");
+        assert!(String::from_utf8(out).unwrap().starts_with("cert\n"));
+    }
+
+    #[test]
+    fn certs_through_agent_with_pattern() {
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("web/cert", EC_CRT, Mode::Pem).unwrap();
+        shrine.set("web/key", EC_KEY, Mode::Text).unwrap();
+        shrine.set("other/cert", EC_CRT, Mode::Pem).unwrap();
+        let key = |key: &str| Key::new(key.to_string(), shrine.get(key).unwrap(), false);
+        let secret = |key: &str| (key.to_string(), Ok(shrine.get(key).unwrap().clone()));
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_ls(
+            "/path/to/shrine",
+            None,
+            false,
+            Ok(vec![key("other/cert"), key("web/cert"), key("web/key")]),
+        );
+        client.with_get_keys(
+            "/path/to/shrine",
+            KeySelection::Keys(vec!["web/cert".to_string(), "web/key".to_string()]),
+            Ok(vec![secret("web/cert"), secret("web/key")]),
+        );
+
+        let mut out = Vec::<u8>::new();
+
+        certs(
+            client,
+            MockShrineProvider::default(),
+            Some("^web/cert$"),
+            Duration::days(30),
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("web/cert\n"));
+        assert!(out.contains("  Key:     web/key\n"));
+        assert!(!out.contains("other/cert"));
+    }
+}
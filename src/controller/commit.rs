@@ -0,0 +1,57 @@
+use crate::controller::log::changes;
+use crate::git::{Open, Operation, Repository};
+use crate::shrine::{Shrine, ShrineProvider};
+use crate::Error;
+use std::io::Write;
+use std::path::Path;
+
+/// Commits the changes made to the shrine, e.g. when `git.commit.auto` is disabled. Without a
+/// `message`, the commit message lists the changed keys.
+pub fn commit<P, O>(mut shrine_provider: P, message: Option<&str>, out: &mut O) -> Result<(), Error>
+where
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    let repository = Repository::new(shrine_provider.path().to_path_buf(), &shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?;
+
+    if !repository.has_changes()? {
+        writeln!(out, "Nothing to commit").unwrap();
+        return Ok(());
+    }
+
+    let operation = match message {
+        Some(message) => Operation::Message(message.to_string()),
+        None => pending_changes(&mut shrine_provider, &repository, &shrine)?,
+    };
+    let commit = repository.create_commit(&operation)?;
+    writeln!(out, "Committed {}", &commit[..7]).unwrap();
+
+    Ok(())
+}
+
+/// The keys changed since the last commit.
+pub(crate) fn pending_changes<P, R>(
+    shrine_provider: &mut P,
+    repository: &Repository<R, Open>,
+    shrine: &Shrine,
+) -> Result<Operation, Error>
+where
+    P: ShrineProvider,
+    R: AsRef<Path>,
+{
+    let committed = match repository.shrine_at("HEAD") {
+        Ok(committed) => Some(shrine_provider.open(committed)?),
+        Err(Error::GitRevisionNotFound(_)) | Err(Error::NotInRevision(_)) => None,
+        Err(e) => return Err(e),
+    };
+
+    Ok(Operation::Update(
+        changes(committed.as_ref(), Some(shrine))
+            .iter()
+            .filter_map(|c| c.key().map(|k| k.to_string()))
+            .collect(),
+    ))
+}
@@ -1,46 +1,145 @@
-use crate::git::Repository;
+use crate::agent::client::Client;
+use crate::git::{Operation, Repository};
 use crate::shrine::ShrineProvider;
 
 use crate::Error;
 use rpassword::prompt_password;
 
-use std::io::{stdout, Write};
+use std::io::Write;
 
-pub fn set<P>(mut shrine_provider: P, key: String, value: Option<String>) -> Result<(), Error>
+pub fn set<C, P>(
+    client: C,
+    mut shrine_provider: P,
+    key: String,
+    value: Option<String>,
+) -> Result<(), Error>
 where
+    C: Client,
     P: ShrineProvider,
 {
+    if client.is_running() {
+        let value = value.unwrap_or_else(|| prompt_password("Value: ").unwrap());
+        return client.set_config(shrine_provider.path().to_str().unwrap(), &key, &value);
+    }
+
     let mut shrine = shrine_provider.load_open()?;
 
     let repository = Repository::new(shrine_provider.path(), &shrine);
 
     let value = value.unwrap_or_else(|| prompt_password("Value: ").unwrap());
 
-    shrine.set_private(key, value);
+    shrine.set_private(key.clone(), value);
     shrine_provider.save_open(shrine)?;
 
     if let Some(repository) = repository {
         if repository.commit_auto() {
             repository
                 .open()
-                .and_then(|r| r.create_commit("Update shrine"))?;
+                .and_then(|r| r.create_commit(&Operation::Config(key)))?;
         }
     }
 
     Ok(())
 }
 
-pub fn get<P>(mut shrine_provider: P, key: &str) -> Result<(), Error>
+pub fn get<C, P, O>(client: C, mut shrine_provider: P, key: &str, out: &mut O) -> Result<(), Error>
 where
+    C: Client,
     P: ShrineProvider,
+    O: Write,
 {
-    let shrine = shrine_provider.load_open()?;
+    let value = if client.is_running() {
+        client
+            .config(shrine_provider.path().to_str().unwrap())?
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    } else {
+        shrine_provider
+            .load_open()?
+            .get_private(key)
+            .map(|v| v.to_string())
+    };
 
-    let secret = shrine
-        .get_private(key)
-        .ok_or(Error::KeyNotFound(key.to_string()))?;
+    let value = value.ok_or(Error::KeyNotFound(key.to_string()))?;
 
-    let _ = stdout().write_all(secret.as_bytes());
+    let _ = out.write_all(value.as_bytes());
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::ShrinePassword;
+
+    #[test]
+    fn set_and_get() {
+        let shrine_provider = MockShrineProvider::default();
+
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+        set(
+            client,
+            shrine_provider.clone(),
+            "git.enabled".to_string(),
+            Some("false".to_string()),
+        )
+        .expect("expected Ok(())");
+
+        let shrine = shrine_provider
+            .load_closed()
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap();
+        assert_eq!(shrine.get_private("git.enabled"), Some("false"));
+        shrine_provider
+            .save_closed(shrine.close(&ShrinePassword::default()).unwrap())
+            .unwrap();
+
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+        let mut out = Vec::<u8>::new();
+        get(client, shrine_provider, "git.enabled", &mut out).expect("expected Ok(())");
+        assert_eq!(out, "false".as_bytes());
+    }
+
+    #[test]
+    fn set_and_get_through_agent() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_set_config("/path/to/shrine", "git.enabled", "false", Ok(()));
+        set(
+            client,
+            MockShrineProvider::default(),
+            "git.enabled".to_string(),
+            Some("false".to_string()),
+        )
+        .expect("expected Ok(())");
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_config(
+            "/path/to/shrine",
+            Ok(vec![("git.enabled".to_string(), "false".to_string())]),
+        );
+        let mut out = Vec::<u8>::new();
+        get(
+            client,
+            MockShrineProvider::default(),
+            "git.enabled",
+            &mut out,
+        )
+        .expect("expected Ok(())");
+        assert_eq!(out, "false".as_bytes());
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_config("/path/to/shrine", Ok(Vec::new()));
+        let err = get(client, MockShrineProvider::default(), "missing", &mut out)
+            .expect_err("expected Err(..)");
+        assert_eq!(err.to_string(), "Key `missing` does not exist");
+    }
+}
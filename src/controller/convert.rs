@@ -1,16 +1,23 @@
-use crate::git::Repository;
+use crate::agent::client::Client;
+use crate::git::{Operation, Repository};
 use crate::shrine::{EncryptionAlgorithm, ShrineBuilder};
 use crate::shrine::{ShrinePassword, ShrineProvider};
-use crate::utils::read_new_password;
+use crate::utils::{read_new_password, read_password};
 use crate::Error;
 
-pub fn convert<P>(
-    mut shrine_provider: P,
+/// Re-encrypts the shrine. Unless its password is provided, the agent converts it with the cached
+/// password when it is running; otherwise the password is read from the passwords file or
+/// prompted.
+pub fn convert<C, P>(
+    client: C,
+    shrine_provider: P,
+    password: Option<ShrinePassword>,
     change_password: bool,
     new_password: Option<ShrinePassword>,
     encryption_algorithm: Option<EncryptionAlgorithm>,
 ) -> Result<(), Error>
 where
+    C: Client,
     P: ShrineProvider,
 {
     let change_password = change_password || new_password.is_some();
@@ -20,7 +27,30 @@ where
 
     let mut change_password = change_password;
 
-    let shrine = shrine_provider.load_open()?;
+    let shrine = shrine_provider.load_closed()?;
+    let uuid = shrine.uuid();
+
+    if password.is_none() && client.is_running() {
+        let algorithm = encryption_algorithm.unwrap_or(shrine.encryption_algorithm());
+        let change_password = change_password || algorithm != shrine.encryption_algorithm();
+        let new_password = if change_password && algorithm.requires_password() {
+            Some(new_password.map(Ok).unwrap_or_else(read_new_password)?)
+        } else {
+            None
+        };
+        return client.convert(
+            shrine_provider.path().to_str().unwrap(),
+            new_password,
+            encryption_algorithm,
+        );
+    }
+
+    let password = if shrine.requires_password() {
+        password.unwrap_or_else(|| read_password(uuid))
+    } else {
+        ShrinePassword::default()
+    };
+    let shrine = shrine.open(&password)?;
 
     let shrine_builder =
         ShrineBuilder::new().with_encryption_algorithm(shrine.encryption_algorithm());
@@ -39,24 +69,93 @@ where
 
     let repository = Repository::new(shrine_provider.path(), &new_shrine);
 
-    if change_password {
-        let new_password = if new_shrine.requires_password() {
-            new_password.map(Ok).unwrap_or_else(read_new_password)?
-        } else {
-            ShrinePassword::default()
-        };
-        shrine_provider.save_closed(new_shrine.close(&new_password)?)?;
+    let new_uuid = new_shrine.uuid();
+    let requires_password = new_shrine.requires_password();
+    let new_password = if !change_password {
+        password
+    } else if requires_password {
+        new_password.map(Ok).unwrap_or_else(read_new_password)?
     } else {
-        shrine_provider.save_open(new_shrine)?;
+        ShrinePassword::default()
+    };
+    shrine_provider.save_closed(new_shrine.close(&new_password)?)?;
+
+    if client.is_running() {
+        client.delete_password(uuid)?;
+        if requires_password {
+            client.set_password(new_uuid, new_password)?;
+        }
     }
 
     if let Some(repository) = repository {
         if repository.commit_auto() {
             repository
                 .open()
-                .and_then(|r| r.create_commit("Update shrine"))?;
+                .and_then(|r| r.create_commit(&Operation::Convert))?;
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+
+    #[test]
+    fn convert_through_agent() {
+        let shrine_provider = MockShrineProvider::new(
+            ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+                .build()
+                .close(&ShrinePassword::from("password"))
+                .unwrap(),
+        );
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_convert("/path/to/shrine", Some("new"), None, Ok(()));
+
+        convert(
+            client,
+            shrine_provider,
+            None,
+            false,
+            Some(ShrinePassword::from("new")),
+            None,
+        )
+        .expect("expected Ok(())");
+    }
+
+    #[test]
+    fn convert_to_plain_through_agent() {
+        let shrine_provider = MockShrineProvider::new(
+            ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+                .build()
+                .close(&ShrinePassword::from("password"))
+                .unwrap(),
+        );
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_convert(
+            "/path/to/shrine",
+            None,
+            Some(EncryptionAlgorithm::Plain),
+            Ok(()),
+        );
+
+        convert(
+            client,
+            shrine_provider,
+            None,
+            false,
+            None,
+            Some(EncryptionAlgorithm::Plain),
+        )
+        .expect("expected Ok(())");
+    }
+}
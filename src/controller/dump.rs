@@ -1,61 +1,222 @@
-use crate::shrine::{Mode, ShrineProvider};
+use crate::agent::client::Client;
+use crate::agent::KeySelection;
+use crate::controller::{load_at, Format};
+use crate::record::Record;
+use crate::shrine::{Mode, Secret, ShrineProvider};
 use crate::{Error, SHRINE_FILENAME};
 use base64::Engine;
 use regex::Regex;
+use serde_json::{Map, Value};
+use std::io::Write;
 
-pub fn dump<P>(mut shrine_provider: P, pattern: Option<&String>, private: bool) -> Result<(), Error>
+/// Prints the secrets, or the secrets as committed at `revision` (see
+/// [`Repository::shrine_at`](crate::git::Repository::shrine_at)). The current secrets are read
+/// through the agent if it is running.
+pub fn dump<C, P, O>(
+    client: C,
+    mut shrine_provider: P,
+    pattern: Option<&String>,
+    private: bool,
+    format: Format,
+    revision: Option<&str>,
+    out: &mut O,
+) -> Result<(), Error>
 where
+    C: Client,
     P: ShrineProvider,
+    O: Write,
 {
     let regex = pattern
         .map(|p| Regex::new(p.as_ref()))
         .transpose()
         .map_err(Error::InvalidPattern)?;
 
-    let shrine = shrine_provider.load_open()?;
-
-    let mut keys = shrine
-        .keys()
-        .into_iter()
-        .filter(|k| regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
-        .collect::<Vec<String>>();
-    keys.sort_unstable();
-
-    println!(
-        "Shrine `{}/{}`",
-        shrine_provider.path().display(),
-        SHRINE_FILENAME
-    );
-    println!("Secrets:");
-    for key in keys.iter() {
-        let secret = shrine.get(key)?;
-        let value = match secret.mode() {
-            Mode::Binary => base64::engine::general_purpose::STANDARD
-                .encode(secret.value().expose_secret_as_bytes()),
-            Mode::Text => {
-                String::from_utf8_lossy(secret.value().expose_secret_as_bytes()).to_string()
-            }
+    let (secrets, config) = if revision.is_none() && client.is_running() {
+        let path = shrine_provider.path().to_str().unwrap();
+        let secrets = client
+            .get_keys(path, &KeySelection::Pattern(pattern.cloned()))?
+            .into_iter()
+            .map(|(k, s)| s.map(|s| (k, s)))
+            .collect::<Result<Vec<(String, Secret)>, Error>>()?;
+        let config = if private {
+            client
+                .config(path)?
+                .into_iter()
+                .filter(|(k, _)| regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        (secrets, config)
+    } else {
+        let shrine = match revision {
+            None => shrine_provider.load_open()?,
+            Some(revision) => load_at(&mut shrine_provider, revision)?,
         };
-        println!("  {}={}", key, value)
-    }
 
-    if private {
         let mut keys = shrine
-            .keys_private()
+            .keys()
             .into_iter()
             .filter(|k| regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
+            .collect::<Vec<String>>();
+        keys.sort_unstable();
+        let secrets = keys
+            .into_iter()
+            .map(|k| shrine.get(&k).cloned().map(|s| (k, s)))
+            .collect::<Result<Vec<(String, Secret)>, Error>>()?;
+
+        let mut keys = shrine
+            .keys_private()
+            .into_iter()
+            .filter(|k| private && regex.as_ref().map(|r| r.is_match(k)).unwrap_or(true))
             .collect::<Vec<&str>>();
         keys.sort_unstable();
+        let config = keys
+            .into_iter()
+            .map(|k| (k.to_string(), shrine.get_private(k).unwrap().to_string()))
+            .collect::<Vec<(String, String)>>();
+
+        (secrets, config)
+    };
+
+    if let Format::Json = format {
+        let mut json = Map::new();
+        for (key, secret) in secrets.iter() {
+            let value = match secret.mode() {
+                Mode::Record => serde_json::to_value(Record::from_secret(key, secret)?)
+                    .expect("a record is always serializable"),
+                _ => Value::String(to_string(secret)),
+            };
+            json.insert(key.clone(), value);
+        }
+        writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(&json).map_err(Error::JsonWrite)?
+        )
+        .map_err(Error::IoWrite)?;
+        return Ok(());
+    }
+
+    match revision {
+        None => writeln!(
+            out,
+            "Shrine `{}/{}`",
+            shrine_provider.path().display(),
+            SHRINE_FILENAME
+        ),
+        Some(revision) => writeln!(
+            out,
+            "Shrine `{}/{}` at `{}`",
+            shrine_provider.path().display(),
+            SHRINE_FILENAME,
+            revision
+        ),
+    }
+    .map_err(Error::IoWrite)?;
+    writeln!(out, "Secrets:").map_err(Error::IoWrite)?;
+    for (key, secret) in secrets.iter() {
+        writeln!(out, "  {}={}", key, to_string(secret)).map_err(Error::IoWrite)?;
+    }
 
-        println!("Configuration:");
-        for key in keys.iter() {
-            println!(
-                "  {}={}",
-                key,
-                String::from_utf8_lossy(shrine.get_private(key.as_ref()).unwrap().as_ref())
-            )
+    if private {
+        writeln!(out, "Configuration:").map_err(Error::IoWrite)?;
+        for (key, value) in config.iter() {
+            writeln!(out, "  {}={}", key, value).map_err(Error::IoWrite)?;
         }
     }
 
     Ok(())
 }
+
+fn to_string(secret: &Secret) -> String {
+    match secret.mode() {
+        Mode::Binary | Mode::File => base64::engine::general_purpose::STANDARD
+            .encode(secret.value().expose_secret_as_bytes()),
+        Mode::Text | Mode::Totp | Mode::Record | Mode::Json | Mode::Pem | Mode::Ssh => {
+            String::from_utf8_lossy(secret.value().expose_secret_as_bytes()).to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, ShrineBuilder, ShrinePassword};
+
+    #[test]
+    fn dump_from_file() {
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("b", "2", Mode::Text).unwrap();
+        shrine.set("a", "1", Mode::Text).unwrap();
+        shrine.set("c", [0u8, 1].as_slice(), Mode::Binary).unwrap();
+        shrine.set_private("git.enabled".to_string(), "false".to_string());
+        let shrine_provider =
+            MockShrineProvider::new(shrine.close(&ShrinePassword::default()).unwrap());
+
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut out = Vec::<u8>::new();
+        dump(
+            client,
+            shrine_provider,
+            None,
+            true,
+            Format::Env,
+            None,
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Shrine `/path/to/shrine/shrine`\nSecrets:\n  a=1\n  b=2\n  c=AAE=\nConfiguration:\n  git.enabled=false\n"
+        );
+    }
+
+    #[test]
+    fn dump_through_agent() {
+        let mut shrine = ShrineBuilder::new().build();
+        shrine.set("key", "value", Mode::Text).unwrap();
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_get_keys(
+            "/path/to/shrine",
+            KeySelection::Pattern(Some("^(key|git)".to_string())),
+            Ok(vec![(
+                "key".to_string(),
+                Ok(shrine.get("key").unwrap().clone()),
+            )]),
+        );
+        client.with_config(
+            "/path/to/shrine",
+            Ok(vec![
+                ("git.enabled".to_string(), "false".to_string()),
+                ("kdf.iterations".to_string(), "1".to_string()),
+            ]),
+        );
+
+        let mut out = Vec::<u8>::new();
+        dump(
+            client,
+            MockShrineProvider::default(),
+            Some(&"^(key|git)".to_string()),
+            true,
+            Format::Env,
+            None,
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Shrine `/path/to/shrine/shrine`\nSecrets:\n  key=value\nConfiguration:\n  git.enabled=false\n"
+        );
+    }
+}
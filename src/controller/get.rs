@@ -1,15 +1,21 @@
 use crate::agent::client::Client;
+use crate::controller::load_at;
+use crate::record::Record;
 use crate::shrine::{Mode, Secret, ShrineProvider};
 use crate::Error;
 use atty::Stream;
 use base64::Engine;
 use std::io::Write;
 
+/// Prints a secret's value, or its value as committed at `revision` (see
+/// [`Repository::shrine_at`](crate::git::Repository::shrine_at)).
 pub fn get<C, P, O>(
     client: C,
     mut shrine_provider: P,
     key: &str,
+    field: Option<&str>,
     encoding: Encoding,
+    revision: Option<&str>,
     out: &mut O,
 ) -> Result<(), Error>
 where
@@ -17,12 +23,24 @@ where
     P: ShrineProvider,
     O: Write,
 {
-    let secret = if client.is_running() {
-        encoding.encode(&client.get_key(shrine_provider.path().to_str().unwrap(), key)?)
+    let encode = |secret: &Secret| match field {
+        None => Ok(encoding.encode(secret)),
+        Some(field) => Record::from_secret(key, secret)?
+            .get(field)
+            .map(|v| v.as_bytes().to_vec())
+            .ok_or_else(|| Error::FieldNotFound(key.to_string(), field.to_string())),
+    };
+
+    let secret = if let Some(revision) = revision {
+        let shrine = load_at(&mut shrine_provider, revision)?;
+        let secret = shrine.get(key)?;
+        encode(secret)?
+    } else if client.is_running() {
+        encode(&client.get_key(shrine_provider.path().to_str().unwrap(), key)?)?
     } else {
         let shrine = shrine_provider.load_open()?;
         let secret = shrine.get(key)?;
-        encoding.encode(secret)
+        encode(secret)?
     };
 
     out.write_all(secret.as_slice()).unwrap();
@@ -39,7 +57,7 @@ impl Encoding {
     fn encode(&self, secret: &Secret) -> Vec<u8> {
         match self {
             Encoding::Auto => match secret.mode() {
-                Mode::Binary => {
+                Mode::Binary | Mode::File => {
                     if atty::is(Stream::Stdout) {
                         base64::engine::general_purpose::STANDARD
                             .encode(secret.value().expose_secret_as_bytes())
@@ -48,7 +66,20 @@ impl Encoding {
                         secret.value().expose_secret_as_bytes().to_vec()
                     }
                 }
-                Mode::Text => secret.value().expose_secret_as_bytes().to_vec(),
+                Mode::Json if atty::is(Stream::Stdout) => {
+                    serde_json::from_slice::<serde_json::Value>(
+                        secret.value().expose_secret_as_bytes(),
+                    )
+                    .and_then(|v| serde_json::to_vec_pretty(&v))
+                    .map(|mut v| {
+                        v.push(b'\n');
+                        v
+                    })
+                    .unwrap_or_else(|_| secret.value().expose_secret_as_bytes().to_vec())
+                }
+                Mode::Text | Mode::Totp | Mode::Record | Mode::Json | Mode::Pem | Mode::Ssh => {
+                    secret.value().expose_secret_as_bytes().to_vec()
+                }
             },
             Encoding::Raw => secret.value().expose_secret_as_bytes().to_vec(),
             Encoding::Base64 => base64::engine::general_purpose::STANDARD
@@ -80,7 +111,16 @@ mod tests {
 
         let mut out = Vec::<u8>::new();
 
-        get(client, shrine_provider, "key", Encoding::Raw, &mut out).expect("expected Ok(())");
+        get(
+            client,
+            shrine_provider,
+            "key",
+            None,
+            Encoding::Raw,
+            None,
+            &mut out,
+        )
+        .expect("expected Ok(())");
 
         assert_eq!(out.as_slice(), "secret".as_bytes());
     }
@@ -111,11 +151,75 @@ mod tests {
             client,
             MockShrineProvider::default(),
             "key",
+            None,
             Encoding::Raw,
+            None,
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        assert_eq!(out.as_slice(), "secret".as_bytes());
+    }
+
+    #[test]
+    fn get_field() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut record = Record::new();
+        record.set("user", "alice");
+        record.set("password", "secret");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine
+            .set("key", record.to_bytes().as_slice(), Mode::Record)
+            .unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        get(
+            client,
+            MockShrineProvider::new(shrine),
+            "key",
+            Some("password"),
+            Encoding::Auto,
+            None,
             &mut out,
         )
         .expect("expected Ok(())");
 
         assert_eq!(out.as_slice(), "secret".as_bytes());
     }
+
+    #[test]
+    fn get_unknown_field() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine
+            .set("key", Record::new().to_bytes().as_slice(), Mode::Record)
+            .unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        let err = get(
+            client,
+            MockShrineProvider::new(shrine),
+            "key",
+            Some("password"),
+            Encoding::Auto,
+            None,
+            &mut out,
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(err.to_string(), "Field `password` does not exist in `key`");
+    }
 }
@@ -1,29 +1,32 @@
+use crate::agent::client::Client;
+use crate::controller::Format;
+use crate::record::Record;
 use crate::shrine::{Mode, ShrineProvider};
+use crate::totp::Totp;
 
 use crate::Error;
 use dotenv_parser::parse_dotenv;
 
 use std::fs::read_to_string;
 
+use serde_json::Value;
 use std::path::{Path, PathBuf};
 
 // https://crates.io/crates/dotenv-parser
 // todo compliant with https://hexdocs.pm/dotenvy/dotenv-file-format.html
 
-pub fn import<P>(mut shrine_provider: P, file: &PathBuf, prefix: Option<&str>) -> Result<(), Error>
+/// Imports the secrets of `file`, through the agent if it is running.
+pub fn import<C, P>(
+    client: C,
+    mut shrine_provider: P,
+    file: &PathBuf,
+    prefix: Option<&str>,
+    format: Format,
+) -> Result<(), Error>
 where
+    C: Client,
     P: ShrineProvider,
 {
-    let mut shrine = shrine_provider.load_open()?;
-    // let password = password.unwrap_or_else(|| {
-    //     if shrine.requires_password() {
-    //         read_password(shrine.uuid())
-    //     } else {
-    //         ShrinePassword::default()
-    //     }
-    // });
-    // let mut shrine = shrine.open(&password)?;
-
     let prefix = prefix.unwrap_or_default();
 
     let file = Path::new(file);
@@ -33,12 +36,188 @@ where
 
     let content = read_to_string(file).map_err(Error::IoRead)?;
 
-    let secrets =
-        parse_dotenv(&content).map_err(|e| Error::InvalidDotEnv(e, file.to_path_buf()))?;
+    let secrets = match format {
+        Format::Env => parse_dotenv(&content)
+            .map_err(|e| Error::InvalidDotEnv(e, file.to_path_buf()))?
+            .into_iter()
+            .map(|(key, value)| {
+                let mode = text_mode(&value);
+                (key, value.into_bytes(), mode)
+            })
+            .collect::<Vec<(String, Vec<u8>, Mode)>>(),
+        Format::Json => parse_json(&content, file)?,
+    };
+
+    let secrets = secrets
+        .into_iter()
+        .map(|(key, value, mode)| (format!("{}{}", prefix, key), value, mode))
+        .collect::<Vec<(String, Vec<u8>, Mode)>>();
+
+    if client.is_running() {
+        return client.set_keys(shrine_provider.path().to_str().unwrap(), secrets);
+    }
 
-    for (key, value) in secrets {
-        shrine.set(&format!("{}{}", prefix, key), value.as_bytes(), Mode::Text)?
+    let mut shrine = shrine_provider.load_open()?;
+    for (key, value, mode) in secrets {
+        shrine.set(&key, value.as_slice(), mode)?
     }
 
     shrine_provider.save_open(shrine)
 }
+
+/// Only valid TOTP URIs are imported as TOTP secrets; other `otpauth://` URIs, e.g. HOTP or TOTP
+/// URIs without a secret, are kept as text.
+fn text_mode(value: &str) -> Mode {
+    if value.parse::<Totp>().is_ok() {
+        Mode::Totp
+    } else {
+        Mode::Text
+    }
+}
+
+/// Parses a JSON object; string values are imported as text and objects as records.
+fn parse_json(content: &str, file: &Path) -> Result<Vec<(String, Vec<u8>, Mode)>, Error> {
+    let invalid = |e: Box<dyn std::error::Error>| Error::InvalidJson(e, file.to_path_buf());
+
+    let object = match serde_json::from_str::<Value>(content).map_err(|e| invalid(e.into()))? {
+        Value::Object(object) => object,
+        _ => return Err(invalid("expected an object".into())),
+    };
+
+    object
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(value) => {
+                let mode = text_mode(&value);
+                Ok((key, value.into_bytes(), mode))
+            }
+            value @ Value::Object(_) => serde_json::from_value::<Record>(value)
+                .map(|record| (key, record.to_bytes(), Mode::Record))
+                .map_err(|e| invalid(e.into())),
+            _ => Err(invalid(
+                format!("value of `{}` must be a string or an object", key).into(),
+            )),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, ShrineBuilder, ShrinePassword};
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn import_json() {
+        let folder = tempdir().unwrap();
+        let file = folder.path().join("secrets.json");
+        write!(
+            File::create(&file).unwrap(),
+            r#"{{"text": "value", "db": {{"user": "alice", "password": "secret"}}}}"#
+        )
+        .unwrap();
+
+        let shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build()
+            .close(&ShrinePassword::default())
+            .unwrap();
+        let shrine_provider = MockShrineProvider::new(shrine);
+
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        import(
+            client,
+            shrine_provider.clone(),
+            &file,
+            Some("env/"),
+            Format::Json,
+        )
+        .expect("expected Ok(())");
+
+        let shrine = shrine_provider
+            .load_closed()
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap();
+
+        let text = shrine.get("env/text").unwrap();
+        assert_eq!(text.mode(), Mode::Text);
+        assert_eq!(text.value().expose_secret_as_bytes(), "value".as_bytes());
+
+        let record = Record::from_secret("env/db", shrine.get("env/db").unwrap()).unwrap();
+        assert_eq!(record.names(), vec!["user", "password"]);
+        assert_eq!(record.get("password"), Some("secret"));
+    }
+
+    #[test]
+    fn import_invalid_json() {
+        let folder = tempdir().unwrap();
+        let file = folder.path().join("secrets.json");
+        write!(File::create(&file).unwrap(), r#"{{"key": 1}}"#).unwrap();
+
+        let err = import(
+            MockClient::default(),
+            MockShrineProvider::default(),
+            &file,
+            None,
+            Format::Json,
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The input file `{}` is not a valid JSON file: value of `key` must be a string or an object",
+                file.display()
+            )
+        );
+    }
+
+    #[test]
+    fn import_through_agent() {
+        let folder = tempdir().unwrap();
+        let file = folder.path().join("secrets.env");
+        write!(
+            File::create(&file).unwrap(),
+            "KEY=value\nOTP=otpauth://totp/x?secret=JBSWY3DPEHPK3PXP\nBAD=otpauth://totp/x\n\
+            HOTP=otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP"
+        )
+        .unwrap();
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_set_keys(
+            "/path/to/shrine",
+            vec![
+                ("env/BAD", "otpauth://totp/x".as_bytes(), Mode::Text),
+                (
+                    "env/HOTP",
+                    "otpauth://hotp/x?secret=JBSWY3DPEHPK3PXP".as_bytes(),
+                    Mode::Text,
+                ),
+                ("env/KEY", "value".as_bytes(), Mode::Text),
+                (
+                    "env/OTP",
+                    "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP".as_bytes(),
+                    Mode::Totp,
+                ),
+            ],
+            Ok(()),
+        );
+
+        import(
+            client,
+            MockShrineProvider::default(),
+            &file,
+            Some("env/"),
+            Format::Env,
+        )
+        .expect("expected Ok(())");
+    }
+}
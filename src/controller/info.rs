@@ -1,3 +1,5 @@
+use crate::agent::client::Client;
+use crate::agent::Info;
 use crate::shrine::ShrineProvider;
 use crate::{Error, SHRINE_FILENAME};
 
@@ -8,11 +10,17 @@ pub enum Fields {
     Encryption,
 }
 
-pub fn info<P>(shrine_provider: P, field: Option<Fields>) -> Result<(), Error>
+/// Prints the shrine's metadata, read through the agent if it is running.
+pub fn info<C, P>(client: C, shrine_provider: P, field: Option<Fields>) -> Result<(), Error>
 where
+    C: Client,
     P: ShrineProvider,
 {
-    let shrine = shrine_provider.load_closed()?;
+    let info = if client.is_running() {
+        client.info(shrine_provider.path().to_str().unwrap())?
+    } else {
+        Info::from(&shrine_provider.load_closed()?)
+    };
     match field {
         None => {
             println!(
@@ -20,22 +28,22 @@ where
                 shrine_provider.path().display(),
                 SHRINE_FILENAME
             );
-            println!("Version:       {}", shrine.version());
-            println!("UUID:          {}", shrine.uuid());
-            println!("Serialization: {}", shrine.serialization_format());
-            println!("Encryption:    {}", shrine.encryption_algorithm());
+            println!("Version:       {}", info.version);
+            println!("UUID:          {}", info.uuid);
+            println!("Serialization: {}", info.serialization_format);
+            println!("Encryption:    {}", info.encryption_algorithm);
         }
         Some(Fields::Version) => {
-            println!("{}", shrine.version());
+            println!("{}", info.version);
         }
         Some(Fields::Uuid) => {
-            println!("{}", shrine.uuid());
+            println!("{}", info.uuid);
         }
         Some(Fields::Serialization) => {
-            println!("{}", shrine.serialization_format());
+            println!("{}", info.serialization_format);
         }
         Some(Fields::Encryption) => {
-            println!("{}", shrine.encryption_algorithm());
+            println!("{}", info.encryption_algorithm);
         }
     }
 
@@ -1,4 +1,4 @@
-use crate::git::Repository;
+use crate::git::{Operation, Repository};
 use crate::shrine::{EncryptionAlgorithm, ShrineBuilder, ShrinePassword, ShrineProvider};
 use crate::utils::read_new_password;
 use crate::{git, Error, SHRINE_FILENAME};
@@ -47,7 +47,7 @@ where
     if let Some(repository) = repository {
         let commit = repository
             .open()
-            .and_then(|r| r.create_commit("Initialize shrine"))?;
+            .and_then(|r| r.create_commit(&Operation::Init))?;
         print!("; git commit {}", commit);
     }
 
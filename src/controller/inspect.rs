@@ -0,0 +1,209 @@
+use crate::agent::client::Client;
+use crate::pem::{Block, Pem};
+use crate::record::Record;
+use crate::shrine::{Mode, Secret, ShrineProvider};
+use crate::ssh::SshKey;
+use crate::Error;
+use serde_json::Value;
+use std::io::Write;
+
+/// Prints the metadata of a secret: its mode, history and, depending on the mode, the parsed
+/// certificate, key or JSON details. The secret's value itself is never printed.
+pub fn inspect<C, P, O>(
+    client: C,
+    mut shrine_provider: P,
+    key: &str,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+    O: Write,
+{
+    let lines = if client.is_running() {
+        describe(
+            key,
+            &client.get_key(shrine_provider.path().to_str().unwrap(), key)?,
+        )?
+    } else {
+        let shrine = shrine_provider.load_open()?;
+        describe(key, shrine.get(key)?)?
+    };
+
+    for (label, value) in lines {
+        out.write_all(format!("{:15}{}\n", format!("{}:", label), value).as_bytes())
+            .unwrap();
+    }
+    Ok(())
+}
+
+fn describe(key: &str, secret: &Secret) -> Result<Vec<(String, String)>, Error> {
+    let value = secret.value().expose_secret_as_bytes();
+
+    let mut lines = vec![
+        ("Key".to_string(), key.to_string()),
+        ("Mode".to_string(), secret.mode().to_string()),
+        (
+            "Created".to_string(),
+            format!("{} by {}", secret.created_at(), secret.created_by()),
+        ),
+    ];
+    if let (Some(at), Some(by)) = (secret.updated_at(), secret.updated_by()) {
+        lines.push(("Updated".to_string(), format!("{} by {}", at, by)));
+    }
+
+    let mut push = |label: &str, value: String| lines.push((label.to_string(), value));
+
+    match secret.mode() {
+        Mode::Binary | Mode::Text | Mode::Totp => {}
+        Mode::File => {
+            if let Some(attachment) = secret.attachment() {
+                push("Filename", attachment.filename.clone());
+                push("Size", format!("{} bytes", attachment.size));
+                push("MIME type", attachment.mime.clone());
+                push("SHA-256", attachment.sha256.clone());
+            }
+        }
+        Mode::Record => push(
+            "Fields",
+            Record::from_secret(key, secret)?.names().join(", "),
+        ),
+        Mode::Json => push(
+            "Type",
+            match serde_json::from_slice::<Value>(value)
+                .map_err(|e| Error::InvalidJsonValue(e.to_string()))?
+            {
+                Value::Null => "null".to_string(),
+                Value::Bool(_) => "boolean".to_string(),
+                Value::Number(_) => "number".to_string(),
+                Value::String(_) => "string".to_string(),
+                Value::Array(a) => format!("array ({} items)", a.len()),
+                Value::Object(o) => format!("object ({} entries)", o.len()),
+            },
+        ),
+        Mode::Pem => {
+            for block in Pem::parse(value)?.blocks {
+                match block {
+                    Block::Certificate(c) => {
+                        push("Certificate", c.subject);
+                        push("  Issuer", c.issuer);
+                        if !c.sans.is_empty() {
+                            push("  SANs", c.sans.join(", "));
+                        }
+                        push("  Not before", c.not_before.to_string());
+                        push("  Not after", c.not_after.to_string());
+                        push("  Fingerprint", c.fingerprint);
+                        push("  Public key", c.public_key.fingerprint());
+                    }
+                    Block::PrivateKey(k) => {
+                        push("Private key", k.key_type.to_string());
+                        if let Some(public_key) = k.public_key {
+                            push("  Public key", public_key.fingerprint());
+                        }
+                    }
+                    Block::PublicKey(k) => push("Public key", k.fingerprint()),
+                }
+            }
+        }
+        Mode::Ssh => {
+            let ssh = SshKey::parse(value)?;
+            push(
+                "Type",
+                match ssh.bits {
+                    Some(bits) => format!("{} ({} bits)", ssh.key_type, bits),
+                    None => ssh.key_type,
+                },
+            );
+            push("Fingerprint", ssh.fingerprint);
+            if let Some(comment) = ssh.comment {
+                push("Comment", comment);
+            }
+            push(
+                "Encrypted",
+                if ssh.encrypted { "yes" } else { "no" }.to_string(),
+            );
+        }
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, ShrineBuilder, ShrinePassword};
+
+    fn inspect_direct(mode: Mode, value: &[u8]) -> String {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("key", value, mode).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        inspect(client, MockShrineProvider::new(shrine), "key", &mut out).expect("expected Ok(())");
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn inspect_certificate() {
+        let out = inspect_direct(Mode::Pem, include_bytes!("../../tests/fixtures/ec.crt"));
+
+        assert!(out.starts_with("Key:           key\nMode:          pem\n"));
+        assert!(out.contains("Certificate:   CN=example.com, O=Shrine\n"));
+        assert!(out.contains("  SANs:        DNS:example.com, DNS:www.example.com, IP:127.0.0.1\n"));
+        assert!(out.contains("  Not after:   2126-09-24 12:36:35 UTC\n"));
+    }
+
+    #[test]
+    fn inspect_ssh_key() {
+        let out = inspect_direct(
+            Mode::Ssh,
+            include_bytes!("../../tests/fixtures/ssh_ed25519"),
+        );
+
+        assert!(out.contains("Type:          ssh-ed25519 (256 bits)\n"));
+        assert!(out.contains("Fingerprint:   SHA256:Tw41FW0WQNVA6UiAwtxfZm6/qbnw8a357+uDSNZ7exM\n"));
+        assert!(out.contains("Comment:       alice@example.com\n"));
+        assert!(out.contains("Encrypted:     no\n"));
+    }
+
+    #[test]
+    fn inspect_json() {
+        let out = inspect_direct(Mode::Json, br#"{"a": 1, "b": [true]}"#);
+
+        assert!(out.contains("Type:          object (2 entries)\n"));
+    }
+
+    #[test]
+    fn inspect_through_agent() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_get_key(
+            "/path/to/shrine",
+            "key",
+            Ok(serde_json::from_value::<Secret>(serde_json::json!({
+                "value": include_bytes!("../../tests/fixtures/ec.key").to_vec(),
+                "mode": "Pem",
+                "created_by": "cpollet@localhost",
+                "created_at": "2023-06-20T17:51:11.786655084Z"
+            }))
+            .unwrap()),
+        );
+
+        let mut out = Vec::<u8>::new();
+
+        inspect(client, MockShrineProvider::default(), "key", &mut out).expect("expected Ok(())");
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Private key:   EC P-256\n"));
+        assert!(out.contains("  Public key:  SHA256:"));
+    }
+}
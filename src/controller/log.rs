@@ -0,0 +1,390 @@
+use crate::git::{Repository, Revision};
+use crate::shrine::{Shrine, ShrineProvider};
+use crate::Error;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+/// The opened shrines, by blob id.
+type Shrines = HashMap<String, Result<Shrine, Error>>;
+
+/// Lists the commits that changed the shrine, most recent first, along with the keys they added,
+/// changed or removed; values are never printed. If `key` is provided, only the commits that
+/// changed it are listed.
+pub fn log<P, O>(mut shrine_provider: P, key: Option<&str>, out: &mut O) -> Result<(), Error>
+where
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    let (revisions, shrines) = history(&mut shrine_provider, &shrine)?;
+    write_log(&revisions, &shrines, key, out);
+    Ok(())
+}
+
+/// Prints, for each key, the commit that last changed it and its author.
+pub fn blame<P, O>(mut shrine_provider: P, out: &mut O) -> Result<(), Error>
+where
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    let (revisions, shrines) = history(&mut shrine_provider, &shrine)?;
+    write_blame(&shrine, &revisions, &shrines, out);
+    Ok(())
+}
+
+/// Reads the commits and opens each distinct version of the shrine file they hold.
+fn history<P>(shrine_provider: &mut P, shrine: &Shrine) -> Result<(Vec<Revision>, Shrines), Error>
+where
+    P: ShrineProvider,
+{
+    let revisions = Repository::new(shrine_provider.path(), shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?
+        .revisions()?;
+
+    let mut shrines = Shrines::new();
+    for (id, content) in revisions.iter().filter_map(|r| r.shrine.as_ref()) {
+        if !shrines.contains_key(id) {
+            let shrine = Shrine::from_bytes(content).and_then(|s| shrine_provider.open(s));
+            shrines.insert(id.clone(), shrine);
+        }
+    }
+
+    Ok((revisions, shrines))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Change {
+    Added(String),
+    Changed(String),
+    Removed(String),
+    Config(String),
+}
+
+impl Change {
+    /// The secret's key, `None` for configuration changes.
+    pub(crate) fn key(&self) -> Option<&str> {
+        match self {
+            Change::Added(key) | Change::Changed(key) | Change::Removed(key) => Some(key),
+            Change::Config(_) => None,
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(key) => write!(f, "added    {}", key),
+            Change::Changed(key) => write!(f, "changed  {}", key),
+            Change::Removed(key) => write!(f, "removed  {}", key),
+            Change::Config(key) => write!(f, "config   {}", key),
+        }
+    }
+}
+
+/// The keys and configuration options that differ between two versions of the shrine.
+pub(crate) fn changes(before: Option<&Shrine>, after: Option<&Shrine>) -> Vec<Change> {
+    let keys = |shrine: Option<&Shrine>| shrine.map(|s| s.keys()).unwrap_or_default();
+    let keys = keys(before)
+        .into_iter()
+        .chain(keys(after))
+        .collect::<BTreeSet<_>>();
+
+    let mut changes = keys
+        .into_iter()
+        .filter_map(|key| {
+            match (
+                before.and_then(|s| s.get(&key).ok()),
+                after.and_then(|s| s.get(&key).ok()),
+            ) {
+                (None, Some(_)) => Some(Change::Added(key)),
+                (Some(_), None) => Some(Change::Removed(key)),
+                (Some(b), Some(a)) if !b.is_same_version(a) => Some(Change::Changed(key)),
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let keys = |shrine: Option<&Shrine>| {
+        shrine
+            .map(|s| s.keys_private())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>()
+    };
+    let keys = keys(before)
+        .into_iter()
+        .chain(keys(after))
+        .collect::<BTreeSet<_>>();
+    changes.extend(
+        keys.into_iter()
+            .filter(|k| {
+                before.and_then(|s| s.get_private(k)) != after.and_then(|s| s.get_private(k))
+            })
+            .map(Change::Config),
+    );
+
+    changes
+}
+
+fn write_header<O>(revision: &Revision, out: &mut O)
+where
+    O: Write,
+{
+    writeln!(
+        out,
+        "{} {} {}  {}",
+        &revision.id[..7],
+        revision.time.format("%Y-%m-%d %H:%M"),
+        revision.author,
+        revision.summary
+    )
+    .unwrap();
+}
+
+fn write_log<O>(revisions: &[Revision], shrines: &Shrines, key: Option<&str>, out: &mut O)
+where
+    O: Write,
+{
+    let by_id = revisions
+        .iter()
+        .map(|r| (r.id.as_str(), r))
+        .collect::<HashMap<_, _>>();
+    fn blob(revision: Option<&Revision>) -> Option<&str> {
+        revision
+            .and_then(|r| r.shrine.as_ref())
+            .map(|(id, _)| id.as_str())
+    }
+
+    for revision in revisions {
+        let parent = revision
+            .parents
+            .first()
+            .and_then(|p| by_id.get(p.as_str()))
+            .copied();
+        if blob(Some(revision)) == blob(parent) {
+            continue;
+        }
+
+        let open = |revision: Option<&Revision>| match blob(revision) {
+            None => Ok(None),
+            Some(id) => shrines[id].as_ref().map(Some),
+        };
+        let changes = match (open(parent), open(Some(revision))) {
+            (Ok(before), Ok(after)) => changes(before, after),
+            (Err(e), _) | (_, Err(e)) => {
+                if key.is_none() {
+                    write_header(revision, out);
+                    writeln!(out, "    could not open the shrine: {}\n", e).unwrap();
+                }
+                continue;
+            }
+        };
+        let changes = changes
+            .into_iter()
+            .filter(|c| key.is_none() || c.key() == key)
+            .collect::<Vec<_>>();
+        if key.is_some() && changes.is_empty() {
+            continue;
+        }
+
+        write_header(revision, out);
+        for change in changes {
+            writeln!(out, "    {}", change).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+}
+
+fn write_blame<O>(shrine: &Shrine, revisions: &[Revision], shrines: &Shrines, out: &mut O)
+where
+    O: Write,
+{
+    let by_id = revisions
+        .iter()
+        .map(|r| (r.id.as_str(), r))
+        .collect::<HashMap<_, _>>();
+
+    let blamed = shrine
+        .keys()
+        .into_iter()
+        .map(|key| {
+            let secret = shrine.get(&key).expect("key exists");
+            let has_secret = |revision: &Revision| {
+                revision
+                    .shrine
+                    .as_ref()
+                    .and_then(|(id, _)| shrines[id].as_ref().ok())
+                    .and_then(|s| s.get(&key).ok())
+                    .map(|s| s.is_same_version(secret))
+                    .unwrap_or_default()
+            };
+            // the commit that introduced the current version of the secret
+            let revision = revisions.iter().find(|r| {
+                has_secret(r)
+                    && !r
+                        .parents
+                        .iter()
+                        .filter_map(|p| by_id.get(p.as_str()))
+                        .any(|p| has_secret(p))
+            });
+            (key, revision)
+        })
+        .collect::<Vec<_>>();
+
+    let width = blamed
+        .iter()
+        .map(|(_, r)| r.map(|r| r.author.len()).unwrap_or(NOT_COMMITTED.len()))
+        .max()
+        .unwrap_or_default();
+
+    for (key, revision) in blamed {
+        match revision {
+            Some(revision) => writeln!(
+                out,
+                "{} {} {:width$}  {}",
+                &revision.id[..7],
+                revision.time.format("%Y-%m-%d %H:%M"),
+                revision.author,
+                key,
+                width = width
+            ),
+            None => writeln!(
+                out,
+                "0000000 {:16} {:width$}  {}",
+                "",
+                NOT_COMMITTED,
+                key,
+                width = width
+            ),
+        }
+        .unwrap();
+    }
+}
+
+const NOT_COMMITTED: &str = "Not committed yet";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shrine::{Closed, EncryptionAlgorithm, Mode, ShrineBuilder, ShrinePassword};
+    use chrono::{FixedOffset, TimeZone};
+
+    fn open(shrine: &[u8]) -> Shrine {
+        Shrine::from_bytes(shrine)
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap()
+    }
+
+    fn close(shrine: Shrine) -> Vec<u8> {
+        let shrine: Shrine<Closed> = shrine.close(&ShrinePassword::default()).unwrap();
+        shrine.as_bytes().unwrap()
+    }
+
+    fn revision(id: char, parent: Option<char>, author: &str, shrine: &[u8]) -> Revision {
+        Revision {
+            id: id.to_string().repeat(40),
+            parents: parent
+                .map(|p| p.to_string().repeat(40))
+                .into_iter()
+                .collect(),
+            author: author.to_string(),
+            time: FixedOffset::east_opt(0)
+                .unwrap()
+                .with_ymd_and_hms(2026, 10, 18, 12, 0, 0)
+                .unwrap(),
+            summary: "Update shrine".to_string(),
+            shrine: Some((format!("blob-{}", id), shrine.to_vec())),
+        }
+    }
+
+    /// Three commits: `a` is added, then `b` is added, then `a` is changed and `b` is removed.
+    fn history() -> (Vec<Revision>, Shrines, Vec<u8>) {
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("a", "1", Mode::Text).unwrap();
+        let first = close(shrine);
+
+        let mut shrine = open(&first);
+        shrine.set("b", "1", Mode::Text).unwrap();
+        shrine.set_private("git.enabled".to_string(), "true".to_string());
+        let second = close(shrine);
+
+        let mut shrine = open(&second);
+        shrine.set("a", "2", Mode::Text).unwrap();
+        shrine.remove("b");
+        let third = close(shrine);
+
+        let revisions = vec![
+            revision('3', Some('2'), "carol", &third),
+            revision('2', Some('1'), "bob", &second),
+            revision('1', None, "alice", &first),
+        ];
+        let shrines = revisions
+            .iter()
+            .map(|r| {
+                let (id, content) = r.shrine.as_ref().unwrap();
+                (id.clone(), Ok(open(content)))
+            })
+            .collect();
+
+        (revisions, shrines, third)
+    }
+
+    #[test]
+    fn log() {
+        let (revisions, shrines, _) = history();
+        let mut out = Vec::<u8>::new();
+
+        write_log(&revisions, &shrines, None, &mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "3333333 2026-10-18 12:00 carol  Update shrine\n    \
+                changed  a\n    \
+                removed  b\n\n\
+            2222222 2026-10-18 12:00 bob  Update shrine\n    \
+                added    b\n    \
+                config   git.enabled\n\n\
+            1111111 2026-10-18 12:00 alice  Update shrine\n    \
+                added    a\n\n"
+        );
+    }
+
+    #[test]
+    fn log_key() {
+        let (revisions, shrines, _) = history();
+        let mut out = Vec::<u8>::new();
+
+        write_log(&revisions, &shrines, Some("b"), &mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "3333333 2026-10-18 12:00 carol  Update shrine\n    \
+                removed  b\n\n\
+            2222222 2026-10-18 12:00 bob  Update shrine\n    \
+                added    b\n\n"
+        );
+    }
+
+    #[test]
+    fn blame() {
+        let (revisions, shrines, third) = history();
+        let mut shrine = open(&third);
+        shrine.set("c", "1", Mode::Text).unwrap();
+        let mut out = Vec::<u8>::new();
+
+        write_blame(&shrine, &revisions, &shrines, &mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "3333333 2026-10-18 12:00 carol              a\n\
+            0000000                  Not committed yet  c\n"
+        );
+    }
+}
@@ -9,6 +9,7 @@ pub fn ls<C, P, W>(
     client: C,
     mut shrine_provider: P,
     pattern: Option<&str>,
+    long: bool,
     out: &mut W,
 ) -> Result<(), Error>
 where
@@ -17,7 +18,7 @@ where
     W: Write,
 {
     let keys = if client.is_running() {
-        client.ls(shrine_provider.path().to_str().unwrap(), pattern)?
+        client.ls(shrine_provider.path().to_str().unwrap(), pattern, long)?
     } else {
         let regex = pattern
             .map(Regex::new)
@@ -35,22 +36,24 @@ where
 
         keys.into_iter()
             .map(|k| (shrine.get(&k).expect("must be there"), k))
-            .map(|(s, k)| Key::from((k, s)))
+            .map(|(s, k)| Key::new(k, s, long))
             .collect::<Vec<Key>>()
     };
 
-    print(out, keys);
+    print(out, keys, long);
 
     Ok(())
 }
 
-fn print<W>(out: &mut W, keys: Vec<Key>)
+fn print<W>(out: &mut W, keys: Vec<Key>, long: bool)
 where
     W: Write,
 {
+    let mut mode_width = 0;
     let mut created_by_width = 0;
     let mut updated_by_width = 0;
     for key in keys.iter() {
+        mode_width = mode_width.max(key.mode.to_string().len());
         if key.created_by.len() > created_by_width {
             created_by_width = key.created_by.len();
         }
@@ -64,10 +67,27 @@ where
     out.write_all(format!("total {}\n", keys.len()).as_bytes())
         .unwrap();
 
-    for key in keys {
+    let names = keys
+        .iter()
+        .map(|key| {
+            if long && !key.fields.is_empty() {
+                format!("{} {{{}}}", key.key, key.fields.join(", "))
+            } else {
+                key.key.clone()
+            }
+        })
+        .collect::<Vec<String>>();
+    let name_width = names.iter().map(|n| n.len()).max().unwrap_or_default();
+
+    for (key, name) in keys.into_iter().zip(names) {
+        let name = match key.info.as_ref().filter(|_| long) {
+            Some(info) => format!("{:nwidth$}  {}", name, info, nwidth = name_width),
+            None => name,
+        };
+
         out.write_all(
             format!(
-                "{} {:cwidth$} {} {} {:uwidth$} {:10} {:5} {}\n",
+                "{:mwidth$} {:cwidth$} {} {} {:uwidth$} {:10} {:5} {}\n",
                 key.mode,
                 key.created_by,
                 key.created_at.format("%Y-%m-%d"),
@@ -79,7 +99,8 @@ where
                 key.updated_at
                     .map(|dt| dt.format("%H:%M").to_string())
                     .unwrap_or_default(),
-                key.key,
+                name,
+                mwidth = mode_width,
                 cwidth = created_by_width,
                 uwidth = updated_by_width
             )
@@ -93,6 +114,7 @@ where
 mod tests {
     use super::*;
     use crate::agent::client::mock::MockClient;
+    use crate::record::Record;
     use crate::shrine::mocks::MockShrineProvider;
     use crate::shrine::{EncryptionAlgorithm, Mode, ShrineBuilder, ShrinePassword};
 
@@ -111,7 +133,7 @@ mod tests {
 
         let mut out = Vec::<u8>::new();
 
-        ls(client, shrine_provider, Some("pattern"), &mut out).expect("expected Ok(())");
+        ls(client, shrine_provider, Some("pattern"), false, &mut out).expect("expected Ok(())");
 
         let out = String::from_utf8(out).unwrap();
         assert!(out.contains(&format!(
@@ -129,6 +151,7 @@ mod tests {
         client.with_ls(
             "/path/to/shrine",
             Some("pattern"),
+            false,
             Ok(vec![Key {
                 key: "pattern".to_string(),
                 mode: Mode::Text,
@@ -136,6 +159,8 @@ mod tests {
                 created_at: Default::default(),
                 updated_by: None,
                 updated_at: None,
+                fields: vec![],
+                info: None,
             }]),
         );
 
@@ -143,11 +168,45 @@ mod tests {
 
         let mut out = Vec::<u8>::new();
 
-        ls(client, shrine_provider, Some("pattern"), &mut out).expect("expected Ok(())");
+        ls(client, shrine_provider, Some("pattern"), false, &mut out).expect("expected Ok(())");
 
         assert_eq!(
             String::from_utf8(out).unwrap(),
             "total 1\ntxt cpollet 1970-01-01 00:00                   pattern\n".to_string()
         );
     }
+
+    #[test]
+    fn ls_long() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut record = Record::new();
+        record.set("user", "alice");
+        record.set("password", "secret");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine
+            .set("record", record.to_bytes().as_slice(), Mode::Record)
+            .unwrap();
+        shrine.set("text", "secret", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        ls(
+            client,
+            MockShrineProvider::new(shrine),
+            None,
+            true,
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(" record {user, password}\n"));
+        assert!(out.contains(" text\n"));
+    }
 }
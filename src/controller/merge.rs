@@ -0,0 +1,287 @@
+use crate::agent::client::Client;
+use crate::controller::git_command;
+use crate::git::Repository;
+use crate::shrine::{Shrine, ShrinePassword, ShrineProvider};
+use crate::utils::read_password;
+use crate::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Merges the shrine files `ours` and `theirs` key by key, `base` being their common ancestor,
+/// and writes the result to `ours`. Meant to be run by git, see [`install_merge_driver`].
+///
+/// Without a provided password, the shrines are merged by the agent if it is running, so that
+/// the passwords it caches never leave it; otherwise the passwords are read from the passwords
+/// file or prompted.
+pub fn merge_driver<C, O>(
+    client: C,
+    password: Option<ShrinePassword>,
+    base: &Path,
+    ours: &Path,
+    theirs: &Path,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    C: Client,
+    O: Write,
+{
+    let read = |path: &Path| -> Result<Vec<u8>, Error> {
+        let bytes = fs::read(path).map_err(|e| Error::ReadFile(path.to_path_buf(), e))?;
+        match bytes.is_empty() {
+            true => Err(Error::FileNotFound(path.to_path_buf())),
+            false => Ok(bytes),
+        }
+    };
+
+    // git provides an empty file when there is no common ancestor
+    let base = match read(base) {
+        Err(Error::FileNotFound(_)) => None,
+        base => Some(base?),
+    };
+    let (ours_bytes, theirs_bytes) = (read(ours)?, read(theirs)?);
+
+    let (merged, conflicts) = merge_shrines(
+        &client,
+        password,
+        base.as_deref(),
+        &ours_bytes,
+        &theirs_bytes,
+    )?;
+
+    fs::write(ours, merged).map_err(|e| Error::WriteFile(ours.to_path_buf(), e))?;
+
+    for conflict in conflicts.iter() {
+        writeln!(out, "CONFLICT: {}", conflict).unwrap();
+    }
+    if !conflicts.is_empty() {
+        return Err(Error::MergeConflicts(conflicts.len()));
+    }
+    Ok(())
+}
+
+/// Merges the shrine files with the agent if it is running and no password is provided, or with
+/// [`merge_files`] otherwise. Returns the merged file and the conflicts.
+pub(crate) fn merge_shrines<C>(
+    client: &C,
+    password: Option<ShrinePassword>,
+    base: Option<&[u8]>,
+    ours: &[u8],
+    theirs: &[u8],
+) -> Result<(Vec<u8>, Vec<String>), Error>
+where
+    C: Client,
+{
+    if password.is_none() && client.is_running() {
+        client.merge(base, ours, theirs)
+    } else {
+        merge_files(password, base, ours, theirs)
+    }
+}
+
+/// Merges the shrine files, opened with the provided password or the ones read from the
+/// passwords file or prompted, once per shrine. Returns the merged file and the conflicts.
+fn merge_files(
+    password: Option<ShrinePassword>,
+    base: Option<&[u8]>,
+    ours: &[u8],
+    theirs: &[u8],
+) -> Result<(Vec<u8>, Vec<String>), Error> {
+    let mut passwords = HashMap::<Uuid, ShrinePassword>::new();
+    let mut open = |bytes: &[u8]| -> Result<(Shrine, ShrinePassword), Error> {
+        let shrine = Shrine::from_bytes(bytes)?;
+        let password = if shrine.requires_password() {
+            let uuid = shrine.uuid();
+            passwords
+                .entry(uuid)
+                .or_insert_with(|| password.clone().unwrap_or_else(|| read_password(uuid)))
+                .clone()
+        } else {
+            ShrinePassword::default()
+        };
+        Ok((shrine.open(&password)?, password))
+    };
+
+    let base = base.map(&mut open).transpose()?.map(|(shrine, _)| shrine);
+    let (mut merged, password) = open(ours)?;
+    let (theirs, _) = open(theirs)?;
+
+    let conflicts = merged.merge(base.as_ref(), &theirs)?;
+
+    Ok((
+        merged.close(&password)?.as_bytes()?,
+        conflicts.iter().map(|c| c.to_string()).collect(),
+    ))
+}
+
+/// Declares [`merge_driver`] as the merge driver of the shrine file in `.gitattributes` and in
+/// the repository's configuration.
+pub fn install_merge_driver<P, O>(mut shrine_provider: P, out: &mut O) -> Result<(), Error>
+where
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    let repository = Repository::new(shrine_provider.path(), &shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?;
+
+    repository.install_merge_driver(&git_command("merge-driver %O %A %B"))?;
+
+    writeln!(
+        out,
+        "Merge driver installed; commit `.gitattributes` to share it"
+    )
+    .unwrap();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::{Closed, EncryptionAlgorithm, Mode, ShrineBuilder};
+
+    fn write(path: &Path, shrine: Shrine<Closed>) {
+        fs::write(path, shrine.as_bytes().unwrap()).unwrap();
+    }
+
+    fn read(path: &Path, password: &ShrinePassword) -> Shrine {
+        Shrine::from_bytes(&fs::read(path).unwrap())
+            .unwrap()
+            .open(password)
+            .unwrap()
+    }
+
+    #[test]
+    fn merge_with_password() {
+        let folder = tempfile::tempdir().unwrap();
+        let (base, ours, theirs) = (
+            folder.path().join("base"),
+            folder.path().join("ours"),
+            folder.path().join("theirs"),
+        );
+        let password = ShrinePassword::from("password");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        shrine.set("common", "value", Mode::Text).unwrap();
+        let uuid = shrine.uuid();
+        write(&base, shrine.close(&password).unwrap());
+
+        let mut shrine = read(&base, &password);
+        shrine.set("ours", "value", Mode::Text).unwrap();
+        write(&ours, shrine.close(&password).unwrap());
+
+        let mut shrine = read(&base, &password);
+        shrine.set("theirs", "value", Mode::Text).unwrap();
+        shrine.remove("common");
+        write(&theirs, shrine.close(&password).unwrap());
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+
+        let mut out = Vec::<u8>::new();
+        merge_driver(
+            client,
+            Some(password.clone()),
+            &base,
+            &ours,
+            &theirs,
+            &mut out,
+        )
+        .expect("expected Ok(())");
+
+        assert!(out.is_empty());
+        let merged = read(&ours, &password);
+        assert_eq!(merged.uuid(), uuid);
+        assert_eq!(merged.keys(), vec!["ours", "theirs"]);
+    }
+
+    #[test]
+    fn merge_conflict() {
+        let folder = tempfile::tempdir().unwrap();
+        let (base, ours, theirs) = (
+            folder.path().join("base"),
+            folder.path().join("ours"),
+            folder.path().join("theirs"),
+        );
+        let password = ShrinePassword::default();
+        fs::write(&base, "").unwrap();
+
+        let new = || {
+            ShrineBuilder::new()
+                .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+                .build()
+        };
+        let mut shrine = new();
+        shrine.set("key", "ours", Mode::Text).unwrap();
+        write(&ours, shrine.close(&password).unwrap());
+        let mut shrine = new();
+        shrine.set("key", "theirs", Mode::Text).unwrap();
+        write(&theirs, shrine.close(&password).unwrap());
+
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut out = Vec::<u8>::new();
+        let err = merge_driver(client, None, &base, &ours, &theirs, &mut out)
+            .expect_err("expected Err(..)");
+
+        assert_eq!(
+            err.to_string(),
+            "1 conflict(s) while merging the shrine; our version of the conflicting keys was kept"
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CONFLICT: `key` was modified on both sides\n"
+        );
+        assert_eq!(
+            read(&ours, &password)
+                .get("key")
+                .unwrap()
+                .value()
+                .expose_secret_as_bytes(),
+            b"ours"
+        );
+    }
+
+    #[test]
+    fn merge_with_agent() {
+        let folder = tempfile::tempdir().unwrap();
+        let (base, ours, theirs) = (
+            folder.path().join("base"),
+            folder.path().join("ours"),
+            folder.path().join("theirs"),
+        );
+        fs::write(&base, "").unwrap();
+        fs::write(&ours, "ours").unwrap();
+        fs::write(&theirs, "theirs").unwrap();
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_merge(
+            None,
+            b"ours",
+            b"theirs",
+            Ok((
+                b"merged".to_vec(),
+                vec!["`key` was modified on both sides".to_string()],
+            )),
+        );
+
+        let mut out = Vec::<u8>::new();
+        let err = merge_driver(client, None, &base, &ours, &theirs, &mut out)
+            .expect_err("expected Err(..)");
+
+        assert!(matches!(err, Error::MergeConflicts(1)));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CONFLICT: `key` was modified on both sides\n"
+        );
+        assert_eq!(fs::read(&ours).unwrap(), b"merged");
+    }
+}
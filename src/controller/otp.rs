@@ -0,0 +1,98 @@
+use crate::agent::client::Client;
+use crate::shrine::ShrineProvider;
+use crate::totp::Totp;
+use crate::Error;
+use std::io::Write;
+
+pub fn otp<C, P, O>(client: C, mut shrine_provider: P, key: &str, out: &mut O) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+    O: Write,
+{
+    let code = if client.is_running() {
+        client.get_otp(shrine_provider.path().to_str().unwrap(), key)?
+    } else {
+        let shrine = shrine_provider.load_open()?;
+        Totp::from_secret(key, shrine.get(key)?)?.generate()
+    };
+
+    out.write_all(format!("{} ({}s remaining)\n", code.code, code.remaining).as_bytes())
+        .unwrap();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{EncryptionAlgorithm, Mode, ShrineBuilder, ShrinePassword};
+    use crate::totp::Code;
+
+    #[test]
+    fn otp_direct() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine
+            .set(
+                "key",
+                "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ&digits=8",
+                Mode::Totp,
+            )
+            .unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        otp(client, MockShrineProvider::new(shrine), "key", &mut out).expect("expected Ok(())");
+
+        let out = String::from_utf8(out).unwrap();
+        let (code, remaining) = out.split_once(' ').unwrap();
+        assert_eq!(code.len(), 8);
+        assert!(remaining.ends_with("s remaining)\n"));
+    }
+
+    #[test]
+    fn otp_not_totp() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("key", "secret", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let mut out = Vec::<u8>::new();
+
+        let err = otp(client, MockShrineProvider::new(shrine), "key", &mut out)
+            .expect_err("expected Err(..)");
+
+        assert_eq!(err.to_string(), "Key `key` is not a TOTP secret");
+    }
+
+    #[test]
+    fn otp_through_agent() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_get_otp(
+            "/path/to/shrine",
+            "key",
+            Ok(Code {
+                code: "123456".to_string(),
+                remaining: 12,
+            }),
+        );
+
+        let mut out = Vec::<u8>::new();
+
+        otp(client, MockShrineProvider::default(), "key", &mut out).expect("expected Ok(())");
+
+        assert_eq!(out.as_slice(), "123456 (12s remaining)\n".as_bytes());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::git::{Repository, BACKUP_REFS};
+use crate::shrine::{Shrine, ShrineProvider};
+use crate::Error;
+use std::io::Write;
+
+/// Re-encrypts every version of the shrine in the git history with the current password, e.g.
+/// after `convert --change-password`, so that the previous password no longer opens any of them.
+/// If `squash` is set, the current branch's history is then replaced by a single commit.
+pub fn rewrite_history<P, O>(mut shrine_provider: P, squash: bool, out: &mut O) -> Result<(), Error>
+where
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    let repository = Repository::new(shrine_provider.path().to_path_buf(), &shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?;
+
+    if repository.has_changes()? {
+        return Err(Error::GitUncommittedChanges);
+    }
+
+    let rewrite = repository.rewrite_history(
+        |content| {
+            let version = Shrine::from_bytes(content)?;
+            // already encrypted with the current password
+            if version.uuid() == shrine.uuid() {
+                return Ok(content.to_vec());
+            }
+            let mut reencrypted = shrine.empty_copy();
+            shrine_provider.open(version)?.move_to(&mut reencrypted);
+            shrine_provider.close(reencrypted)?.as_bytes()
+        },
+        squash,
+    )?;
+
+    writeln!(
+        out,
+        "Rewrote {} commit(s) on {}",
+        rewrite.commits,
+        rewrite.branches.join(", ")
+    )
+    .unwrap();
+    if !rewrite.tags.is_empty() {
+        writeln!(out, "Rewrote tag(s) {}", rewrite.tags.join(", ")).unwrap();
+    }
+    if !rewrite.others.is_empty() {
+        writeln!(
+            out,
+            "\nWARNING: these references were not rewritten and may still reach the previous \
+            versions of the shrine; delete them before pruning:\n    {}",
+            rewrite.others.join("\n    ")
+        )
+        .unwrap();
+    }
+    writeln!(
+        out,
+        "\nThe previous history, including the remote-tracking branches, is kept under `{0}`. \
+        Once checked, delete it along with the unreachable objects:\n    \
+            git for-each-ref --format='delete %(refname)' {0} | git update-ref --stdin\n    \
+            git reflog expire --expire=now --all && git gc --prune=now\n\
+        \nThen force-push the rewritten branches; other clones must be cloned again:\n    \
+            git push --force {1}{2} {3}",
+        BACKUP_REFS,
+        if rewrite.tags.is_empty() {
+            ""
+        } else {
+            "--tags "
+        },
+        repository.remote(),
+        rewrite.branches.join(" ")
+    )
+    .unwrap();
+
+    Ok(())
+}
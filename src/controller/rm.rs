@@ -1,5 +1,5 @@
 use crate::agent::client::Client;
-use crate::git::Repository;
+use crate::git::{Operation, Repository};
 use crate::shrine::ShrineProvider;
 
 use crate::Error;
@@ -24,7 +24,7 @@ where
             if repository.commit_auto() {
                 repository
                     .open()
-                    .and_then(|r| r.create_commit("Update shrine"))?;
+                    .and_then(|r| r.create_commit(&Operation::Remove(vec![key.to_string()])))?;
             }
         }
     }
@@ -1,5 +1,6 @@
 use crate::agent::client::Client;
-use crate::git::Repository;
+use crate::git::{Operation, Repository};
+use crate::record::Record;
 use crate::shrine::{Mode, ShrineProvider};
 
 use crate::Error;
@@ -10,6 +11,8 @@ pub struct Input<'a> {
     pub read_from_stdin: bool,
     pub mode: Mode,
     pub value: Option<&'a str>,
+    /// The record's fields to set, as name and optional value; prompted if no value is given.
+    pub fields: Vec<(&'a str, Option<&'a str>)>,
 }
 
 pub fn set<C, P>(
@@ -22,40 +25,50 @@ where
     C: Client,
     P: ShrineProvider,
 {
-    let value = if input.read_from_stdin {
-        let mut input = Vec::new();
-        let stdin = std::io::stdin();
-        let mut handle = stdin.lock();
-        handle.read_to_end(&mut input).map_err(Error::ReadStdIn)?;
-        input
-    } else {
-        input
-            .value
-            .map(|v| v.to_string())
-            .unwrap_or_else(|| prompt_password(format!("Enter `{}` value: ", key)).unwrap())
-            .as_bytes()
-            .to_vec()
-    };
-    let value = value.as_slice();
-
     if client.is_running() {
-        client.set_key(
-            shrine_provider.path().to_str().unwrap(),
-            key,
-            value,
-            input.mode,
-        )?;
+        let path = shrine_provider.path().to_str().unwrap();
+        let (value, mode) = if input.fields.is_empty() {
+            (read_value(key, &input)?, input.mode)
+        } else {
+            let record = match client.get_key(path, key) {
+                Ok(secret) => Record::from_secret(key, &secret)?,
+                Err(Error::KeyNotFound(_)) => Record::new(),
+                Err(e) => return Err(e),
+            };
+            (fill_record(key, record, &input.fields), Mode::Record)
+        };
+
+        client.set_key(path, key, value.as_slice(), mode)?;
     } else {
+        let value = if input.fields.is_empty() {
+            Some(read_value(key, &input)?)
+        } else {
+            None
+        };
+
         let mut shrine = shrine_provider.load_open()?;
+
+        let (value, mode) = match value {
+            Some(value) => (value, input.mode),
+            None => {
+                let record = match shrine.get(key) {
+                    Ok(secret) => Record::from_secret(key, secret)?,
+                    Err(Error::KeyNotFound(_)) => Record::new(),
+                    Err(e) => return Err(e),
+                };
+                (fill_record(key, record, &input.fields), Mode::Record)
+            }
+        };
+
         let repository = Repository::new(shrine_provider.path(), &shrine);
-        shrine.set(key, value, input.mode)?;
+        shrine.set(key, value.as_slice(), mode)?;
         shrine_provider.save_open(shrine)?;
 
         if let Some(repository) = repository {
             if repository.commit_auto() {
                 repository
                     .open()
-                    .and_then(|r| r.create_commit("Update shrine"))?;
+                    .and_then(|r| r.create_commit(&Operation::Set(key.to_string())))?;
             }
         }
     }
@@ -63,6 +76,33 @@ where
     Ok(())
 }
 
+fn read_value(key: &str, input: &Input<'_>) -> Result<Vec<u8>, Error> {
+    if input.read_from_stdin {
+        let mut input = Vec::new();
+        let stdin = std::io::stdin();
+        let mut handle = stdin.lock();
+        handle.read_to_end(&mut input).map_err(Error::ReadStdIn)?;
+        Ok(input)
+    } else {
+        Ok(input
+            .value
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| prompt_password(format!("Enter `{}` value: ", key)).unwrap())
+            .as_bytes()
+            .to_vec())
+    }
+}
+
+fn fill_record(key: &str, mut record: Record, fields: &[(&str, Option<&str>)]) -> Vec<u8> {
+    for (name, value) in fields {
+        let value = value.map(|v| v.to_string()).unwrap_or_else(|| {
+            prompt_password(format!("Enter `{}` field `{}` value: ", key, name)).unwrap()
+        });
+        record.set(*name, value);
+    }
+    record.to_bytes()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,6 +131,7 @@ mod tests {
                 read_from_stdin: false,
                 mode: Mode::Text,
                 value: Some("value"),
+                fields: vec![],
             },
         )
         .expect("expected Ok(())");
@@ -126,6 +167,108 @@ mod tests {
                 read_from_stdin: false,
                 mode: Mode::Text,
                 value: Some("value"),
+                fields: vec![],
+            },
+        )
+        .expect("Expect Ok(())")
+    }
+
+    #[test]
+    fn set_fields_direct() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut record = Record::new();
+        record.set("user", "alice");
+        record.set("password", "secret");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine
+            .set("key", record.to_bytes().as_slice(), Mode::Record)
+            .unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let shrine_provider = MockShrineProvider::new(shrine);
+
+        set(
+            client,
+            shrine_provider.clone(),
+            "key",
+            Input {
+                read_from_stdin: false,
+                mode: Mode::Text,
+                value: None,
+                fields: vec![("url", Some("https://example.com")), ("user", Some("bob"))],
+            },
+        )
+        .expect("expected Ok(())");
+
+        let shrine = shrine_provider
+            .load_closed()
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap();
+        let record = Record::from_secret("key", shrine.get("key").unwrap()).unwrap();
+        assert_eq!(record.names(), vec!["user", "password", "url"]);
+        assert_eq!(record.get("user"), Some("bob"));
+        assert_eq!(record.get("password"), Some("secret"));
+    }
+
+    #[test]
+    fn set_fields_not_a_record() {
+        let mut client = MockClient::default();
+        client.with_is_running(false);
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Plain)
+            .build();
+        shrine.set("key", "secret", Mode::Text).unwrap();
+        let shrine = shrine.close(&ShrinePassword::default()).unwrap();
+
+        let err = set(
+            client,
+            MockShrineProvider::new(shrine),
+            "key",
+            Input {
+                read_from_stdin: false,
+                mode: Mode::Text,
+                value: None,
+                fields: vec![("user", Some("bob"))],
+            },
+        )
+        .expect_err("expected Err(..)");
+
+        assert_eq!(err.to_string(), "Key `key` is not a record");
+    }
+
+    #[test]
+    fn set_fields_through_agent() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_get_key(
+            "/path/to/shrine",
+            "key",
+            Err(Error::KeyNotFound("key".to_string())),
+        );
+        client.with_set_key(
+            "/path/to/shrine",
+            "key",
+            br#"{"user":"alice"}"#,
+            &Mode::Record,
+            Ok(()),
+        );
+
+        set(
+            client,
+            MockShrineProvider::default(),
+            "key",
+            Input {
+                read_from_stdin: false,
+                mode: Mode::Text,
+                value: None,
+                fields: vec![("user", Some("alice"))],
             },
         )
         .expect("Expect Ok(())")
@@ -0,0 +1,53 @@
+use crate::agent::client::Client;
+use crate::controller::commit::pending_changes;
+use crate::controller::merge::merge_shrines;
+use crate::git::{Pull, Repository};
+use crate::shrine::{ShrinePassword, ShrineProvider};
+use crate::Error;
+use std::io::Write;
+
+/// Commits pending changes, integrates the remote changes and pushes the result. Diverged
+/// histories are merged in a merge commit, the shrine being merged key by key like the merge
+/// driver does; nothing is merged if keys conflict.
+pub fn sync<C, P, O>(
+    client: C,
+    password: Option<ShrinePassword>,
+    mut shrine_provider: P,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    let repository = Repository::new(shrine_provider.path().to_path_buf(), &shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?;
+
+    if repository.has_changes()? {
+        let operation = pending_changes(&mut shrine_provider, &repository, &shrine)?;
+        repository.commit(&repository.message(&operation))?;
+    }
+
+    let pull = repository.pull(|base, ours, theirs| {
+        let (merged, conflicts) = merge_shrines(&client, password, base, ours, theirs)?;
+        for conflict in conflicts.iter() {
+            writeln!(out, "CONFLICT: {}", conflict).unwrap();
+        }
+        match conflicts.is_empty() {
+            true => Ok(merged),
+            false => Err(Error::MergeConflicts(conflicts.len())),
+        }
+    })?;
+    match pull {
+        Pull::UpToDate => writeln!(out, "Already up to date").unwrap(),
+        Pull::FastForward(commit) => writeln!(out, "Fast-forwarded to {}", &commit[..7]).unwrap(),
+        Pull::Merged(commit) => writeln!(out, "Merged in {}", &commit[..7]).unwrap(),
+    }
+
+    repository.push()?;
+    writeln!(out, "Pushed").unwrap();
+
+    Ok(())
+}
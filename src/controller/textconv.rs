@@ -0,0 +1,190 @@
+use crate::agent::client::Client;
+use crate::controller::git_command;
+use crate::encrypt::aes::derive_hash_key;
+use crate::git::Repository;
+use crate::shrine::{Shrine, ShrinePassword, ShrineProvider};
+use crate::utils::read_password;
+use crate::Error;
+use chrono::SecondsFormat;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Prints a redacted listing of the shrine `file`, one line per key with its mode, last author,
+/// last update and a hash of its value. Meant to be run by git to diff shrines, see
+/// [`install_textconv`].
+///
+/// Without a provided password, the listing is made by the agent if it is running, so that the
+/// password it caches never leaves it; otherwise the password is read from the passwords file or
+/// prompted.
+pub fn textconv<C, O>(
+    client: C,
+    password: Option<ShrinePassword>,
+    file: &Path,
+    out: &mut O,
+) -> Result<(), Error>
+where
+    C: Client,
+    O: Write,
+{
+    let bytes = fs::read(file).map_err(|e| Error::ReadFile(file.to_path_buf(), e))?;
+    if password.is_none() && client.is_running() {
+        write!(out, "{}", client.textconv(&bytes)?).unwrap();
+        return Ok(());
+    }
+
+    let shrine = Shrine::from_bytes(&bytes)?;
+    let password = if shrine.requires_password() {
+        password.unwrap_or_else(|| read_password(shrine.uuid()))
+    } else {
+        ShrinePassword::default()
+    };
+    let shrine = shrine.open(&password)?;
+
+    write_listing(&shrine, &password, out);
+    Ok(())
+}
+
+/// Lines are not aligned, so that adding a key only changes its own line.
+pub(crate) fn write_listing<O>(shrine: &Shrine, password: &ShrinePassword, out: &mut O)
+where
+    O: Write,
+{
+    let hash_key = derive_hash_key(password, &shrine.uuid());
+    for key in shrine.keys() {
+        let secret = shrine.get(&key).expect("key exists");
+        writeln!(
+            out,
+            "{} {} {} {} {}",
+            key,
+            secret.mode(),
+            secret.updated_by().unwrap_or(secret.created_by()),
+            secret
+                .updated_at()
+                .unwrap_or(secret.created_at())
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            hash(
+                &hash_key,
+                shrine,
+                &key,
+                secret.value().expose_secret_as_bytes()
+            )
+        )
+        .unwrap();
+    }
+}
+
+/// A hash of `value`, keyed with a key derived from the password so that values cannot be guessed
+/// from it, see [`derive_hash_key`], and salted with the shrine's UUID and the key.
+fn hash(hash_key: &Secret<[u8; 32]>, shrine: &Shrine, key: &str, value: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(hash_key.expose_secret())
+        .expect("HMAC accepts keys of any size");
+    mac.update(shrine.uuid().as_bytes());
+    mac.update(key.as_bytes());
+    mac.update(&[0]);
+    mac.update(value);
+    let hash = mac.finalize().into_bytes();
+
+    format!(
+        "hmac-sha256:{}",
+        hash[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+/// Declares [`textconv`] as the diff filter of the shrine file in `.gitattributes` and in the
+/// repository's configuration.
+pub fn install_textconv<P, O>(mut shrine_provider: P, out: &mut O) -> Result<(), Error>
+where
+    P: ShrineProvider,
+    O: Write,
+{
+    let shrine = shrine_provider.load_open()?;
+    Repository::new(shrine_provider.path(), &shrine)
+        .ok_or(Error::GitDisabled)?
+        .open()?
+        .install_textconv(&git_command("textconv"))?;
+
+    writeln!(
+        out,
+        "Diff filter installed; commit `.gitattributes` to share it"
+    )
+    .unwrap();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::shrine::{EncryptionAlgorithm, Mode, ShrineBuilder};
+
+    #[test]
+    fn textconv_with_password() {
+        let folder = tempfile::tempdir().unwrap();
+        let file = folder.path().join("shrine");
+        let password = ShrinePassword::from("password");
+
+        let mut shrine = ShrineBuilder::new()
+            .with_encryption_algorithm(EncryptionAlgorithm::Aes)
+            .build();
+        shrine.set("b", "secret", Mode::Text).unwrap();
+        shrine.set("a", "secret", Mode::Binary).unwrap();
+        let created_by = shrine.get("a").unwrap().created_by().to_string();
+        fs::write(&file, shrine.close(&password).unwrap().as_bytes().unwrap()).unwrap();
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+
+        let mut out = Vec::<u8>::new();
+        textconv(client, Some(password), &file, &mut out).expect("expected Ok(())");
+
+        let out = String::from_utf8(out).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("a bin {} ", created_by)));
+        assert!(lines[1].starts_with(&format!("b txt {} ", created_by)));
+        assert!(!out.contains("secret"));
+
+        // the same value has a different hash under another key
+        let hash = |line: &str| line.rsplit_once(' ').unwrap().1.to_string();
+        assert!(hash(lines[0]).starts_with("hmac-sha256:"));
+        assert_ne!(hash(lines[0]), hash(lines[1]));
+    }
+
+    #[test]
+    fn textconv_with_agent() {
+        let folder = tempfile::tempdir().unwrap();
+        let file = folder.path().join("shrine");
+        fs::write(&file, "shrine file").unwrap();
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_textconv(b"shrine file", Ok("key txt listing\n".to_string()));
+
+        let mut out = Vec::<u8>::new();
+        textconv(client, None, &file, &mut out).expect("expected Ok(())");
+
+        assert_eq!(String::from_utf8(out).unwrap(), "key txt listing\n");
+    }
+
+    #[test]
+    fn hash_depends_on_password() {
+        let mut shrine = ShrineBuilder::new().build();
+        shrine.set("key", "value", Mode::Text).unwrap();
+
+        let hash = |password: &str| {
+            let mut out = Vec::<u8>::new();
+            write_listing(&shrine, &ShrinePassword::from(password), &mut out);
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(hash("p"), hash("p"));
+        assert_ne!(hash("p"), hash("q"));
+    }
+}
@@ -0,0 +1,176 @@
+use crate::agent::client::Client;
+use crate::shrine::ShrineProvider;
+use crate::Error;
+use std::io::Write;
+use std::process::{Child, Command};
+
+/// Watches the secrets matching `pattern` through the agent. Each change is printed to `out`, or,
+/// if a `command` is provided, the command is run once then run again, after stopping it if it
+/// is still running, on every change; the last run is waited for when watching stops.
+pub fn watch<C, P, W>(
+    client: C,
+    shrine_provider: P,
+    pattern: Option<&str>,
+    command: &[String],
+    out: &mut W,
+) -> Result<(), Error>
+where
+    C: Client,
+    P: ShrineProvider,
+    W: Write,
+{
+    if !client.is_running() {
+        return Err(Error::AgentNotRunning);
+    }
+
+    let mut child = match command.is_empty() {
+        true => None,
+        false => Some(spawn(command)?),
+    };
+
+    let mut result = Ok(());
+    client.watch(
+        shrine_provider.path().to_str().unwrap(),
+        pattern,
+        &mut |changes| {
+            match child.as_mut() {
+                None => {
+                    for change in changes {
+                        writeln!(out, "{}", change).unwrap();
+                    }
+                }
+                Some(running) => {
+                    if let Err(e) = stop(running) {
+                        result = Err(Error::Command(command[0].clone(), e));
+                        return false;
+                    }
+                    match spawn(command) {
+                        Ok(spawned) => *running = spawned,
+                        Err(e) => {
+                            result = Err(e);
+                            return false;
+                        }
+                    }
+                }
+            }
+            true
+        },
+    )?;
+
+    if let Some(mut running) = child {
+        running
+            .wait()
+            .map_err(|e| Error::Command(command[0].clone(), e))?;
+    }
+
+    result
+}
+
+fn spawn(command: &[String]) -> Result<Child, Error> {
+    Command::new(&command[0])
+        .args(&command[1..])
+        .spawn()
+        .map_err(|e| Error::Command(command[0].clone(), e))
+}
+
+/// Kills the `child` unless it already exited, and waits for it.
+fn stop(child: &mut Child) -> std::io::Result<()> {
+    if child.try_wait()?.is_none() {
+        child.kill()?;
+    }
+    child.wait().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::client::mock::MockClient;
+    use crate::agent::{Change, ChangeKind};
+    use crate::shrine::mocks::MockShrineProvider;
+    use crate::shrine::{Closed, Shrine, ShrineBuilder, ShrinePassword};
+
+    fn shrine() -> Shrine<Closed> {
+        ShrineBuilder::new()
+            .build()
+            .close(&ShrinePassword::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn watch_prints_changes() {
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_watch(
+            "/path/to/shrine",
+            Some("^key"),
+            vec![
+                vec![Change {
+                    key: "key1".to_string(),
+                    kind: ChangeKind::Set,
+                }],
+                vec![
+                    Change {
+                        key: "key2".to_string(),
+                        kind: ChangeKind::Deleted,
+                    },
+                    Change {
+                        key: "key3".to_string(),
+                        kind: ChangeKind::Set,
+                    },
+                ],
+            ],
+        );
+        let shrine_provider = MockShrineProvider::new(shrine());
+
+        let mut out = Vec::<u8>::new();
+        watch(client, shrine_provider, Some("^key"), &[], &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "set key1\ndeleted key2\nset key3\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn watch_runs_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("runs");
+
+        let mut client = MockClient::default();
+        client.with_is_running(true);
+        client.with_watch(
+            "/path/to/shrine",
+            None,
+            vec![vec![Change {
+                key: "key".to_string(),
+                kind: ChangeKind::Set,
+            }]],
+        );
+        let shrine_provider = MockShrineProvider::new(shrine());
+
+        let command = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            format!("echo run >> {}", file.display()),
+        ];
+        let mut out = Vec::<u8>::new();
+        watch(client, shrine_provider, None, &command, &mut out).unwrap();
+
+        assert!(out.is_empty());
+        // the first run may have been killed before writing
+        let runs = std::fs::read_to_string(&file).unwrap_or_default();
+        assert!(runs == "run\n" || runs == "run\nrun\n", "{:?}", runs);
+    }
+
+    #[test]
+    fn watch_without_agent() {
+        let client = MockClient::default();
+        let shrine_provider = MockShrineProvider::new(shrine());
+
+        match watch(client, shrine_provider, None, &[], &mut Vec::new()) {
+            Err(Error::AgentNotRunning) => {}
+            _ => panic!("expected Err(Error::AgentNotRunning)"),
+        }
+    }
+}
@@ -10,14 +10,55 @@ use crate::encrypt::EncDec;
 use crate::shrine::ShrinePassword;
 use crate::Error;
 
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
 pub struct Aes<'pwd> {
-    password: &'pwd ShrinePassword,
+    secret: AesSecret<'pwd>,
     aad: Option<String>,
 }
 
+enum AesSecret<'a> {
+    Password(&'a ShrinePassword),
+    Key(&'a DerivedKey),
+}
+
 impl<'pwd> Aes<'pwd> {
     pub fn new(password: &'pwd ShrinePassword, aad: Option<String>) -> Self {
-        Self { password, aad }
+        Self {
+            secret: AesSecret::Password(password),
+            aad,
+        }
+    }
+
+    /// Encrypts with an already derived key, reusing its salt, and decrypts only what was
+    /// encrypted with that salt.
+    pub fn with_key(key: &'pwd DerivedKey, aad: Option<String>) -> Self {
+        Self {
+            secret: AesSecret::Key(key),
+            aad,
+        }
+    }
+}
+
+/// A key derived from the password and a salt, to decrypt and re-encrypt a shrine without running
+/// PBKDF2 again.
+#[derive(Clone)]
+pub struct DerivedKey {
+    salt: [u8; KEY_SALT_LEN],
+    key: Secret<[u8; 32]>,
+}
+
+impl DerivedKey {
+    /// Derives the key `ciphertext` was encrypted with.
+    pub fn derive(password: &ShrinePassword, ciphertext: &[u8]) -> Result<Self, Error> {
+        let salt: [u8; KEY_SALT_LEN] = salt(ciphertext)
+            .and_then(|s| s.try_into().ok())
+            .ok_or(Error::CryptoRead)?;
+        Ok(Self {
+            salt,
+            key: Secret::new(derive(password, &salt)),
+        })
     }
 }
 
@@ -26,14 +67,18 @@ const NONCE_LEN: usize = 96 / 8;
 
 impl<'pwd> EncDec for Aes<'pwd> {
     fn encrypt(&self, cleartext: &[u8]) -> Result<Vec<u8>, Error> {
-        let mut salt = [0u8; KEY_SALT_LEN];
-        OsRng.fill_bytes(&mut salt);
+        let (salt, cipher) = match self.secret {
+            AesSecret::Password(password) => {
+                let mut salt = [0u8; KEY_SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                (salt, cipher(&derive(password, &salt)))
+            }
+            AesSecret::Key(key) => (key.salt, cipher(key.key.expose_secret())),
+        };
 
         let mut nonce = [0u8; NONCE_LEN];
         OsRng.fill_bytes(&mut nonce);
 
-        let cipher = self.cipher(&salt);
-
         let ciphertext = cipher
             .encrypt(Nonce::from_slice(&nonce), self.payload(cleartext))
             .map_err(|_| Error::CryptoWrite)?;
@@ -52,7 +97,11 @@ impl<'pwd> EncDec for Aes<'pwd> {
         let nonce = &ciphertext[KEY_SALT_LEN..KEY_SALT_LEN + NONCE_LEN];
         let ciphertext = &ciphertext[KEY_SALT_LEN + NONCE_LEN..];
 
-        let cipher = self.cipher(salt);
+        let cipher = match self.secret {
+            AesSecret::Password(password) => cipher(&derive(password, salt)),
+            AesSecret::Key(key) if key.salt == salt => cipher(key.key.expose_secret()),
+            AesSecret::Key(_) => return Err(Error::CryptoRead),
+        };
         cipher
             .decrypt(Nonce::from_slice(nonce), self.payload(ciphertext))
             .map_err(|_| Error::CryptoRead)
@@ -65,18 +114,26 @@ const PBKDF2_ROUNDS: u32 = 1;
 #[cfg(not(debug_assertions))]
 const PBKDF2_ROUNDS: u32 = 600_000;
 
-impl<'pwd> Aes<'pwd> {
-    fn cipher(&self, salt: &[u8]) -> Aes256GcmSiv {
-        let key = pbkdf2_hmac_array::<Sha256, 32>(
-            self.password.expose_secret_as_bytes(),
-            salt,
-            PBKDF2_ROUNDS,
-        );
-        let key = Key::<Aes256GcmSiv>::from_slice(&key);
+/// The salt the key `ciphertext` was encrypted with is derived with.
+pub fn salt(ciphertext: &[u8]) -> Option<&[u8]> {
+    ciphertext.get(0..KEY_SALT_LEN)
+}
 
-        Aes256GcmSiv::new(key)
-    }
+fn derive(password: &ShrinePassword, salt: &[u8]) -> [u8; 32] {
+    pbkdf2_hmac_array::<Sha256, 32>(password.expose_secret_as_bytes(), salt, PBKDF2_ROUNDS)
+}
 
+/// Derives a key from the password like the encryption keys, but salted with the shrine's UUID
+/// instead of a random salt, so that it does not change each time the shrine is encrypted again.
+pub fn derive_hash_key(password: &ShrinePassword, uuid: &Uuid) -> Secret<[u8; 32]> {
+    Secret::new(derive(password, uuid.as_bytes()))
+}
+
+fn cipher(key: &[u8; 32]) -> Aes256GcmSiv {
+    Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(key))
+}
+
+impl<'pwd> Aes<'pwd> {
     fn payload<'msg, 'aad>(&'aad self, msg: &'msg [u8]) -> Payload<'msg, 'aad> {
         let aad: &[u8] = match &self.aad {
             None => &[],
@@ -86,3 +143,47 @@ impl<'pwd> Aes<'pwd> {
         Payload { msg, aad }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derived_key() {
+        let password = ShrinePassword::from("password");
+        let ciphertext = Aes::new(&password, None).encrypt(b"secret").unwrap();
+
+        let key = DerivedKey::derive(&password, &ciphertext).unwrap();
+        assert_eq!(
+            Aes::with_key(&key, None).decrypt(&ciphertext).unwrap(),
+            b"secret"
+        );
+
+        let reencrypted = Aes::with_key(&key, None).encrypt(b"other").unwrap();
+        assert_eq!(reencrypted[..KEY_SALT_LEN], ciphertext[..KEY_SALT_LEN]);
+        assert_eq!(
+            Aes::new(&password, None).decrypt(&reencrypted).unwrap(),
+            b"other"
+        );
+
+        let other = Aes::new(&password, None).encrypt(b"secret").unwrap();
+        assert!(Aes::with_key(&key, None).decrypt(&other).is_err());
+    }
+
+    #[test]
+    fn hash_key() {
+        let password = ShrinePassword::from("password");
+        let uuid = Uuid::new_v4();
+
+        let key = derive_hash_key(&password, &uuid);
+        assert_eq!(
+            key.expose_secret(),
+            derive_hash_key(&password, &uuid).expose_secret()
+        );
+        assert_ne!(
+            key.expose_secret(),
+            derive_hash_key(&password, &Uuid::new_v4()).expose_secret()
+        );
+        assert_ne!(key.expose_secret(), password.expose_secret_as_bytes());
+    }
+}
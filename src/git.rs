@@ -1,14 +1,58 @@
 use crate::shrine::Shrine;
+use crate::utils::parse_date;
 use crate::{shrine, Error, SHRINE_FILENAME};
-use chrono::Local;
-use git2::{Commit, ErrorClass, ErrorCode, ObjectType, RepositoryInitOptions, Signature, Time};
-use std::path::Path;
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use git2::build::{CheckoutBuilder, TreeUpdateBuilder};
+use git2::{
+    BranchType, Commit, ConfigLevel, Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions,
+    FileMode, ObjectType, Oid, PushOptions, RemoteCallbacks, RepositoryInitOptions, Signature,
+    Sort, StatusOptions, Time, Tree,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::{fs, io};
+
+const DEFAULT_REMOTE: &str = "origin";
+const GITATTRIBUTES_FILENAME: &str = ".gitattributes";
 
 struct Configuration {
     enabled: bool,
     commit_auto: bool,
     push_auto: bool,
+    remote: String,
+    /// The commit message template, see [`Repository::message`].
+    commit_template: Option<String>,
+    /// Whether to leave the keys' names out of the commit messages.
+    commit_hide_keys: bool,
+    /// How to sign the commits, if at all.
+    commit_sign: Option<Signing>,
+}
+
+/// The commit signature format, from `git.commit.sign`: `gpg`, `ssh`, or `true` to follow git's
+/// `gpg.format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signing {
+    Gpg,
+    Ssh,
+    /// Whatever git's `gpg.format` is.
+    Git,
+}
+
+impl FromStr for Signing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gpg" | "openpgp" => Ok(Signing::Gpg),
+            "ssh" => Ok(Signing::Ssh),
+            "true" => Ok(Signing::Git),
+            _ => Err(()),
+        }
+    }
 }
 
 impl Configuration {
@@ -26,6 +70,20 @@ impl Configuration {
                 .get_private("git.push.auto")
                 .map(|s| bool::from_str(s).unwrap_or_default())
                 .unwrap_or_default(),
+            remote: shrine
+                .get_private("git.remote")
+                .unwrap_or(DEFAULT_REMOTE)
+                .to_string(),
+            commit_template: shrine
+                .get_private("git.commit.template")
+                .map(|s| s.to_string()),
+            commit_hide_keys: shrine
+                .get_private("git.commit.hide-keys")
+                .map(|s| bool::from_str(s).unwrap_or_default())
+                .unwrap_or_default(),
+            commit_sign: shrine
+                .get_private("git.commit.sign")
+                .and_then(|s| Signing::from_str(s).ok()),
         }
     }
 
@@ -42,6 +100,10 @@ impl Default for Configuration {
             enabled: true,
             commit_auto: true,
             push_auto: false,
+            remote: DEFAULT_REMOTE.to_string(),
+            commit_template: None,
+            commit_hide_keys: false,
+            commit_sign: None,
         }
     }
 }
@@ -59,6 +121,8 @@ pub struct Closed;
 
 pub struct Open {
     repository: git2::Repository,
+    /// The shrine file's path, relative to the repository's working directory.
+    file: PathBuf,
 }
 
 impl<P> Repository<P>
@@ -87,51 +151,55 @@ impl<P, State> Repository<P, State>
 where
     P: AsRef<Path>,
 {
-    fn signature<'a>() -> Result<Signature<'a>, Error> {
-        let now = Local::now();
-        let username = whoami::username();
-        let hostname = whoami::hostname();
-
-        Signature::new(
-            &username,
-            &format!("{}@{}", username, hostname),
-            &Time::new(now.timestamp(), now.offset().local_minus_utc() / 60),
-        )
-        .map_err(Error::Git)
-    }
-
     pub fn commit_auto(&self) -> bool {
         self.configuration.commit_auto
     }
+
+    pub fn push_auto(&self) -> bool {
+        self.configuration.push_auto
+    }
 }
 
 impl<P> Repository<P, Closed>
 where
     P: AsRef<Path>,
 {
+    /// Opens the repository containing the shrine, which may live in a subdirectory of it, or
+    /// initializes a new one in the shrine's folder.
     pub fn open(self) -> Result<Repository<P, Open>, Error>
     where
         P: AsRef<Path>,
     {
-        let mut git_folder = self.path.as_ref().to_path_buf();
-        git_folder.push(".git");
+        let repository = match git2::Repository::discover(&self.path) {
+            Ok(repository) => repository,
+            Err(e) if e.code() == ErrorCode::NotFound => {
+                let mut init_opts = RepositoryInitOptions::new();
+                init_opts.no_reinit(true);
+                init_opts.mkdir(false);
+                init_opts.mkpath(false);
+                init_opts.external_template(false);
 
-        let repository = if git_folder.exists() {
-            git2::Repository::open(&self.path)?
-        } else {
-            let mut init_opts = RepositoryInitOptions::new();
-            init_opts.no_reinit(true);
-            init_opts.mkdir(false);
-            init_opts.mkpath(false);
-            init_opts.external_template(false);
+                git2::Repository::init_opts(&self.path, &init_opts)?
+            }
+            Err(e) => return Err(Error::Git(e)),
+        };
 
-            git2::Repository::init_opts(&self.path, &init_opts)?
+        let canonicalize = |path: &Path| {
+            path.canonicalize()
+                .map_err(|e| Error::ReadFile(path.to_path_buf(), e))
         };
+        let workdir = canonicalize(repository.workdir().ok_or_else(|| {
+            git2::Error::from_str("the repository does not have a working directory")
+        })?)?;
+        let file = canonicalize(self.path.as_ref())?
+            .strip_prefix(&workdir)
+            .map_err(|_| git2::Error::from_str("the shrine is outside the repository"))?
+            .join(SHRINE_FILENAME);
 
         Ok(Repository {
             path: self.path,
             configuration: self.configuration,
-            state: Open { repository },
+            state: Open { repository, file },
         })
     }
 }
@@ -140,35 +208,731 @@ impl<P> Repository<P, Open>
 where
     P: AsRef<Path>,
 {
-    pub fn create_commit(&self, message: &str) -> Result<String, Error> {
-        let mut index = self.state.repository.index()?;
-        index.add_path(Path::new(SHRINE_FILENAME))?;
+    /// Commits the shrine file with a message describing `operation` and, if `git.push.auto` is
+    /// set, pushes the commit. A failed push only prints a warning: the commit is kept, and is
+    /// pushed along with the next one.
+    pub fn create_commit(&self, operation: &Operation) -> Result<String, Error> {
+        let commit = self.commit(&self.message(operation))?;
+        if self.configuration.push_auto {
+            if let Err(e) = self.push() {
+                match e {
+                    Error::Git(e) => {
+                        eprintln!("WARNING: could not push the commit: {}", e.message())
+                    }
+                    e => eprintln!("WARNING: could not push the commit: {}", e),
+                }
+            }
+        }
+        Ok(commit)
+    }
+
+    /// The commit message of `operation`, built from `git.commit.template` if set. The template's
+    /// `{message}` placeholder is replaced by the default message (e.g. `set prod/db/password`),
+    /// `{action}` by the operation (e.g. `set`) and `{keys}` by the affected keys. Keys are
+    /// replaced by their count if `git.commit.hide-keys` is set.
+    pub fn message(&self, operation: &Operation) -> String {
+        let action = operation.action();
+        let keys = operation.keys(self.configuration.commit_hide_keys);
+        let message = if keys.is_empty() {
+            action.to_string()
+        } else {
+            format!("{} {}", action, keys)
+        };
+
+        match &self.configuration.commit_template {
+            None => message,
+            Some(template) => template
+                .replace("{message}", &message)
+                .replace("{action}", action)
+                .replace("{keys}", &keys),
+        }
+    }
 
+    /// Commits the shrine file, and only it: the commit's tree is HEAD's tree with the shrine
+    /// file replaced, so that the changes staged by the user are not committed along.
+    pub fn commit(&self, message: &str) -> Result<String, Error> {
+        let repository = &self.state.repository;
+        let parent = self.find_last_commit()?;
+
+        let blob = repository.blob_path(&self.path.as_ref().join(SHRINE_FILENAME))?;
+        let baseline = match &parent {
+            Some(parent) => parent.tree()?,
+            None => repository.find_tree(repository.treebuilder(None)?.write()?)?,
+        };
+        let tree = TreeUpdateBuilder::new()
+            .upsert(&self.state.file, blob, FileMode::Blob)
+            .create_updated(repository, &baseline)?;
+        let tree = repository.find_tree(tree)?;
+
+        let signature = self.signature()?;
+        let parents = parent.as_ref().into_iter().collect::<Vec<&Commit>>();
+        let commit = self.write_commit(&signature, &signature, message, &tree, &parents)?;
+
+        let head = repository.find_reference("HEAD")?;
+        match head.symbolic_target() {
+            Some(branch) => {
+                repository.reference(branch, commit, true, message)?;
+            }
+            None => repository.set_head_detached(commit)?,
+        }
+
+        // the index's shrine entry matches the new commit, its other entries are left untouched
+        let mut index = repository.index()?;
+        index.add_path(&self.state.file)?;
         index.write()?;
-        let oid = index.write_tree()?;
-        let tree = self.state.repository.find_tree(oid)?;
 
-        let signature = Self::signature()?;
+        Ok(commit.to_string())
+    }
 
-        self.state
-            .repository
-            .commit(
-                Some("HEAD"),
-                &signature,
-                &signature,
-                message,
+    /// Writes a commit, signed if `git.commit.sign` is set, without updating any reference.
+    fn write_commit(
+        &self,
+        author: &Signature,
+        committer: &Signature,
+        message: &str,
+        tree: &Tree,
+        parents: &[&Commit],
+    ) -> Result<Oid, Error> {
+        let repository = &self.state.repository;
+        match self.configuration.commit_sign {
+            None => Ok(repository.commit(None, author, committer, message, tree, parents)?),
+            Some(signing) => {
+                let content =
+                    repository.commit_create_buffer(author, committer, message, tree, parents)?;
+                let content = content
+                    .as_str()
+                    .ok_or_else(|| Error::GitSign("the commit is not valid UTF-8".to_string()))?;
+                Ok(repository.commit_signed(
+                    content,
+                    &self.sign(signing, content, committer)?,
+                    None,
+                )?)
+            }
+        }
+    }
+
+    /// Rewrites the commits of the local branches and tags, replacing each version of the shrine
+    /// file by `rewrite(content)`. If `squash` is set, the current branch is then replaced by a
+    /// single commit holding its last version. The remote-tracking branches, which still reach the
+    /// previous versions until the rewritten branches are pushed, are deleted. The previous
+    /// branches, tags and remote-tracking branches are kept under [`BACKUP_REFS`]; the rewritten
+    /// ones are returned, along with the references left untouched.
+    pub fn rewrite_history<F>(&self, mut rewrite: F, squash: bool) -> Result<Rewrite, Error>
+    where
+        F: FnMut(&[u8]) -> Result<Vec<u8>, Error>,
+    {
+        let repository = &self.state.repository;
+
+        let mut branches = Vec::new();
+        for branch in repository.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            let reference = branch.get();
+            if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+                branches.push((name.to_string(), target));
+            }
+        }
+
+        let mut tags = Vec::new();
+        let mut remotes = Vec::new();
+        let mut others = Vec::new();
+        for reference in repository.references()? {
+            let reference = reference?;
+            let name = match reference.name() {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if name.starts_with("refs/heads/") || name.starts_with(BACKUP_REFS) {
+                continue;
+            }
+            if name.starts_with("refs/remotes/") {
+                remotes.push((name, reference.target()));
+                continue;
+            }
+            match (reference.target(), reference.peel_to_commit()) {
+                (Some(target), Ok(commit)) if name.starts_with("refs/tags/") => {
+                    tags.push((name, target, commit.id()))
+                }
+                _ => others.push(name),
+            }
+        }
+
+        let mut revwalk = repository.revwalk()?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+        for (_, target) in branches.iter() {
+            revwalk.push(*target)?;
+        }
+        for (_, _, commit) in tags.iter() {
+            revwalk.push(*commit)?;
+        }
+
+        let mut blobs = HashMap::<Oid, Oid>::new();
+        let mut commits = HashMap::<Oid, Oid>::new();
+        for oid in revwalk {
+            let commit = repository.find_commit(oid?)?;
+
+            let mut tree = commit.tree()?;
+            match tree.get_path(&self.state.file) {
+                Ok(entry) => {
+                    let blob = match blobs.get(&entry.id()) {
+                        Some(blob) => *blob,
+                        None => {
+                            let content = repository.find_blob(entry.id())?;
+                            let blob = repository.blob(&rewrite(content.content())?)?;
+                            blobs.insert(entry.id(), blob);
+                            blob
+                        }
+                    };
+                    let id = TreeUpdateBuilder::new()
+                        .upsert(&self.state.file, blob, FileMode::Blob)
+                        .create_updated(repository, &tree)?;
+                    tree = repository.find_tree(id)?;
+                }
+                Err(e) if e.code() == ErrorCode::NotFound => {}
+                Err(e) => return Err(Error::Git(e)),
+            }
+
+            // the parents missing from a shallow clone are dropped
+            let parents = commit
+                .parent_ids()
+                .filter_map(|p| commits.get(&p))
+                .map(|p| repository.find_commit(*p))
+                .collect::<Result<Vec<_>, _>>()?;
+            let rewritten = self.write_commit(
+                &commit.author(),
+                &commit.committer(),
+                commit.message().unwrap_or_default(),
                 &tree,
-                self.find_last_commit()?
-                    .as_ref()
-                    .into_iter()
-                    .collect::<Vec<&Commit>>()
-                    .as_slice(),
-            )
-            .map_err(Error::Git)
-            .map(|c| c.to_string())
+                &parents.iter().collect::<Vec<_>>(),
+            )?;
+            commits.insert(commit.id(), rewritten);
+        }
+
+        if squash {
+            let current = format!("refs/heads/{}", self.branch()?);
+            if let Some((_, target)) = branches.iter().find(|(name, _)| *name == current) {
+                let tree = repository.find_commit(commits[target])?.tree()?;
+                let signature = self.signature()?;
+                let squashed =
+                    self.write_commit(&signature, &signature, "Squash shrine history", &tree, &[])?;
+                commits.insert(*target, squashed);
+            }
+        }
+
+        let backup = |name: &str, target: Oid| {
+            let backup = format!("{}{}", BACKUP_REFS, name.trim_start_matches("refs/"));
+            repository.reference(&backup, target, true, "shrine: backup before rewrite")
+        };
+
+        for (name, target) in branches.iter() {
+            backup(name, *target)?;
+            repository.reference(name, commits[target], true, "shrine: rewrite history")?;
+        }
+
+        for (name, target, commit) in tags.iter() {
+            backup(name, *target)?;
+            let rewritten = commits[commit];
+            // annotated tags are created again, unsigned, with the same tagger and message
+            let target = match repository.find_tag(*target) {
+                Ok(tag) => repository.tag_annotation_create(
+                    tag.name().unwrap_or_default(),
+                    &repository.find_object(rewritten, None)?,
+                    &tag.tagger().map_or_else(|| self.signature(), Ok)?,
+                    tag.message().unwrap_or_default(),
+                )?,
+                Err(_) => rewritten,
+            };
+            repository.reference(name, target, true, "shrine: rewrite history")?;
+        }
+
+        for (name, target) in remotes.iter() {
+            if let Some(target) = target {
+                backup(name, *target)?;
+            }
+            repository.find_reference(name)?.delete()?;
+        }
+
+        let short = |name: String, prefix: &str| name.trim_start_matches(prefix).to_string();
+        Ok(Rewrite {
+            commits: commits.len(),
+            branches: branches
+                .into_iter()
+                .map(|(name, _)| short(name, "refs/heads/"))
+                .collect(),
+            tags: tags
+                .into_iter()
+                .map(|(name, _, _)| short(name, "refs/tags/"))
+                .collect(),
+            others,
+        })
+    }
+
+    /// The name of the remote commits are pushed to.
+    pub fn remote(&self) -> &str {
+        &self.configuration.remote
+    }
+
+    /// The author and committer: `user.name` and `user.email` from git's configuration, or the
+    /// current user at the local host.
+    fn signature(&self) -> Result<Signature<'static>, Error> {
+        let config = self.state.repository.config()?;
+        let now = Local::now();
+        let username = whoami::username();
+
+        let name = config
+            .get_string("user.name")
+            .unwrap_or_else(|_| username.clone());
+        let email = config
+            .get_string("user.email")
+            .unwrap_or_else(|_| format!("{}@{}", username, whoami::hostname()));
+
+        Signature::new(
+            &name,
+            &email,
+            &Time::new(now.timestamp(), now.offset().local_minus_utc() / 60),
+        )
+        .map_err(Error::Git)
+    }
+
+    /// Signs the commit `content` the way git does: with `gpg.program` (`gpg` by default) or
+    /// `gpg.ssh.program` (`ssh-keygen` by default), using `user.signingkey`.
+    fn sign(
+        &self,
+        signing: Signing,
+        content: &str,
+        committer: &Signature,
+    ) -> Result<String, Error> {
+        let config = self.state.repository.config()?;
+        let signing = match signing {
+            Signing::Git => match config.get_string("gpg.format").as_deref() {
+                Ok("ssh") => Signing::Ssh,
+                Ok("openpgp") | Err(_) => Signing::Gpg,
+                Ok(format) => {
+                    return Err(Error::GitSign(format!(
+                        "unsupported `gpg.format`: {}",
+                        format
+                    )))
+                }
+            },
+            signing => signing,
+        };
+
+        let mut command = match signing {
+            Signing::Ssh => {
+                let key = config.get_path("user.signingkey").map_err(|_| {
+                    Error::GitSign("`user.signingkey` must be set to an SSH key file".to_string())
+                })?;
+                let mut command = Command::new(
+                    config
+                        .get_string("gpg.ssh.program")
+                        .unwrap_or_else(|_| "ssh-keygen".to_string()),
+                );
+                command.args(["-Y", "sign", "-n", "git", "-f"]).arg(key);
+                command
+            }
+            _ => {
+                let key = config
+                    .get_string("user.signingkey")
+                    .unwrap_or_else(|_| committer.to_string());
+                let mut command = Command::new(
+                    config
+                        .get_string("gpg.program")
+                        .unwrap_or_else(|_| "gpg".to_string()),
+                );
+                command.args(["--status-fd=2", "-bsau", &key]);
+                command
+            }
+        };
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| Error::GitSign(e.to_string()))?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(content.as_bytes())
+            .map_err(|e| Error::GitSign(e.to_string()))?;
+        let output = child
+            .wait_with_output()
+            .map_err(|e| Error::GitSign(e.to_string()))?;
+
+        if !output.status.success() {
+            return Err(Error::GitSign(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            ));
+        }
+        String::from_utf8(output.stdout).map_err(|e| Error::GitSign(e.to_string()))
+    }
+
+    /// Whether the shrine file differs from its last committed version.
+    pub fn has_changes(&self) -> Result<bool, Error> {
+        let status = match self.state.repository.status_file(&self.state.file) {
+            Ok(status) => status,
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(false),
+            Err(e) => return Err(Error::Git(e)),
+        };
+        Ok(!status.is_empty() && !status.is_ignored())
+    }
+
+    /// Whether any tracked file of the working directory differs from its last committed version.
+    fn has_tracked_changes(&self) -> Result<bool, Error> {
+        let mut options = StatusOptions::new();
+        options.include_untracked(false).include_ignored(false);
+        Ok(!self
+            .state
+            .repository
+            .statuses(Some(&mut options))?
+            .is_empty())
+    }
+
+    /// Pushes the current branch to the configured remote (`git.remote`, `origin` by default).
+    pub fn push(&self) -> Result<(), Error> {
+        let branch = self.branch()?;
+        let mut remote = self
+            .state
+            .repository
+            .find_remote(&self.configuration.remote)?;
+
+        let rejection = RefCell::new(None);
+        {
+            let mut callbacks = self.callbacks();
+            callbacks.push_update_reference(|_, status| {
+                if let Some(status) = status {
+                    *rejection.borrow_mut() = Some(status.to_string());
+                }
+                Ok(())
+            });
+
+            let mut options = PushOptions::new();
+            options.remote_callbacks(callbacks);
+            remote.push(
+                &[format!("refs/heads/{0}:refs/heads/{0}", branch)],
+                Some(&mut options),
+            )?;
+        }
+
+        match rejection.into_inner() {
+            None => Ok(()),
+            Some(reason) => Err(Error::GitPushRejected(reason)),
+        }
+    }
+
+    /// Fetches the current branch from the configured remote and fast-forwards to it. If the
+    /// histories diverged, a merge commit is created, the shrine file being merged with
+    /// `merge(base, ours, theirs)` if both sides changed it.
+    pub fn pull<F>(&self, merge: F) -> Result<Pull, Error>
+    where
+        F: FnOnce(Option<&[u8]>, &[u8], &[u8]) -> Result<Vec<u8>, Error>,
+    {
+        let branch = self.branch()?;
+        let mut remote = self
+            .state
+            .repository
+            .find_remote(&self.configuration.remote)?;
+
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(self.callbacks());
+        remote.fetch(
+            &[format!(
+                "+refs/heads/*:refs/remotes/{}/*",
+                self.configuration.remote
+            )],
+            Some(&mut options),
+            None,
+        )?;
+
+        let tracking = match self.state.repository.find_reference(&format!(
+            "refs/remotes/{}/{}",
+            self.configuration.remote, branch
+        )) {
+            Ok(tracking) => tracking,
+            // the remote does not have the branch yet
+            Err(e) if e.code() == ErrorCode::NotFound => return Ok(Pull::UpToDate),
+            Err(e) => return Err(Error::Git(e)),
+        };
+        let remote_commit = self
+            .state
+            .repository
+            .reference_to_annotated_commit(&tracking)?;
+
+        let (analysis, _) = self.state.repository.merge_analysis(&[&remote_commit])?;
+
+        if analysis.is_up_to_date() {
+            Ok(Pull::UpToDate)
+        } else if analysis.is_fast_forward() || analysis.is_unborn() {
+            // the shrine may live in a larger repository: its other files are left alone
+            if self.has_tracked_changes()? {
+                return Err(Error::GitDirtyWorkdir);
+            }
+
+            let target = remote_commit.id();
+            // the working directory still matches the index, so that a safe checkout updates it
+            self.state.repository.checkout_tree(
+                self.state.repository.find_commit(target)?.as_object(),
+                Some(CheckoutBuilder::default().safe()),
+            )?;
+            let refname = format!("refs/heads/{}", branch);
+            match self.state.repository.find_reference(&refname) {
+                Ok(mut reference) => {
+                    reference.set_target(target, "shrine: fast-forward")?;
+                }
+                Err(_) => {
+                    self.state.repository.reference(
+                        &refname,
+                        target,
+                        true,
+                        "shrine: fast-forward",
+                    )?;
+                }
+            }
+            self.state.repository.set_head(&refname)?;
+            Ok(Pull::FastForward(target.to_string()))
+        } else {
+            if self.has_tracked_changes()? {
+                return Err(Error::GitDirtyWorkdir);
+            }
+            let message = format!("merge {}/{}", self.configuration.remote, branch);
+            self.merge(remote_commit.id(), &message, merge)
+                .map(|commit| Pull::Merged(commit.to_string()))
+        }
+    }
+
+    /// Commits the merge of HEAD and `theirs`. The shrine file is merged with `merge`, the other
+    /// files by git, which must not find any conflict.
+    fn merge<F>(&self, theirs: Oid, message: &str, merge: F) -> Result<Oid, Error>
+    where
+        F: FnOnce(Option<&[u8]>, &[u8], &[u8]) -> Result<Vec<u8>, Error>,
+    {
+        let repository = &self.state.repository;
+        let ours = self.find_last_commit()?.ok_or(Error::GitDiverged)?;
+        let theirs = repository.find_commit(theirs)?;
+        let base = match repository.merge_base(ours.id(), theirs.id()) {
+            Ok(base) => Some(repository.find_commit(base)?),
+            Err(e) if e.code() == ErrorCode::NotFound => None,
+            Err(e) => return Err(Error::Git(e)),
+        };
+
+        let blob = |commit: &Commit| match commit.tree()?.get_path(&self.state.file) {
+            Ok(entry) => Ok(Some(entry.id())),
+            Err(e) if e.code() == ErrorCode::NotFound => Ok(None),
+            Err(e) => Err(Error::Git(e)),
+        };
+        let base_blob = base.as_ref().map(blob).transpose()?.flatten();
+        let (ours_blob, theirs_blob) = (blob(&ours)?, blob(&theirs)?);
+
+        // the shrine file is merged below, whatever git found
+        let mut index = repository.merge_commits(&ours, &theirs, None)?;
+        index.remove_path(&self.state.file)?;
+        if index.has_conflicts() {
+            return Err(Error::GitDiverged);
+        }
+        let tree = repository.find_tree(index.write_tree_to(repository)?)?;
+
+        // the shrine is only merged when both sides changed it
+        let merged = match (ours_blob, theirs_blob) {
+            (Some(o), Some(t)) if o != t && base_blob != Some(o) && base_blob != Some(t) => {
+                let content = |oid: Oid| repository.find_blob(oid).map(|b| b.content().to_vec());
+                let base = base_blob.map(content).transpose()?;
+                Some(repository.blob(&merge(base.as_deref(), &content(o)?, &content(t)?)?)?)
+            }
+            (Some(o), t) if base_blob == Some(o) => t,
+            (Some(o), _) => Some(o),
+            (None, t) => t,
+        };
+        let tree = match merged {
+            Some(blob) => repository.find_tree(
+                TreeUpdateBuilder::new()
+                    .upsert(&self.state.file, blob, FileMode::Blob)
+                    .create_updated(repository, &tree)?,
+            )?,
+            None => tree,
+        };
+
+        let signature = self.signature()?;
+        let commit =
+            self.write_commit(&signature, &signature, message, &tree, &[&ours, &theirs])?;
+
+        // the working directory still matches the index, so that a safe checkout updates it
+        repository.checkout_tree(
+            repository.find_commit(commit)?.as_object(),
+            Some(CheckoutBuilder::default().safe()),
+        )?;
+        repository.reference(
+            &format!("refs/heads/{}", self.branch()?),
+            commit,
+            true,
+            message,
+        )?;
+
+        Ok(commit)
+    }
+
+    /// The commits reachable from HEAD, most recent first, along with the shrine file they hold.
+    pub fn revisions(&self) -> Result<Vec<Revision>, Error> {
+        if self.find_last_commit()?.is_none() {
+            return Ok(Vec::new());
+        }
+
+        let mut revwalk = self.state.repository.revwalk()?;
+        revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        revwalk.push_head()?;
+
+        revwalk
+            .map(|oid| {
+                let commit = self.state.repository.find_commit(oid?)?;
+                let shrine = match commit.tree()?.get_path(&self.state.file) {
+                    Ok(entry) => {
+                        let blob = self.state.repository.find_blob(entry.id())?;
+                        Some((blob.id().to_string(), blob.content().to_vec()))
+                    }
+                    Err(e) if e.code() == ErrorCode::NotFound => None,
+                    Err(e) => return Err(Error::Git(e)),
+                };
+                let time = commit.time();
+                let author = commit.author().name().unwrap_or_default().to_string();
+
+                Ok(Revision {
+                    id: commit.id().to_string(),
+                    parents: commit.parent_ids().map(|p| p.to_string()).collect(),
+                    author,
+                    time: FixedOffset::east_opt(time.offset_minutes() * 60)
+                        .and_then(|o| o.timestamp_opt(time.seconds(), 0).single())
+                        .unwrap_or_default(),
+                    summary: commit.summary().unwrap_or_default().to_string(),
+                    shrine,
+                })
+            })
+            .collect()
+    }
+
+    /// The shrine file as of `revision`: a commit (e.g. a hash, a tag or `HEAD~2`) or a date (see
+    /// [`parse_date`]), in which case the last commit before it is used. The working copy is left
+    /// untouched.
+    pub fn shrine_at(&self, revision: &str) -> Result<Shrine<shrine::Closed>, Error> {
+        let not_found = || Error::GitRevisionNotFound(revision.to_string());
+
+        let commit = match self
+            .state
+            .repository
+            .revparse_single(revision)
+            .and_then(|o| o.peel_to_commit())
+        {
+            Ok(commit) => commit,
+            Err(_) => {
+                let date = parse_date(revision).ok_or_else(not_found)?.timestamp();
+                let mut revwalk = self.state.repository.revwalk()?;
+                revwalk.set_sorting(Sort::TIME)?;
+                revwalk.push_head().map_err(|_| not_found())?;
+                revwalk
+                    .map(|oid| self.state.repository.find_commit(oid?))
+                    .find(|c| {
+                        c.as_ref()
+                            .map(|c| c.time().seconds() <= date)
+                            .unwrap_or(true)
+                    })
+                    .ok_or_else(not_found)??
+            }
+        };
+
+        let entry = commit
+            .tree()?
+            .get_path(&self.state.file)
+            .map_err(|_| Error::NotInRevision(revision.to_string()))?;
+        let blob = self.state.repository.find_blob(entry.id())?;
+        Shrine::from_bytes(blob.content())
+    }
+
+    /// Configures git to merge the shrine file with `command` (e.g. `shrine merge-driver %O %A
+    /// %B`) instead of reporting a binary conflict.
+    pub fn install_merge_driver(&self, command: &str) -> Result<(), Error> {
+        let mut config = self
+            .state
+            .repository
+            .config()?
+            .open_level(ConfigLevel::Local)?;
+        config.set_str("merge.shrine.name", "shrine key-level merge driver")?;
+        config.set_str("merge.shrine.driver", command)?;
+
+        self.add_attribute("merge=shrine")
+    }
+
+    /// Configures git to diff the shrine file as the output of `command` (e.g. `shrine textconv`)
+    /// instead of reporting that binary files differ.
+    pub fn install_textconv(&self, command: &str) -> Result<(), Error> {
+        let mut config = self
+            .state
+            .repository
+            .config()?
+            .open_level(ConfigLevel::Local)?;
+        config.set_str("diff.shrine.textconv", command)?;
+
+        self.add_attribute("diff=shrine")
     }
 
-    fn find_last_commit(&self) -> Result<Option<Commit>, Error> {
+    /// Adds `attribute` to the shrine file in `.gitattributes`, unless it is already there.
+    fn add_attribute(&self, attribute: &str) -> Result<(), Error> {
+        let file = self.path.as_ref().join(GITATTRIBUTES_FILENAME);
+        let attributes = match fs::read_to_string(&file) {
+            Ok(attributes) => attributes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Error::ReadFile(file, e)),
+        };
+
+        let attribute = format!("{} {}", SHRINE_FILENAME, attribute);
+        if !attributes.lines().any(|l| l.trim() == attribute) {
+            let mut attributes = attributes;
+            if !attributes.is_empty() && !attributes.ends_with('\n') {
+                attributes.push('\n');
+            }
+            attributes.push_str(&attribute);
+            attributes.push('\n');
+            fs::write(&file, attributes).map_err(|e| Error::WriteFile(file, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// The name of the branch HEAD points to, even if it does not have any commit yet.
+    fn branch(&self) -> Result<String, Error> {
+        let head = self.state.repository.find_reference("HEAD")?;
+        let target = head
+            .symbolic_target()
+            .or_else(|| head.name())
+            .unwrap_or_default();
+        Ok(target
+            .strip_prefix("refs/heads/")
+            .unwrap_or(target)
+            .to_string())
+    }
+
+    /// Credentials are looked up in the SSH agent for SSH remotes, and using git's credential
+    /// helpers for HTTPS remotes.
+    fn callbacks(&self) -> RemoteCallbacks<'_> {
+        let mut attempts = 0;
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username, allowed| {
+            attempts += 1;
+            if attempts > 3 {
+                return Err(git2::Error::from_str("authentication failed"));
+            }
+
+            if allowed.contains(CredentialType::SSH_KEY) {
+                Cred::ssh_key_from_agent(username.unwrap_or("git"))
+            } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                Cred::credential_helper(&self.state.repository.config()?, url, username)
+            } else if allowed.contains(CredentialType::USERNAME) {
+                Cred::username(username.unwrap_or("git"))
+            } else {
+                Cred::default()
+            }
+        });
+        callbacks
+    }
+
+    fn find_last_commit(&self) -> Result<Option<Commit<'_>>, Error> {
         let head = match self.state.repository.head() {
             Ok(head) => head,
             Err(_) => return Ok(None),
@@ -187,6 +951,851 @@ where
     }
 }
 
+/// What a commit changes, see [`Repository::message`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Init,
+    Set(String),
+    Attach(String),
+    Remove(Vec<String>),
+    /// Changes made with `git.commit.auto` disabled.
+    Update(Vec<String>),
+    Convert,
+    Config(String),
+    /// A message provided by the user.
+    Message(String),
+}
+
+impl Operation {
+    fn action(&self) -> &str {
+        match self {
+            Operation::Init => "init",
+            Operation::Set(_) => "set",
+            Operation::Attach(_) => "attach",
+            Operation::Remove(_) => "rm",
+            Operation::Update(_) => "update",
+            Operation::Convert => "convert",
+            Operation::Config(_) => "config",
+            Operation::Message(message) => message,
+        }
+    }
+
+    fn keys(&self, hide: bool) -> String {
+        let count = |count: usize| match count {
+            1 => "1 key".to_string(),
+            n => format!("{} keys", n),
+        };
+
+        match self {
+            Operation::Init | Operation::Convert | Operation::Message(_) => String::new(),
+            // configuration keys are not secret
+            Operation::Config(key) => key.clone(),
+            Operation::Set(key) | Operation::Attach(key) if hide => count(1),
+            Operation::Set(key) | Operation::Attach(key) => key.clone(),
+            Operation::Update(keys) if keys.is_empty() => "shrine".to_string(),
+            Operation::Remove(keys) | Operation::Update(keys) if hide => count(keys.len()),
+            Operation::Remove(keys) | Operation::Update(keys) if keys.len() == 1 => keys[0].clone(),
+            Operation::Remove(keys) | Operation::Update(keys) => {
+                let prefix = keys
+                    .iter()
+                    .map(|k| k.rsplit_once('/').map(|(p, _)| p).unwrap_or_default())
+                    .reduce(common_prefix)
+                    .unwrap_or_default();
+                if prefix.is_empty() {
+                    count(keys.len())
+                } else {
+                    format!("{} under {}/", count(keys.len()), prefix)
+                }
+            }
+        }
+    }
+}
+
+/// The longest common prefix of two keys, made of whole path segments.
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a
+        .split('/')
+        .zip(b.split('/'))
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len() + 1)
+        .sum::<usize>();
+    &a[..len.saturating_sub(1).min(a.len())]
+}
+
+/// The references under which [`Repository::rewrite_history`] keeps the previous branches.
+pub const BACKUP_REFS: &str = "refs/shrine-backup/";
+
+/// The result of [`Repository::rewrite_history`].
+#[derive(Debug)]
+pub struct Rewrite {
+    /// The number of commits rewritten.
+    pub commits: usize,
+    /// The rewritten branches.
+    pub branches: Vec<String>,
+    /// The rewritten tags.
+    pub tags: Vec<String>,
+    /// The references that were not rewritten, e.g. `refs/stash`, and may still reach the previous
+    /// versions of the shrine.
+    pub others: Vec<String>,
+}
+
+/// A commit, as returned by [`Repository::revisions`].
+#[derive(Debug)]
+pub struct Revision {
+    pub id: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub time: DateTime<FixedOffset>,
+    pub summary: String,
+    /// The id and content of the shrine file's blob, if the commit has one.
+    pub shrine: Option<(String, Vec<u8>)>,
+}
+
+/// The outcome of [`Repository::pull`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pull {
+    UpToDate,
+    /// The local branch was fast-forwarded to the given commit.
+    FastForward(String),
+    /// The diverged histories were merged in the given commit.
+    Merged(String),
+}
+
 pub fn write_configuration(shrine: &mut Shrine<shrine::Open>) {
     Configuration::default().write(shrine);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shrine::{Mode, ShrinePassword};
+    use std::path::PathBuf;
+
+    fn save(folder: &Path, shrine: Shrine<shrine::Open>) {
+        shrine
+            .close(&ShrinePassword::default())
+            .unwrap()
+            .to_path(folder)
+            .unwrap();
+    }
+
+    fn load(folder: &Path) -> Shrine<shrine::Open> {
+        Shrine::from_path(folder)
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap()
+    }
+
+    fn set(folder: &Path, key: &str, push_auto: bool) -> Result<String, Error> {
+        let mut shrine = load(folder);
+        shrine.set(key, "value", Mode::Text).unwrap();
+        shrine.set_private("git.push.auto".to_string(), push_auto.to_string());
+        let repository = Repository::new(folder.to_path_buf(), &shrine).unwrap();
+        save(folder, shrine);
+        repository
+            .open()?
+            .create_commit(&Operation::Set(key.to_string()))
+    }
+
+    fn set_config(folder: &Path, key: &str, value: &str) {
+        let mut shrine = load(folder);
+        shrine.set_private(key.to_string(), value.to_string());
+        save(folder, shrine);
+    }
+
+    /// Verifies the signature of the last commit with `verify`, given the signature's and the
+    /// signed content's paths.
+    fn verify_signature<F>(folder: &Path, verify: F)
+    where
+        F: FnOnce(&Path, &Path) -> std::process::Output,
+    {
+        let repository = open(folder);
+        let commit = repository.find_last_commit().unwrap().unwrap();
+        let (signature, content) = repository
+            .state
+            .repository
+            .extract_signature(&commit.id(), None)
+            .unwrap();
+
+        let files = tempfile::tempdir().unwrap();
+        fs::write(files.path().join("signature"), &*signature).unwrap();
+        fs::write(files.path().join("content"), &*content).unwrap();
+        let output = verify(
+            &files.path().join("signature"),
+            &files.path().join("content"),
+        );
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    /// Creates a bare remote and a clone of it with an initial commit.
+    fn setup() -> (tempfile::TempDir, tempfile::TempDir, PathBuf) {
+        let remote = tempfile::tempdir().unwrap();
+        git2::Repository::init_bare(remote.path()).unwrap();
+
+        let local = tempfile::tempdir().unwrap();
+        let mut shrine = Shrine::default();
+        write_configuration(&mut shrine);
+        shrine.set_private("git.push.auto".to_string(), "true".to_string());
+        let repository = Repository::new(local.path().to_path_buf(), &shrine).unwrap();
+        save(local.path(), shrine);
+
+        let repository = repository.open().unwrap();
+        repository
+            .state
+            .repository
+            .remote(DEFAULT_REMOTE, remote.path().to_str().unwrap())
+            .unwrap();
+        repository.create_commit(&Operation::Init).unwrap();
+
+        let branch = repository.branch().unwrap();
+        git2::Repository::open_bare(remote.path())
+            .unwrap()
+            .set_head(&format!("refs/heads/{}", branch))
+            .unwrap();
+
+        let path = local.path().to_path_buf();
+        (remote, local, path)
+    }
+
+    fn clone(remote: &Path) -> tempfile::TempDir {
+        let folder = tempfile::tempdir().unwrap();
+        git2::Repository::clone(remote.to_str().unwrap(), folder.path()).unwrap();
+        folder
+    }
+
+    /// Merges unencrypted shrine files key by key, failing on conflicts.
+    fn merge(base: Option<&[u8]>, ours: &[u8], theirs: &[u8]) -> Result<Vec<u8>, Error> {
+        let open = |bytes: &[u8]| Shrine::from_bytes(bytes)?.open(&ShrinePassword::default());
+        let base = base.map(open).transpose()?;
+        let mut merged = open(ours)?;
+        if !merged.merge(base.as_ref(), &open(theirs)?)?.is_empty() {
+            return Err(Error::MergeConflicts(1));
+        }
+        merged.close(&ShrinePassword::default())?.as_bytes()
+    }
+
+    fn open(folder: &Path) -> Repository<PathBuf, Open> {
+        Repository::new(folder.to_path_buf(), &load(folder))
+            .unwrap()
+            .open()
+            .unwrap()
+    }
+
+    #[test]
+    fn push_then_pull() {
+        let (remote, _local, local_path) = setup();
+        let other = clone(remote.path());
+
+        set(other.path(), "key", true).unwrap();
+
+        assert!(matches!(
+            open(&local_path).pull(merge).unwrap(),
+            Pull::FastForward(_)
+        ));
+        assert!(load(&local_path).get("key").is_ok());
+        assert_eq!(open(&local_path).pull(merge).unwrap(), Pull::UpToDate);
+    }
+
+    #[test]
+    fn pull_keeps_uncommitted_changes() {
+        let (remote, _local, local_path) = setup();
+        let other = clone(remote.path());
+
+        // another file of the repository
+        fs::write(other.path().join("notes"), "remote").unwrap();
+        let repository = git2::Repository::open(other.path()).unwrap();
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new("notes")).unwrap();
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = repository.head().unwrap().peel_to_commit().unwrap();
+        let signature = git2::Signature::now("other", "other@localhost").unwrap();
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "notes",
+                &tree,
+                &[&parent],
+            )
+            .unwrap();
+        set(other.path(), "key", true).unwrap();
+        open(&local_path).pull(merge).unwrap();
+
+        fs::write(local_path.join("notes"), "local").unwrap();
+        set(other.path(), "other", true).unwrap();
+
+        assert_eq!(
+            open(&local_path).pull(merge).unwrap_err().to_string(),
+            Error::GitDirtyWorkdir.to_string()
+        );
+        assert_eq!(
+            fs::read_to_string(local_path.join("notes")).unwrap(),
+            "local"
+        );
+    }
+
+    #[test]
+    fn push_unreachable_remote() {
+        let (remote, _local, local_path) = setup();
+        open(&local_path)
+            .state
+            .repository
+            .remote_set_url(
+                DEFAULT_REMOTE,
+                &remote.path().join("missing").to_string_lossy(),
+            )
+            .unwrap();
+
+        let commit = set(&local_path, "key", true).unwrap();
+
+        let repository = open(&local_path);
+        let last_commit = repository.find_last_commit().unwrap().unwrap();
+        assert_eq!(last_commit.id().to_string(), commit);
+        assert_eq!(last_commit.summary(), Some("set key"));
+    }
+
+    #[test]
+    fn push_rejected_then_diverged() {
+        let (remote, _local, local_path) = setup();
+        let other = clone(remote.path());
+
+        set(other.path(), "other", true).unwrap();
+
+        // the rejected push does not fail the commit
+        let commit = set(&local_path, "local", true).unwrap();
+        assert_eq!(
+            open(&local_path)
+                .find_last_commit()
+                .unwrap()
+                .unwrap()
+                .id()
+                .to_string(),
+            commit
+        );
+        assert!(matches!(
+            open(&local_path).pull(merge).unwrap(),
+            Pull::Merged(_)
+        ));
+    }
+
+    #[test]
+    fn pull_diverged() {
+        let (remote, _local, local_path) = setup();
+        let other = clone(remote.path());
+
+        set(other.path(), "other", true).unwrap();
+        set(&local_path, "local", false).unwrap();
+
+        let merged = match open(&local_path).pull(merge).unwrap() {
+            Pull::Merged(commit) => commit,
+            pull => panic!("unexpected {:?}", pull),
+        };
+
+        let repository = open(&local_path);
+        let commit = repository.find_last_commit().unwrap().unwrap();
+        assert_eq!(commit.id().to_string(), merged);
+        assert_eq!(commit.parent_count(), 2);
+        assert_eq!(commit.summary(), Some("merge origin/master"));
+
+        let shrine = load(&local_path);
+        assert!(shrine.get("local").is_ok());
+        assert!(shrine.get("other").is_ok());
+        assert!(!repository.has_changes().unwrap());
+
+        repository.push().unwrap();
+        assert!(matches!(
+            open(other.path()).pull(merge).unwrap(),
+            Pull::FastForward(_)
+        ));
+        assert!(load(other.path()).get("local").is_ok());
+    }
+
+    #[test]
+    fn pull_conflict() {
+        let (remote, _local, local_path) = setup();
+        let other = clone(remote.path());
+
+        set(other.path(), "key", true).unwrap();
+        let mut shrine = load(&local_path);
+        shrine.set("key", "local", Mode::Text).unwrap();
+        save(&local_path, shrine);
+        let commit = open(&local_path).commit("set key").unwrap();
+
+        assert_eq!(
+            open(&local_path).pull(merge).unwrap_err().to_string(),
+            Error::MergeConflicts(1).to_string()
+        );
+        let repository = open(&local_path);
+        assert_eq!(
+            repository
+                .find_last_commit()
+                .unwrap()
+                .unwrap()
+                .id()
+                .to_string(),
+            commit
+        );
+    }
+
+    #[test]
+    fn revisions() {
+        let (_remote, _local, local_path) = setup();
+        fs::write(local_path.join("other"), "").unwrap();
+        set(&local_path, "key", false).unwrap();
+
+        let revisions = open(&local_path).revisions().unwrap();
+
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].summary, "set key");
+        assert_eq!(revisions[0].parents, vec![revisions[1].id.clone()]);
+        assert_eq!(revisions[1].summary, "init");
+        assert!(revisions[1].parents.is_empty());
+
+        let (_, content) = revisions[0].shrine.as_ref().unwrap();
+        assert!(Shrine::from_bytes(content)
+            .unwrap()
+            .open(&ShrinePassword::default())
+            .unwrap()
+            .get("key")
+            .is_ok());
+    }
+
+    #[test]
+    fn shrine_at() {
+        let (_remote, _local, local_path) = setup();
+        set(&local_path, "key", false).unwrap();
+
+        let repository = open(&local_path);
+        let open = |revision: &str| {
+            repository
+                .shrine_at(revision)
+                .map(|s| s.open(&ShrinePassword::default()).unwrap())
+        };
+
+        assert!(open("HEAD").unwrap().get("key").is_ok());
+        assert!(open("HEAD~1").unwrap().get("key").is_err());
+        assert!(open("2100-01-01").unwrap().get("key").is_ok());
+        assert_eq!(
+            open("2000-01-01").unwrap_err().to_string(),
+            "Revision `2000-01-01` not found"
+        );
+        assert_eq!(
+            open("unknown").unwrap_err().to_string(),
+            "Revision `unknown` not found"
+        );
+    }
+
+    #[test]
+    fn messages() {
+        let (_remote, _local, local_path) = setup();
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        let message = |configuration: &[(&str, &str)], operation: Operation| {
+            let mut shrine = load(&local_path);
+            for (key, value) in configuration {
+                shrine.set_private(key.to_string(), value.to_string());
+            }
+            Repository::new(&local_path, &shrine)
+                .unwrap()
+                .open()
+                .unwrap()
+                .message(&operation)
+        };
+
+        assert_eq!(
+            message(&[], Operation::Set("prod/db/password".to_string())),
+            "set prod/db/password"
+        );
+        assert_eq!(
+            message(
+                &[],
+                Operation::Remove(keys(&["staging/db/a", "staging/db/b", "staging/api"]))
+            ),
+            "rm 3 keys under staging/"
+        );
+        assert_eq!(
+            message(&[], Operation::Remove(keys(&["a", "staging/b"]))),
+            "rm 2 keys"
+        );
+        assert_eq!(message(&[], Operation::Update(Vec::new())), "update shrine");
+        assert_eq!(
+            message(
+                &[("git.commit.hide-keys", "true")],
+                Operation::Set("prod/db/password".to_string())
+            ),
+            "set 1 key"
+        );
+        assert_eq!(
+            message(
+                &[("git.commit.template", "secrets({action}): {keys}")],
+                Operation::Config("git.push.auto".to_string())
+            ),
+            "secrets(config): git.push.auto"
+        );
+        assert_eq!(
+            message(
+                &[("git.commit.template", "[shrine] {message}")],
+                Operation::Message("Rotate credentials".to_string())
+            ),
+            "[shrine] Rotate credentials"
+        );
+    }
+
+    #[test]
+    fn install_merge_driver() {
+        let (_remote, _local, local_path) = setup();
+        fs::write(local_path.join(".gitattributes"), "*.txt text").unwrap();
+
+        let repository = open(&local_path);
+        repository
+            .install_merge_driver("shrine merge-driver")
+            .unwrap();
+        repository
+            .install_merge_driver("shrine merge-driver")
+            .unwrap();
+
+        assert_eq!(
+            fs::read_to_string(local_path.join(".gitattributes")).unwrap(),
+            "*.txt text\nshrine merge=shrine\n"
+        );
+        let config = repository.state.repository.config().unwrap();
+        assert_eq!(
+            config.get_string("merge.shrine.driver").unwrap(),
+            "shrine merge-driver"
+        );
+    }
+
+    #[test]
+    fn has_changes() {
+        let (_remote, _local, local_path) = setup();
+
+        assert!(!open(&local_path).has_changes().unwrap());
+
+        let mut shrine = load(&local_path);
+        shrine.set("key", "value", Mode::Text).unwrap();
+        save(&local_path, shrine);
+
+        assert!(open(&local_path).has_changes().unwrap());
+    }
+
+    #[test]
+    fn commit_leaves_staged_files() {
+        let (_remote, _local, local_path) = setup();
+        fs::write(local_path.join("staged"), "").unwrap();
+        let repository = open(&local_path);
+        let mut index = repository.state.repository.index().unwrap();
+        index.add_path(Path::new("staged")).unwrap();
+        index.write().unwrap();
+
+        set(&local_path, "key", false).unwrap();
+
+        let repository = open(&local_path);
+        let tree = repository
+            .find_last_commit()
+            .unwrap()
+            .unwrap()
+            .tree()
+            .unwrap();
+        assert!(tree.get_path(Path::new(SHRINE_FILENAME)).is_ok());
+        assert!(tree.get_path(Path::new("staged")).is_err());
+        assert_eq!(
+            repository
+                .state
+                .repository
+                .status_file(Path::new("staged"))
+                .unwrap(),
+            git2::Status::INDEX_NEW
+        );
+        assert!(!repository.has_changes().unwrap());
+    }
+
+    #[test]
+    fn shrine_in_subdirectory() {
+        let folder = tempfile::tempdir().unwrap();
+        let repository = git2::Repository::init(folder.path()).unwrap();
+        fs::write(folder.path().join("README.md"), "").unwrap();
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new("README.md")).unwrap();
+        index.write().unwrap();
+
+        let path = folder.path().join("config").join("secrets");
+        fs::create_dir_all(&path).unwrap();
+        let mut shrine = Shrine::default();
+        write_configuration(&mut shrine);
+        save(&path, shrine);
+        open(&path).create_commit(&Operation::Init).unwrap();
+        set(&path, "key", false).unwrap();
+
+        let repository = open(&path);
+        assert_eq!(
+            repository
+                .state
+                .repository
+                .workdir()
+                .unwrap()
+                .canonicalize()
+                .unwrap(),
+            folder.path().canonicalize().unwrap()
+        );
+        let tree = repository
+            .find_last_commit()
+            .unwrap()
+            .unwrap()
+            .tree()
+            .unwrap();
+        assert!(tree.get_path(Path::new("config/secrets/shrine")).is_ok());
+        assert!(tree.get_path(Path::new("README.md")).is_err());
+        assert_eq!(repository.revisions().unwrap().len(), 2);
+        assert!(repository.shrine_at("HEAD~1").is_ok());
+        assert!(!repository.has_changes().unwrap());
+    }
+
+    #[test]
+    fn signature_from_git_config() {
+        let (_remote, _local, local_path) = setup();
+        let mut config = open(&local_path).state.repository.config().unwrap();
+        config.set_str("user.name", "Alice").unwrap();
+        config.set_str("user.email", "alice@example.com").unwrap();
+
+        set(&local_path, "key", false).unwrap();
+
+        let repository = open(&local_path);
+        let commit = repository.find_last_commit().unwrap().unwrap();
+        assert_eq!(commit.author().name(), Some("Alice"));
+        assert_eq!(commit.committer().email(), Some("alice@example.com"));
+    }
+
+    #[test]
+    fn ssh_signed_commit() {
+        let (_remote, _local, local_path) = setup();
+        let keys = tempfile::tempdir().unwrap();
+        let key = keys.path().join("id_ed25519");
+        assert!(Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(&key)
+            .status()
+            .unwrap()
+            .success());
+        open(&local_path)
+            .state
+            .repository
+            .config()
+            .unwrap()
+            .set_str("user.signingkey", key.to_str().unwrap())
+            .unwrap();
+        set_config(&local_path, "git.commit.sign", "ssh");
+
+        set(&local_path, "key", false).unwrap();
+
+        let repository = open(&local_path);
+        assert_eq!(repository.revisions().unwrap().len(), 2);
+        assert!(!repository.has_changes().unwrap());
+        verify_signature(&local_path, |signature, content| {
+            Command::new("ssh-keygen")
+                .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+                .arg(signature)
+                .stdin(fs::File::open(content).unwrap())
+                .output()
+                .unwrap()
+        });
+    }
+
+    #[test]
+    fn gpg_signed_commit() {
+        let (_remote, _local, local_path) = setup();
+        let home = tempfile::tempdir().unwrap();
+        let gpg = |args: &[&str]| {
+            Command::new("gpg")
+                .arg("--homedir")
+                .arg(home.path())
+                .args(["--batch", "--passphrase", ""])
+                .args(args)
+                .output()
+                .unwrap()
+        };
+        assert!(gpg(&[
+            "--quick-gen-key",
+            "Shrine <shrine@example.com>",
+            "ed25519",
+            "sign",
+            "never"
+        ])
+        .status
+        .success());
+
+        let program = home.path().join("gpg.sh");
+        fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\nexec gpg --homedir '{}' \"$@\"\n",
+                home.path().display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(
+            &program,
+            std::os::unix::fs::PermissionsExt::from_mode(0o700),
+        )
+        .unwrap();
+        let mut config = open(&local_path).state.repository.config().unwrap();
+        config
+            .set_str("gpg.program", program.to_str().unwrap())
+            .unwrap();
+        config
+            .set_str("user.signingkey", "shrine@example.com")
+            .unwrap();
+        set_config(&local_path, "git.commit.sign", "true");
+
+        set(&local_path, "key", false).unwrap();
+
+        verify_signature(&local_path, |signature, content| {
+            gpg(&[
+                "--verify",
+                signature.to_str().unwrap(),
+                content.to_str().unwrap(),
+            ])
+        });
+        let _ = Command::new("gpgconf")
+            .arg("--homedir")
+            .arg(home.path())
+            .args(["--kill", "gpg-agent"])
+            .status();
+    }
+
+    #[test]
+    fn sign_failure() {
+        let (_remote, _local, local_path) = setup();
+        set_config(&local_path, "git.commit.sign", "ssh");
+
+        let err = set(&local_path, "key", false).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Could not sign the commit: `user.signingkey` must be set to an SSH key file"
+        );
+        assert_eq!(open(&local_path).revisions().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rewrite_history() {
+        let (_remote, _local, local_path) = setup();
+        set(&local_path, "a", false).unwrap();
+        let repository = open(&local_path);
+        let first = repository.find_last_commit().unwrap().unwrap();
+        repository
+            .state
+            .repository
+            .branch("other", &first, false)
+            .unwrap();
+        set(&local_path, "b", false).unwrap();
+        let head = open(&local_path).find_last_commit().unwrap().unwrap().id();
+
+        let repository = open(&local_path);
+        let git = &repository.state.repository;
+        git.tag_lightweight("v1", &git.find_object(first.id(), None).unwrap(), false)
+            .unwrap();
+        let tagger = git2::Signature::now("tagger", "tagger@localhost").unwrap();
+        let annotated = git
+            .tag(
+                "v2",
+                &git.find_object(head, None).unwrap(),
+                &tagger,
+                "v2",
+                false,
+            )
+            .unwrap();
+        git.reference("refs/remotes/origin/old", first.id(), true, "")
+            .unwrap();
+        git.reference("refs/stash", first.id(), true, "").unwrap();
+
+        let rewrite = repository
+            .rewrite_history(|content| Ok([content, b"!"].concat()), false)
+            .unwrap();
+
+        assert_eq!(rewrite.commits, 3);
+        assert_eq!(rewrite.branches.len(), 2);
+        assert!(rewrite.branches.contains(&"other".to_string()));
+        assert_eq!(rewrite.tags, vec!["v1", "v2"]);
+        assert_eq!(rewrite.others, vec!["refs/stash"]);
+
+        let shrine_at = |revision: &str| {
+            let commit = git
+                .revparse_single(revision)
+                .unwrap()
+                .peel_to_commit()
+                .unwrap();
+            let entry = commit
+                .tree()
+                .unwrap()
+                .get_path(&repository.state.file)
+                .unwrap();
+            git.find_blob(entry.id()).unwrap().content().to_vec()
+        };
+        assert!(shrine_at("v1").ends_with(b"!"));
+        assert!(shrine_at("v2").ends_with(b"!"));
+        let v2 = git
+            .find_reference("refs/tags/v2")
+            .unwrap()
+            .peel_to_tag()
+            .unwrap();
+        assert_eq!(v2.tagger().unwrap().name(), Some("tagger"));
+        assert_ne!(v2.target_id(), head);
+        assert_eq!(
+            git.refname_to_id(&format!("{}tags/v2", BACKUP_REFS))
+                .unwrap(),
+            annotated
+        );
+
+        assert!(git.find_reference("refs/remotes/origin/old").is_err());
+        assert_eq!(
+            git.refname_to_id(&format!("{}remotes/origin/old", BACKUP_REFS))
+                .unwrap(),
+            first.id()
+        );
+
+        let branch = repository.branch().unwrap();
+        let backup = repository
+            .state
+            .repository
+            .refname_to_id(&format!("{}heads/{}", BACKUP_REFS, branch))
+            .unwrap();
+        assert_eq!(backup, head);
+
+        let revisions = repository.revisions().unwrap();
+        assert_eq!(revisions.len(), 3);
+        assert_eq!(revisions[0].summary, "set b");
+        for revision in revisions {
+            assert!(revision.shrine.unwrap().1.ends_with(b"!"));
+        }
+    }
+
+    #[test]
+    fn rewrite_history_squash() {
+        let (_remote, _local, local_path) = setup();
+        set(&local_path, "a", false).unwrap();
+        let repository = open(&local_path);
+        let tree = repository
+            .find_last_commit()
+            .unwrap()
+            .unwrap()
+            .tree()
+            .unwrap()
+            .id();
+
+        repository
+            .rewrite_history(|content| Ok(content.to_vec()), true)
+            .unwrap();
+
+        let revisions = repository.revisions().unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].summary, "Squash shrine history");
+        assert!(revisions[0].parents.is_empty());
+        let after = repository.find_last_commit().unwrap().unwrap();
+        assert_eq!(after.tree().unwrap().id(), tree);
+        assert!(!repository.has_changes().unwrap());
+    }
+}
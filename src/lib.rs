@@ -3,12 +3,18 @@ use std::error::Error as StdError;
 use std::path::PathBuf;
 
 pub mod agent;
+pub mod attachment;
 pub mod bytes;
 pub mod controller;
 pub mod encrypt;
 pub mod git;
+pub mod pem;
+pub mod record;
 pub mod serialize;
+pub mod settings;
 pub mod shrine;
+pub mod ssh;
+pub mod totp;
 pub mod utils;
 
 static SHRINE_FILENAME: &str = "shrine";
@@ -17,6 +23,26 @@ static SHRINE_FILENAME: &str = "shrine";
 pub enum Error {
     #[error("Could not perform git action")]
     Git(#[from] git2::Error),
+    #[error("Git is not enabled for this shrine")]
+    GitDisabled,
+    #[error("Push rejected by remote: {0}")]
+    GitPushRejected(String),
+    #[error("The local and remote shrines have diverged")]
+    GitDiverged,
+    #[error("The shrine has uncommitted changes; commit them first")]
+    GitUncommittedChanges,
+    #[error("The repository has uncommitted changes; commit or stash them first")]
+    GitDirtyWorkdir,
+    #[error("Could not sign the commit: {0}")]
+    GitSign(String),
+    #[error("Revision `{0}` not found")]
+    GitRevisionNotFound(String),
+    #[error("The shrine does not exist at `{0}`")]
+    NotInRevision(String),
+    #[error(
+        "{0} conflict(s) while merging the shrine; our version of the conflicting keys was kept"
+    )]
+    MergeConflicts(usize),
 
     #[error("The input file `{1}` is not a valid dotenv file: {0}")]
     InvalidDotEnv(Box<dyn StdError>, PathBuf),
@@ -29,6 +55,26 @@ pub enum Error {
 
     #[error("Could not contact agent: {0}")]
     Agent(String),
+    #[error("The agent is not running; start it with `shrine agent start`")]
+    AgentNotRunning,
+    #[error("The agent does not allow `{0}` to read secrets")]
+    ExecutableNotAllowed(String),
+    #[error("The agent rejected the token")]
+    InvalidToken,
+    #[error("The agent token does not allow {0}")]
+    OutOfScope(String),
+    #[error("Token `{0}` already exists")]
+    TokenAlreadyExists(String),
+    #[error("Token `{0}` does not exist")]
+    TokenNotFound(String),
+    #[error("Invalid TLS configuration: {0}")]
+    Tls(String),
+    #[error("Invalid agent URL `{0}`: an `https://` URL is expected")]
+    InvalidAgentUrl(String),
+    #[error("Could not listen on `{0}`")]
+    Socket(PathBuf, #[source] std::io::Error),
+    #[error("`{0}` must only be accessible to the current user")]
+    InsecureAgentDir(PathBuf),
 
     #[error("Could not read shrine")]
     IoRead(#[source] std::io::Error),
@@ -64,6 +110,19 @@ pub enum Error {
     #[error("File `{0}` not found")]
     FileNotFound(PathBuf),
 
+    #[error("Could not read `{0}`")]
+    ReadFile(PathBuf, #[source] std::io::Error),
+    #[error("Could not write `{0}`")]
+    WriteFile(PathBuf, #[source] std::io::Error),
+    #[error("Key `{0}` is not a file attachment")]
+    NotAnAttachment(String),
+    #[error("The attachment's filename `{0}` is not a file name; use `--output`")]
+    InvalidAttachmentName(String),
+    #[error("`{0}` already exists; use `--force` to overwrite it")]
+    OutputExists(PathBuf),
+    #[error("The content of `{0}` does not match its SHA-256 checksum")]
+    ChecksumMismatch(String),
+
     #[error("Could not import file")]
     Import(#[source] std::io::Error),
 
@@ -81,4 +140,34 @@ pub enum Error {
 
     #[error("The password is invalid")]
     InvalidPassword,
+
+    #[error("Invalid otpauth URI: {0}")]
+    InvalidOtpUri(String),
+    #[error("Key `{0}` is not a TOTP secret")]
+    NotTotp(String),
+
+    #[error("Invalid record: {0}")]
+    InvalidRecord(String),
+    #[error("Key `{0}` is not a record")]
+    NotARecord(String),
+    #[error("Field `{1}` does not exist in `{0}`")]
+    FieldNotFound(String, String),
+
+    #[error("Invalid JSON: {0}")]
+    InvalidJsonValue(String),
+    #[error("Invalid PEM: {0}")]
+    InvalidPem(String),
+    #[error("Invalid OpenSSH private key: {0}")]
+    InvalidSshKey(String),
+    #[error("{0} certificate(s) failed the check")]
+    CertificatesCheckFailed(usize),
+    #[error("Invalid duration `{0}`: expected a number followed by `s`, `m`, `h`, `d` or `w`")]
+    InvalidDuration(String),
+    #[error("Invalid value `{1}` for setting `{0}`")]
+    InvalidSetting(String, String),
+    #[error("Could not run `{0}`")]
+    Command(String, #[source] std::io::Error),
+
+    #[error("The input file `{1}` is not a valid JSON file: {0}")]
+    InvalidJson(Box<dyn StdError>, PathBuf),
 }
@@ -0,0 +1,433 @@
+use crate::Error;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use x509_parser::der_parser::ber::BerObject;
+use x509_parser::der_parser::der::parse_der;
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::Pem as PemBlock;
+use x509_parser::prelude::{FromDer, SubjectPublicKeyInfo, X509Certificate};
+
+const OID_RSA: &str = "1.2.840.113549.1.1.1";
+const OID_EC: &str = "1.2.840.10045.2.1";
+const OID_ED25519: &str = "1.3.101.112";
+const OID_ED448: &str = "1.3.101.113";
+
+/// DER encoded OIDs, used to rebuild a private key's `SubjectPublicKeyInfo`.
+const DER_OID_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const DER_OID_EC: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+
+/// The content of a PEM encoded secret: one or more certificates and/or keys.
+///
+/// ```
+/// # use shrine::pem::Pem;
+/// let pem = Pem::parse(include_bytes!("../tests/fixtures/ec.crt")).unwrap();
+///
+/// let certificate = pem.certificates().next().unwrap();
+/// assert_eq!(certificate.subject, "CN=example.com, O=Shrine");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pem {
+    pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Block {
+    Certificate(Certificate),
+    PrivateKey(PrivateKey),
+    PublicKey(PublicKey),
+}
+
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    pub sans: Vec<String>,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    /// The SHA-256 fingerprint of the DER encoded certificate, as colon separated hex bytes.
+    pub fingerprint: String,
+    pub public_key: PublicKey,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrivateKey {
+    pub key_type: KeyType,
+    /// The matching public key, when it can be derived from the private key's encoding.
+    pub public_key: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyType {
+    Rsa(usize),
+    Ec(String),
+    Ed25519,
+    Ed448,
+    Encrypted,
+    Unknown(String),
+}
+
+/// A DER encoded `SubjectPublicKeyInfo`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    spki: Vec<u8>,
+}
+
+impl Pem {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let mut blocks = Vec::new();
+
+        for block in PemBlock::iter_from_buffer(bytes) {
+            let block = block.map_err(|e| Error::InvalidPem(e.to_string()))?;
+            blocks.push(Block::parse(&block)?);
+        }
+
+        if blocks.is_empty() {
+            return Err(Error::InvalidPem("no PEM block found".to_string()));
+        }
+
+        Ok(Self { blocks })
+    }
+
+    pub fn certificates(&self) -> impl Iterator<Item = &Certificate> {
+        self.blocks.iter().filter_map(|b| match b {
+            Block::Certificate(c) => Some(c),
+            _ => None,
+        })
+    }
+
+    pub fn private_keys(&self) -> impl Iterator<Item = &PrivateKey> {
+        self.blocks.iter().filter_map(|b| match b {
+            Block::PrivateKey(k) => Some(k),
+            _ => None,
+        })
+    }
+
+    /// A one-line description of the first block, e.g. `CN=example.com, expires 2030-01-01`.
+    pub fn summary(&self) -> String {
+        match &self.blocks[0] {
+            Block::Certificate(c) => {
+                format!("{}, expires {}", c.subject, c.not_after.format("%Y-%m-%d"))
+            }
+            Block::PrivateKey(k) => format!("{} private key", k.key_type),
+            Block::PublicKey(k) => format!("public key {}", k.fingerprint()),
+        }
+    }
+}
+
+impl Block {
+    fn parse(block: &PemBlock) -> Result<Self, Error> {
+        let invalid = |label: &str| Error::InvalidPem(format!("invalid {}", label.to_lowercase()));
+
+        match block.label.as_str() {
+            "CERTIFICATE" => Certificate::from_der(&block.contents).map(Block::Certificate),
+            "PUBLIC KEY" => {
+                SubjectPublicKeyInfo::from_der(&block.contents)
+                    .map_err(|_| invalid(&block.label))?;
+                Ok(Block::PublicKey(PublicKey {
+                    spki: block.contents.clone(),
+                }))
+            }
+            "RSA PRIVATE KEY" => PrivateKey::from_pkcs1(&block.contents)
+                .map(Block::PrivateKey)
+                .ok_or_else(|| invalid(&block.label)),
+            "EC PRIVATE KEY" => PrivateKey::from_sec1(&block.contents, None)
+                .map(Block::PrivateKey)
+                .ok_or_else(|| invalid(&block.label)),
+            "PRIVATE KEY" => PrivateKey::from_pkcs8(&block.contents)
+                .map(Block::PrivateKey)
+                .ok_or_else(|| invalid(&block.label)),
+            "ENCRYPTED PRIVATE KEY" => Ok(Block::PrivateKey(PrivateKey {
+                key_type: KeyType::Encrypted,
+                public_key: None,
+            })),
+            label => Err(Error::InvalidPem(format!(
+                "unsupported PEM block `{}`",
+                label
+            ))),
+        }
+    }
+}
+
+impl Certificate {
+    fn from_der(der: &[u8]) -> Result<Self, Error> {
+        let (_, cert) =
+            X509Certificate::from_der(der).map_err(|e| Error::InvalidPem(e.to_string()))?;
+
+        let sans = cert
+            .subject_alternative_name()
+            .map_err(|e| Error::InvalidPem(e.to_string()))?
+            .map(|ext| {
+                ext.value
+                    .general_names
+                    .iter()
+                    .map(|name| match name {
+                        GeneralName::DNSName(dns) => format!("DNS:{}", dns),
+                        GeneralName::RFC822Name(email) => format!("email:{}", email),
+                        GeneralName::URI(uri) => format!("URI:{}", uri),
+                        GeneralName::IPAddress(ip) => format!("IP:{}", format_ip(ip)),
+                        other => other.to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            subject: cert.subject().to_string(),
+            issuer: cert.issuer().to_string(),
+            sans,
+            not_before: to_datetime(cert.validity().not_before.timestamp()),
+            not_after: to_datetime(cert.validity().not_after.timestamp()),
+            fingerprint: Sha256::digest(der)
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<String>>()
+                .join(":"),
+            public_key: PublicKey {
+                spki: cert.public_key().raw.to_vec(),
+            },
+        })
+    }
+}
+
+impl PrivateKey {
+    /// `RSAPrivateKey ::= SEQUENCE { version, modulus, publicExponent, ... }` (RFC 8017).
+    fn from_pkcs1(der: &[u8]) -> Option<Self> {
+        let (_, object) = parse_der(der).ok()?;
+        let sequence = object.as_sequence().ok()?;
+        let modulus = strip_leading_zeros(sequence.get(1)?.as_slice().ok()?);
+        let exponent = strip_leading_zeros(sequence.get(2)?.as_slice().ok()?);
+
+        let rsa_public_key = sequence_of(&[integer(modulus), integer(exponent)]);
+        Some(Self {
+            key_type: KeyType::Rsa(modulus.len() * 8),
+            public_key: Some(PublicKey {
+                spki: sequence_of(&[
+                    sequence_of(&[tlv(0x06, DER_OID_RSA), vec![0x05, 0x00]]),
+                    bit_string(&rsa_public_key),
+                ]),
+            }),
+        })
+    }
+
+    /// `ECPrivateKey ::= SEQUENCE { version, privateKey, [0] parameters OPTIONAL,
+    /// [1] publicKey OPTIONAL }` (RFC 5915). The curve may be provided by the PKCS#8 envelope.
+    fn from_sec1(der: &[u8], curve: Option<&BerObject>) -> Option<Self> {
+        let (_, object) = parse_der(der).ok()?;
+        let sequence = object.as_sequence().ok()?;
+
+        let mut curve = curve.cloned();
+        let mut point = None;
+        for item in sequence.iter().skip(2) {
+            let (_, inner) = parse_der(item.as_slice().ok()?).ok()?;
+            match item.tag().0 {
+                0 => curve = Some(inner),
+                1 => point = Some(inner.as_bitstring().ok()?.data.to_vec()),
+                _ => {}
+            }
+        }
+
+        let curve = curve?;
+        let curve_oid = curve.as_oid().ok()?;
+        let public_key = point.map(|point| PublicKey {
+            spki: sequence_of(&[
+                sequence_of(&[tlv(0x06, DER_OID_EC), tlv(0x06, curve_oid.as_bytes())]),
+                bit_string(&point),
+            ]),
+        });
+
+        Some(Self {
+            key_type: KeyType::Ec(curve_name(&curve_oid.to_id_string())),
+            public_key,
+        })
+    }
+
+    /// `PrivateKeyInfo ::= SEQUENCE { version, privateKeyAlgorithm, privateKey, ... }` (RFC 5208).
+    fn from_pkcs8(der: &[u8]) -> Option<Self> {
+        let (_, object) = parse_der(der).ok()?;
+        let sequence = object.as_sequence().ok()?;
+        let algorithm = sequence.get(1)?.as_sequence().ok()?;
+        let private_key = sequence.get(2)?.as_slice().ok()?;
+
+        match algorithm.first()?.as_oid().ok()?.to_id_string().as_str() {
+            OID_RSA => Self::from_pkcs1(private_key),
+            OID_EC => Self::from_sec1(private_key, algorithm.get(1)),
+            OID_ED25519 => Some(Self {
+                key_type: KeyType::Ed25519,
+                public_key: None,
+            }),
+            OID_ED448 => Some(Self {
+                key_type: KeyType::Ed448,
+                public_key: None,
+            }),
+            oid => Some(Self {
+                key_type: KeyType::Unknown(oid.to_string()),
+                public_key: None,
+            }),
+        }
+    }
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyType::Rsa(bits) => write!(f, "RSA {}", bits),
+            KeyType::Ec(curve) => write!(f, "EC {}", curve),
+            KeyType::Ed25519 => write!(f, "Ed25519"),
+            KeyType::Ed448 => write!(f, "Ed448"),
+            KeyType::Encrypted => write!(f, "encrypted"),
+            KeyType::Unknown(oid) => write!(f, "unknown ({})", oid),
+        }
+    }
+}
+
+impl PublicKey {
+    /// The base64 encoded SHA-256 digest of the DER encoded `SubjectPublicKeyInfo`.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "SHA256:{}",
+            base64::engine::general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(&self.spki))
+        )
+    }
+}
+
+fn curve_name(oid: &str) -> String {
+    match oid {
+        "1.2.840.10045.3.1.7" => "P-256".to_string(),
+        "1.3.132.0.34" => "P-384".to_string(),
+        "1.3.132.0.35" => "P-521".to_string(),
+        "1.3.132.0.10" => "secp256k1".to_string(),
+        oid => oid.to_string(),
+    }
+}
+
+fn to_datetime(timestamp: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(timestamp, 0).single().unwrap_or_default()
+}
+
+fn format_ip(ip: &[u8]) -> String {
+    match ip.len() {
+        4 => std::net::Ipv4Addr::from(<[u8; 4]>::try_from(ip).unwrap()).to_string(),
+        16 => std::net::Ipv6Addr::from(<[u8; 16]>::try_from(ip).unwrap()).to_string(),
+        _ => ip.iter().map(|b| format!("{:02x}", b)).collect(),
+    }
+}
+
+fn strip_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        der.push(len as u8);
+    } else {
+        let len_bytes = len.to_be_bytes();
+        let len_bytes = strip_leading_zeros(&len_bytes);
+        der.push(0x80 | len_bytes.len() as u8);
+        der.extend_from_slice(len_bytes);
+    }
+    der.extend_from_slice(content);
+    der
+}
+
+fn sequence_of(items: &[Vec<u8>]) -> Vec<u8> {
+    tlv(0x30, &items.concat())
+}
+
+/// Encodes an unsigned integer, given as big-endian bytes without leading zeros.
+fn integer(value: &[u8]) -> Vec<u8> {
+    if value.first().map(|b| b & 0x80 != 0).unwrap_or(true) {
+        tlv(0x02, &[&[0u8], value].concat())
+    } else {
+        tlv(0x02, value)
+    }
+}
+
+fn bit_string(value: &[u8]) -> Vec<u8> {
+    tlv(0x03, &[&[0u8], value].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EC_CRT: &[u8] = include_bytes!("../tests/fixtures/ec.crt");
+    const EC_KEY: &[u8] = include_bytes!("../tests/fixtures/ec.key");
+    const EC_PKCS8_KEY: &[u8] = include_bytes!("../tests/fixtures/ec.pkcs8.key");
+    const RSA_CRT: &[u8] = include_bytes!("../tests/fixtures/rsa.crt");
+    const RSA_KEY: &[u8] = include_bytes!("../tests/fixtures/rsa.key");
+
+    #[test]
+    fn parse_certificate() {
+        let pem = Pem::parse(EC_CRT).unwrap();
+        let certificate = pem.certificates().next().unwrap();
+
+        assert_eq!(certificate.subject, "CN=example.com, O=Shrine");
+        assert_eq!(
+            certificate.sans,
+            vec!["DNS:example.com", "DNS:www.example.com", "IP:127.0.0.1"]
+        );
+        assert_eq!(
+            certificate.not_after.to_rfc3339(),
+            "2126-09-24T12:36:35+00:00"
+        );
+        assert_eq!(
+            certificate.fingerprint,
+            "4D:BB:7E:B4:02:5B:E5:77:EF:EA:03:BA:6D:C7:D1:82:19:AA:F5:0B:25:7F:EA:6F:45:A0:BC:F6:31:4F:C5:0A"
+        );
+        assert_eq!(
+            pem.summary(),
+            "CN=example.com, O=Shrine, expires 2126-09-24"
+        );
+    }
+
+    #[test]
+    fn parse_ec_private_key() {
+        let certificate = Pem::parse(EC_CRT).unwrap().certificates().next().cloned();
+        let certificate = certificate.unwrap();
+
+        for key in [EC_KEY, EC_PKCS8_KEY] {
+            let pem = Pem::parse(key).unwrap();
+            let key = pem.private_keys().next().unwrap();
+
+            assert_eq!(key.key_type, KeyType::Ec("P-256".to_string()));
+            assert_eq!(key.public_key.as_ref(), Some(&certificate.public_key));
+            assert_eq!(pem.summary(), "EC P-256 private key");
+        }
+    }
+
+    #[test]
+    fn parse_rsa_private_key() {
+        let certificate = Pem::parse(RSA_CRT).unwrap().certificates().next().cloned();
+        let certificate = certificate.unwrap();
+
+        let pem = Pem::parse(RSA_KEY).unwrap();
+        let key = pem.private_keys().next().unwrap();
+
+        assert_eq!(key.key_type, KeyType::Rsa(2048));
+        assert_eq!(key.public_key.as_ref(), Some(&certificate.public_key));
+    }
+
+    #[test]
+    fn parse_bundle() {
+        let pem = Pem::parse(&[EC_CRT, EC_KEY].concat()).unwrap();
+
+        assert_eq!(pem.certificates().count(), 1);
+        assert_eq!(pem.private_keys().count(), 1);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(
+            Pem::parse(b"secret").unwrap_err().to_string(),
+            "Invalid PEM: no PEM block found"
+        );
+        assert!(
+            Pem::parse(b"-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n").is_err()
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::shrine::{Mode, Secret};
+use crate::Error;
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Formatter;
+
+/// A secret made of named fields (e.g. username, password, url, notes), kept in insertion order.
+///
+/// Records are stored as JSON objects.
+///
+/// ```
+/// # use shrine::record::Record;
+/// let mut record = Record::new();
+/// record.set("user", "alice");
+/// record.set("password", "secret");
+///
+/// let record = Record::from_bytes(&record.to_bytes()).unwrap();
+///
+/// assert_eq!(record.get("password"), Some("secret"));
+/// assert_eq!(record.names(), vec!["user", "password"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the record stored in the `key` secret.
+    pub fn from_secret(key: &str, secret: &Secret) -> Result<Self, Error> {
+        if secret.mode() != Mode::Record {
+            return Err(Error::NotARecord(key.to_string()));
+        }
+        Self::from_bytes(secret.value().expose_secret_as_bytes())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|e| Error::InvalidRecord(e.to_string()))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a record is always serializable")
+    }
+
+    /// Sets a field's value, keeping its position if it already exists.
+    pub fn set<N, V>(&mut self, name: N, value: V)
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        let name = name.into();
+        let value = value.into();
+        match self.fields.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((name, value)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the fields' names, in insertion order.
+    pub fn names(&self) -> Vec<&str> {
+        self.fields.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+}
+
+impl Serialize for Record {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for (name, value) in self.fields.iter() {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Record {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RecordVisitor;
+
+        impl<'de> Visitor<'de> for RecordVisitor {
+            type Value = Record;
+
+            fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+                formatter.write_str("an object with string values")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut record = Record::new();
+                while let Some((name, value)) = map.next_entry::<String, String>()? {
+                    record.set(name, value);
+                }
+                Ok(record)
+            }
+        }
+
+        deserializer.deserialize_map(RecordVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_keeps_order() {
+        let mut record = Record::new();
+        record.set("b", "1");
+        record.set("a", "2");
+        record.set("b", "3");
+
+        assert_eq!(record.names(), vec!["b", "a"]);
+        assert_eq!(record.get("b"), Some("3"));
+        assert_eq!(record.get("c"), None);
+    }
+
+    #[test]
+    fn serde_keeps_order() {
+        let mut record = Record::new();
+        record.set("user", "alice");
+        record.set("password", "secret");
+        record.set("notes", "");
+
+        assert_eq!(
+            String::from_utf8(record.to_bytes()).unwrap(),
+            r#"{"user":"alice","password":"secret","notes":""}"#
+        );
+        assert_eq!(Record::from_bytes(&record.to_bytes()).unwrap(), record);
+    }
+
+    #[test]
+    fn from_invalid_bytes() {
+        assert!(Record::from_bytes(br#"{"user":1}"#).is_err());
+        assert!(Record::from_bytes(b"[]").is_err());
+    }
+}
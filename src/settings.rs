@@ -0,0 +1,118 @@
+use crate::utils::{config_dir, parse_duration, parse_size};
+use crate::Error;
+use chrono::Duration;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The user's global settings, read from `$XDG_CONFIG_HOME/shrine/config`: one `key=value` per
+/// line, lines starting with `#` being ignored.
+#[derive(Debug, Default, Clone)]
+pub struct Settings(BTreeMap<String, String>);
+
+impl Settings {
+    /// The settings file's path.
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|p| p.join("config"))
+    }
+
+    /// Loads the settings; a missing file means no settings.
+    pub fn load() -> Result<Self, Error> {
+        let path = match Self::path() {
+            None => return Ok(Self::default()),
+            Some(path) => path,
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::ReadFile(path, e)),
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        Self(
+            content
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .filter_map(|l| l.split_once('='))
+                .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                .collect(),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// The settings whose key starts with `prefix`, keyed by the rest of their key.
+    pub fn with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.0
+            .iter()
+            .filter_map(move |(k, v)| k.strip_prefix(prefix).map(|k| (k, v.as_str())))
+    }
+
+    pub fn get_duration(&self, key: &str) -> Result<Option<Duration>, Error> {
+        self.get(key).map(parse_duration).transpose()
+    }
+
+    /// Parses a size such as `10M`, see [`parse_size`].
+    pub fn get_size(&self, key: &str) -> Result<Option<u64>, Error> {
+        self.get(key)
+            .map(|v| {
+                parse_size(v).ok_or_else(|| Error::InvalidSetting(key.to_string(), v.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Parses the setting with `T`'s [`FromStr`] implementation.
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+        self.get(key)
+            .map(|v| {
+                v.parse::<T>()
+                    .map_err(|_| Error::InvalidSetting(key.to_string(), v.to_string()))
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let settings = Settings::parse(
+            "# agent\n\
+            agent.password.ttl = 1h\n\
+            \n\
+            agent.password.ttl.abc=5m\n\
+            agent.idle-timeout=oops\n\
+            invalid line\n",
+        );
+
+        assert_eq!(settings.get("agent.password.ttl"), Some("1h"));
+        assert_eq!(settings.get("invalid line"), None);
+        assert_eq!(
+            settings.get_duration("agent.password.ttl").unwrap(),
+            Some(Duration::hours(1))
+        );
+        assert_eq!(settings.get_duration("missing").unwrap(), None);
+        assert!(settings.get_duration("agent.idle-timeout").is_err());
+        assert_eq!(
+            settings
+                .with_prefix("agent.password.ttl.")
+                .collect::<Vec<_>>(),
+            vec![("abc", "5m")]
+        );
+        assert_eq!(
+            settings
+                .get_parsed::<u32>("agent.idle-timeout")
+                .unwrap_err()
+                .to_string(),
+            "Invalid value `oops` for setting `agent.idle-timeout`"
+        );
+    }
+}
@@ -1,16 +1,24 @@
 mod holder;
+mod merge;
 
+use crate::attachment::Attachment;
 use crate::bytes::SecretBytes;
-use crate::encrypt::aes::Aes;
+use crate::encrypt::aes::{self, Aes, DerivedKey};
 use crate::encrypt::plain::Plain;
 use crate::encrypt::EncDec;
+use crate::pem::Pem;
+use crate::record::Record;
 use crate::serialize::bson::BsonSerDe;
 use crate::serialize::json::JsonSerDe;
 use crate::serialize::message_pack::MessagePackSerDe;
 use crate::serialize::SerDe;
 use crate::shrine::holder::Holder;
+pub use crate::shrine::merge::Conflict;
+use crate::ssh::SshKey;
+use crate::totp::Totp;
 use crate::{Error, SHRINE_FILENAME};
 use borsh::{BorshDeserialize, BorshSerialize};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 
@@ -65,13 +73,13 @@ impl Zeroize for SerializableSecretString {
 
 pub type Secrets = Holder<Secret>;
 
-#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 pub struct Closed(Vec<u8>);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Open(Secrets);
 
-#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 pub struct Shrine<Payload = Open> {
     /// Always "shrine".
     magic_number: [u8; 6],
@@ -187,11 +195,37 @@ impl Shrine<Closed> {
     ///
     /// assert_eq!(shrine.get("key").unwrap().value().expose_secret_as_bytes(), "val".as_bytes());
     pub fn open(self, password: &ShrinePassword) -> Result<Shrine<Open>, Error> {
-        let bytes = self
+        let encryptor = self
             .metadata
             .encryption_algorithm()
-            .encryptor(password, None)
-            .decrypt(&self.payload.0)?;
+            .encryptor(password, None);
+        self.open_with(encryptor)
+    }
+
+    /// Derives the key the shrine is encrypted with, `None` if it is not encrypted.
+    pub fn derive_key(&self, password: &ShrinePassword) -> Result<Option<DerivedKey>, Error> {
+        match self.encryption_algorithm() {
+            EncryptionAlgorithm::Aes => DerivedKey::derive(password, &self.payload.0).map(Some),
+            EncryptionAlgorithm::Plain => Ok(None),
+        }
+    }
+
+    /// The salt the shrine's key is derived with, `None` if it is not encrypted.
+    pub fn salt(&self) -> Option<&[u8]> {
+        match self.encryption_algorithm() {
+            EncryptionAlgorithm::Aes => aes::salt(&self.payload.0),
+            EncryptionAlgorithm::Plain => None,
+        }
+    }
+
+    /// Decrypts and deserializes the `Shrine` with a key from [`Shrine::derive_key`].
+    pub fn open_with_key(self, key: Option<&DerivedKey>) -> Result<Shrine<Open>, Error> {
+        let encryptor = self.metadata.encryption_algorithm().keyed_encryptor(key)?;
+        self.open_with(encryptor)
+    }
+
+    fn open_with(self, encryptor: Box<dyn EncDec + '_>) -> Result<Shrine<Open>, Error> {
+        let bytes = encryptor.decrypt(&self.payload.0)?;
 
         let holder = self
             .metadata
@@ -225,6 +259,11 @@ impl Shrine<Open> {
         shrine.payload.0 = self.payload.0
     }
 
+    /// An empty shrine with the same UUID, encryption algorithm and serialization format.
+    pub fn empty_copy(&self) -> Shrine<Open> {
+        Shrine::new(self.metadata.clone())
+    }
+
     /// Serialise and encrypt the shrine's content.
     ///
     /// ```
@@ -240,17 +279,28 @@ impl Shrine<Open> {
     ///
     /// assert_eq!(shrine.get("key").unwrap().value().expose_secret_as_bytes(), "val".as_bytes());
     pub fn close(self, password: &ShrinePassword) -> Result<Shrine<Closed>, Error> {
+        let encryptor = self
+            .metadata
+            .encryption_algorithm()
+            .encryptor(password, None);
+        self.close_with(encryptor)
+    }
+
+    /// Serializes and encrypts the `Shrine` with a key from [`Shrine::derive_key`], reusing its
+    /// salt.
+    pub fn close_with_key(self, key: Option<&DerivedKey>) -> Result<Shrine<Closed>, Error> {
+        let encryptor = self.metadata.encryption_algorithm().keyed_encryptor(key)?;
+        self.close_with(encryptor)
+    }
+
+    fn close_with(self, encryptor: Box<dyn EncDec + '_>) -> Result<Shrine<Closed>, Error> {
         let bytes = self
             .metadata
             .serialization_format()
             .serializer()
             .serialize(&self.payload.0)?;
 
-        let bytes = self
-            .metadata
-            .encryption_algorithm()
-            .encryptor(password, None)
-            .encrypt(&bytes)?;
+        let bytes = encryptor.encrypt(&bytes)?;
 
         Ok(Shrine {
             magic_number: self.magic_number,
@@ -275,16 +325,31 @@ impl Shrine<Open> {
     where
         V: Into<SecretBytes>,
     {
+        let value = value.into();
+        mode.validate(value.expose_secret_as_bytes())?;
+
         match self.payload.0.get_mut(key) {
             Ok(secret) => {
-                secret.with_data(value.into());
+                secret.with_data(value, mode);
                 Ok(())
             }
-            Err(Error::KeyNotFound(_)) => self.payload.0.set(key, Secret::new(value.into(), mode)),
+            Err(Error::KeyNotFound(_)) => self.payload.0.set(key, Secret::new(value, mode)),
             Err(e) => Err(e),
         }
     }
 
+    /// Stores a file's content, read with [`Attachment::read`], along with its metadata.
+    pub fn attach(
+        &mut self,
+        key: &str,
+        content: SecretBytes,
+        attachment: Attachment,
+    ) -> Result<(), Error> {
+        self.set(key, content, Mode::File)?;
+        self.payload.0.get_mut(key)?.attachment = Some(attachment);
+        Ok(())
+    }
+
     /// Get a previously set value by its key.
     /// ```
     /// # use secrecy::Secret;
@@ -412,7 +477,7 @@ impl Default for Shrine {
     }
 }
 
-#[derive(Debug, BorshSerialize, BorshDeserialize)]
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 enum Metadata {
     V0 {
         uuid: u128,
@@ -466,7 +531,19 @@ impl Default for Metadata {
 }
 
 /// The list of encryption algorithms used to encrypt the payload.
-#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, BorshSerialize, BorshDeserialize)]
+#[derive(
+    Default,
+    Debug,
+    Clone,
+    Copy,
+    Eq,
+    PartialEq,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+    Serialize,
+    Deserialize,
+)]
 pub enum EncryptionAlgorithm {
     /// AES-GCM-SIV encryption
     #[default]
@@ -476,7 +553,7 @@ pub enum EncryptionAlgorithm {
 }
 
 impl EncryptionAlgorithm {
-    fn requires_password(&self) -> bool {
+    pub fn requires_password(&self) -> bool {
         match self {
             EncryptionAlgorithm::Aes => true,
             EncryptionAlgorithm::Plain => false,
@@ -497,6 +574,17 @@ impl EncryptionAlgorithm {
             EncryptionAlgorithm::Plain => Box::new(Plain::new()),
         }
     }
+
+    fn keyed_encryptor<'k>(
+        &self,
+        key: Option<&'k DerivedKey>,
+    ) -> Result<Box<dyn EncDec + 'k>, Error> {
+        match (self, key) {
+            (EncryptionAlgorithm::Aes, Some(key)) => Ok(Box::new(Aes::with_key(key, None))),
+            (EncryptionAlgorithm::Aes, None) => Err(Error::CryptoRead),
+            (EncryptionAlgorithm::Plain, _) => Ok(Box::new(Plain::new())),
+        }
+    }
 }
 
 impl Display for EncryptionAlgorithm {
@@ -521,7 +609,7 @@ pub enum SerializationFormat {
 }
 
 impl SerializationFormat {
-    fn serializer(&self) -> Box<dyn SerDe<Secrets>> {
+    fn serializer(&self) -> Box<dyn SerDe<'_, Secrets>> {
         match self {
             SerializationFormat::Bson => Box::new(BsonSerDe::new()),
             SerializationFormat::Json => Box::new(JsonSerDe::new()),
@@ -570,7 +658,7 @@ impl ShrineBuilder {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Secret {
     value: SecretBytes,
     mode: Mode,
@@ -578,6 +666,9 @@ pub struct Secret {
     created_at: DateTime<Utc>,
     updated_by: Option<String>,
     updated_at: Option<DateTime<Utc>>,
+    /// The original file's metadata, for [`Mode::File`] secrets.
+    #[serde(default)]
+    attachment: Option<Attachment>,
 }
 
 impl Secret {
@@ -589,6 +680,7 @@ impl Secret {
             created_at: Utc::now(),
             updated_by: None,
             updated_at: None,
+            attachment: None,
         }
     }
 
@@ -618,11 +710,26 @@ impl Secret {
     pub fn updated_at(&self) -> Option<&DateTime<Utc>> {
         self.updated_at.as_ref()
     }
+
+    pub fn attachment(&self) -> Option<&Attachment> {
+        self.attachment.as_ref()
+    }
+
+    /// Whether both secrets are the same version, i.e. have the same value and timestamps.
+    pub fn is_same_version(&self, other: &Secret) -> bool {
+        self.mode == other.mode
+            && self.attachment == other.attachment
+            && self.value.expose_secret_as_bytes() == other.value.expose_secret_as_bytes()
+            && self.created_at == other.created_at
+            && self.updated_at == other.updated_at
+    }
 }
 
 impl Secret {
-    fn with_data(&mut self, data: SecretBytes) -> &mut Self {
+    fn with_data(&mut self, data: SecretBytes, mode: Mode) -> &mut Self {
         self.value = data;
+        self.mode = mode;
+        self.attachment = None;
         self.updated_by = Some(format!("{}@{}", whoami::username(), whoami::hostname()));
         self.updated_at = Some(Utc::now());
         self
@@ -633,13 +740,56 @@ impl Secret {
 pub enum Mode {
     Binary,
     Text,
+    /// An `otpauth://totp/` URI, used to generate time-based one-time codes.
+    Totp,
+    /// A JSON object of named fields, see [`Record`].
+    Record,
+    /// Any JSON value.
+    Json,
+    /// PEM encoded X.509 certificates and/or private keys, see [`Pem`].
+    Pem,
+    /// An OpenSSH private key, see [`SshKey`].
+    Ssh,
+    /// A file's content, along with its metadata, see [`Attachment`].
+    File,
+}
+
+impl Mode {
+    /// Checks that `value` is a valid value for the mode.
+    fn validate(&self, value: &[u8]) -> Result<(), Error> {
+        match self {
+            Mode::Binary | Mode::Text | Mode::File => Ok(()),
+            Mode::Totp => String::from_utf8_lossy(value).parse::<Totp>().map(|_| ()),
+            Mode::Record => Record::from_bytes(value).map(|_| ()),
+            Mode::Json => serde_json::from_slice::<serde_json::Value>(value)
+                .map(|_| ())
+                .map_err(|e| Error::InvalidJsonValue(e.to_string())),
+            Mode::Pem => Pem::parse(value).map(|_| ()),
+            Mode::Ssh => SshKey::parse(value).map(|_| ()),
+        }
+    }
+
+    /// A one-line description of `value`'s metadata, for modes that have some.
+    fn summary(&self, value: &[u8]) -> Option<String> {
+        match self {
+            Mode::Pem => Pem::parse(value).ok().map(|p| p.summary()),
+            Mode::Ssh => SshKey::parse(value).ok().map(|k| k.summary()),
+            _ => None,
+        }
+    }
 }
 
 impl Display for Mode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Mode::Binary => write!(f, "bin"),
-            Mode::Text => write!(f, "txt"),
+            Mode::Binary => f.pad("bin"),
+            Mode::Text => f.pad("txt"),
+            Mode::Totp => f.pad("otp"),
+            Mode::Record => f.pad("rec"),
+            Mode::Json => f.pad("json"),
+            Mode::Pem => f.pad("pem"),
+            Mode::Ssh => f.pad("ssh"),
+            Mode::File => f.pad("file"),
         }
     }
 }
@@ -652,10 +802,32 @@ pub struct Key {
     pub created_at: DateTime<Utc>,
     pub updated_by: Option<String>,
     pub updated_at: Option<DateTime<Utc>>,
+    /// The fields' names, for records.
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// A one-line description of the value, for PEM and SSH secrets.
+    #[serde(default)]
+    pub info: Option<String>,
 }
 
-impl From<(String, &Secret)> for Key {
-    fn from((key, secret): (String, &Secret)) -> Self {
+impl Key {
+    /// The listing entry of a secret. Its fields and summary, which require parsing the value, are
+    /// only computed for the `long` listing.
+    pub fn new(key: String, secret: &Secret, long: bool) -> Self {
+        let (fields, info) = if long {
+            let fields = Record::from_secret(&key, secret)
+                .map(|r| r.names().into_iter().map(|n| n.to_string()).collect())
+                .unwrap_or_default();
+            let info = match secret.attachment() {
+                Some(attachment) => Some(attachment.summary()),
+                None => secret
+                    .mode()
+                    .summary(secret.value().expose_secret_as_bytes()),
+            };
+            (fields, info)
+        } else {
+            (Vec::new(), None)
+        };
         Self {
             key,
             mode: secret.mode(),
@@ -663,6 +835,8 @@ impl From<(String, &Secret)> for Key {
             created_at: *secret.created_at(),
             updated_by: secret.updated_by().map(|s| s.to_string()),
             updated_at: secret.updated_at().copied(),
+            fields,
+            info,
         }
     }
 }
@@ -676,17 +850,36 @@ pub trait ShrineProvider {
 
     fn save_open(&self, shrine: Shrine) -> Result<(), Error>;
 
+    /// Opens another version of the shrine, e.g. from the git history. Its password is asked for
+    /// if its UUID differs from the current shrine's one.
+    fn open(&mut self, shrine: Shrine<Closed>) -> Result<Shrine<Open>, Error>;
+
+    /// Closes a shrine with the current shrine's password, e.g. to re-encrypt another version.
+    fn close(&self, shrine: Shrine<Open>) -> Result<Shrine<Closed>, Error>;
+
     fn path(&self) -> &Path;
 }
 
 pub struct FilesystemShrineProvider {
     path: PathBuf,
     password: Option<ShrinePassword>,
+    /// The UUID of the shrine opened with `password`.
+    uuid: Option<Uuid>,
+    /// The passwords of the other versions of the shrine.
+    passwords: HashMap<Uuid, ShrinePassword>,
+    /// The keys derived to open versions of the shrine, by UUID and salt.
+    keys: HashMap<(Uuid, Vec<u8>), DerivedKey>,
 }
 
 impl FilesystemShrineProvider {
     pub fn new(path: PathBuf, password: Option<ShrinePassword>) -> Self {
-        Self { path, password }
+        Self {
+            path,
+            password,
+            uuid: None,
+            passwords: HashMap::new(),
+            keys: HashMap::new(),
+        }
     }
 }
 
@@ -697,6 +890,7 @@ impl ShrineProvider for FilesystemShrineProvider {
 
     fn load_open(&mut self) -> Result<Shrine<Open>, Error> {
         let shrine = self.load_closed()?;
+        self.uuid = Some(shrine.uuid());
 
         if shrine.requires_password() {
             if self.password.is_none() {
@@ -716,6 +910,40 @@ impl ShrineProvider for FilesystemShrineProvider {
         self.save_closed(shrine.close(self.password.as_ref().expect("we must have the password"))?)
     }
 
+    fn open(&mut self, shrine: Shrine<Closed>) -> Result<Shrine<Open>, Error> {
+        if !shrine.requires_password() {
+            return shrine.open(&ShrinePassword::default());
+        }
+
+        // versions written by the agent share their salt, and thus their key
+        let uuid = shrine.uuid();
+        let salt = shrine.salt().ok_or(Error::CryptoRead)?.to_vec();
+        if let Some(key) = self.keys.get(&(uuid, salt.clone())) {
+            return shrine.open_with_key(Some(key));
+        }
+
+        let password = match &self.password {
+            Some(password) if self.uuid.is_none() || self.uuid == Some(uuid) => password,
+            _ => self
+                .passwords
+                .entry(uuid)
+                .or_insert_with(|| read_password(uuid)),
+        };
+        let key = shrine.derive_key(password)?;
+        let shrine = shrine.open_with_key(key.as_ref())?;
+        if let Some(key) = key {
+            self.keys.insert((uuid, salt), key);
+        }
+        Ok(shrine)
+    }
+
+    fn close(&self, shrine: Shrine<Open>) -> Result<Shrine<Closed>, Error> {
+        if !shrine.requires_password() {
+            return shrine.close(&ShrinePassword::default());
+        }
+        shrine.close(self.password.as_ref().expect("we must have the password"))
+    }
+
     fn path(&self) -> &Path {
         self.path.as_ref()
     }
@@ -778,6 +1006,14 @@ pub mod mocks {
             self.save_closed(shrine.close(&ShrinePassword::default()).unwrap())
         }
 
+        fn open(&mut self, shrine: Shrine<Closed>) -> Result<Shrine<Open>, Error> {
+            shrine.open(&ShrinePassword::default())
+        }
+
+        fn close(&self, shrine: Shrine<Open>) -> Result<Shrine<Closed>, Error> {
+            shrine.close(&ShrinePassword::default())
+        }
+
         fn path(&self) -> &Path {
             Path::new("/path/to/shrine")
         }
@@ -868,6 +1104,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_validates_mode() {
+        let mut shrine = Shrine::default();
+
+        assert_eq!(
+            shrine
+                .set("key", "not an uri", Mode::Totp)
+                .unwrap_err()
+                .to_string(),
+            "Invalid otpauth URI: expected an `otpauth://totp/` URI"
+        );
+        assert!(shrine.set("key", "{", Mode::Json).is_err());
+        assert!(shrine.set("key", "not a certificate", Mode::Pem).is_err());
+        assert!(shrine.set("key", "not a key", Mode::Ssh).is_err());
+        assert!(shrine.get("key").is_err());
+
+        shrine.set("key", "[1, 2]", Mode::Json).unwrap();
+        shrine
+            .set("key", include_str!("../tests/fixtures/ec.crt"), Mode::Pem)
+            .unwrap();
+        shrine
+            .set(
+                "key",
+                include_str!("../tests/fixtures/ssh_ed25519"),
+                Mode::Ssh,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn set_changes_mode() {
+        let mut shrine = Shrine::default();
+        shrine.set("key", "value", Mode::Text).unwrap();
+        shrine
+            .set(
+                "key",
+                "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ",
+                Mode::Totp,
+            )
+            .unwrap();
+
+        assert_eq!(shrine.get("key").unwrap().mode(), Mode::Totp);
+    }
+
     #[test]
     fn move_content() {
         let mut shrine = Shrine::default();
@@ -885,4 +1165,30 @@ mod tests {
             "value".as_bytes()
         );
     }
+
+    #[test]
+    fn open_versions_with_cached_keys() {
+        let password = ShrinePassword::from("p");
+        let mut shrine = ShrineBuilder::new().build();
+        shrine.set("key", "value", Mode::Text).unwrap();
+        let first = shrine.close(&password).unwrap();
+
+        let key = first.derive_key(&password).unwrap();
+        let bytes = first.as_bytes().unwrap();
+        let opened = Shrine::from_bytes(&bytes).unwrap().open(&password).unwrap();
+        let same_salt = opened.close_with_key(key.as_ref()).unwrap();
+        let opened = Shrine::from_bytes(&bytes).unwrap().open(&password).unwrap();
+        let other_salt = opened.close(&password).unwrap();
+
+        let mut provider = FilesystemShrineProvider::new(PathBuf::new(), Some(password));
+        for version in [first, same_salt, other_salt] {
+            let version = provider.open(version).unwrap();
+            assert_eq!(
+                version.get("key").unwrap().value.expose_secret_as_bytes(),
+                "value".as_bytes()
+            );
+        }
+
+        assert_eq!(provider.keys.len(), 2);
+    }
 }
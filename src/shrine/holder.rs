@@ -7,7 +7,7 @@ use std::hash::Hash;
 use std::rc::Rc;
 
 /// Holds the secrets
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Holder<T> {
     /// Secrets and data private to the shrine.
     private: HashMap<String, String>, // fixme should this be secret as well?
@@ -97,7 +97,7 @@ impl<T> Holder<T> {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum Node<T> {
     Index(HashMap<String, Box<Node<T>>>),
     Secret(T),
@@ -265,10 +265,10 @@ where
     K: Hash + Eq,
     V: Default,
 {
-    fn get_or_default_mut<'q, Q: ?Sized>(&mut self, key: &'q Q) -> &mut V
+    fn get_or_default_mut<'q, Q>(&mut self, key: &'q Q) -> &mut V
     where
         K: Borrow<Q> + From<&'q Q>,
-        Q: Hash + Eq;
+        Q: Hash + Eq + ?Sized;
 }
 
 impl<K, V> GetOrDefault<K, V> for HashMap<K, V>
@@ -276,10 +276,10 @@ where
     K: Hash + Eq,
     V: Default,
 {
-    fn get_or_default_mut<'q, Q: ?Sized>(&mut self, key: &'q Q) -> &mut V
+    fn get_or_default_mut<'q, Q>(&mut self, key: &'q Q) -> &mut V
     where
         K: Borrow<Q> + From<&'q Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         if self.get(key).is_none() {
             self.insert(key.into(), V::default());
@@ -407,7 +407,7 @@ mod tests {
 
         let keys = holder.keys();
         assert_eq!(keys.len(), 4);
-        assert_eq!(keys.get(0).unwrap(), "1/2");
+        assert_eq!(keys.first().unwrap(), "1/2");
         assert_eq!(keys.get(1).unwrap(), "a/b/c");
         assert_eq!(keys.get(2).unwrap(), "a/b/d");
         assert_eq!(keys.get(3).unwrap(), "key");
@@ -0,0 +1,308 @@
+use crate::shrine::{Open, Secret, Shrine};
+use crate::Error;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+
+/// A key that both sides of a merge changed in incompatible ways.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// Both sides set different values; our value is kept.
+    Modified(String),
+    /// One side set a value while the other removed the key; the value is kept.
+    ModifiedAndRemoved(String),
+    /// Both sides set different configuration values; our value is kept.
+    Config(String),
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::Modified(key) => write!(f, "`{}` was modified on both sides", key),
+            Conflict::ModifiedAndRemoved(key) => write!(
+                f,
+                "`{}` was modified on one side and removed on the other",
+                key
+            ),
+            Conflict::Config(key) => {
+                write!(f, "configuration `{}` was modified on both sides", key)
+            }
+        }
+    }
+}
+
+enum Resolution {
+    Ours,
+    Theirs,
+    Conflict,
+}
+
+impl Shrine<Open> {
+    /// Merges `theirs` into the current shrine, key by key, `base` being their common ancestor,
+    /// if any. Returns the conflicts, for which the current shrine's version is kept.
+    ///
+    /// A key changed on one side only gets that side's version. A key set to the same value on
+    /// both sides gets the most recently updated secret.
+    pub fn merge(
+        &mut self,
+        base: Option<&Shrine<Open>>,
+        theirs: &Shrine<Open>,
+    ) -> Result<Vec<Conflict>, Error> {
+        let mut conflicts = Vec::new();
+
+        let keys = self
+            .keys()
+            .into_iter()
+            .chain(theirs.keys())
+            .chain(base.map(|b| b.keys()).unwrap_or_default())
+            .collect::<BTreeSet<_>>();
+
+        let mut removed = Vec::new();
+        let mut updated = Vec::new();
+        for key in keys {
+            let ours_secret = self.get(&key).ok();
+            let theirs_secret = theirs.get(&key).ok();
+
+            let resolution = match resolve(
+                base.and_then(|b| b.get(&key).ok()),
+                ours_secret,
+                theirs_secret,
+                |a, b| a.is_same_version(b),
+            ) {
+                Resolution::Conflict => match (ours_secret, theirs_secret) {
+                    (Some(ours), Some(theirs)) if same_value(ours, theirs) => {
+                        if last_update(theirs) > last_update(ours) {
+                            Resolution::Theirs
+                        } else {
+                            Resolution::Ours
+                        }
+                    }
+                    (Some(_), Some(_)) => {
+                        conflicts.push(Conflict::Modified(key.clone()));
+                        Resolution::Ours
+                    }
+                    (None, _) | (_, None) => {
+                        conflicts.push(Conflict::ModifiedAndRemoved(key.clone()));
+                        if ours_secret.is_some() {
+                            Resolution::Ours
+                        } else {
+                            Resolution::Theirs
+                        }
+                    }
+                },
+                resolution => resolution,
+            };
+
+            if let Resolution::Theirs = resolution {
+                match theirs_secret {
+                    None => removed.push(key),
+                    Some(secret) => updated.push((key, secret.clone())),
+                }
+            }
+        }
+
+        for key in removed {
+            self.payload.0.remove(&key);
+        }
+        for (key, secret) in updated {
+            self.payload.0.set(&key, secret)?;
+        }
+
+        let keys = self
+            .keys_private()
+            .into_iter()
+            .chain(theirs.keys_private())
+            .chain(base.map(|b| b.keys_private()).unwrap_or_default())
+            .map(|k| k.to_string())
+            .collect::<BTreeSet<_>>();
+
+        for key in keys {
+            let theirs_value = theirs.get_private(&key);
+            match resolve(
+                base.and_then(|b| b.get_private(&key)),
+                self.get_private(&key),
+                theirs_value,
+                |a, b| a == b,
+            ) {
+                Resolution::Ours => {}
+                Resolution::Theirs => match theirs_value {
+                    None => self.remove_private(&key),
+                    Some(value) => self.set_private(key, value.to_string()),
+                },
+                Resolution::Conflict => conflicts.push(Conflict::Config(key)),
+            }
+        }
+
+        Ok(conflicts)
+    }
+}
+
+/// Three-way resolution of a single key: a side that did not change the base value yields to the
+/// other one.
+fn resolve<T, F>(base: Option<T>, ours: Option<T>, theirs: Option<T>, identical: F) -> Resolution
+where
+    F: Fn(&T, &T) -> bool,
+{
+    let eq = |a: &Option<T>, b: &Option<T>| match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => identical(a, b),
+        _ => false,
+    };
+
+    if eq(&ours, &theirs) || eq(&base, &theirs) {
+        Resolution::Ours
+    } else if eq(&base, &ours) {
+        Resolution::Theirs
+    } else {
+        Resolution::Conflict
+    }
+}
+
+fn same_value(a: &Secret, b: &Secret) -> bool {
+    a.mode == b.mode
+        && a.attachment == b.attachment
+        && a.value.expose_secret_as_bytes() == b.value.expose_secret_as_bytes()
+}
+
+fn last_update(secret: &Secret) -> chrono::DateTime<chrono::Utc> {
+    secret.updated_at.unwrap_or(secret.created_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shrine::{Mode, ShrineBuilder};
+    use chrono::Duration;
+
+    fn secret(value: &str) -> Secret {
+        Secret::new(value.into(), Mode::Text)
+    }
+
+    fn shrine(secrets: &[(&str, &Secret)]) -> Shrine {
+        let mut shrine = ShrineBuilder::new().build();
+        for (key, secret) in secrets {
+            shrine.payload.0.set(key, (*secret).clone()).unwrap();
+        }
+        shrine
+    }
+
+    fn value(shrine: &Shrine, key: &str) -> String {
+        String::from_utf8(
+            shrine
+                .get(key)
+                .unwrap()
+                .value()
+                .expose_secret_as_bytes()
+                .to_vec(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn disjoint_changes() {
+        let a = secret("a");
+        let b = secret("b");
+        let base = shrine(&[("a", &a), ("b", &b)]);
+        let mut ours = shrine(&[("a", &a), ("b", &secret("b2")), ("c", &secret("c"))]);
+        let theirs = shrine(&[("b", &b), ("d/e", &secret("e"))]);
+
+        let conflicts = ours.merge(Some(&base), &theirs).unwrap();
+
+        assert!(conflicts.is_empty());
+        assert_eq!(ours.keys(), vec!["b", "c", "d/e"]);
+        assert_eq!(value(&ours, "b"), "b2");
+        assert_eq!(value(&ours, "d/e"), "e");
+    }
+
+    #[test]
+    fn without_base() {
+        let mut ours = shrine(&[("a", &secret("a"))]);
+        let theirs = shrine(&[("b", &secret("b"))]);
+
+        let conflicts = ours.merge(None, &theirs).unwrap();
+
+        assert!(conflicts.is_empty());
+        assert_eq!(ours.keys(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn both_modified() {
+        let base = shrine(&[("a", &secret("a"))]);
+        let mut ours = shrine(&[("a", &secret("ours"))]);
+        let theirs = shrine(&[("a", &secret("theirs"))]);
+
+        let conflicts = ours.merge(Some(&base), &theirs).unwrap();
+
+        assert_eq!(conflicts, vec![Conflict::Modified("a".to_string())]);
+        assert_eq!(conflicts[0].to_string(), "`a` was modified on both sides");
+        assert_eq!(value(&ours, "a"), "ours");
+    }
+
+    #[test]
+    fn both_modified_to_same_value() {
+        let base = shrine(&[("a", &secret("a"))]);
+        let mut ours = shrine(&[("a", &secret("same"))]);
+        let mut newer = secret("same");
+        newer.updated_at = Some(newer.created_at + Duration::minutes(1));
+        let theirs = shrine(&[("a", &newer)]);
+
+        let conflicts = ours.merge(Some(&base), &theirs).unwrap();
+
+        assert!(conflicts.is_empty());
+        assert_eq!(ours.get("a").unwrap().updated_at(), newer.updated_at());
+    }
+
+    #[test]
+    fn modified_and_removed() {
+        let a = secret("a");
+        let base = shrine(&[("a", &a), ("b", &secret("b"))]);
+        let mut ours = shrine(&[("b", &secret("b"))]);
+        let theirs = shrine(&[("a", &secret("a2"))]);
+
+        let conflicts = ours.merge(Some(&base), &theirs).unwrap();
+
+        assert_eq!(
+            conflicts,
+            vec![
+                Conflict::ModifiedAndRemoved("a".to_string()),
+                Conflict::ModifiedAndRemoved("b".to_string()),
+            ]
+        );
+        assert_eq!(value(&ours, "a"), "a2");
+        assert_eq!(value(&ours, "b"), "b");
+    }
+
+    #[test]
+    fn removed_on_one_side() {
+        let a = secret("a");
+        let b = secret("b");
+        let base = shrine(&[("a", &a), ("b", &b)]);
+        let mut ours = shrine(&[("a", &a), ("b", &b)]);
+        let theirs = shrine(&[("b", &b)]);
+
+        let conflicts = ours.merge(Some(&base), &theirs).unwrap();
+
+        assert!(conflicts.is_empty());
+        assert_eq!(ours.keys(), vec!["b"]);
+    }
+
+    #[test]
+    fn config() {
+        let mut base = shrine(&[]);
+        base.set_private("git.enabled".to_string(), "true".to_string());
+        base.set_private("git.remote".to_string(), "origin".to_string());
+        let mut ours = shrine(&[]);
+        ours.set_private("git.enabled".to_string(), "false".to_string());
+        ours.set_private("git.remote".to_string(), "upstream".to_string());
+        let mut theirs = shrine(&[]);
+        theirs.set_private("git.enabled".to_string(), "true".to_string());
+        theirs.set_private("git.remote".to_string(), "backup".to_string());
+        theirs.set_private("git.push.auto".to_string(), "true".to_string());
+
+        let conflicts = ours.merge(Some(&base), &theirs).unwrap();
+
+        assert_eq!(conflicts, vec![Conflict::Config("git.remote".to_string())]);
+        assert_eq!(ours.get_private("git.enabled"), Some("false"));
+        assert_eq!(ours.get_private("git.remote"), Some("upstream"));
+        assert_eq!(ours.get_private("git.push.auto"), Some("true"));
+    }
+}
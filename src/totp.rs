@@ -0,0 +1,340 @@
+use crate::shrine::{Mode, Secret};
+use crate::Error;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SCHEME: &str = "otpauth://totp/";
+
+/// Time-based one-time password parameters, as found in `otpauth://` URIs.
+///
+/// See <https://github.com/google/google-authenticator/wiki/Key-Uri-Format>.
+///
+/// ```
+/// # use shrine::totp::Totp;
+/// let totp: Totp = "otpauth://totp/ACME:alice?secret=GEZDGNBVGY3TQOJQ&issuer=ACME"
+///     .parse()
+///     .unwrap();
+///
+/// assert_eq!(totp.issuer(), Some("ACME"));
+/// assert_eq!(totp.account(), Some("alice"));
+/// assert_eq!(totp.code_at(59), "263420");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Totp {
+    secret: Vec<u8>,
+    algorithm: Algorithm,
+    digits: u32,
+    period: u64,
+    issuer: Option<String>,
+    account: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// A one-time code along with the count of seconds it stays valid.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Code {
+    pub code: String,
+    pub remaining: u64,
+}
+
+impl Totp {
+    /// Reads the TOTP parameters stored in the `key` secret.
+    pub fn from_secret(key: &str, secret: &Secret) -> Result<Self, Error> {
+        if secret.mode() != Mode::Totp {
+            return Err(Error::NotTotp(key.to_string()));
+        }
+        String::from_utf8_lossy(secret.value().expose_secret_as_bytes()).parse()
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn digits(&self) -> u32 {
+        self.digits
+    }
+
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Computes the RFC 6238 code valid at `timestamp` (seconds since the Unix epoch).
+    pub fn code_at(&self, timestamp: u64) -> String {
+        let counter = (timestamp / self.period).to_be_bytes();
+
+        let hash = match self.algorithm {
+            Algorithm::Sha1 => hmac::<Hmac<Sha1>>(&self.secret, &counter),
+            Algorithm::Sha256 => hmac::<Hmac<Sha256>>(&self.secret, &counter),
+            Algorithm::Sha512 => hmac::<Hmac<Sha512>>(&self.secret, &counter),
+        };
+
+        // https://datatracker.ietf.org/doc/html/rfc4226#section-5.4
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+
+        format!(
+            "{:0width$}",
+            binary as u64 % 10u64.pow(self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// Computes the code valid at `timestamp` along with the count of seconds it stays valid.
+    pub fn generate_at(&self, timestamp: u64) -> Code {
+        Code {
+            code: self.code_at(timestamp),
+            remaining: self.period - timestamp % self.period,
+        }
+    }
+
+    /// Computes the currently valid code.
+    pub fn generate(&self) -> Code {
+        self.generate_at(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("time went backwards")
+                .as_secs(),
+        )
+    }
+}
+
+fn hmac<M>(key: &[u8], message: &[u8]) -> Vec<u8>
+where
+    M: Mac + hmac::digest::KeyInit,
+{
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl FromStr for Totp {
+    type Err = Error;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| Error::InvalidOtpUri(reason.to_string());
+
+        let rest = uri
+            .trim()
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("expected an `otpauth://totp/` URI"))?;
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let label = urlencoding::decode(label).map_err(|_| invalid("label is not valid UTF-8"))?;
+        let (mut issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim()),
+            None => (None, label.trim()),
+        };
+        let account = Some(account.to_string()).filter(|a| !a.is_empty());
+
+        let mut secret = None;
+        let mut algorithm = Algorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+
+        for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+            let value =
+                urlencoding::decode(value).map_err(|_| invalid("parameter is not valid UTF-8"))?;
+            match name {
+                "secret" => {
+                    secret = Some(base32_decode(&value).ok_or_else(|| invalid("invalid secret"))?)
+                }
+                "issuer" => issuer = Some(value.to_string()),
+                "algorithm" => {
+                    algorithm = match value.to_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => return Err(invalid("unsupported algorithm")),
+                    }
+                }
+                "digits" => {
+                    digits = value
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|d| (6..=9).contains(d))
+                        .ok_or_else(|| invalid("digits must be between 6 and 9"))?
+                }
+                "period" => {
+                    period = value
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|p| *p > 0)
+                        .ok_or_else(|| invalid("period must be a positive integer"))?
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            secret: secret
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| invalid("missing secret"))?,
+            algorithm,
+            digits,
+            period,
+            issuer,
+            account,
+        })
+    }
+}
+
+/// Decodes RFC 4648 base32, ignoring case, padding and whitespace.
+fn base32_decode(input: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+
+    for c in input.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u64 - 'A' as u64,
+            c @ '2'..='7' => c as u64 - '2' as u64 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // https://datatracker.ietf.org/doc/html/rfc6238#appendix-B
+    const SEED_SHA1: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    const SEED_SHA256: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA";
+    const SEED_SHA512: &str =
+        "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA";
+
+    fn totp(seed: &str, algorithm: &str) -> Totp {
+        format!(
+            "otpauth://totp/test?secret={}&algorithm={}&digits=8",
+            seed, algorithm
+        )
+        .parse()
+        .unwrap()
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        let sha1 = totp(SEED_SHA1, "SHA1");
+        let sha256 = totp(SEED_SHA256, "SHA256");
+        let sha512 = totp(SEED_SHA512, "SHA512");
+
+        for (time, expected_sha1, expected_sha256, expected_sha512) in [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ] {
+            assert_eq!(sha1.code_at(time), expected_sha1);
+            assert_eq!(sha256.code_at(time), expected_sha256);
+            assert_eq!(sha512.code_at(time), expected_sha512);
+        }
+    }
+
+    #[test]
+    fn generate_at() {
+        let totp = totp(SEED_SHA1, "SHA1");
+
+        assert_eq!(
+            totp.generate_at(1111111109),
+            Code {
+                code: "07081804".to_string(),
+                remaining: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_uri() {
+        let totp: Totp = "otpauth://totp/ACME%20Co:john.doe@email.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ&issuer=ACME%20Co&algorithm=SHA256&digits=7&period=60"
+            .parse()
+            .unwrap();
+
+        assert_eq!(totp.issuer(), Some("ACME Co"));
+        assert_eq!(totp.account(), Some("john.doe@email.com"));
+        assert_eq!(totp.algorithm(), Algorithm::Sha256);
+        assert_eq!(totp.digits(), 7);
+        assert_eq!(totp.period(), 60);
+    }
+
+    #[test]
+    fn parse_uri_defaults() {
+        let totp: Totp = "otpauth://totp/alice?secret=GEZDGNBVGY3TQOJQ"
+            .parse()
+            .unwrap();
+
+        assert_eq!(totp.issuer(), None);
+        assert_eq!(totp.account(), Some("alice"));
+        assert_eq!(totp.algorithm(), Algorithm::Sha1);
+        assert_eq!(totp.digits(), 6);
+        assert_eq!(totp.period(), 30);
+    }
+
+    #[test]
+    fn parse_invalid_uri() {
+        for (uri, error) in [
+            (
+                "https://example.com",
+                "Invalid otpauth URI: expected an `otpauth://totp/` URI",
+            ),
+            (
+                "otpauth://totp/alice",
+                "Invalid otpauth URI: missing secret",
+            ),
+            (
+                "otpauth://totp/alice?secret=1",
+                "Invalid otpauth URI: invalid secret",
+            ),
+            (
+                "otpauth://totp/alice?secret=GEZA&algorithm=MD5",
+                "Invalid otpauth URI: unsupported algorithm",
+            ),
+            (
+                "otpauth://totp/alice?secret=GEZA&digits=4",
+                "Invalid otpauth URI: digits must be between 6 and 9",
+            ),
+        ] {
+            assert_eq!(uri.parse::<Totp>().unwrap_err().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn base32() {
+        assert_eq!(base32_decode("MZXW6YTBOI======").unwrap(), b"foobar");
+        assert_eq!(base32_decode("mzxw 6ytb oi").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+    }
+}
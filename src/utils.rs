@@ -1,5 +1,6 @@
 use crate::shrine::ShrinePassword;
 use crate::Error;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use csv::ReaderBuilder;
 use serde::Deserialize;
 use std::env;
@@ -18,19 +19,34 @@ struct Row {
     password: String,
 }
 
-pub fn read_password(uuid: Uuid) -> ShrinePassword {
+/// shrine's configuration folder: `$XDG_CONFIG_HOME/shrine`, or `~/.config/shrine`.
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// shrine's state folder: `$XDG_STATE_HOME/shrine`, or `~/.local/state/shrine`.
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+fn xdg_dir(variable: &str, default: &str) -> Option<PathBuf> {
     // https://specifications.freedesktop.org/basedir-spec/latest/ar01s03.html
-    let config = env::var_os("XDG_CONFIG_HOME")
+    env::var_os(variable)
         .map(PathBuf::from)
         .or_else(|| {
             env::var_os("HOME").map(PathBuf::from).map(|mut p| {
-                p.push(OsString::from(".config"));
+                p.push(OsString::from(default));
                 p
             })
-        });
+        })
+        .map(|mut p| {
+            p.push("shrine");
+            p
+        })
+}
 
-    if let Some(mut config) = config {
-        config.push("shrine");
+pub fn read_password(uuid: Uuid) -> ShrinePassword {
+    if let Some(mut config) = config_dir() {
         config.push("passwords");
 
         let password_file = Path::new(&config);
@@ -78,6 +94,64 @@ pub fn read_password(uuid: Uuid) -> ShrinePassword {
     read_password_from_tty()
 }
 
+/// Parses durations such as `30d`, `12h`, `2w`, `15m` or `90s`.
+pub fn parse_duration(value: &str) -> Result<Duration, Error> {
+    let invalid = || Error::InvalidDuration(value.to_string());
+
+    let value = value.trim();
+    let split = value.len() - value.chars().last().ok_or_else(invalid)?.len_utf8();
+    let amount = value[..split].parse::<u32>().map_err(|_| invalid())? as i64;
+
+    match &value[split..] {
+        "s" => Ok(Duration::seconds(amount)),
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        "w" => Ok(Duration::weeks(amount)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses sizes such as `512`, `64K`, `10M` or `1G`, in bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (amount, unit) = match value.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&value[..i], c.to_ascii_uppercase()),
+        _ => (value, 'B'),
+    };
+    let amount = amount.parse::<u64>().ok()?;
+
+    match unit {
+        'B' => Some(amount),
+        'K' => amount.checked_mul(1 << 10),
+        'M' => amount.checked_mul(1 << 20),
+        'G' => amount.checked_mul(1 << 30),
+        _ => None,
+    }
+}
+
+/// Parses a date such as `2026-10-01T12:00:00+02:00`, `2026-10-01 12:00` or `2026-10-01`, in the
+/// local timezone unless specified; a day alone means its beginning.
+pub fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date.with_timezone(&Utc));
+    }
+
+    let date = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(value, f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })?;
+    Local
+        .from_local_datetime(&date)
+        .earliest()
+        .map(|d| d.with_timezone(&Utc))
+}
+
 pub fn read_new_password() -> Result<ShrinePassword, Error> {
     let password1 = rpassword::prompt_password("Enter new shrine password: ").unwrap();
     let password2 = rpassword::prompt_password("Enter new shrine password (again): ").unwrap();
@@ -90,3 +164,54 @@ pub fn read_new_password() -> Result<ShrinePassword, Error> {
 pub fn read_password_from_tty() -> ShrinePassword {
     ShrinePassword::from(rpassword::prompt_password("Enter shrine password: ").unwrap())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::seconds(90));
+        assert_eq!(parse_duration("15m").unwrap(), Duration::minutes(15));
+        assert_eq!(parse_duration("12h").unwrap(), Duration::hours(12));
+        assert_eq!(parse_duration("30d").unwrap(), Duration::days(30));
+        assert_eq!(parse_duration("2w").unwrap(), Duration::weeks(2));
+
+        for invalid in ["", "d", "30", "-1d", "30y"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("64K"), Some(64 * 1024));
+        assert_eq!(parse_size("10m"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Some(1024 * 1024 * 1024));
+
+        for invalid in ["", "M", "10T", "-1", "1.5M"] {
+            assert_eq!(parse_size(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(
+            parse_date("2026-10-01T12:00:00+02:00").unwrap(),
+            Utc.with_ymd_and_hms(2026, 10, 1, 10, 0, 0).unwrap()
+        );
+
+        let local = |h, m| {
+            Local
+                .with_ymd_and_hms(2026, 10, 1, h, m, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        assert_eq!(parse_date("2026-10-01 12:30").unwrap(), local(12, 30));
+        assert_eq!(parse_date("2026-10-01").unwrap(), local(0, 0));
+
+        for invalid in ["", "HEAD~1", "2026-13-01", "abc1234"] {
+            assert!(parse_date(invalid).is_none(), "{}", invalid);
+        }
+    }
+}
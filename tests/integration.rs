@@ -1,4 +1,4 @@
-use predicates::prelude::predicate;
+use predicates::prelude::{predicate, PredicateBooleanExt};
 use std::fs;
 use std::fs::File;
 use std::io::Write;
@@ -281,7 +281,7 @@ fn git() {
         .args(vec!["log", "-n", "1", "--format=format:%s"])
         .assert()
         .success()
-        .stdout("Initialize shrine");
+        .stdout("init");
 }
 
 #[test]
@@ -330,6 +330,55 @@ fn git_disable_auto_commit() {
         .stdout("2\n");
 }
 
+#[test]
+fn git_commit_messages() {
+    let folder = tempfile::tempdir().unwrap();
+    let shrine = |args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .args(["--password", "p"])
+            .args(args)
+            .assert()
+            .success()
+    };
+    let last_message = || {
+        let output = assert_cmd::Command::new("git")
+            .current_dir(&folder)
+            .args(vec!["log", "-n", "1", "--format=format:%s"])
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone();
+        String::from_utf8(output).unwrap()
+    };
+
+    shrine(&["init", "--git"]);
+    shrine(&["set", "prod/db/password", "secret"]);
+    assert_eq!(last_message(), "set prod/db/password");
+
+    shrine(&["config", "set", "git.commit.template", "shrine: {message}"]);
+    shrine(&["config", "set", "git.commit.hide-keys", "true"]);
+    shrine(&["rm", "prod/db/password"]);
+    assert_eq!(last_message(), "shrine: rm 1 key");
+
+    shrine(&["config", "set", "git.commit.auto", "false"]);
+    assert_eq!(last_message(), "shrine: config git.commit.auto");
+
+    shrine(&["set", "staging/a", "1"]);
+    shrine(&["set", "staging/b", "1"]);
+    shrine(&["config", "set", "git.commit.hide-keys", "false"]);
+    shrine(&["commit"]).stdout(predicate::str::starts_with("Committed "));
+    assert_eq!(last_message(), "shrine: update 2 keys under staging/");
+
+    shrine(&["commit"]).stdout("Nothing to commit\n");
+
+    shrine(&["set", "staging/a", "2"]);
+    shrine(&["commit", "-m", "Rotate staging credentials"]);
+    assert_eq!(last_message(), "shrine: Rotate staging credentials");
+}
+
 #[test]
 fn git_then_disable_git() {
     let folder = tempfile::tempdir().unwrap();
@@ -375,3 +424,774 @@ fn git_then_disable_git() {
         .success()
         .stdout("2\n");
 }
+
+#[test]
+fn otp() {
+    let folder = create_shrine("p");
+
+    let file_path = folder.path().join("env-file");
+    let mut file = File::create(file_path.clone()).unwrap();
+    writeln!(
+        file,
+        "github=otpauth://totp/GitHub:alice?secret=GEZDGNBVGY3TQOJQ&issuer=GitHub"
+    )
+    .unwrap();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "import",
+            file_path.display().to_string().as_str(),
+        ])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "otp", "github"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("^[0-9]{6} \\([0-9]+s remaining\\)\n$").unwrap());
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "set",
+            "key",
+            "--mode",
+            "totp",
+            "val",
+        ])
+        .assert()
+        .failure()
+        .stderr("Invalid otpauth URI: expected an `otpauth://totp/` URI\n");
+}
+
+#[test]
+fn record() {
+    let folder = create_shrine("p");
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "set",
+            "db",
+            "--field",
+            "user=alice",
+            "--field",
+            "password=secret",
+        ])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "get", "db", "--field", "password"])
+        .assert()
+        .success()
+        .stdout("secret");
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "ls", "-l"])
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(" db {user, password}\n"));
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "dump", "--format", "json"])
+        .assert()
+        .success()
+        .stdout("{\n  \"db\": {\n    \"user\": \"alice\",\n    \"password\": \"secret\"\n  }\n}\n");
+}
+
+#[test]
+fn typed_secrets() {
+    let folder = create_shrine("p");
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "set",
+            "cert",
+            "--mode",
+            "pem",
+            "--stdin",
+        ])
+        .write_stdin(include_str!("fixtures/ec.crt"))
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "set",
+            "cert",
+            "--mode",
+            "pem",
+            "not a certificate",
+        ])
+        .assert()
+        .failure()
+        .stderr("Invalid PEM: no PEM block found\n");
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "inspect", "cert"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Certificate:   CN=example.com, O=Shrine\n",
+        ));
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "ls", "-l"])
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            " cert  CN=example.com, O=Shrine, expires 2126-09-24\n",
+        ));
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "get", "cert"])
+        .assert()
+        .success()
+        .stdout(include_str!("fixtures/ec.crt"));
+}
+
+#[test]
+fn certs() {
+    let folder = create_shrine("p");
+
+    for (key, fixture) in [
+        ("web/cert", include_str!("fixtures/ec.crt")),
+        ("web/key", include_str!("fixtures/ec.key")),
+    ] {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .args(vec!["--password", "p", "set", key, "--stdin"])
+            .write_stdin(fixture)
+            .assert()
+            .success();
+    }
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "certs", "--expiring-within", "30d"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "  Key:     web/key\n  Status:  ok\n",
+        ));
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "certs",
+            "web/.*",
+            "--expiring-within",
+            "40000d",
+        ])
+        .assert()
+        .failure()
+        .stderr("1 certificate(s) failed the check\n");
+}
+
+#[test]
+fn attach() {
+    let folder = create_shrine("p");
+
+    let file_path = folder.path().join("config.json");
+    fs::write(&file_path, "{\"key\": \"value\"}").unwrap();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "attach",
+            "config",
+            file_path.display().to_string().as_str(),
+        ])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "ls", "-l"])
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            " config  config.json (16 B, application/json)\n",
+        ));
+
+    let output = folder.path().join("restored.json");
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec![
+            "--password",
+            "p",
+            "extract",
+            "config",
+            "-o",
+            output.display().to_string().as_str(),
+        ])
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(&output).unwrap(), "{\"key\": \"value\"}");
+
+    fs::write(&output, "previous").unwrap();
+    let extract = |force: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .args(["--password", "p", "extract", "config", "-o"])
+            .arg(&output)
+            .args(force)
+            .assert()
+    };
+    extract(&[]).failure();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "previous");
+    extract(&["--force"]).success();
+    assert_eq!(fs::read_to_string(&output).unwrap(), "{\"key\": \"value\"}");
+}
+
+#[test]
+fn git_sync() {
+    let remote = tempfile::tempdir().unwrap();
+    assert_cmd::Command::new("git")
+        .current_dir(&remote)
+        .args(vec!["init", "--bare", "--initial-branch", "master"])
+        .assert()
+        .success();
+
+    let folder = tempfile::tempdir().unwrap();
+    assert_cmd::Command::new("git")
+        .current_dir(&folder)
+        .args(vec!["init", "--initial-branch", "master"])
+        .assert()
+        .success();
+    assert_cmd::Command::new("git")
+        .current_dir(&folder)
+        .args(vec![
+            "remote",
+            "add",
+            "origin",
+            remote.path().display().to_string().as_str(),
+        ])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "init", "--git"])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "sync"])
+        .assert()
+        .success()
+        .stdout("Already up to date\nPushed\n");
+
+    let clone = tempfile::tempdir().unwrap();
+    assert_cmd::Command::new("git")
+        .current_dir(&clone)
+        .args(vec![
+            "clone",
+            remote.path().display().to_string().as_str(),
+            ".",
+        ])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&clone)
+        .args(vec![
+            "--password",
+            "p",
+            "config",
+            "set",
+            "git.push.auto",
+            "true",
+        ])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&clone)
+        .args(vec!["--password", "p", "set", "key", "value"])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "sync"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Fast-forwarded to "));
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "get", "key"])
+        .assert()
+        .success()
+        .stdout("value");
+
+    // both sides change the shrine: the histories diverge
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&clone)
+        .args(vec!["--password", "p", "set", "remote", "value"])
+        .assert()
+        .success();
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "set", "local", "value"])
+        .assert()
+        .success();
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&folder)
+        .args(vec!["--password", "p", "sync"])
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::starts_with("Merged in ").and(predicate::str::ends_with("Pushed\n")),
+        );
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&clone)
+        .args(vec!["--password", "p", "sync"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Fast-forwarded to "));
+
+    assert_cmd::Command::cargo_bin("shrine")
+        .unwrap()
+        .current_dir(&clone)
+        .args(vec!["--password", "p", "ls"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("local").and(predicate::str::contains("remote")));
+}
+
+#[test]
+fn git_push_unreachable_remote() {
+    let folder = tempfile::tempdir().unwrap();
+    assert_cmd::Command::new("git")
+        .current_dir(&folder)
+        .args(vec!["init"])
+        .assert()
+        .success();
+    assert_cmd::Command::new("git")
+        .current_dir(&folder)
+        .args(vec![
+            "remote",
+            "add",
+            "origin",
+            folder.path().join("missing").display().to_string().as_str(),
+        ])
+        .assert()
+        .success();
+
+    let shrine = |args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .args(["--password", "p"])
+            .args(args)
+            .assert()
+            .success()
+    };
+    shrine(&["init", "--git"]);
+    shrine(&["config", "set", "git.push.auto", "true"]);
+
+    shrine(&["set", "key", "value"]).stderr(predicate::str::contains(
+        "WARNING: could not push the commit",
+    ));
+
+    assert_cmd::Command::new("git")
+        .current_dir(&folder)
+        .args(vec!["log", "-1", "--format=%s"])
+        .assert()
+        .success()
+        .stdout("set key\n");
+}
+
+#[test]
+fn git_merge_driver() {
+    let git = |folder: &TempDir, args: &[&str]| {
+        assert_cmd::Command::new("git")
+            .current_dir(folder)
+            .args(["-c", "user.name=test", "-c", "user.email=test@localhost"])
+            .args(args)
+            .assert()
+            .success();
+    };
+    let shrine = |folder: &TempDir, args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(folder)
+            .args(["--password", "p"])
+            .args(args)
+            .assert()
+            .success()
+    };
+
+    let remote = tempfile::tempdir().unwrap();
+    git(&remote, &["init", "--bare", "--initial-branch", "master"]);
+
+    let folder = tempfile::tempdir().unwrap();
+    git(&folder, &["init", "--initial-branch", "master"]);
+    git(
+        &folder,
+        &["remote", "add", "origin", remote.path().to_str().unwrap()],
+    );
+    shrine(&folder, &["init", "--git", "--encryption", "none"]);
+    shrine(&folder, &["set", "common", "value"]);
+    shrine(&folder, &["sync"]);
+
+    let clone = tempfile::tempdir().unwrap();
+    git(&clone, &["clone", remote.path().to_str().unwrap(), "."]);
+    shrine(&clone, &["git", "install-merge-driver"])
+        .stdout("Merge driver installed; commit `.gitattributes` to share it\n");
+
+    shrine(&folder, &["set", "folder", "value"]);
+    shrine(&folder, &["sync"]);
+
+    shrine(&clone, &["set", "clone", "value"]);
+    git(
+        &clone,
+        &["pull", "--no-rebase", "--no-edit", "origin", "master"],
+    );
+
+    shrine(&clone, &["ls"])
+        .stdout(predicate::str::contains("clone"))
+        .stdout(predicate::str::contains("common"))
+        .stdout(predicate::str::contains("folder"));
+}
+
+#[test]
+fn git_log_and_blame() {
+    let folder = tempfile::tempdir().unwrap();
+    let shrine = |args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .args(["--password", "p"])
+            .args(args)
+            .assert()
+            .success()
+    };
+
+    shrine(&["init", "--git"]);
+    shrine(&["set", "a", "1"]);
+    shrine(&["set", "b", "1"]);
+    shrine(&["set", "a", "2"]);
+
+    shrine(&["log"])
+        .stdout(predicate::str::contains("    changed  a\n"))
+        .stdout(predicate::str::contains("    added    b\n"))
+        .stdout(predicate::str::contains("    config   git.enabled\n"));
+
+    let output = shrine(&["log", "b"]).get_output().stdout.clone();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), 3);
+    assert!(output.ends_with("set b\n    added    b\n\n"));
+
+    let output = shrine(&["blame"]).get_output().stdout.clone();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), 2);
+    assert!(output.lines().next().unwrap().ends_with("  a"));
+}
+
+#[test]
+fn get_and_dump_at_revision() {
+    let folder = tempfile::tempdir().unwrap();
+    let config = tempfile::tempdir().unwrap();
+    let shrine = |password: &str, args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .env("XDG_CONFIG_HOME", config.path())
+            .args(["--password", password])
+            .args(args)
+            .assert()
+    };
+
+    shrine("p", &["init", "--git"]).success();
+    shrine("p", &["set", "key", "v1"]).success();
+    shrine("p", &["set", "key", "v2"]).success();
+
+    shrine("p", &["get", "key", "--at", "HEAD~1"])
+        .success()
+        .stdout("v1");
+    shrine("p", &["dump", "--at", "HEAD~1"])
+        .success()
+        .stdout(predicate::str::contains("  key=v1\n"));
+    shrine("p", &["get", "key", "--at", "2000-01-01"])
+        .failure()
+        .stderr("Revision `2000-01-01` not found\n");
+
+    // the revisions encrypted with the previous password are opened with the passwords file
+    let uuid = shrine("p", &["info", "--field", "uuid"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let uuid = String::from_utf8(uuid).unwrap();
+    shrine("p", &["convert", "--new-password", "q"]).success();
+
+    fs::create_dir(config.path().join("shrine")).unwrap();
+    let passwords = config.path().join("shrine").join("passwords");
+    fs::write(&passwords, format!("{}=p\n", uuid.trim())).unwrap();
+    fs::set_permissions(
+        &passwords,
+        std::os::unix::fs::PermissionsExt::from_mode(0o600),
+    )
+    .unwrap();
+
+    shrine("q", &["get", "key", "--at", "HEAD~2"])
+        .success()
+        .stdout("v1");
+    shrine("q", &["get", "key"]).success().stdout("v2");
+}
+
+#[test]
+fn git_rewrite_history() {
+    let folder = tempfile::tempdir().unwrap();
+    let config = tempfile::tempdir().unwrap();
+    let shrine = |password: &str, args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .env("XDG_CONFIG_HOME", config.path())
+            .args(["--password", password])
+            .args(args)
+            .assert()
+    };
+
+    shrine("p", &["init", "--git"]).success();
+    shrine("p", &["set", "key", "v1"]).success();
+    let uuid = shrine("p", &["info", "--field", "uuid"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let uuid = String::from_utf8(uuid).unwrap();
+    shrine("p", &["convert", "--new-password", "q"]).success();
+
+    fs::create_dir(config.path().join("shrine")).unwrap();
+    let passwords = config.path().join("shrine").join("passwords");
+    fs::write(&passwords, format!("{}=p\n", uuid.trim())).unwrap();
+    fs::set_permissions(
+        &passwords,
+        std::os::unix::fs::PermissionsExt::from_mode(0o600),
+    )
+    .unwrap();
+
+    shrine("q", &["git", "rewrite-history"])
+        .success()
+        .stdout(predicate::str::starts_with("Rewrote 3 commit(s) on "))
+        .stdout(predicate::str::contains("refs/shrine-backup/"))
+        .stdout(predicate::str::contains("git push --force origin "));
+
+    // the previous password is no longer needed to read the history
+    fs::remove_file(&passwords).unwrap();
+    shrine("q", &["get", "key", "--at", "HEAD~1"])
+        .success()
+        .stdout("v1");
+    shrine("q", &["log", "key"])
+        .success()
+        .stdout(predicate::str::contains("set key\n    added    key\n"));
+}
+
+#[test]
+fn git_textconv() {
+    let folder = tempfile::tempdir().unwrap();
+    let config = tempfile::tempdir().unwrap();
+    let shrine = |args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .env("XDG_CONFIG_HOME", config.path())
+            .args(["--password", "p"])
+            .args(args)
+            .assert()
+            .success()
+    };
+
+    shrine(&["init", "--git"]);
+    shrine(&["set", "a", "secret-a"]);
+    shrine(&["git", "install-textconv"])
+        .stdout("Diff filter installed; commit `.gitattributes` to share it\n");
+    shrine(&["set", "b", "secret-b"]);
+
+    // git runs the filter without `--password`: it is read from the passwords file
+    let uuid = shrine(&["info", "--field", "uuid"])
+        .get_output()
+        .stdout
+        .clone();
+    let uuid = String::from_utf8(uuid).unwrap();
+    fs::create_dir(config.path().join("shrine")).unwrap();
+    let passwords = config.path().join("shrine").join("passwords");
+    fs::write(&passwords, format!("{}=p\n", uuid.trim())).unwrap();
+    fs::set_permissions(
+        &passwords,
+        std::os::unix::fs::PermissionsExt::from_mode(0o600),
+    )
+    .unwrap();
+
+    let output = assert_cmd::Command::new("git")
+        .current_dir(&folder)
+        .env("XDG_CONFIG_HOME", config.path())
+        .args(["diff", "HEAD~1", "HEAD", "--", "shrine"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.contains("\n a txt "), "{}", output);
+    assert!(output.contains("\n+b txt "), "{}", output);
+    assert!(!output.contains("secret"), "{}", output);
+}
+
+#[test]
+fn agent_autostart() {
+    let folder = tempfile::tempdir().unwrap();
+    let runtime = tempfile::tempdir().unwrap();
+    let config = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::create_dir(config.path().join("shrine")).unwrap();
+    fs::write(
+        config.path().join("shrine").join("config"),
+        "agent.autostart=true\nagent.idle-timeout=1m\n",
+    )
+    .unwrap();
+
+    let shrine = |args: &[&str]| {
+        let mut command = std::process::Command::new(assert_cmd::cargo::cargo_bin("shrine"));
+        command
+            .current_dir(&folder)
+            .env("XDG_RUNTIME_DIR", runtime.path())
+            .env("XDG_CONFIG_HOME", config.path())
+            .env("XDG_STATE_HOME", state.path())
+            .args(args);
+        command
+    };
+
+    // the agent's commands do not start it
+    let output = shrine(&["agent", "status"]).output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Is running: false\n"
+    );
+
+    assert!(shrine(&["--no-agent", "init", "--encryption", "none"])
+        .status()
+        .unwrap()
+        .success());
+
+    // concurrent commands start a single agent
+    let children = (0..4)
+        .map(|i| {
+            shrine(&["set", &format!("key{}", i), "value"])
+                .spawn()
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    for mut child in children {
+        assert!(child.wait().unwrap().success());
+    }
+
+    let pid = fs::read_to_string(runtime.path().join("shrine.pid")).unwrap();
+    let output = shrine(&["agent", "status"]).output().unwrap();
+    assert!(
+        String::from_utf8_lossy(&output.stdout).contains(&format!("PID:        {}\n", pid.trim()))
+    );
+
+    let output = shrine(&["ls"]).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("total 4\n"));
+
+    assert!(shrine(&["agent", "stop"]).status().unwrap().success());
+}
+
+#[test]
+fn agent_autostart_failure() {
+    let folder = tempfile::tempdir().unwrap();
+    let runtime = tempfile::tempdir().unwrap();
+    let config = tempfile::tempdir().unwrap();
+    let state = tempfile::tempdir().unwrap();
+    fs::create_dir(config.path().join("shrine")).unwrap();
+    // the agent refuses to start with an invalid setting
+    fs::write(
+        config.path().join("shrine").join("config"),
+        "agent.autostart=true\nagent.password.ttl=invalid\n",
+    )
+    .unwrap();
+
+    let shrine = |args: &[&str]| {
+        assert_cmd::Command::cargo_bin("shrine")
+            .unwrap()
+            .current_dir(&folder)
+            .env("XDG_RUNTIME_DIR", runtime.path())
+            .env("XDG_CONFIG_HOME", config.path())
+            .env("XDG_STATE_HOME", state.path())
+            .args(args)
+            .assert()
+            .success()
+    };
+
+    shrine(&["--no-agent", "--password", "p", "init"]);
+    shrine(&["--password", "p", "set", "key", "value"]).stderr(predicate::str::contains(
+        "WARNING: Could not contact agent: `agent start` failed",
+    ));
+    shrine(&["--no-agent", "--password", "p", "get", "key"]).stdout("value");
+}